@@ -0,0 +1,163 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use lihkg::config::ExtractorConfig;
+use lihkg::pipeline::process_archive;
+use lihkg::{
+    convert_html_to_text, count_matching_chars, filter_irrelevant_chars,
+    filter_irrelevant_chars_scalar, is_valid_para, Batch, Extractor, CJK_REGEX,
+};
+use std::path::PathBuf;
+
+// About 500 bytes: a quoted reply, line breaks, an emoji icon and a link
+const MESSAGE: &str = concat!(
+    r#"<blockquote><blockquote>呢間茶記好食過隔離嗰間</blockquote>真係咁好食？</blockquote>"#,
+    r#"我上個禮拜先去過，菠蘿油一般啦<br />"#,
+    r#"<img src="/assets/faces/normal/smile.gif" class="hkgmoji" /> 奶茶就真係唔錯<br />"#,
+    r#"<br />"#,
+    r#"星期六朝早排咗成個鐘<br />"#,
+    r#"<a href="https://lih.kg/3312345" target="_blank">https://lih.kg/3312345</a><br />"#,
+    r#"<strong>上次有巴打話佢哋改咗餐牌</strong>，唔知係咪真<br />"#,
+    r#"下次試下佢個沙嗲牛麵先"#,
+);
+
+// 100 chars of CJK, ASCII, punctuation and emoji
+const MIXED: &str = concat!(
+    "今日放工去旺角食嘢，OK啦都幾好食！price 唔算貴，",
+    "$68 有個set，仲有杯嘢飲 (凍檸茶) 😂😂 下次再去試吓",
+    "佢嘅招牌菜 ABC123，大家有冇推介？",
+    "聽日落雨記得帶遮 umbrella 呀！！",
+);
+
+// A mostly English post, the case the SIMD fast path is for
+const ASCII_HEAVY: &str = concat!(
+    "Just got back from the new place in Mong Kok, the set lunch is $68 with a drink ",
+    "and honestly the portion (rice + soup) is not bad at all. Will try again next week, ",
+    "anyone been to the one in TST? 好食過呢間？",
+);
+
+const PARAS: [&str; 10] = [
+    "我哋今日去咗飲茶",
+    "",
+    "此回覆已被刪除",
+    "https://lih.kg/3312345",
+    "hello world",
+    "2023-10-15",
+    "12:34:56",
+    "哈哈哈哈哈哈哈哈哈哈哈哈",
+    "呢個post真係好正",
+    "分享自 LIHKG 討論區",
+];
+
+fn line() -> String {
+    let obj = serde_json::json!({
+        "success": 1,
+        "response": {
+            "thread_id": "3312345",
+            "item_data": (0..25).map(|i| serde_json::json!({
+                "post_id": format!("3312345:{}", i),
+                "msg": MESSAGE,
+                "reply_time": 1697356800 + i * 60,
+                "like_count": "3",
+                "dislike_count": "0",
+            })).collect::<Vec<_>>(),
+        },
+    });
+    format!("3312345\t1\t{}", obj)
+}
+
+fn bench_parser(c: &mut Criterion) {
+    c.bench_function("convert_html_to_text", |b| {
+        b.iter(|| convert_html_to_text(black_box(MESSAGE)))
+    });
+    c.bench_function("is_valid_para x10k", |b| {
+        b.iter(|| {
+            PARAS
+                .iter()
+                .cycle()
+                .take(10_000)
+                .filter(|para| is_valid_para(black_box(para)))
+                .count()
+        })
+    });
+    c.bench_function("filter_irrelevant_chars", |b| {
+        b.iter(|| filter_irrelevant_chars(black_box(MIXED)))
+    });
+    c.bench_function("filter_irrelevant_chars ascii", |b| {
+        b.iter(|| filter_irrelevant_chars(black_box(ASCII_HEAVY)))
+    });
+    c.bench_function("filter_irrelevant_chars_scalar ascii", |b| {
+        b.iter(|| filter_irrelevant_chars_scalar(black_box(ASCII_HEAVY)))
+    });
+    c.bench_function("count_matching_chars", |b| {
+        b.iter(|| count_matching_chars(black_box(MIXED), &CJK_REGEX))
+    });
+
+    let extractor = Extractor::new(&ExtractorConfig::default()).unwrap();
+    let line = line();
+    c.bench_function("process_line", |b| {
+        b.iter(|| {
+            let mut batch = Batch::default();
+            extractor
+                .process_line(black_box(&line), &mut batch)
+                .unwrap();
+            batch
+        })
+    });
+}
+
+// Size of the generated input of the batch size benchmark
+const ARCHIVE_MB: usize = 100;
+
+// A dump of pages like `line()` with varying thread ids, written uncompressed
+// so the benchmark measures extraction rather than xz. LIHKG_BENCH_ARCHIVE
+// points it at a real archive instead.
+fn bench_archive() -> PathBuf {
+    if let Some(path) = std::env::var_os("LIHKG_BENCH_ARCHIVE") {
+        return path.into();
+    }
+    let path = std::env::temp_dir().join(format!("lihkg-bench-{}MB.csv", ARCHIVE_MB));
+    if !path.exists() {
+        let line = line();
+        let mut data = String::with_capacity(ARCHIVE_MB << 20);
+        let mut thread_id = 3312345;
+        while data.len() < ARCHIVE_MB << 20 {
+            data.push_str(&line.replace("3312345", &thread_id.to_string()));
+            data.push('\n');
+            thread_id += 1;
+        }
+        std::fs::write(&path, data).unwrap();
+    }
+    path
+}
+
+fn bench_batch_size(c: &mut Criterion) {
+    let path = bench_archive();
+    let mut group = c.benchmark_group("process_archive");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(std::fs::metadata(&path).unwrap().len()));
+    for batch_size in [None, Some(100), Some(1000), Some(10000)] {
+        let extractor = Extractor::new(&ExtractorConfig {
+            batch_size,
+            ..Default::default()
+        })
+        .unwrap();
+        let id = match batch_size {
+            Some(n) => BenchmarkId::new("batch_size", n),
+            None => BenchmarkId::new("batch_size", "auto"),
+        };
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                let mut sentences = 0;
+                process_archive(&path, &extractor, None, |_, batch| {
+                    sentences += batch.records.len();
+                    Ok(())
+                })
+                .unwrap();
+                sentences
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parser, bench_batch_size);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// The dict files of rime-cantonese (CC BY 4.0) the jyutping feature builds
+// its table from, https://github.com/rime/rime-cantonese
+const RIME_DICTS: [&str; 2] = ["jyut6ping3.chars.dict.yaml", "jyut6ping3.words.dict.yaml"];
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=RIME_CANTONESE_DIR");
+    if env::var_os("CARGO_FEATURE_JYUTPING").is_none() {
+        return;
+    }
+    // a checkout in data/rime-cantonese unless RIME_CANTONESE_DIR names one
+    let dir = match env::var_os("RIME_CANTONESE_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("data/rime-cantonese")
+        }
+    };
+    // the entries of every file, without the yaml headers and comments
+    let mut table = String::new();
+    for name in RIME_DICTS {
+        let path = dir.join(name);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let dict = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!(
+                "the jyutping feature needs {}: {}. Clone \
+                 https://github.com/rime/rime-cantonese into data/rime-cantonese \
+                 or set RIME_CANTONESE_DIR to a checkout of it.",
+                path.display(),
+                e
+            )
+        });
+        let body = match dict.split_once("\n...\n") {
+            Some((_, body)) => body,
+            None => panic!("{} has no yaml header ending with ...", path.display()),
+        };
+        for line in body.lines() {
+            let line = line.trim_end_matches('\r');
+            if !line.is_empty() && !line.starts_with('#') {
+                writeln!(table, "{}", line).unwrap();
+            }
+        }
+    }
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("jyutping.tsv");
+    std::fs::write(out, table).unwrap();
+}
@@ -0,0 +1,29 @@
+"""Reads the sentences written by --output-arrow with pyarrow.
+
+    lihkg dump.tar.xz --output-arrow out.arrow
+    python examples/arrow_usage.py out.arrow
+"""
+
+import sys
+
+import pyarrow.ipc
+
+
+def main(path):
+    with open(path, "rb") as f:
+        reader = pyarrow.ipc.open_stream(f)
+        print(reader.schema)
+        # one record batch per archive entry, columns read without parsing
+        for batch in reader:
+            sources = batch.column("source_file").unique().to_pylist()
+            print(f"{batch.num_rows} sentences from {sources}")
+
+    # or all at once, as a table
+    with open(path, "rb") as f:
+        table = pyarrow.ipc.open_stream(f).read_all()
+    print(table.num_rows, "sentences")
+    print(table.slice(0, 5))
+
+
+if __name__ == "__main__":
+    main(sys.argv[1] if len(sys.argv) > 1 else "out.arrow")
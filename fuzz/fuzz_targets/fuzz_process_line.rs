@@ -0,0 +1,17 @@
+#![no_main]
+
+use lazy_static::lazy_static;
+use libfuzzer_sys::fuzz_target;
+use lihkg::config::ExtractorConfig;
+use lihkg::{Batch, Extractor};
+
+lazy_static! {
+    static ref EXTRACTOR: Extractor = Extractor::new(&ExtractorConfig::default()).unwrap();
+}
+
+// Any input either parses or fails with a `serde_json::Error`, a panic or
+// abort is a bug
+fuzz_target!(|line: &str| {
+    let mut batch = Batch::default();
+    let _: Result<(), serde_json::Error> = EXTRACTOR.process_line(line, &mut batch);
+});
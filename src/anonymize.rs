@@ -0,0 +1,68 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io;
+
+// Environment variable holding the key when --anonymize-key is not given
+pub const ANON_KEY_ENV: &str = "LIHKG_ANON_KEY";
+
+// Hex digits kept of each pseudonym
+const PSEUDONYM_LEN: usize = 16;
+
+// Replaces user ids by a keyed hash, the same id always getting the same
+// pseudonym under the same key, while without the key the ids cannot be
+// recovered by hashing candidate ids
+pub struct Anonymizer {
+    key: Vec<u8>,
+}
+
+impl Anonymizer {
+    pub fn from_hex(key: &str) -> io::Result<Self> {
+        let key = key.trim();
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the anonymize key must be a non-empty even number of hex digits",
+            )
+        };
+        if key.is_empty() || !key.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let key = (0..key.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(key.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        Ok(Anonymizer { key })
+    }
+
+    // HMAC-SHA256 of the id, truncated to 16 hex digits
+    pub fn pseudonym(&self, user_id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length");
+        mac.update(user_id.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let mut hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        hex.truncate(PSEUDONYM_LEN);
+        hex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudonyms_are_keyed_and_stable() {
+        // RFC 4231 test case 2, key "Jefe"
+        let anonymizer = Anonymizer::from_hex("4a656665").unwrap();
+        assert_eq!(
+            anonymizer.pseudonym("what do ya want for nothing?"),
+            "5bdcc146bf60754e"
+        );
+        assert_eq!(anonymizer.pseudonym("123"), anonymizer.pseudonym("123"));
+        let other = Anonymizer::from_hex("4A656666").unwrap();
+        assert_ne!(anonymizer.pseudonym("123"), other.pseudonym("123"));
+        for key in ["", "abc", "zz", "é1"] {
+            assert!(Anonymizer::from_hex(key).is_err(), "{}", key);
+        }
+    }
+}
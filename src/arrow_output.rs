@@ -0,0 +1,145 @@
+use crate::sink::{SentenceSink, SinkReport};
+use crate::{cjk_ratio, SentenceRecord};
+use arrow2::array::{Float32Array, Int32Array, Utf8Array};
+use arrow2::chunk::Chunk;
+use arrow2::datatypes::{DataType, Field, Schema};
+use arrow2::io::ipc::write::{StreamWriter, WriteOptions};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+// The columns of the Parquet output
+fn schema() -> Schema {
+    Schema::from(vec![
+        Field::new("text", DataType::Utf8, false),
+        Field::new("source_file", DataType::Utf8, true),
+        Field::new("cjk_ratio", DataType::Float32, false),
+        Field::new("char_count", DataType::Int32, false),
+    ])
+}
+
+// The written sentences as an Arrow IPC stream, one record batch per archive
+// entry, which pyarrow reads without parsing. Rows taken by `push` are
+// written by the next `write_batch` with its source file.
+pub struct ArrowOutput {
+    writer: StreamWriter<BufWriter<File>>,
+    texts: Vec<String>,
+    rows: u64,
+}
+
+impl ArrowOutput {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut writer = StreamWriter::new(file, WriteOptions { compression: None });
+        writer.start(&schema(), None).map_err(io::Error::other)?;
+        Ok(ArrowOutput {
+            writer,
+            texts: Vec::new(),
+            rows: 0,
+        })
+    }
+
+    pub fn push(&mut self, record: &SentenceRecord) {
+        self.texts.push(record.text.clone());
+    }
+
+    pub fn write_batch(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        if self.texts.is_empty() {
+            return Ok(());
+        }
+        let texts = std::mem::take(&mut self.texts);
+        self.rows += texts.len() as u64;
+        let ratios: Vec<f32> = texts.iter().map(|t| cjk_ratio(t) as f32).collect();
+        let counts: Vec<i32> = texts.iter().map(|t| t.chars().count() as i32).collect();
+        let sources = vec![source_file; texts.len()];
+        let chunk = Chunk::new(vec![
+            Utf8Array::<i32>::from_slice(&texts).boxed(),
+            Utf8Array::<i32>::from(sources).boxed(),
+            Float32Array::from_vec(ratios).boxed(),
+            Int32Array::from_vec(counts).boxed(),
+        ]);
+        self.writer.write(&chunk, None).map_err(io::Error::other)
+    }
+
+    // Writes the last batch and the end of stream marker
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_batch(None)?;
+        self.writer.finish().map_err(io::Error::other)
+    }
+}
+
+impl SentenceSink for ArrowOutput {
+    fn write(&mut self, record: &SentenceRecord) -> io::Result<()> {
+        self.push(record);
+        Ok(())
+    }
+
+    fn end_entry(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        self.write_batch(source_file)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<SinkReport> {
+        self.write_batch(None)?;
+        let records = self.rows;
+        ArrowOutput::finish(*self)?;
+        Ok(SinkReport {
+            records,
+            bytes: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow2::io::ipc::read::{read_stream_metadata, StreamReader, StreamState};
+
+    #[test]
+    fn batches_are_readable() {
+        let path = std::env::temp_dir().join(format!("lihkg-{}.arrow", std::process::id()));
+        let mut output = ArrowOutput::create(&path).unwrap();
+        for text in ["我哋今日去咗飲茶", "OK啦"] {
+            output.push(&SentenceRecord {
+                text: text.to_string(),
+                ..Default::default()
+            });
+        }
+        output.write_batch(Some("3300001.csv")).unwrap();
+        output.push(&SentenceRecord {
+            text: "點心好好食".to_string(),
+            ..Default::default()
+        });
+        output.finish().unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let metadata = read_stream_metadata(&mut file).unwrap();
+        assert_eq!(metadata.schema, schema());
+        let chunks: Vec<_> = StreamReader::new(file, metadata, None)
+            .map(|state| match state.unwrap() {
+                StreamState::Some(chunk) => chunk,
+                StreamState::Waiting => panic!("stream cut short"),
+            })
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(chunks.len(), 2);
+        let columns = chunks[0].arrays();
+        let column = |i: usize| columns[i].as_any();
+        assert_eq!(
+            column(0).downcast_ref::<Utf8Array<i32>>().unwrap(),
+            &Utf8Array::<i32>::from_slice(["我哋今日去咗飲茶", "OK啦"])
+        );
+        assert_eq!(
+            column(1).downcast_ref::<Utf8Array<i32>>().unwrap(),
+            &Utf8Array::<i32>::from([Some("3300001.csv"), Some("3300001.csv")])
+        );
+        assert_eq!(
+            column(2).downcast_ref::<Float32Array>().unwrap(),
+            &Float32Array::from_vec(vec![1.0, 1.0 / 3.0])
+        );
+        assert_eq!(
+            column(3).downcast_ref::<Int32Array>().unwrap(),
+            &Int32Array::from_vec(vec![8, 3])
+        );
+        assert!(chunks[1].arrays()[1].is_null(0));
+    }
+}
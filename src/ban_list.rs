@@ -0,0 +1,134 @@
+use crate::{list_entries, value_as_i64};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock};
+use std::time::SystemTime;
+
+// Accounts whose posts are skipped, a user_id or nickname per line of a
+// list file. The file is read again by `reload` once it changes, so a watch
+// picks up new entries without a restart.
+pub struct BanList {
+    path: PathBuf,
+    state: RwLock<BanState>,
+}
+
+struct BanState {
+    modified: Option<SystemTime>,
+    entries: HashSet<String>,
+}
+
+impl BanList {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Ok(BanList {
+            path: path.to_path_buf(),
+            state: RwLock::new(read_state(path)?),
+        })
+    }
+
+    // Reads the file again if it changed since it was last read, true if it
+    // did
+    pub fn reload(&self) -> io::Result<bool> {
+        let modified = std::fs::metadata(&self.path)?.modified().ok();
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        if modified.is_some() && modified == state.modified {
+            return Ok(false);
+        }
+        *state = read_state(&self.path)?;
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entries
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The entry a post's user_id or nickname matches, the user_id first
+    pub fn matching(&self, item: &Value) -> Option<String> {
+        let state = self.state.read().unwrap_or_else(PoisonError::into_inner);
+        let user = &item["user"];
+        let user_id = value_as_i64(&user["user_id"]).or_else(|| value_as_i64(&item["user_id"]));
+        if let Some(user_id) = user_id.map(|id| id.to_string()) {
+            if state.entries.contains(&user_id) {
+                return Some(user_id);
+            }
+        }
+        let nickname = user["nickname"]
+            .as_str()
+            .or_else(|| item["user_nickname"].as_str())?
+            .trim();
+        state
+            .entries
+            .contains(nickname)
+            .then(|| nickname.to_string())
+    }
+}
+
+fn read_state(path: &Path) -> io::Result<BanState> {
+    let modified = std::fs::metadata(path)?.modified().ok();
+    let contents = std::fs::read_to_string(path)?;
+    Ok(BanState {
+        modified,
+        entries: list_entries(&contents)
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn posts_match_by_user_id_or_nickname() {
+        let path = std::env::temp_dir().join(format!("lihkg-ban-{}.txt", std::process::id()));
+        std::fs::write(&path, "# lottery bot\n12345\n\n六合彩報告\n").unwrap();
+        let bans = BanList::from_file(&path).unwrap();
+        assert_eq!(bans.len(), 2);
+        let post = |user: Value| json!({ "user": user, "msg": "今期攪珠結果" });
+        assert_eq!(
+            bans.matching(&post(json!({"user_id": "12345", "nickname": "巴打"}))),
+            Some("12345".to_string())
+        );
+        assert_eq!(
+            bans.matching(&post(json!({"user_id": 7, "nickname": " 六合彩報告 "}))),
+            Some("六合彩報告".to_string())
+        );
+        assert_eq!(
+            bans.matching(&json!({"user_id": 12345, "user_nickname": "巴打"})),
+            Some("12345".to_string())
+        );
+        assert_eq!(
+            bans.matching(&post(json!({"user_id": "1", "nickname": "巴打"}))),
+            None
+        );
+
+        // unchanged, then rewritten with a later modification time
+        assert!(!bans.reload().unwrap());
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::write(&path, "巴打\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(bans.reload().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(
+            bans.matching(&post(json!({"user_id": "12345", "nickname": "巴打"}))),
+            Some("巴打".to_string())
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use std::io;
+use std::path::Path;
+
+// Scores sentences by how natural a masked language model finds them: the
+// geometric mean of the probabilities it gives each token with that token
+// masked, i.e. 1 over the pseudo-perplexity, between 0 and 1. Every token is
+// a forward pass of its own, `batch_size` of which run together.
+//
+// The model directory holds a BERT checkpoint converted for rust-bert, its
+// config.json, vocab.txt and rust_model.ot, e.g. those of
+// bert-base-multilingual-cased.
+pub struct BertScorer {
+    model: masked_lm::MaskedLm,
+    batch_size: usize,
+}
+
+impl BertScorer {
+    pub fn load(dir: &Path, batch_size: usize) -> io::Result<Self> {
+        Ok(BertScorer {
+            model: masked_lm::MaskedLm::load(dir)?,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    pub fn score(&self, sentences: &[&str]) -> io::Result<Vec<f64>> {
+        let tokens = self.model.tokenize(sentences);
+        // (sentence, position) of every masked copy
+        let masked: Vec<(usize, usize)> = tokens
+            .iter()
+            .enumerate()
+            .flat_map(|(i, ids)| (1..ids.len().saturating_sub(1)).map(move |j| (i, j)))
+            .collect();
+        let mut log_likelihoods = vec![0.0; sentences.len()];
+        for batch in masked.chunks(self.batch_size) {
+            let copies: Vec<(&[i64], usize)> =
+                batch.iter().map(|&(i, j)| (&tokens[i][..], j)).collect();
+            for (&(i, _), log_p) in batch.iter().zip(self.model.log_probs(&copies)?) {
+                log_likelihoods[i] += log_p;
+            }
+        }
+        Ok(tokens
+            .iter()
+            .zip(log_likelihoods)
+            .map(|(ids, log_likelihood)| match ids.len().saturating_sub(2) {
+                0 => 0.0,
+                n => (log_likelihood / n as f64).exp(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(feature = "bert-filter")]
+mod masked_lm {
+    use rust_bert::bert::{BertConfig, BertForMaskedLM};
+    use rust_bert::Config;
+    use rust_tokenizers::tokenizer::{BertTokenizer, Tokenizer, TruncationStrategy};
+    use rust_tokenizers::vocab::Vocab;
+    use std::io;
+    use std::path::Path;
+    use tch::{nn, Device, Kind, Tensor};
+
+    // Tokens per sentence with [CLS] and [SEP], longer ones are cut
+    const MAX_TOKENS: usize = 128;
+
+    pub struct MaskedLm {
+        model: BertForMaskedLM,
+        tokenizer: BertTokenizer,
+        mask_id: i64,
+        pad_id: i64,
+        device: Device,
+        // holds the weights of `model`
+        _vars: nn::VarStore,
+    }
+
+    impl MaskedLm {
+        pub fn load(dir: &Path) -> io::Result<Self> {
+            let exists = |name: &str| {
+                let path = dir.join(name);
+                match path.is_file() {
+                    true => Ok(path),
+                    false => Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("the bert model has no {}", path.display()),
+                    )),
+                }
+            };
+            let config = BertConfig::from_file(exists("config.json")?);
+            let tokenizer = BertTokenizer::from_file(exists("vocab.txt")?, false, false)
+                .map_err(io::Error::other)?;
+            let device = Device::cuda_if_available();
+            let mut vars = nn::VarStore::new(device);
+            let model = BertForMaskedLM::new(vars.root(), &config);
+            vars.load(exists("rust_model.ot")?)
+                .map_err(io::Error::other)?;
+            let vocab = tokenizer.vocab();
+            Ok(MaskedLm {
+                mask_id: vocab.token_to_id(vocab.get_mask_value()),
+                pad_id: vocab.token_to_id(vocab.get_pad_value()),
+                model,
+                tokenizer,
+                device,
+                _vars: vars,
+            })
+        }
+
+        pub fn tokenize(&self, sentences: &[&str]) -> Vec<Vec<i64>> {
+            self.tokenizer
+                .encode_list(sentences, MAX_TOKENS, &TruncationStrategy::LongestFirst, 0)
+                .into_iter()
+                .map(|input| input.token_ids)
+                .collect()
+        }
+
+        // The log probability of the token at each position with it masked
+        pub fn log_probs(&self, copies: &[(&[i64], usize)]) -> io::Result<Vec<f64>> {
+            let len = copies.iter().map(|(ids, _)| ids.len()).max().unwrap_or(0);
+            let mut input = Vec::with_capacity(copies.len() * len);
+            let mut attention = Vec::with_capacity(copies.len() * len);
+            for (ids, position) in copies {
+                for j in 0..len {
+                    input.push(match ids.get(j) {
+                        Some(_) if j == *position => self.mask_id,
+                        Some(&id) => id,
+                        None => self.pad_id,
+                    });
+                    attention.push((j < ids.len()) as i64);
+                }
+            }
+            let shape = [copies.len() as i64, len as i64];
+            let input = Tensor::from_slice(&input).view(shape).to(self.device);
+            let attention = Tensor::from_slice(&attention).view(shape).to(self.device);
+            let output = tch::no_grad(|| {
+                self.model.forward_t(
+                    Some(&input),
+                    Some(&attention),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                )
+            });
+            let log_probs = output.prediction_scores.log_softmax(-1, Kind::Float);
+            Ok(copies
+                .iter()
+                .enumerate()
+                .map(|(k, (ids, position))| {
+                    log_probs.double_value(&[k as i64, *position as i64, ids[*position]])
+                })
+                .collect())
+        }
+    }
+}
+
+#[cfg(not(feature = "bert-filter"))]
+mod masked_lm {
+    use std::io;
+    use std::path::Path;
+
+    // never built, loading fails without the feature
+    pub enum MaskedLm {}
+
+    impl MaskedLm {
+        pub fn load(_dir: &Path) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the bert filter needs the bert-filter feature",
+            ))
+        }
+
+        pub fn tokenize(&self, _sentences: &[&str]) -> Vec<Vec<i64>> {
+            match *self {}
+        }
+
+        pub fn log_probs(&self, _copies: &[(&[i64], usize)]) -> io::Result<Vec<f64>> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "bert-filter"))]
+    #[test]
+    fn loading_needs_the_feature() {
+        let e = BertScorer::load(Path::new("model"), 32).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+pub(crate) const MAGIC: &[u8; 8] = b"LIHKGB1\n";
+
+// The bits and hash count of a filter holding `items` keys with about
+// `fp_rate` of the keys not in it taken for ones in it
+pub fn bloom_params(items: u64, fp_rate: f64) -> (u64, u32) {
+    let items = items.max(1) as f64;
+    let ln2 = std::f64::consts::LN_2;
+    let bits = (-items * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+    (bits as u64, optimal_hashes(bits as u64, items as u64))
+}
+
+// The hash count minimizing false positives for `items` keys in `bits`
+pub fn optimal_hashes(bits: u64, items: u64) -> u32 {
+    let hashes = bits as f64 / items.max(1) as f64 * std::f64::consts::LN_2;
+    (hashes.round() as u32).clamp(1, 32)
+}
+
+// A Bloom filter of 64 bit keys, which are hashes already, so the positions
+// are derived from the key by double hashing. Memory is fixed by the bits
+// whatever the number of keys, at the cost of false positives growing with
+// them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    words: Vec<u64>,
+    bits: u64,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(bits: u64, hashes: u32) -> Self {
+        let bits = bits.max(1);
+        BloomFilter {
+            words: vec![0; bits.div_ceil(64) as usize],
+            bits,
+            hashes: hashes.max(1),
+        }
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        positions(key, self.bits, self.hashes)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    // Adds a key, false if it was already taken to be in the filter
+    pub fn insert(&mut self, key: u64) -> bool {
+        let mut new = false;
+        for bit in positions(key, self.bits, self.hashes) {
+            let word = &mut self.words[(bit / 64) as usize];
+            new |= *word & (1 << (bit % 64)) == 0;
+            *word |= 1 << (bit % 64);
+        }
+        new
+    }
+
+    // The chance a key not in the filter is taken for one in it, from the
+    // share of bits set
+    pub fn false_positive_rate(&self) -> f64 {
+        let set: u64 = self.words.iter().map(|w| w.count_ones() as u64).sum();
+        (set as f64 / self.bits as f64).powi(self.hashes as i32)
+    }
+
+    // Adds the keys of a filter of the same size
+    pub fn union(&mut self, other: &BloomFilter) -> io::Result<()> {
+        if (self.bits, self.hashes) != (other.bits, other.hashes) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "bloom filter of {} bits and {} hashes where {} bits and {} hashes are expected",
+                    other.bits, other.hashes, self.bits, self.hashes
+                ),
+            ));
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+        Ok(())
+    }
+
+    // The file holds a tag byte of its owner's choosing, the dedup hash the
+    // keys were made with
+    pub fn save(&self, path: &Path, tag: u8) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[tag])?;
+        writer.write_all(&self.bits.to_le_bytes())?;
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        for word in &self.words {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<(BloomFilter, u8)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a bloom filter dedup state file",
+            ));
+        }
+        let mut tag = [0; 1];
+        reader.read_exact(&mut tag)?;
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        let bits = u64::from_le_bytes(bytes);
+        let mut hashes = [0; 4];
+        reader.read_exact(&mut hashes)?;
+        let mut filter = BloomFilter::new(bits, u32::from_le_bytes(hashes));
+        for word in &mut filter.words {
+            reader.read_exact(&mut bytes)?;
+            *word = u64::from_le_bytes(bytes);
+        }
+        Ok((filter, tag[0]))
+    }
+}
+
+fn positions(key: u64, bits: u64, hashes: u32) -> impl Iterator<Item = u64> {
+    let step = splitmix64(key) | 1;
+    (0..hashes as u64).map(move |i| key.wrapping_add(i.wrapping_mul(step)) % bits)
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xxhash_rust::xxh3::xxh3_64;
+
+    #[test]
+    fn false_positives_stay_near_the_target() {
+        for target in [0.01, 0.001] {
+            let items = 20_000;
+            let (bits, hashes) = bloom_params(items, target);
+            let mut filter = BloomFilter::new(bits, hashes);
+            for i in 0..items {
+                filter.insert(xxh3_64(format!("第{}句", i).as_bytes()));
+            }
+            let tries = 200_000;
+            let false_positives = (0..tries)
+                .filter(|i| filter.contains(xxh3_64(format!("另一句{}", i).as_bytes())))
+                .count();
+            let measured = false_positives as f64 / tries as f64;
+            assert!(measured < target * 1.5, "{} for {}", measured, target);
+            // the estimate from the bits set agrees with the measure
+            let estimate = filter.false_positive_rate();
+            assert!((estimate - measured).abs() < target * 0.5, "{}", estimate);
+        }
+    }
+
+    #[test]
+    fn saved_filters_load_and_merge() {
+        let path = std::env::temp_dir().join(format!("lihkg-bloom-{}", std::process::id()));
+        let mut filter = BloomFilter::new(1000, 3);
+        filter.insert(1);
+        filter.save(&path, 7).unwrap();
+        let (mut loaded, tag) = BloomFilter::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((&loaded, tag), (&filter, 7));
+        let mut other = BloomFilter::new(1000, 3);
+        other.insert(2);
+        loaded.union(&other).unwrap();
+        assert!(loaded.contains(1) && loaded.contains(2));
+        assert!(loaded.union(&BloomFilter::new(999, 3)).is_err());
+    }
+}
@@ -0,0 +1,909 @@
+use crate::dedup::{DedupHash, DedupKey};
+use crate::list_entries;
+use crate::pipeline::Shard;
+use crate::profanity::ProfanityMode;
+use crate::throttle::IoNice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use unicode_segmentation::UnicodeSegmentation;
+
+pub const DEFAULT_INPUT: &str = "./data/lihkg-1800000-2800000-csv.tar.xz";
+pub const DEFAULT_OUTPUT: &str = "sentences2.txt";
+pub const DEFAULT_CORPUS_STATS: &str = "corpus_stats.json";
+pub const DEFAULT_DOC_SEPARATOR: &str = " ";
+pub const DEFAULT_RARE_CHAR_TOKEN: &str = "\u{FFFD}";
+pub const DEFAULT_RARE_THRESHOLD: u64 = 3;
+pub const DEFAULT_MAX_OPEN_FILES: usize = 1000;
+pub const DEFAULT_WRITE_BUFFER_MB: usize = 64;
+pub const DEFAULT_PARQUET_ROW_GROUP_SIZE: usize = 65536;
+pub const DEFAULT_WATCH_MANIFEST: &str = "watch-manifest.txt";
+pub const DEFAULT_SETTLE_SECS: u64 = 10;
+pub const DEFAULT_WEIGHT_EXPONENT: f64 = 1.0;
+pub const DEFAULT_NGRAM_N: usize = 2;
+pub const DEFAULT_SPM_VOCAB_SIZE: usize = 8000;
+pub const DEFAULT_SPM_OUTPUT: &str = "spm.model";
+pub const DEFAULT_QUOTE_DEPTH: usize = 1;
+pub const DEFAULT_REPLY_GRAPH_WINDOW: usize = 200;
+// chars posters lengthen for emphasis, see `normalize_elongation`
+pub const DEFAULT_ELONGATION_CHARS: &str = "呀啊喇囉哈嘻w";
+// Cantonese sentence-final particles, see `ends_sentence`
+pub const DEFAULT_SENTENCE_END_PARTICLES: &str = "呀喎啩囉咋嘛啦㗎喇咯呢嘅";
+pub const DEFAULT_MIN_LEN: usize = 5;
+// the list file of --deleted-patterns built in, see `filters::DeletionMarkers`
+pub const BUILTIN_DELETED_PATTERNS: &str = include_str!("deleted_patterns.txt");
+pub const DEFAULT_MAX_LEN: usize = 20;
+pub const DEFAULT_MIN_CJK_RATIO: f64 = 0.8;
+// word bounds of `english::EnglishFilter`
+pub const DEFAULT_ENGLISH_MIN_WORDS: usize = 3;
+pub const DEFAULT_ENGLISH_MAX_WORDS: usize = 40;
+// thresholds of `news::NewsDetector`
+pub const DEFAULT_NEWS_MIN_PARAGRAPHS: usize = 4;
+pub const DEFAULT_NEWS_MIN_AVG_LEN: usize = 30;
+pub const DEFAULT_NEWS_MAX_CANTONESE_DENSITY: f64 = 0.01;
+// what --dedup-bloom sizes its filter for, see `bloom::bloom_params`
+pub const DEFAULT_BLOOM_ITEMS: u64 = 100_000_000;
+pub const DEFAULT_BLOOM_FP_RATE: f64 = 0.001;
+// masked copies of sentences `bert::BertScorer` runs through the model at a time
+pub const DEFAULT_BERT_BATCH_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    // one sentence per line
+    #[default]
+    Text,
+    // one JSON object per line with the post metadata
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "txt",
+            OutputFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+// What happens to <del>, <s> and <strike> text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StrikethroughMode {
+    // as plain text
+    #[default]
+    Keep,
+    Drop,
+}
+
+// What happens to the text of spoiler elements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SpoilerMode {
+    // as plain text
+    #[default]
+    Keep,
+    Drop,
+    // between `SPOILER_OPEN` and `SPOILER_CLOSE`
+    Token,
+}
+
+// What --two-pass does with a sentence holding rare chars
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RareCharsMode {
+    #[default]
+    DropSentence,
+    // each rare char by `ExtractorConfig::rare_char_token`
+    Replace,
+}
+
+// What happens to the sentences of posts pasting a news article, see
+// `news::NewsDetector`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NewsMode {
+    // not detected
+    #[default]
+    Keep,
+    Drop,
+    // marked news_like in jsonl records
+    Tag,
+}
+
+// The tree the HTML of posts is parsed into, see `html_parser`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HtmlParserKind {
+    #[default]
+    Scraper,
+    // needs the kuchiki feature
+    Kuchiki,
+}
+
+// What one output line holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    // one sentence
+    #[default]
+    Sentence,
+    // the sentences of a blank line separated block of a post
+    Paragraph,
+    // the sentences of a post
+    Document,
+    // the sentences of a post one per line, posts separated by a blank line
+    Post,
+}
+
+// What paragraph lengths are counted in, for the length bounds, the CJK
+// ratio and the repeated chars rule. Chars stay the default so existing
+// bounds and outputs keep their meaning.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    // code points, a family emoji of four people joined by ZWJs is seven
+    #[default]
+    Chars,
+    // extended grapheme clusters, what a reader takes for one char
+    Graphemes,
+}
+
+impl LengthUnit {
+    pub fn count(self, text: &str) -> usize {
+        match self {
+            LengthUnit::Chars => text.chars().count(),
+            LengthUnit::Graphemes => text.graphemes(true).count(),
+        }
+    }
+
+    // Distinct chars or graphemes of the text
+    pub fn distinct(self, text: &str) -> usize {
+        match self {
+            LengthUnit::Chars => text.chars().collect::<HashSet<_>>().len(),
+            LengthUnit::Graphemes => text.graphemes(true).collect::<HashSet<_>>().len(),
+        }
+    }
+
+    // Bytes of the text with a grapheme weighing as its first char, what
+    // the repeated chars rule compares the distinct ones with
+    pub fn bytes(self, text: &str) -> usize {
+        match self {
+            LengthUnit::Chars => text.len(),
+            LengthUnit::Graphemes => text
+                .graphemes(true)
+                .filter_map(|grapheme| grapheme.chars().next())
+                .map(char::len_utf8)
+                .sum(),
+        }
+    }
+}
+
+// Bounds of the base rules and optional paragraph filters on top of them,
+// all off by default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParaConfig {
+    // accepted paragraph length in `length_unit`s, inclusive
+    pub min_len: usize,
+    pub max_len: usize,
+    pub length_unit: LengthUnit,
+    // paragraphs made only of CJK ideographs skip the minimum length and
+    // the CJK ratio check, for short labels and tags
+    pub allow_short_cjk: bool,
+    // fraction of the chars that must be CJK
+    pub min_cjk_ratio: f64,
+    pub max_bigram_fraction: Option<f32>,
+    pub max_letter_run: Option<usize>,
+    // see `filters::LatinRun`, shortening the runs instead with
+    // truncate_latin_run
+    pub max_latin_run: Option<usize>,
+    pub truncate_latin_run: bool,
+    // count each run of Latin letters and digits as one char in the CJK
+    // ratio, so an English word weighs like the Chinese one it stands for
+    pub ratio_latin_as_word: bool,
+    pub min_distinct_tokens: Option<usize>,
+    // see `filters::SymbolSpam`
+    pub max_symbol_fraction: Option<f64>,
+    pub reject_latin: bool,
+    // see `filters::CantoneseMarkers`
+    pub require_cantonese: bool,
+    // paragraphs containing one of these are rejected as deleted
+    pub deletion_markers: Vec<String>,
+}
+
+impl Default for ParaConfig {
+    fn default() -> Self {
+        ParaConfig {
+            min_len: DEFAULT_MIN_LEN,
+            max_len: DEFAULT_MAX_LEN,
+            length_unit: LengthUnit::Chars,
+            allow_short_cjk: false,
+            min_cjk_ratio: DEFAULT_MIN_CJK_RATIO,
+            max_bigram_fraction: None,
+            max_letter_run: None,
+            max_latin_run: None,
+            truncate_latin_run: false,
+            ratio_latin_as_word: false,
+            min_distinct_tokens: None,
+            max_symbol_fraction: None,
+            reject_latin: false,
+            require_cantonese: false,
+            deletion_markers: list_entries(BUILTIN_DELETED_PATTERNS)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtractorConfig {
+    pub para: ParaConfig,
+    // shorten runs of the same char to this many before filtering
+    pub collapse_repeats: Option<usize>,
+    // shorten expressive lengthening of the elongation chars to two, see
+    // `normalize_elongation`
+    pub normalize_elongation: bool,
+    pub elongation_chars: String,
+    // drop sentences not ending like a finished one, see `ends_sentence`
+    pub require_sentence_end: bool,
+    pub sentence_end_particles: String,
+    // replace URLs with `URL_TOKEN` before filtering instead of rejecting them
+    pub urls_as_tokens: bool,
+    // keep emoji in the written sentences
+    pub keep_emoji: bool,
+    // write each paragraph before character filtering beside its sentence
+    pub keep_raw: bool,
+    pub profanity: ProfanityMode,
+    // replaces the built-in profanity word list
+    pub profanity_list: Option<PathBuf>,
+    // replaces the deletion markers with the patterns of this list file
+    pub deleted_patterns: Option<PathBuf>,
+    // skip the posts of the user_ids and nicknames listed, see `BanList`
+    pub ban_users: Option<PathBuf>,
+    // skip the pages of threads with fewer replies
+    pub min_replies: Option<u64>,
+    // skip posts with fewer likes
+    pub min_likes: Option<u64>,
+    // add the like and dislike counts of the post to jsonl records
+    pub include_votes: bool,
+    // add the poster's user id as a keyed hash to jsonl records
+    pub anonymize_users: bool,
+    // hex HMAC key of the pseudonyms, never written back out
+    #[serde(skip_serializing)]
+    pub anonymize_key: Option<String>,
+    // add the question and options of vote threads' polls, see `poll_texts`
+    pub extract_polls: bool,
+    // pair quoted posts with the replies quoting them, see `split_quote`
+    pub extract_pairs: bool,
+    // levels of a chain of nested quotes paired, see `split_quote`
+    pub quote_depth: usize,
+    // struck through and spoiler text of posts, see `convert_post`
+    pub strikethrough: StrikethroughMode,
+    pub spoiler: SpoilerMode,
+    pub html_parser: HtmlParserKind,
+    pub news_posts: NewsMode,
+    pub news_min_paragraphs: usize,
+    pub news_min_avg_len: usize,
+    pub news_max_cantonese_density: f64,
+    // count the posts of each nickname, see `nickname`
+    pub collect_nicknames: bool,
+    // the thread ids of responses without success, see `Batch::api_errors`
+    pub collect_api_errors: bool,
+    // per-thread totals, see `Extractor::thread_stats`
+    pub collect_thread_stats: bool,
+    // posts per number of valid sentences, see `Stats::sentences_per_post`
+    pub collect_post_sentences: bool,
+    // the posts of threads and their quotes, see `Batch::quotes`
+    pub collect_reply_graph: bool,
+    // the posts of threads with what they reply to, see `Batch::thread_posts`
+    pub collect_reply_trees: bool,
+    // what responses say of their threads, see `Batch::threads`
+    pub collect_thread_meta: bool,
+    // English paragraphs passing their own checks, see `Batch::english`
+    pub collect_english: bool,
+    pub english_min_words: usize,
+    pub english_max_words: usize,
+    // write rejected sentences too, each with its quality score and features
+    pub score_all: bool,
+    // skip posts whose raw msg html was already seen in this run
+    pub dedup_posts: bool,
+    // drop sentences already emitted in this run
+    pub deduplicate: bool,
+    // only count a sentence as a duplicate if emitted within this many days
+    pub dedup_window_days: Option<u32>,
+    // loaded before and saved after the run, see `dedup::DedupState`
+    pub dedup_state: Option<PathBuf>,
+    // how sentences are keyed for deduplication
+    pub dedup_hash: DedupHash,
+    // and on which field
+    pub dedup_key: DedupKey,
+    // keep the keys of emitted sentences in a Bloom filter of fixed size,
+    // which now and then drops a sentence never seen
+    pub dedup_bloom: bool,
+    // filter size, from bloom_items and bloom_fp_rate unless given
+    pub bloom_bits: Option<u64>,
+    pub bloom_hashes: Option<u32>,
+    pub bloom_items: u64,
+    pub bloom_fp_rate: f64,
+    // collect corpus-wide counts in a first pass and prune in a second one
+    pub two_pass: bool,
+    pub min_char_count: Option<u64>,
+    // private use chars count as rare whatever their count
+    pub rare_pua: bool,
+    pub rare_chars: RareCharsMode,
+    pub rare_char_token: String,
+    pub max_threads_per_sentence: Option<u32>,
+    // reused instead of rerunning pass one when it exists, written otherwise
+    pub pass1_state: Option<PathBuf>,
+    // hold all sentences until the end and drop those contained in a longer one
+    pub drop_substrings: bool,
+    // external command scoring each sentence, see `scorer::ExternalScorer`
+    pub score_cmd: Option<String>,
+    pub score_threshold: Option<f64>,
+    // masked language model scoring each sentence, see `bert::BertScorer`
+    pub bert_model: Option<PathBuf>,
+    pub bert_threshold: Option<f64>,
+    pub bert_batch_size: usize,
+    // external command rewriting each sentence, see
+    // `post_process::PostProcessCommand`
+    pub post_process: Option<String>,
+    // built-in rewrites of each sentence, see `PostProcessorChain::parse`
+    pub post_processors: Option<String>,
+    // keep sentences with a probability growing with the post score, see
+    // `sampling::WeightedSampler`
+    pub weight_by_score: bool,
+    pub weight_exponent: f64,
+    // write a uniform sample of this many sentences at the end of the run,
+    // see `sampling::Reservoir`
+    pub reservoir: Option<usize>,
+    pub seed: u64,
+    // only process this part of the input, passes over the whole corpus
+    // such as two-pass counting still see all of it
+    pub shard: Option<Shard>,
+    // lines of an entry processed per rayon job, between N and 4N
+    pub batch_size: Option<usize>,
+    // resident memory past which the results of an entry are written early,
+    // entries are read a chunk of lines at a time with it
+    pub max_memory_mb: Option<u64>,
+    // read the lines that are not UTF-8 in the encoding detected for them
+    // instead of skipping their entry as corrupt
+    pub detect_encoding: bool,
+    // MB per second read from the input files as stored, see
+    // `throttle::ReadLimit`
+    pub max_read_mbps: Option<f64>,
+}
+
+impl Default for ExtractorConfig {
+    fn default() -> Self {
+        ExtractorConfig {
+            para: ParaConfig::default(),
+            collapse_repeats: None,
+            normalize_elongation: false,
+            elongation_chars: DEFAULT_ELONGATION_CHARS.into(),
+            require_sentence_end: false,
+            sentence_end_particles: DEFAULT_SENTENCE_END_PARTICLES.into(),
+            urls_as_tokens: false,
+            keep_emoji: false,
+            keep_raw: false,
+            profanity: ProfanityMode::default(),
+            profanity_list: None,
+            deleted_patterns: None,
+            ban_users: None,
+            min_replies: None,
+            min_likes: None,
+            include_votes: false,
+            anonymize_users: false,
+            anonymize_key: None,
+            extract_polls: false,
+            extract_pairs: false,
+            quote_depth: DEFAULT_QUOTE_DEPTH,
+            strikethrough: StrikethroughMode::Keep,
+            spoiler: SpoilerMode::Keep,
+            html_parser: HtmlParserKind::Scraper,
+            news_posts: NewsMode::Keep,
+            news_min_paragraphs: DEFAULT_NEWS_MIN_PARAGRAPHS,
+            news_min_avg_len: DEFAULT_NEWS_MIN_AVG_LEN,
+            news_max_cantonese_density: DEFAULT_NEWS_MAX_CANTONESE_DENSITY,
+            collect_nicknames: false,
+            collect_api_errors: false,
+            collect_thread_stats: false,
+            collect_post_sentences: false,
+            collect_reply_graph: false,
+            collect_reply_trees: false,
+            collect_thread_meta: false,
+            collect_english: false,
+            english_min_words: DEFAULT_ENGLISH_MIN_WORDS,
+            english_max_words: DEFAULT_ENGLISH_MAX_WORDS,
+            score_all: false,
+            dedup_posts: false,
+            deduplicate: false,
+            dedup_window_days: None,
+            dedup_state: None,
+            dedup_hash: DedupHash::default(),
+            dedup_key: DedupKey::default(),
+            dedup_bloom: false,
+            bloom_bits: None,
+            bloom_hashes: None,
+            bloom_items: DEFAULT_BLOOM_ITEMS,
+            bloom_fp_rate: DEFAULT_BLOOM_FP_RATE,
+            two_pass: false,
+            min_char_count: None,
+            rare_pua: false,
+            rare_chars: RareCharsMode::default(),
+            rare_char_token: DEFAULT_RARE_CHAR_TOKEN.into(),
+            max_threads_per_sentence: None,
+            pass1_state: None,
+            drop_substrings: false,
+            score_cmd: None,
+            score_threshold: None,
+            bert_model: None,
+            bert_threshold: None,
+            bert_batch_size: DEFAULT_BERT_BATCH_SIZE,
+            post_process: None,
+            post_processors: None,
+            weight_by_score: false,
+            weight_exponent: DEFAULT_WEIGHT_EXPONENT,
+            reservoir: None,
+            seed: 0,
+            shard: None,
+            batch_size: None,
+            max_memory_mb: None,
+            detect_encoding: false,
+            max_read_mbps: None,
+        }
+    }
+}
+
+// Presets of the extractor settings for common uses, applied before the
+// --config file and the command line flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    Strict,
+    #[default]
+    Default,
+    Lenient,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 3] = [Profile::Strict, Profile::Default, Profile::Lenient];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Strict => "strict",
+            Profile::Default => "default",
+            Profile::Lenient => "lenient",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Profile::Strict => {
+                "5 to 30 char Cantonese sentences without Latin letters, deduplicated, \
+                 with repeated chars collapsed"
+            }
+            Profile::Default => "the settings without a profile",
+            Profile::Lenient => {
+                "up to 60 chars, keeping code-switched text, emoji and URLs as a token"
+            }
+        }
+    }
+
+    pub fn extractor(&self) -> ExtractorConfig {
+        let mut config = ExtractorConfig::default();
+        match self {
+            Profile::Strict => {
+                config.para.max_len = 30;
+                config.para.reject_latin = true;
+                config.para.require_cantonese = true;
+                config.para.max_bigram_fraction = Some(0.5);
+                config.collapse_repeats = Some(3);
+                config.deduplicate = true;
+            }
+            Profile::Default => {}
+            Profile::Lenient => {
+                config.para.max_len = 60;
+                config.para.min_cjk_ratio = 0.3;
+                config.urls_as_tokens = true;
+                config.keep_emoji = true;
+            }
+        }
+        config
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            profile: *self,
+            extractor: self.extractor(),
+            ..Settings::default()
+        }
+    }
+}
+
+// Everything an extraction run is configured with, as read from a --config
+// TOML file. Keys missing from the file take the values of the profile,
+// unknown keys are an error.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub profile: Profile,
+    pub input: PathBuf,
+    // read after `input`, or alongside it when interleaving
+    pub extra_inputs: Vec<PathBuf>,
+    // write the posts of the inputs in turn instead of one input after another
+    pub interleave: bool,
+    pub output: PathBuf,
+    // add to the output files instead of replacing them
+    pub append: bool,
+    // write no output file, as for the stats subcommand
+    pub discard_output: bool,
+    // run everything but write nothing, see `Settings::without_outputs`
+    pub dry_run: bool,
+    // capacity of the output file's write buffer
+    pub write_buffer_mb: usize,
+    pub format: OutputFormat,
+    pub output_mode: OutputMode,
+    // joins the sentences of a line in the paragraph and document modes
+    pub doc_separator: String,
+    // where each written sentence came from, line by line
+    pub index: Option<PathBuf>,
+    // write each thread's sentences to <output_dir>/<thread_id>.<format>
+    pub group_by_thread: bool,
+    pub output_dir: Option<PathBuf>,
+    pub max_open_files: usize,
+    // write each archive entry's sentences to a file of its own in this dir
+    pub per_entry_output: Option<PathBuf>,
+    // also write the sentences to a YYYY-MM file per post month in this dir
+    pub by_month: Option<PathBuf>,
+    // write train/validation/test jsonl files and their metadata to this dir
+    // instead of the output, see `hf::HfLayout`
+    pub hf_layout: Option<PathBuf>,
+    // the written sentences as a table of this SQLite database
+    pub output_sqlite: Option<PathBuf>,
+    // the written sentences as a Parquet file, in row groups of this size
+    pub output_parquet: Option<PathBuf>,
+    pub parquet_row_group_size: usize,
+    // the written sentences as an Arrow IPC stream
+    pub output_arrow: Option<PathBuf>,
+    pub hf_zstd: bool,
+    // shares of the threads going to the validation and test splits
+    pub validation_fraction: f64,
+    pub test_fraction: f64,
+    pub stats_file: Option<PathBuf>,
+    // the inputs, settings, outputs and output digest of the run as json,
+    // see `manifest::RunManifest`
+    pub run_manifest: Option<PathBuf>,
+    // write the output to this dir, named by hashes of the settings and
+    // inputs, with a .meta.json beside it; see `manifest::auto_output_name`
+    pub output_auto: Option<PathBuf>,
+    // with output_auto, build an output that was built already
+    pub rebuild: bool,
+    // the thread ids of responses without success per error code, as jsonl
+    pub errors_jsonl: Option<PathBuf>,
+    // serve Prometheus metrics of the run on this port
+    pub metrics_port: Option<u16>,
+    pub verbose: bool,
+    // rayon worker threads, all logical CPUs if unset
+    pub threads: Option<usize>,
+    // the I/O scheduling class of the process, Linux only
+    pub io_nice: Option<IoNice>,
+    pub watch: Option<PathBuf>,
+    pub manifest: PathBuf,
+    pub settle_secs: u64,
+    pub verify: bool,
+    pub per_entry_stats: Option<PathBuf>,
+    pub corpus_stats: Option<PathBuf>,
+    pub cjk_coverage: Option<PathBuf>,
+    // written chars per Unicode block as tsv
+    pub unicode_block_stats: Option<PathBuf>,
+    pub length_histogram: Option<PathBuf>,
+    pub hist_bin_width: usize,
+    // written chars seen fewer than `rare_threshold` times and private use
+    // chars with example sentences as tsv
+    pub rare_chars_report: Option<PathBuf>,
+    pub rare_threshold: u64,
+    pub ngrams: Option<PathBuf>,
+    pub ngram_n: usize,
+    // where poll texts go instead of the output
+    pub polls: Option<PathBuf>,
+    // (quoted, reply) sentence pairs, one tab separated pair per line
+    pub output_pairs: Option<PathBuf>,
+    // quoting posts and the posts they quote as json lines, matched within
+    // the last reply_graph_window posts of their thread
+    pub reply_graph: Option<PathBuf>,
+    pub reply_graph_window: usize,
+    // the posts of each thread nested by reply_id or parent_id, a json line
+    // per thread written after the run
+    pub output_tree: Option<PathBuf>,
+    // English paragraphs, one per line, beside the output
+    pub english_out: Option<PathBuf>,
+    // posts per nickname over the run as tsv
+    pub nicknames: Option<PathBuf>,
+    // per-thread totals over the run as tsv
+    pub thread_stats: Option<PathBuf>,
+    // the lines that were not UTF-8 as json lines, with --detect-encoding
+    pub error_log: Option<PathBuf>,
+    // metadata and written sentences per thread as csv, or as json lines
+    // for a .jsonl path, written after the run
+    pub threads_out: Option<PathBuf>,
+    // threads giving no written sentence are listed too
+    pub threads_out_all: bool,
+    // posts per number of valid sentences over the run as tsv
+    pub post_sentence_hist: Option<PathBuf>,
+    // SentencePiece model splitting the written sentences into pieces
+    pub tokenize_spm: Option<PathBuf>,
+    // the jyutping of each written sentence as a field of its line
+    pub jyutping: bool,
+    // rime dict files adding to the built-in readings
+    pub jyutping_dicts: Vec<PathBuf>,
+    // train a BPE model on the written sentences after the run
+    pub train_spm: bool,
+    pub spm_vocab_size: usize,
+    pub spm_output: PathBuf,
+    pub extractor: ExtractorConfig,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            profile: Profile::default(),
+            input: DEFAULT_INPUT.into(),
+            extra_inputs: Vec::new(),
+            interleave: false,
+            output: DEFAULT_OUTPUT.into(),
+            append: false,
+            discard_output: false,
+            dry_run: false,
+            write_buffer_mb: DEFAULT_WRITE_BUFFER_MB,
+            format: OutputFormat::default(),
+            output_mode: OutputMode::default(),
+            doc_separator: DEFAULT_DOC_SEPARATOR.into(),
+            index: None,
+            group_by_thread: false,
+            output_dir: None,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            per_entry_output: None,
+            by_month: None,
+            hf_layout: None,
+            output_sqlite: None,
+            output_parquet: None,
+            parquet_row_group_size: DEFAULT_PARQUET_ROW_GROUP_SIZE,
+            output_arrow: None,
+            hf_zstd: false,
+            validation_fraction: 0.0,
+            test_fraction: 0.0,
+            stats_file: None,
+            run_manifest: None,
+            output_auto: None,
+            rebuild: false,
+            errors_jsonl: None,
+            metrics_port: None,
+            verbose: false,
+            threads: None,
+            io_nice: None,
+            watch: None,
+            manifest: DEFAULT_WATCH_MANIFEST.into(),
+            settle_secs: DEFAULT_SETTLE_SECS,
+            verify: false,
+            per_entry_stats: None,
+            corpus_stats: None,
+            cjk_coverage: None,
+            unicode_block_stats: None,
+            length_histogram: None,
+            hist_bin_width: 1,
+            rare_chars_report: None,
+            rare_threshold: DEFAULT_RARE_THRESHOLD,
+            ngrams: None,
+            ngram_n: DEFAULT_NGRAM_N,
+            polls: None,
+            output_pairs: None,
+            reply_graph: None,
+            reply_graph_window: DEFAULT_REPLY_GRAPH_WINDOW,
+            output_tree: None,
+            english_out: None,
+            nicknames: None,
+            thread_stats: None,
+            error_log: None,
+            threads_out: None,
+            threads_out_all: false,
+            post_sentence_hist: None,
+            tokenize_spm: None,
+            jyutping: false,
+            jyutping_dicts: Vec::new(),
+            train_spm: false,
+            spm_vocab_size: DEFAULT_SPM_VOCAB_SIZE,
+            spm_output: DEFAULT_SPM_OUTPUT.into(),
+            extractor: ExtractorConfig::default(),
+        }
+    }
+}
+
+impl Settings {
+    // `profile` takes precedence over the one named in the file
+    pub fn load(path: &Path, profile: Option<Profile>) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::from_toml(&text, profile).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn from_toml(text: &str, profile: Option<Profile>) -> Result<Self, toml::de::Error> {
+        let table: toml::Table = text.parse()?;
+        let profile = match (profile, table.get("profile")) {
+            (Some(profile), _) => profile,
+            (None, Some(name)) => Profile::deserialize(name.clone())?,
+            (None, None) => Profile::default(),
+        };
+        let mut settings = toml::Table::try_from(profile.settings()).unwrap();
+        merge_tables(&mut settings, table);
+        let mut settings: Settings = toml::Value::Table(settings).try_into()?;
+        settings.profile = profile;
+        Ok(settings)
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.input.as_path()).chain(self.extra_inputs.iter().map(PathBuf::as_path))
+    }
+
+    // The settings of a dry run: the same extraction with every file it
+    // would write left out. State files are still read, a pass one state
+    // only if it exists since pass one writes a missing one.
+    pub fn without_outputs(&self) -> Settings {
+        let mut settings = Settings {
+            discard_output: true,
+            append: false,
+            index: None,
+            group_by_thread: false,
+            output_dir: None,
+            per_entry_output: None,
+            by_month: None,
+            hf_layout: None,
+            output_sqlite: None,
+            output_parquet: None,
+            output_arrow: None,
+            stats_file: None,
+            run_manifest: None,
+            output_auto: None,
+            errors_jsonl: None,
+            per_entry_stats: None,
+            corpus_stats: None,
+            cjk_coverage: None,
+            unicode_block_stats: None,
+            length_histogram: None,
+            rare_chars_report: None,
+            ngrams: None,
+            polls: None,
+            output_pairs: None,
+            reply_graph: None,
+            output_tree: None,
+            english_out: None,
+            nicknames: None,
+            thread_stats: None,
+            error_log: None,
+            threads_out: None,
+            post_sentence_hist: None,
+            train_spm: false,
+            ..self.clone()
+        };
+        let pass1_state = &mut settings.extractor.pass1_state;
+        if pass1_state.as_ref().is_some_and(|path| !path.exists()) {
+            *pass1_state = None;
+        }
+        settings
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+}
+
+// Sets every key of `over` in `base`, descending into tables present in both
+fn merge_tables(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge_tables(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_take_defaults() {
+        let settings = Settings::from_toml(
+            r#"
+            output = "out.jsonl"
+            format = "jsonl"
+
+            [extractor]
+            deduplicate = true
+
+            [extractor.para]
+            max_letter_run = 4
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(settings.output, PathBuf::from("out.jsonl"));
+        assert_eq!(settings.format, OutputFormat::Jsonl);
+        assert_eq!(settings.input, PathBuf::from(DEFAULT_INPUT));
+        assert!(settings.extractor.deduplicate);
+        assert_eq!(settings.extractor.para.max_letter_run, Some(4));
+        assert_eq!(settings.extractor.weight_exponent, DEFAULT_WEIGHT_EXPONENT);
+    }
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        let error = Settings::from_toml("[extractor]\ndeduplcate = true\n", None).unwrap_err();
+        assert!(error.to_string().contains("deduplcate"), "{}", error);
+        assert!(Settings::from_toml("ouptut = \"x\"\n", None).is_err());
+    }
+
+    #[test]
+    fn printed_settings_round_trip() {
+        let mut settings = Settings {
+            stats_file: Some("stats.json".into()),
+            ..Default::default()
+        };
+        settings.extractor.shard = Some(Shard { index: 1, count: 4 });
+        settings.extractor.para.max_bigram_fraction = Some(0.5);
+        assert_eq!(
+            Settings::from_toml(&settings.to_toml(), None).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn files_override_the_profile() {
+        let text = "profile = \"strict\"\n[extractor.para]\nmax_len = 25\n";
+        let settings = Settings::from_toml(text, None).unwrap();
+        assert_eq!(settings.profile, Profile::Strict);
+        assert_eq!(settings.extractor.para.max_len, 25);
+        assert!(settings.extractor.para.reject_latin);
+        assert!(settings.extractor.deduplicate);
+
+        let settings = Settings::from_toml(text, Some(Profile::Lenient)).unwrap();
+        assert_eq!(settings.profile, Profile::Lenient);
+        assert_eq!(settings.extractor.para.max_len, 25);
+        assert!(!settings.extractor.para.reject_latin);
+        assert!(settings.extractor.keep_emoji);
+    }
+
+    #[test]
+    fn dry_runs_write_nothing() {
+        let mut settings = Settings {
+            index: Some("index.tsv".into()),
+            hf_layout: Some("dataset".into()),
+            stats_file: Some("stats.json".into()),
+            dry_run: true,
+            ..Default::default()
+        };
+        settings.extractor.deduplicate = true;
+        settings.extractor.pass1_state = Some("missing-pass1-state".into());
+        let dry = settings.without_outputs();
+        assert!(dry.discard_output);
+        assert_eq!(
+            (dry.index, dry.hf_layout, dry.stats_file),
+            (None, None, None)
+        );
+        assert_eq!(dry.extractor.pass1_state, None);
+        assert!(dry.extractor.deduplicate);
+    }
+
+    #[test]
+    fn default_profile_is_the_default() {
+        assert_eq!(Profile::Default.settings(), Settings::default());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{CJK_REGEX, WORD_REGEX};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use unicode_blocks::find_unicode_block;
+
+pub const CJK_BLOCKS: &[(&str, u32, u32)] = &[
+    ("CJK Unified Ideographs", 0x4E00, 0x9FFF),
+    ("CJK Unified Ideographs Extension A", 0x3400, 0x4DBF),
+    ("CJK Unified Ideographs Extension B", 0x20000, 0x2A6DF),
+    ("CJK Unified Ideographs Extension C", 0x2A700, 0x2B73F),
+    ("CJK Unified Ideographs Extension D", 0x2B740, 0x2B81F),
+    ("CJK Unified Ideographs Extension E", 0x2B820, 0x2CEAF),
+    ("CJK Unified Ideographs Extension F", 0x2CEB0, 0x2EBEF),
+    ("CJK Unified Ideographs Extension I", 0x2EBF0, 0x2EE5F),
+    ("CJK Unified Ideographs Extension G", 0x30000, 0x3134F),
+    ("CJK Unified Ideographs Extension H", 0x31350, 0x323AF),
+    ("CJK Compatibility Ideographs", 0xF900, 0xFAFF),
+    ("CJK Compatibility Ideographs Supplement", 0x2F800, 0x2FA1F),
+];
+
+lazy_static! {
+    static ref UNIFIED_IDEOGRAPH_COUNT: usize = CJK_BLOCKS
+        .iter()
+        .flat_map(|(_, start, end)| *start..=*end)
+        .filter_map(char::from_u32)
+        .filter(|c| CJK_REGEX.is_match(c.encode_utf8(&mut [0; 4])))
+        .count();
+}
+
+pub fn cjk_block(c: char) -> Option<&'static str> {
+    let c = c as u32;
+    CJK_BLOCKS
+        .iter()
+        .find(|(_, start, end)| (*start..=*end).contains(&c))
+        .map(|(name, _, _)| *name)
+}
+
+// Character and length statistics over the written sentences
+#[derive(Debug, Default)]
+pub struct CorpusStats {
+    chars: HashMap<char, u64>,
+    lengths: BTreeMap<usize, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockCoverage {
+    pub unique_chars: usize,
+    pub total_chars: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorpusReport {
+    pub total_chars: u64,
+    pub unique_chars: usize,
+    pub total_cjk_chars: u64,
+    pub unique_cjk_chars: usize,
+    pub total_sentences: u64,
+    pub mean_length: f64,
+    pub std_dev_length: f64,
+    pub p10_length: usize,
+    pub p50_length: usize,
+    pub p90_length: usize,
+    pub top_chars: Vec<(char, u64)>,
+    pub cjk_blocks: BTreeMap<&'static str, BlockCoverage>,
+}
+
+impl CorpusStats {
+    pub fn observe(&mut self, sentence: &str) {
+        let mut len = 0;
+        for c in sentence.chars() {
+            *self.chars.entry(c).or_default() += 1;
+            len += 1;
+        }
+        *self.lengths.entry(len).or_default() += 1;
+    }
+
+    pub fn report(&self) -> CorpusReport {
+        let total_sentences: u64 = self.lengths.values().sum();
+        let total_chars: u64 = self.chars.values().sum();
+        let mean_length = if total_sentences == 0 {
+            0.0
+        } else {
+            total_chars as f64 / total_sentences as f64
+        };
+        let variance = if total_sentences == 0 {
+            0.0
+        } else {
+            self.lengths
+                .iter()
+                .map(|(&len, &count)| (len as f64 - mean_length).powi(2) * count as f64)
+                .sum::<f64>()
+                / total_sentences as f64
+        };
+
+        let mut top_chars: Vec<(char, u64)> = self.chars.iter().map(|(&c, &n)| (c, n)).collect();
+        top_chars.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top_chars.truncate(20);
+
+        let mut total_cjk_chars = 0;
+        let mut unique_cjk_chars = 0;
+        let mut cjk_blocks = BTreeMap::new();
+        for (&c, &count) in &self.chars {
+            if CJK_REGEX.is_match(c.encode_utf8(&mut [0; 4])) {
+                total_cjk_chars += count;
+                unique_cjk_chars += 1;
+            }
+            if let Some(block) = cjk_block(c) {
+                let coverage = cjk_blocks.entry(block).or_insert(BlockCoverage {
+                    unique_chars: 0,
+                    total_chars: 0,
+                });
+                coverage.unique_chars += 1;
+                coverage.total_chars += count;
+            }
+        }
+
+        CorpusReport {
+            total_chars,
+            unique_chars: self.chars.len(),
+            total_cjk_chars,
+            unique_cjk_chars,
+            total_sentences,
+            mean_length,
+            std_dev_length: variance.sqrt(),
+            p10_length: self.percentile(total_sentences, 0.1),
+            p50_length: self.percentile(total_sentences, 0.5),
+            p90_length: self.percentile(total_sentences, 0.9),
+            top_chars,
+            cjk_blocks,
+        }
+    }
+
+    // `codepoint\tchar\tcount\tunicode_block` rows for every CJK character,
+    // most frequent first
+    pub fn cjk_coverage_tsv(&self) -> String {
+        let mut chars: Vec<(char, u64)> = self
+            .chars
+            .iter()
+            .filter(|(c, _)| CJK_REGEX.is_match(c.encode_utf8(&mut [0; 4])))
+            .map(|(&c, &n)| (c, n))
+            .collect();
+        chars.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut tsv = String::from("codepoint\tchar\tcount\tunicode_block\n");
+        for (c, count) in chars {
+            tsv.push_str(&format!(
+                "U+{:04X}\t{}\t{}\t{}\n",
+                c as u32,
+                c,
+                count,
+                cjk_block(c).unwrap_or("Other")
+            ));
+        }
+        tsv
+    }
+
+    // `block_name\tchar_count\tpct_of_total` rows for every Unicode block of
+    // the written chars, the most common first. A large share of a rare
+    // block such as CJK Extension B may point to OCR errors or synthetic
+    // text. Unassigned code points count as No_Block.
+    pub fn unicode_blocks_tsv(&self) -> String {
+        let mut blocks: HashMap<&'static str, u64> = HashMap::new();
+        for (&c, &count) in &self.chars {
+            let block = find_unicode_block(c).map_or("No_Block", |block| block.name());
+            *blocks.entry(block).or_default() += count;
+        }
+        let total: u64 = blocks.values().sum();
+        let mut blocks: Vec<(&str, u64)> = blocks.into_iter().collect();
+        blocks.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let mut tsv = String::from("block_name\tchar_count\tpct_of_total\n");
+        for (block, count) in blocks {
+            let pct = count as f64 * 100.0 / total as f64;
+            tsv.push_str(&format!("{}\t{}\t{:.2}\n", block, count, pct));
+        }
+        tsv
+    }
+
+    // Fraction of all Unified_Ideograph code points seen at least once
+    pub fn cjk_coverage(&self) -> (usize, usize) {
+        let seen = self
+            .chars
+            .keys()
+            .filter(|c| CJK_REGEX.is_match(c.encode_utf8(&mut [0; 4])))
+            .count();
+        (seen, *UNIFIED_IDEOGRAPH_COUNT)
+    }
+
+    // Nearest-rank percentile of the sentence lengths
+    fn percentile(&self, total: u64, p: f64) -> usize {
+        let rank = ((p * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&len, &count) in &self.lengths {
+            seen += count;
+            if seen >= rank {
+                return len;
+            }
+        }
+        0
+    }
+}
+
+// `length\tcount` rows where each length is the start of a bin of `bin_width`
+pub fn length_histogram_tsv(lengths: &BTreeMap<usize, u64>, bin_width: usize) -> String {
+    let bin_width = bin_width.max(1);
+    let mut bins: BTreeMap<usize, u64> = BTreeMap::new();
+    for (&len, &count) in lengths {
+        *bins.entry(len / bin_width * bin_width).or_default() += count;
+    }
+    let mut tsv = String::from("length\tcount\n");
+    for (len, count) in bins {
+        tsv.push_str(&format!("{}\t{}\n", len, count));
+    }
+    tsv
+}
+
+// Occurrences of every run of `n` WORD_REGEX tokens within a written
+// sentence, never spanning two sentences
+#[derive(Debug)]
+pub struct NgramCounts {
+    n: usize,
+    counts: HashMap<String, u64>,
+}
+
+impl NgramCounts {
+    pub fn new(n: usize) -> Self {
+        NgramCounts {
+            n: n.max(1),
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, sentence: &str) {
+        let tokens: Vec<&str> = WORD_REGEX.find_iter(sentence).map(|m| m.as_str()).collect();
+        for ngram in tokens.windows(self.n) {
+            *self.counts.entry(ngram.join(" ")).or_default() += 1;
+        }
+    }
+
+    // Most frequent first, ties in n-gram order
+    pub fn tsv(&self) -> String {
+        counts_tsv("ngram", &self.counts)
+    }
+}
+
+// Counts as `<column>\tcount` rows, the most common first, ties in key order
+pub fn counts_tsv(column: &str, counts: &HashMap<String, u64>) -> String {
+    let mut counts: Vec<(&String, &u64)> = counts.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    let mut tsv = format!("{}\tcount\n", column);
+    for (key, count) in counts {
+        tsv.push_str(&format!("{}\t{}\n", key, count));
+    }
+    tsv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_histogram_bins() {
+        let lengths = BTreeMap::from([(5, 2), (6, 1), (9, 1), (10, 4), (17, 1)]);
+        assert_eq!(
+            length_histogram_tsv(&lengths, 1),
+            "length\tcount\n5\t2\n6\t1\n9\t1\n10\t4\n17\t1\n"
+        );
+        assert_eq!(
+            length_histogram_tsv(&lengths, 5),
+            "length\tcount\n5\t4\n10\t4\n15\t1\n"
+        );
+    }
+
+    #[test]
+    fn report_counts_and_lengths() {
+        let mut stats = CorpusStats::default();
+        for sentence in ["好好食呀", "今日天氣好好", "ok喎𠝹開佢", "我想去行山呀各位"]
+        {
+            stats.observe(sentence);
+        }
+        let report = stats.report();
+        assert_eq!(report.total_sentences, 4);
+        assert_eq!(report.total_chars, 4 + 6 + 6 + 8);
+        assert_eq!(report.total_cjk_chars, 22);
+        assert_eq!(report.top_chars[0], ('好', 4));
+        assert_eq!(report.mean_length, 6.0);
+        assert_eq!(report.std_dev_length, 2.0f64.sqrt());
+        assert_eq!(
+            (report.p10_length, report.p50_length, report.p90_length),
+            (4, 6, 8)
+        );
+        assert_eq!(
+            report.cjk_blocks["CJK Unified Ideographs Extension B"].unique_chars,
+            1
+        );
+    }
+
+    #[test]
+    fn cjk_coverage_rows() {
+        let mut stats = CorpusStats::default();
+        stats.observe("好好ok𠝹");
+        assert_eq!(
+            stats.cjk_coverage_tsv(),
+            "codepoint\tchar\tcount\tunicode_block\n\
+             U+597D\t好\t2\tCJK Unified Ideographs\n\
+             U+20779\t𠝹\t1\tCJK Unified Ideographs Extension B\n"
+        );
+        let (seen, total) = stats.cjk_coverage();
+        assert_eq!(seen, 2);
+        assert!(total > 90_000);
+    }
+
+    #[test]
+    fn unicode_block_rows() {
+        let mut stats = CorpusStats::default();
+        stats.observe("好好食ok𠝹");
+        stats.observe("\u{0378}");
+        assert_eq!(
+            stats.unicode_blocks_tsv(),
+            "block_name\tchar_count\tpct_of_total\n\
+             CJK Unified Ideographs\t3\t42.86\n\
+             Basic Latin\t2\t28.57\n\
+             CJK Unified Ideographs Extension B\t1\t14.29\n\
+             Greek and Coptic\t1\t14.29\n"
+        );
+        assert_eq!(
+            CorpusStats::default().unicode_blocks_tsv(),
+            "block_name\tchar_count\tpct_of_total\n"
+        );
+    }
+
+    #[test]
+    fn empty_corpus() {
+        let report = CorpusStats::default().report();
+        assert_eq!(report.total_sentences, 0);
+        assert_eq!(report.p50_length, 0);
+    }
+
+    #[test]
+    fn ngrams_stay_within_sentences() {
+        let mut ngrams = NgramCounts::new(2);
+        ngrams.observe("我哋OK啦");
+        ngrams.observe("啦我哋");
+        ngrams.observe("好");
+        assert_eq!(
+            ngrams.tsv(),
+            "ngram\tcount\n我 哋\t2\nOK 啦\t1\n哋 OK\t1\n啦 我\t1\n"
+        );
+    }
+}
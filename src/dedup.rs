@@ -0,0 +1,595 @@
+use crate::bloom::{self, BloomFilter};
+use crate::SentenceRecord;
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher13;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use xxhash_rust::xxh3::xxh3_64;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+const MAGIC: &[u8; 8] = b"LIHKGD2\n";
+// the state files of xxh64 hashes written before the key was selectable
+const MAGIC_V1: &[u8; 8] = b"LIHKGD1\n";
+
+// How sentences are keyed for deduplication
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupHash {
+    // xxh3, the fastest
+    #[default]
+    Xxhash,
+    // SipHash-1-3 with zero keys, slower and better mixed. Pinned rather
+    // than std's `DefaultHasher`, whose algorithm may change between
+    // releases, but giving the keys it gave so far.
+    Siphash,
+    // the sentence itself, no collisions but the text of every distinct
+    // sentence is held for the whole run and cannot be saved as state
+    Identity,
+}
+
+impl DedupHash {
+    fn tag(self) -> u8 {
+        match self {
+            DedupHash::Xxhash => 0,
+            DedupHash::Siphash => 1,
+            DedupHash::Identity => 2,
+        }
+    }
+
+    // The key of a sentence, None for identity keys, which are handed out
+    // in order by `Dedup`
+    pub fn hash(self, text: &str) -> Option<u64> {
+        match self {
+            DedupHash::Xxhash => Some(xxh3_64(text.as_bytes())),
+            DedupHash::Siphash => {
+                // as `Hash for str` feeds it
+                let mut hasher = SipHasher13::new();
+                hasher.write(text.as_bytes());
+                hasher.write_u8(0xff);
+                Some(hasher.finish())
+            }
+            DedupHash::Identity => None,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        [DedupHash::Xxhash, DedupHash::Siphash, DedupHash::Identity]
+            .into_iter()
+            .find(|hash| hash.tag() == tag)
+    }
+}
+
+// The field of a record deduplication keys on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupKey {
+    // the written sentence
+    #[default]
+    Text,
+    // the paragraph before character filtering, kept with --keep-raw; the
+    // text for records without one
+    Raw,
+}
+
+enum Seen {
+    // drop every repeat of a sentence within the run, keyed by text hash
+    Exact(HashSet<u64>),
+    // the same, with the reply_time each was last emitted at for the state
+    // saved by --dedup-state
+    ExactWithTimes(HashMap<u64, i64>),
+    // drop repeats only if emitted within the window before the post
+    Window(WindowDedup),
+    // drop every repeat in fixed memory, and now and then a sentence never
+    // seen, see `BloomFilter`
+    Bloom(BloomFilter),
+}
+
+pub struct Dedup {
+    hash: DedupHash,
+    field: DedupKey,
+    seen: Seen,
+    // the id of each distinct sentence with `DedupHash::Identity`
+    ids: HashMap<String, u64>,
+    // distinct keys taken, for the collision estimate
+    keys: u64,
+}
+
+impl Dedup {
+    pub fn exact(hash: DedupHash) -> Self {
+        Dedup::new(hash, Seen::Exact(HashSet::new()))
+    }
+
+    // Exact dedup whose `state` keeps the reply_times, for a run saving it
+    pub fn exact_with_times(hash: DedupHash) -> Self {
+        Dedup::new(hash, Seen::ExactWithTimes(HashMap::new()))
+    }
+
+    pub fn window_days(days: u32, hash: DedupHash) -> Self {
+        let window = WindowDedup::new(days as i64 * SECONDS_PER_DAY);
+        Dedup::new(hash, Seen::Window(window))
+    }
+
+    pub fn bloom(filter: BloomFilter, hash: DedupHash) -> io::Result<Self> {
+        if hash == DedupHash::Identity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bloom filter dedup needs hashed dedup keys",
+            ));
+        }
+        Ok(Dedup::new(hash, Seen::Bloom(filter)))
+    }
+
+    fn new(hash: DedupHash, seen: Seen) -> Self {
+        Dedup {
+            hash,
+            field: DedupKey::default(),
+            seen,
+            ids: HashMap::new(),
+            keys: 0,
+        }
+    }
+
+    pub fn keyed_on(mut self, field: DedupKey) -> Self {
+        self.field = field;
+        self
+    }
+
+    fn key(&mut self, text: &str) -> u64 {
+        self.hash.hash(text).unwrap_or_else(|| {
+            let next = self.ids.len() as u64;
+            *self.ids.entry(text.to_string()).or_insert(next)
+        })
+    }
+
+    pub fn is_duplicate(&mut self, record: &SentenceRecord) -> bool {
+        let key = match (self.field, &record.raw) {
+            (DedupKey::Raw, Some(raw)) => self.key(raw),
+            _ => self.key(&record.text),
+        };
+        let duplicate = match &mut self.seen {
+            Seen::Exact(seen) => !seen.insert(key),
+            Seen::ExactWithTimes(seen) => {
+                seen.insert(key, record.reply_time.unwrap_or(0)).is_some()
+            }
+            Seen::Window(window) => window.is_duplicate(key, record.reply_time),
+            Seen::Bloom(filter) => !filter.insert(key),
+        };
+        self.keys += !duplicate as u64;
+        duplicate
+    }
+
+    // The expected number of pairs of distinct sentences taken for each other
+    // so far, n^2 / 2^65 for n keys by the birthday bound
+    pub fn expected_collisions(&self) -> Option<f64> {
+        if self.hash == DedupHash::Identity {
+            return None;
+        }
+        let n = self.keys as f64;
+        Some(n * (n - 1.0) / 2f64.powi(65))
+    }
+
+    // The share of unseen sentences a Bloom filter takes for repeats by now
+    pub fn false_positive_rate(&self) -> Option<f64> {
+        match &self.seen {
+            Seen::Bloom(filter) => Some(filter.false_positive_rate()),
+            _ => None,
+        }
+    }
+
+    // The sentences emitted so far, at time 0 without times kept and none
+    // for a Bloom filter, which is saved as its bits by `save_state`
+    pub fn state(&self) -> DedupState {
+        let sentences = match &self.seen {
+            Seen::Exact(seen) => seen.iter().map(|&hash| (hash, 0)).collect(),
+            Seen::ExactWithTimes(seen) => seen.clone(),
+            Seen::Window(window) => window.last_emitted.clone(),
+            Seen::Bloom(_) => HashMap::new(),
+        };
+        DedupState {
+            hash: self.hash,
+            sentences,
+        }
+    }
+
+    pub fn save_state(&self, path: &Path) -> io::Result<()> {
+        match &self.seen {
+            Seen::Bloom(filter) => filter.save(path, self.hash.tag()),
+            _ => self.state().save(path),
+        }
+    }
+
+    // Continues from the state file of an earlier run, which must have
+    // keyed its sentences the same way and, for a Bloom filter, used one of
+    // the same size
+    pub fn load_state(&mut self, path: &Path) -> io::Result<()> {
+        match &mut self.seen {
+            Seen::Bloom(filter) => {
+                let (saved, tag) = BloomFilter::load(path)?;
+                let hash = DedupHash::from_tag(tag).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "unknown dedup hash")
+                })?;
+                check_hash(self.hash, hash)?;
+                filter.union(&saved)
+            }
+            _ => self.restore(DedupState::load(path)?),
+        }
+    }
+
+    // Continues from the sentences emitted by an earlier run, which must
+    // have keyed them the same way
+    pub fn restore(&mut self, state: DedupState) -> io::Result<()> {
+        check_hash(self.hash, state.hash)?;
+        match &mut self.seen {
+            Seen::Exact(seen) => seen.extend(state.sentences.into_keys()),
+            Seen::ExactWithTimes(seen) => {
+                for (hash, time) in state.sentences {
+                    let last = seen.entry(hash).or_insert(time);
+                    *last = (*last).max(time);
+                }
+            }
+            Seen::Window(window) => {
+                for (hash, time) in state.sentences {
+                    window.restore(hash, time);
+                }
+            }
+            Seen::Bloom(filter) => {
+                for hash in state.sentences.into_keys() {
+                    filter.insert(hash);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_hash(expected: DedupHash, found: DedupHash) -> io::Result<()> {
+    if expected == found {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "dedup state of {:?} hashes where {:?} ones are expected",
+            found, expected
+        ),
+    ))
+}
+
+// The emitted sentence hashes of a run with the reply_time each was last
+// emitted at, saved with --dedup-state so later runs or other shards skip
+// them. States of several runs merge by keeping the latest time.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DedupState {
+    pub hash: DedupHash,
+    pub sentences: HashMap<u64, i64>,
+}
+
+impl DedupState {
+    pub fn merge(&mut self, other: DedupState) -> io::Result<()> {
+        check_hash(self.hash, other.hash)?;
+        for (hash, time) in other.sentences {
+            let last = self.sentences.entry(hash).or_insert(time);
+            *last = (*last).max(time);
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = DedupStateWriter::create(path, self.hash)?;
+        for (&hash, &time) in &self.sentences {
+            writer.push(hash, time)?;
+        }
+        writer.finish()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        DedupState::load_where(path, |_| true)
+    }
+
+    // Only the sentences whose hash `keep` takes, to load a large state a
+    // part at a time
+    pub fn load_where(path: &Path, keep: impl Fn(u64) -> bool) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic == bloom::MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dedup state of a bloom filter, which only --dedup-bloom reads",
+            ));
+        }
+        if &magic == MAGIC_V1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "dedup state of an older version, hashed differently",
+            ));
+        }
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a dedup state file",
+            ));
+        }
+        let mut tag = [0; 1];
+        reader.read_exact(&mut tag)?;
+        let hash = DedupHash::from_tag(tag[0])
+            .filter(|&hash| hash != DedupHash::Identity)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown dedup hash"))?;
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        let len = u64::from_le_bytes(bytes);
+        let mut sentences = HashMap::new();
+        for _ in 0..len {
+            reader.read_exact(&mut bytes)?;
+            let hash = u64::from_le_bytes(bytes);
+            reader.read_exact(&mut bytes)?;
+            if keep(hash) {
+                sentences.insert(hash, i64::from_le_bytes(bytes));
+            }
+        }
+        Ok(DedupState { hash, sentences })
+    }
+}
+
+// Writes a state file a sentence at a time, for states too large to hold.
+// The count in the header is filled in by `finish`.
+pub struct DedupStateWriter {
+    writer: BufWriter<File>,
+    len: u64,
+}
+
+impl DedupStateWriter {
+    pub fn create(path: &Path, hash: DedupHash) -> io::Result<Self> {
+        if hash == DedupHash::Identity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "identity dedup keys cannot be saved",
+            ));
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[hash.tag()])?;
+        writer.write_all(&0u64.to_le_bytes())?;
+        Ok(DedupStateWriter { writer, len: 0 })
+    }
+
+    // Each hash must be pushed once
+    pub fn push(&mut self, hash: u64, time: i64) -> io::Result<()> {
+        self.writer.write_all(&hash.to_le_bytes())?;
+        self.writer.write_all(&time.to_le_bytes())?;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(MAGIC.len() as u64 + 1))?;
+        file.write_all(&self.len.to_le_bytes())
+    }
+}
+
+// Sentence hashes bucketed by the reply_time they were emitted at. Buckets
+// older than the window behind the latest timestamp seen are evicted, so
+// memory is bounded by the number of distinct sentences per window.
+pub struct WindowDedup {
+    window: i64,
+    by_time: BTreeMap<i64, HashSet<u64>>,
+    last_emitted: HashMap<u64, i64>,
+    watermark: i64,
+}
+
+impl WindowDedup {
+    pub fn new(window: i64) -> Self {
+        WindowDedup {
+            window,
+            by_time: BTreeMap::new(),
+            last_emitted: HashMap::new(),
+            watermark: i64::MIN,
+        }
+    }
+
+    // Posts without a timestamp are treated as posted at the latest time seen
+    pub fn is_duplicate(&mut self, hash: u64, time: Option<i64>) -> bool {
+        let time = time.unwrap_or(self.watermark);
+        self.watermark = self.watermark.max(time);
+        self.evict();
+
+        if let Some(&previous) = self.last_emitted.get(&hash) {
+            if (time - previous).abs() <= self.window {
+                return true;
+            }
+        }
+        self.last_emitted.insert(hash, time);
+        self.by_time.entry(time).or_default().insert(hash);
+        false
+    }
+
+    fn restore(&mut self, hash: u64, time: i64) {
+        if self
+            .last_emitted
+            .get(&hash)
+            .is_some_and(|&last| last >= time)
+        {
+            return;
+        }
+        if let Some(last) = self.last_emitted.insert(hash, time) {
+            if let Some(hashes) = self.by_time.get_mut(&last) {
+                hashes.remove(&hash);
+            }
+        }
+        self.by_time.entry(time).or_default().insert(hash);
+        self.watermark = self.watermark.max(time);
+    }
+
+    fn evict(&mut self) {
+        let cutoff = self.watermark.saturating_sub(self.window);
+        while let Some(entry) = self.by_time.first_entry() {
+            if *entry.key() >= cutoff {
+                break;
+            }
+            let (time, hashes) = entry.remove_entry();
+            for hash in hashes {
+                // the hash may have been re-emitted later, out of order
+                if self.last_emitted.get(&hash) == Some(&time) {
+                    self.last_emitted.remove(&hash);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_emitted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_emitted.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = SECONDS_PER_DAY;
+
+    #[test]
+    fn repeats_within_window_are_duplicates() {
+        let mut dedup = WindowDedup::new(7 * DAY);
+        assert!(!dedup.is_duplicate(1, Some(0)));
+        assert!(dedup.is_duplicate(1, Some(3 * DAY)));
+        assert!(dedup.is_duplicate(1, Some(7 * DAY)));
+        assert!(!dedup.is_duplicate(2, Some(7 * DAY)));
+    }
+
+    #[test]
+    fn repeats_after_window_are_emitted_again() {
+        let mut dedup = WindowDedup::new(7 * DAY);
+        assert!(!dedup.is_duplicate(1, Some(0)));
+        assert!(!dedup.is_duplicate(1, Some(365 * DAY)));
+        assert!(dedup.is_duplicate(1, Some(366 * DAY)));
+    }
+
+    #[test]
+    fn old_buckets_are_evicted() {
+        let mut dedup = WindowDedup::new(DAY);
+        for i in 0..100 {
+            dedup.is_duplicate(i, Some(i as i64 * DAY));
+        }
+        assert!(dedup.len() <= 2);
+        assert!(dedup.by_time.len() <= 2);
+    }
+
+    #[test]
+    fn restored_state_counts_as_emitted() {
+        let record = |text: &str, time| SentenceRecord {
+            text: text.to_string(),
+            reply_time: Some(time),
+            ..Default::default()
+        };
+        let mut shard1 = Dedup::window_days(7, DedupHash::Xxhash);
+        assert!(!shard1.is_duplicate(&record("今日天氣好好", 0)));
+        let mut shard2 = Dedup::exact_with_times(DedupHash::Xxhash);
+        assert!(!shard2.is_duplicate(&record("講多無謂食飯要緊", DAY)));
+
+        let path = std::env::temp_dir().join(format!("lihkg-dedup-{}", std::process::id()));
+        let mut state = shard1.state();
+        state.merge(shard2.state()).unwrap();
+        state.save(&path).unwrap();
+        let loaded = DedupState::load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded, state);
+
+        let mut window = Dedup::window_days(7, DedupHash::Xxhash);
+        window.restore(loaded.clone()).unwrap();
+        assert!(window.is_duplicate(&record("今日天氣好好", 2 * DAY)));
+        assert!(window.is_duplicate(&record("講多無謂食飯要緊", 2 * DAY)));
+        assert!(!window.is_duplicate(&record("今日天氣好好", 30 * DAY)));
+        let mut exact = Dedup::exact(DedupHash::Xxhash);
+        exact.restore(loaded.clone()).unwrap();
+        assert!(exact.is_duplicate(&record("今日天氣好好", 30 * DAY)));
+        // keys of another hash mean nothing
+        let mut siphash = Dedup::exact(DedupHash::Siphash);
+        assert!(siphash.restore(loaded).is_err());
+    }
+
+    #[test]
+    fn every_hash_finds_repeats() {
+        let record = |text: &str| SentenceRecord {
+            text: text.to_string(),
+            ..Default::default()
+        };
+        for hash in [DedupHash::Xxhash, DedupHash::Siphash, DedupHash::Identity] {
+            let mut dedup = Dedup::exact(hash);
+            let duplicates: Vec<bool> = ["今日天氣好好", "講多無謂", "今日天氣好好"]
+                .into_iter()
+                .map(|text| dedup.is_duplicate(&record(text)))
+                .collect();
+            assert_eq!(duplicates, [false, false, true], "{:?}", hash);
+            let expected = dedup.expected_collisions();
+            assert_eq!(expected.is_none(), hash == DedupHash::Identity);
+            assert!(expected.unwrap_or(0.0) < 1e-18);
+        }
+        // pinned so saved states keep matching
+        assert_eq!(
+            DedupHash::Siphash.hash("今日天氣好好"),
+            Some(13674835993592890682)
+        );
+        // a billion sentences make a collision likely
+        let mut dedup = Dedup::exact(DedupHash::Xxhash);
+        dedup.keys = 1 << 32;
+        assert!((dedup.expected_collisions().unwrap() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn raw_keys_tell_surface_forms_apart() {
+        let record = |raw: &str| SentenceRecord {
+            text: "笑死我".to_string(),
+            raw: Some(raw.to_string()),
+            ..Default::default()
+        };
+        let mut dedup = Dedup::exact(DedupHash::Xxhash).keyed_on(DedupKey::Raw);
+        assert!(!dedup.is_duplicate(&record("笑死我😂")));
+        assert!(!dedup.is_duplicate(&record("笑死我 😂😂")));
+        assert!(dedup.is_duplicate(&record("笑死我😂")));
+        let mut dedup = Dedup::exact(DedupHash::Xxhash);
+        assert!(!dedup.is_duplicate(&record("笑死我😂")));
+        assert!(dedup.is_duplicate(&record("笑死我 😂😂")));
+    }
+
+    #[test]
+    fn bloom_state_carries_over_runs() {
+        let record = |text: &str| SentenceRecord {
+            text: text.to_string(),
+            ..Default::default()
+        };
+        let mut first = Dedup::bloom(BloomFilter::new(4096, 4), DedupHash::Xxhash).unwrap();
+        assert!(!first.is_duplicate(&record("今日天氣好好")));
+        assert!(first.is_duplicate(&record("今日天氣好好")));
+        assert!(first.false_positive_rate().unwrap() < 1e-6);
+
+        let path = std::env::temp_dir().join(format!("lihkg-dedup-bloom-{}", std::process::id()));
+        first.save_state(&path).unwrap();
+        let mut second = Dedup::bloom(BloomFilter::new(4096, 4), DedupHash::Xxhash).unwrap();
+        second.load_state(&path).unwrap();
+        assert!(second.is_duplicate(&record("今日天氣好好")));
+        // only a filter of the same size and hash takes the bits
+        let mut smaller = Dedup::bloom(BloomFilter::new(2048, 4), DedupHash::Xxhash).unwrap();
+        assert!(smaller.load_state(&path).is_err());
+        let mut siphash = Dedup::bloom(BloomFilter::new(4096, 4), DedupHash::Siphash).unwrap();
+        assert!(siphash.load_state(&path).is_err());
+        assert!(DedupState::load(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+        assert!(Dedup::bloom(BloomFilter::new(64, 1), DedupHash::Identity).is_err());
+    }
+
+    #[test]
+    fn missing_timestamp_uses_latest_time() {
+        let mut dedup = WindowDedup::new(DAY);
+        assert!(!dedup.is_duplicate(1, Some(10 * DAY)));
+        assert!(dedup.is_duplicate(1, None));
+    }
+}
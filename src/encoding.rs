@@ -0,0 +1,46 @@
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::Encoding;
+use serde::Serialize;
+
+// A line of an archive entry that is not UTF-8, kept for --error-log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NonUtf8Line {
+    // 1-based, among the lines of the entry
+    pub line: usize,
+    // the encoding the line was read in, None when it did not decode
+    pub encoding: Option<&'static str>,
+    // the line read as UTF-8 with replacement chars
+    pub text: String,
+}
+
+// The line in the encoding chardetng takes it for, such as Big5 or GB18030
+// for older dumps, None when the bytes are not valid in that encoding
+pub fn transcode(bytes: &[u8]) -> Option<(String, &'static Encoding)> {
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(bytes, true);
+    // the line already failed as UTF-8
+    let encoding = detector.guess(None, Utf8Detection::Deny);
+    let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+    Some((text.into_owned(), encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big5_and_gb18030_lines_become_utf8() {
+        for (encoding, msg) in [
+            (encoding_rs::BIG5, "今日去飲茶，個點心真係好好食"),
+            (encoding_rs::GB18030, "今天去喝茶，那里的点心真的很好吃"),
+        ] {
+            let line = format!("1\t1\t{{\"msg\":\"{}\"}}", msg);
+            let (bytes, _, unmappable) = encoding.encode(&line);
+            assert!(!unmappable);
+            assert!(std::str::from_utf8(&bytes).is_err());
+            // GB18030 text is taken for GBK, which it decodes the same as
+            let (text, _) = transcode(&bytes).unwrap();
+            assert_eq!(text, line);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use crate::WORD_REGEX;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // paragraphs of Latin letters and spaces only, rejected as english_only
+    pub static ref ENGLISH_ONLY_REGEX: Regex = Regex::new(r"^[A-Za-z ]+$").unwrap();
+}
+
+// Share of uppercase letters past which a paragraph is shouting
+const MAX_UPPERCASE_FRACTION: f64 = 0.5;
+
+pub fn is_english_only(para: &str) -> bool {
+    ENGLISH_ONLY_REGEX.is_match(para)
+}
+
+// Why an English paragraph is left out of --english-out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnglishReject {
+    WordCount,
+    Url,
+    Shouting,
+}
+
+// The checks English paragraphs go through instead of the ones for
+// Cantonese, with words counted as the alphanumeric `WORD_REGEX` tokens
+pub struct EnglishFilter {
+    pub min_words: usize,
+    pub max_words: usize,
+}
+
+impl EnglishFilter {
+    pub fn check(&self, para: &str) -> Result<(), EnglishReject> {
+        let words = WORD_REGEX
+            .find_iter(para)
+            .filter(|word| word.as_str().chars().all(char::is_alphanumeric))
+            .count();
+        if !(self.min_words..=self.max_words).contains(&words) {
+            return Err(EnglishReject::WordCount);
+        }
+        if para.contains("http://") || para.contains("https://") || para.contains("www.") {
+            return Err(EnglishReject::Url);
+        }
+        let letters = para.chars().filter(char::is_ascii_alphabetic).count();
+        let upper = para.chars().filter(char::is_ascii_uppercase).count();
+        if letters > 1 && upper as f64 > letters as f64 * MAX_UPPERCASE_FRACTION {
+            return Err(EnglishReject::Shouting);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_english_paragraphs() {
+        let filter = EnglishFilter {
+            min_words: 3,
+            max_words: 8,
+        };
+        assert_eq!(filter.check("I love Hong Kong milk tea"), Ok(()));
+        assert_eq!(filter.check("thank you"), Err(EnglishReject::WordCount));
+        assert_eq!(
+            filter.check("this is a very long sentence that goes on and on"),
+            Err(EnglishReject::WordCount)
+        );
+        assert_eq!(
+            filter.check("go to www.lihkg.com now"),
+            Err(EnglishReject::Url)
+        );
+        assert_eq!(
+            filter.check("WHY IS IT SO HOT"),
+            Err(EnglishReject::Shouting)
+        );
+        assert!(is_english_only("I love Hong Kong") && !is_english_only("I love 香港"));
+    }
+}
@@ -0,0 +1,305 @@
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BASE_URL: &str = "https://lihkg.com/api_v2";
+
+// Requests per second across all workers, higher rates are refused
+pub const MAX_RATE: f64 = 5.0;
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    // thread ids in from..to
+    pub from: u64,
+    pub to: u64,
+    pub out: PathBuf,
+    // ids of completed threads, one per line, skipped when resuming
+    pub progress: PathBuf,
+    pub concurrency: usize,
+    pub rate: f64,
+    pub max_retries: u32,
+    pub base_url: String,
+}
+
+#[derive(Debug, Default)]
+pub struct FetchStats {
+    pub threads: AtomicU64,
+    pub pages: AtomicU64,
+    pub missing: AtomicU64,
+    pub retries: AtomicU64,
+}
+
+impl FetchStats {
+    pub fn summary(&self) -> String {
+        format!(
+            "threads={} pages={} missing={} retries={}",
+            self.threads.load(Ordering::Relaxed),
+            self.pages.load(Ordering::Relaxed),
+            self.missing.load(Ordering::Relaxed),
+            self.retries.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// Hands out request slots at most `rate` times per second, shared by all workers
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Reserves the next free slot at or after `now`
+    pub fn reserve(&self, now: Instant) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let slot = (*next).max(now);
+        *next = slot + self.interval;
+        slot
+    }
+
+    pub fn wait(&self) {
+        let now = Instant::now();
+        thread::sleep(self.reserve(now) - now);
+    }
+}
+
+// Delay before retry number `attempt`, starting at zero
+pub fn backoff(attempt: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF)
+}
+
+// Rate limiting and server errors are retried, other client errors are final
+pub fn is_retryable(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+// A page in the `thread\tpage\tjson` layout read by `Extractor::process_line`
+pub fn format_line(thread_id: u64, page: u64, body: &str) -> String {
+    format!("{}\t{}\t{}\n", thread_id, page, body.trim())
+}
+
+pub fn load_progress(path: &Path) -> io::Result<HashSet<u64>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+    // a trailing partial line from an interrupted run is ignored
+    Ok(content
+        .split_inclusive('\n')
+        .filter(|line| line.ends_with('\n'))
+        .filter_map(|line| line.trim().parse().ok())
+        .collect())
+}
+
+struct Fetcher<'a> {
+    config: &'a FetchConfig,
+    agent: ureq::Agent,
+    limiter: RateLimiter,
+    stats: &'a FetchStats,
+}
+
+impl Fetcher<'_> {
+    fn get(&self, url: &str) -> io::Result<Option<String>> {
+        let mut attempt = 0;
+        loop {
+            self.limiter.wait();
+            let delay = match self.agent.get(url).call() {
+                Ok(response) => return response.into_string().map(Some),
+                Err(ureq::Error::Status(status, response)) if is_retryable(status) => {
+                    let retry_after = response
+                        .header("Retry-After")
+                        .and_then(|s| s.trim().parse().ok())
+                        .map(Duration::from_secs)
+                        .unwrap_or_default();
+                    if attempt >= self.config.max_retries {
+                        return Err(io::Error::other(format!(
+                            "{} still answered {} after {} retries",
+                            url, status, attempt
+                        )));
+                    }
+                    backoff(attempt).max(retry_after)
+                }
+                Err(ureq::Error::Status(_, _)) => return Ok(None),
+                Err(ureq::Error::Transport(e)) => {
+                    if attempt >= self.config.max_retries {
+                        return Err(io::Error::other(format!("{}: {}", url, e)));
+                    }
+                    backoff(attempt)
+                }
+            };
+            attempt += 1;
+            self.stats.retries.fetch_add(1, Ordering::Relaxed);
+            thread::sleep(delay);
+        }
+    }
+
+    // All pages of a thread as output lines, empty if the thread does not exist
+    fn fetch_thread(&self, thread_id: u64) -> io::Result<String> {
+        let mut lines = String::new();
+        let mut page = 1;
+        loop {
+            let url = format!(
+                "{}/thread/{}/page/{}?order=reply_time",
+                self.config.base_url, thread_id, page
+            );
+            let Some(body) = self.get(&url)? else {
+                break;
+            };
+            let obj: Value = serde_json::from_str(&body).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", url, e))
+            })?;
+            if obj["success"].as_i64() != Some(1) {
+                break;
+            }
+            lines.push_str(&format_line(thread_id, page, &body));
+            self.stats.pages.fetch_add(1, Ordering::Relaxed);
+            let total_pages = crate::value_as_i64(&obj["response"]["total_page"]).unwrap_or(1);
+            if page as i64 >= total_pages {
+                break;
+            }
+            page += 1;
+        }
+        Ok(lines)
+    }
+}
+
+// Downloads every thread in the range not listed in the progress file. Each
+// thread is appended to the output whole, then recorded as done, so an
+// interrupted run resumes at thread granularity; a thread written but not
+// yet recorded is fetched again.
+pub fn run(config: &FetchConfig) -> io::Result<FetchStats> {
+    if !(config.rate > 0.0 && config.rate <= MAX_RATE) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("rate must be above 0 and at most {} per second", MAX_RATE),
+        ));
+    }
+    let done = load_progress(&config.progress)?;
+    let pending = Mutex::new((config.from..config.to).filter(|id| !done.contains(id)));
+    let output = Mutex::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.out)?,
+    );
+    let progress = Mutex::new(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.progress)?,
+    );
+    let stats = FetchStats::default();
+    // set by the first failing worker so the others stop taking threads
+    let failed = AtomicBool::new(false);
+    let fetcher = Fetcher {
+        config,
+        agent: ureq::AgentBuilder::new()
+            .user_agent(concat!("lihkg-parser/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(30))
+            .build(),
+        limiter: RateLimiter::new(config.rate),
+        stats: &stats,
+    };
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.concurrency.max(1))
+            .map(|_| {
+                scope.spawn(|| -> io::Result<()> {
+                    let result = (|| loop {
+                        if failed.load(Ordering::Relaxed) {
+                            return Ok(());
+                        }
+                        let Some(thread_id) = pending.lock().unwrap().next() else {
+                            return Ok(());
+                        };
+                        let lines = fetcher.fetch_thread(thread_id)?;
+                        if lines.is_empty() {
+                            stats.missing.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            let mut output = output.lock().unwrap();
+                            output.write_all(lines.as_bytes())?;
+                            output.flush()?;
+                            stats.threads.fetch_add(1, Ordering::Relaxed);
+                        }
+                        writeln!(progress.lock().unwrap(), "{}", thread_id)?;
+                    })();
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().unwrap())
+    })?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiter_spaces_slots() {
+        let limiter = RateLimiter::new(2.0);
+        let now = Instant::now();
+        let first = limiter.reserve(now);
+        let second = limiter.reserve(now);
+        let third = limiter.reserve(now);
+        assert_eq!(second - first, Duration::from_millis(500));
+        assert_eq!(third - second, Duration::from_millis(500));
+        // an idle limiter does not bank slots
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(later), later);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(40), MAX_BACKOFF);
+        assert!(is_retryable(429) && is_retryable(503));
+        assert!(!is_retryable(404));
+    }
+
+    #[test]
+    fn progress_skips_partial_lines() {
+        let path = std::env::temp_dir().join(format!("lihkg-progress-{}", std::process::id()));
+        std::fs::write(&path, "3300000\n3300002\n33000").unwrap();
+        let done = load_progress(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(done, HashSet::from([3300000, 3300002]));
+        assert!(load_progress(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lines_feed_the_extractor() {
+        let body = r#"{"success":1,"response":{"item_data":[{"msg":"我哋今日去咗飲茶"}]}}"#;
+        let line = format_line(3300000, 1, &format!("{}\n", body));
+        let extractor = crate::Extractor::default();
+        let mut batch = crate::Batch::default();
+        extractor.process_line(line.trim_end(), &mut batch).unwrap();
+        assert_eq!(batch.records[0].text, "我哋今日去咗飲茶");
+    }
+}
@@ -0,0 +1,892 @@
+use crate::config::{LengthUnit, ParaConfig};
+use crate::{count_matching_chars, english, is_all_cjk, CJK_REGEX, WORD_REGEX};
+use aho_corasick::AhoCorasick;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+// Read back by the names of `as_str`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    Empty,
+    Deleted,
+    Shared,
+    Length,
+    Url,
+    EnglishOnly,
+    Date,
+    Time,
+    RepeatedChars,
+    RepeatedBigram,
+    LetterRun,
+    LatinRun,
+    FewTokens,
+    CjkRatio,
+    Latin,
+    NotCantonese,
+    Profanity,
+    RareChar,
+    Copypasta,
+    Symbols,
+    NewsLike,
+    SentenceEnd,
+    Score,
+    Bert,
+    PostProcess,
+    Sampled,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::Empty => "empty",
+            RejectReason::Deleted => "deleted",
+            RejectReason::Shared => "shared",
+            RejectReason::Length => "length",
+            RejectReason::Url => "url",
+            RejectReason::EnglishOnly => "english_only",
+            RejectReason::Date => "date",
+            RejectReason::Time => "time",
+            RejectReason::RepeatedChars => "repeated_chars",
+            RejectReason::RepeatedBigram => "repeated_bigram",
+            RejectReason::LetterRun => "letter_run",
+            RejectReason::LatinRun => "latin_run",
+            RejectReason::FewTokens => "few_tokens",
+            RejectReason::CjkRatio => "cjk_ratio",
+            RejectReason::Latin => "latin",
+            RejectReason::NotCantonese => "not_cantonese",
+            RejectReason::Profanity => "profanity",
+            RejectReason::RareChar => "rare_char",
+            RejectReason::Copypasta => "copypasta",
+            RejectReason::Symbols => "symbols",
+            RejectReason::NewsLike => "news_like",
+            RejectReason::SentenceEnd => "sentence_end",
+            RejectReason::Score => "score",
+            RejectReason::Bert => "bert",
+            RejectReason::PostProcess => "post_process",
+            RejectReason::Sampled => "sampled",
+        }
+    }
+}
+
+impl Serialize for RejectReason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+pub trait ParaFilter: Send + Sync {
+    fn check(&self, para: &str) -> Result<(), RejectReason>;
+
+    // The type name without its path, for traces
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
+lazy_static! {
+    // math, modifier and other symbols, which covers emoji and kaomoji parts
+    // like °□╯, plus the box drawing and block element ranges of ASCII art
+    static ref SYMBOL_REGEX: Regex =
+        Regex::new(r"[\p{So}\p{Sk}\p{Sm}\u{2500}-\u{259F}]").unwrap();
+    // uninterrupted runs of ASCII letters and digits, a word, an id or a hash
+    pub static ref LATIN_RUN_REGEX: Regex = Regex::new(r"[A-Za-z0-9]+").unwrap();
+    static ref DATE_REGEX: Regex = Regex::new(r"^\d{4}.\d{2}.\d{2}$").unwrap();
+    static ref TIME_REGEX: Regex = Regex::new(r"^\d{2}:\d{2}:\d{2}$").unwrap();
+}
+
+// Rejects paragraphs where symbols make up more than `max_fraction` of the
+// non-whitespace chars, e.g. "(╯°□°）╯︵ ┻━┻" or box drawing art. Runs on
+// the paragraph before `filter_irrelevant_chars` strips the symbols.
+pub struct SymbolSpam {
+    pub max_fraction: f64,
+}
+
+impl ParaFilter for SymbolSpam {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if symbol_fraction(para) > self.max_fraction {
+            Err(RejectReason::Symbols)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub fn symbol_fraction(para: &str) -> f64 {
+    let total = para.chars().filter(|c| !c.is_whitespace()).count();
+    if total == 0 {
+        return 0.0;
+    }
+    SYMBOL_REGEX.find_iter(para).count() as f64 / total as f64
+}
+
+// Rejects paragraphs containing what is shown in place of a deleted post,
+// e.g. "此回覆已被刪除。", so moderation is told apart from the other
+// rejections. All markers are searched for in one pass.
+pub struct DeletionMarkers {
+    matcher: AhoCorasick,
+}
+
+impl DeletionMarkers {
+    pub fn new<S: AsRef<str>>(markers: &[S]) -> Self {
+        // an empty marker would be found in every paragraph
+        let markers = markers
+            .iter()
+            .map(|marker| marker.as_ref().trim())
+            .filter(|marker| !marker.is_empty());
+        DeletionMarkers {
+            matcher: AhoCorasick::new(markers).unwrap(),
+        }
+    }
+}
+
+impl ParaFilter for DeletionMarkers {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if self.matcher.is_match(para) {
+            Err(RejectReason::Deleted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Distinct chars a paragraph needs per this many bytes, see `RepeatedChars`
+pub(crate) const BYTES_PER_DISTINCT: usize = 5;
+
+// Rejects the footer the app adds to posts shared from it
+pub struct SharedFooter;
+
+impl ParaFilter for SharedFooter {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if para.contains("分享自 LIHKG 討論區") {
+            Err(RejectReason::Shared)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Rejects empty paragraphs and ones shorter than `min` or longer than `max`
+// `unit`s, 5 and 20 chars by default
+pub struct Length {
+    pub min: usize,
+    pub max: usize,
+    pub unit: LengthUnit,
+    // see `ParaConfig::allow_short_cjk`
+    pub allow_short_cjk: bool,
+}
+
+impl ParaFilter for Length {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if para.is_empty() {
+            return Err(RejectReason::Empty);
+        }
+        let min = if self.allow_short_cjk && is_all_cjk(para) {
+            1
+        } else {
+            self.min
+        };
+        if (min..=self.max).contains(&self.unit.count(para)) {
+            Ok(())
+        } else {
+            Err(RejectReason::Length)
+        }
+    }
+}
+
+pub struct Url;
+
+impl ParaFilter for Url {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if para.contains("http://") || para.contains("https://") {
+            Err(RejectReason::Url)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Rejects paragraphs of English words only, see `english::is_english_only`
+pub struct EnglishOnly;
+
+impl ParaFilter for EnglishOnly {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if english::is_english_only(para) {
+            Err(RejectReason::EnglishOnly)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Rejects paragraphs that are only a date or a time, as left by quoting
+pub struct DateTime;
+
+impl ParaFilter for DateTime {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if DATE_REGEX.is_match(para) {
+            Err(RejectReason::Date)
+        } else if TIME_REGEX.is_match(para) {
+            Err(RejectReason::Time)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Rejects paragraphs with fewer distinct `unit`s than one per
+// `bytes_per_distinct` bytes of text, e.g. "哈哈哈哈哈哈"
+pub struct RepeatedChars {
+    pub bytes_per_distinct: usize,
+    pub unit: LengthUnit,
+}
+
+impl ParaFilter for RepeatedChars {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if self.unit.distinct(para) * self.bytes_per_distinct < self.unit.bytes(para) {
+            Err(RejectReason::RepeatedChars)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Rejects paragraphs where CJK chars are not more than `min_ratio` of the
+// length, or fewer than 5
+pub struct CjkRatio {
+    pub min_ratio: f64,
+    pub unit: LengthUnit,
+    // see `ParaConfig::ratio_latin_as_word`
+    pub latin_as_word: bool,
+    pub allow_short_cjk: bool,
+}
+
+impl CjkRatio {
+    pub fn from_config(config: &ParaConfig) -> Self {
+        CjkRatio {
+            min_ratio: config.min_cjk_ratio,
+            unit: config.length_unit,
+            latin_as_word: config.ratio_latin_as_word,
+            allow_short_cjk: config.allow_short_cjk,
+        }
+    }
+}
+
+impl ParaFilter for CjkRatio {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        let num_cjk = count_matching_chars(para, &CJK_REGEX);
+        let num_total = match self.latin_as_word {
+            true => len_latin_as_word(para, self.unit),
+            false => self.unit.count(para),
+        };
+        let short_cjk = self.allow_short_cjk && num_cjk < 5 && num_cjk == num_total;
+        if short_cjk
+            || num_cjk >= 5 && num_cjk > ((num_total as f64 * self.min_ratio).round() as usize)
+        {
+            Ok(())
+        } else {
+            Err(RejectReason::CjkRatio)
+        }
+    }
+}
+
+// Rejects paragraphs where the occurrences of a single bigram cover
+// more than `max_fraction` of the characters, e.g. "恭喜恭喜恭喜大家發財"
+pub struct RepeatedBigram {
+    pub max_fraction: f32,
+}
+
+impl ParaFilter for RepeatedBigram {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if bigram_coverage(para) > self.max_fraction {
+            Err(RejectReason::RepeatedBigram)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Fraction of characters covered by the most frequent bigram
+pub fn bigram_coverage(para: &str) -> f32 {
+    let chars: Vec<char> = para.chars().collect();
+    if chars.len() < 4 {
+        return 0.0;
+    }
+    let mut positions: HashMap<(char, char), Vec<usize>> = HashMap::new();
+    for (i, pair) in chars.windows(2).enumerate() {
+        positions.entry((pair[0], pair[1])).or_default().push(i);
+    }
+    let covered = positions
+        .values()
+        .filter(|starts| starts.len() > 1)
+        .map(|starts| {
+            // overlapping occurrences ("哈哈哈") must not be counted twice
+            let mut covered = 0;
+            let mut end = 0;
+            for &start in starts {
+                covered += start + 2 - start.max(end);
+                end = start + 2;
+            }
+            covered
+        })
+        .max()
+        .unwrap_or(0);
+    covered as f32 / chars.len() as f32
+}
+
+// Rejects runs of the same Latin letter longer than `max_run`, e.g. "LOLLLLLLLL"
+pub struct LetterRun {
+    pub max_run: usize,
+}
+
+impl ParaFilter for LetterRun {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if longest_letter_run(para) > self.max_run {
+            Err(RejectReason::LetterRun)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub fn longest_letter_run(para: &str) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut prev = None;
+    for c in para.chars() {
+        if c.is_ascii_alphabetic() {
+            let c = c.to_ascii_lowercase();
+            run = if prev == Some(c) { run + 1 } else { 1 };
+            prev = Some(c);
+            longest = longest.max(run);
+        } else {
+            run = 0;
+            prev = None;
+        }
+    }
+    longest
+}
+
+// Rejects paragraphs with a run of Latin letters and digits longer than
+// `max_run`, e.g. a pasted id or hash, while short English borrowings like
+// "check吓" pass
+pub struct LatinRun {
+    pub max_run: usize,
+}
+
+impl ParaFilter for LatinRun {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if longest_latin_run(para) > self.max_run {
+            Err(RejectReason::LatinRun)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub fn longest_latin_run(para: &str) -> usize {
+    LATIN_RUN_REGEX
+        .find_iter(para)
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0)
+}
+
+// Cuts each run of Latin letters and digits to its first `max_run` chars
+pub fn truncate_latin_runs(para: &str, max_run: usize) -> Cow<'_, str> {
+    LATIN_RUN_REGEX.replace_all(para, |caps: &regex::Captures| {
+        caps[0][..max_run.min(caps[0].len())].to_string()
+    })
+}
+
+// Length of a paragraph with each run of Latin letters and digits counted as
+// one
+pub fn len_latin_as_word(para: &str, unit: LengthUnit) -> usize {
+    let latin: usize = LATIN_RUN_REGEX
+        .find_iter(para)
+        .map(|run| run.len() - 1)
+        .sum();
+    unit.count(para) - latin
+}
+
+// Rejects paragraphs with fewer than `min_tokens` distinct WORD_REGEX tokens
+pub struct DistinctTokens {
+    pub min_tokens: usize,
+}
+
+impl ParaFilter for DistinctTokens {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        let tokens: HashSet<&str> = WORD_REGEX.find_iter(para).map(|m| m.as_str()).collect();
+        if tokens.len() < self.min_tokens {
+            Err(RejectReason::FewTokens)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Rejects paragraphs with any Latin letter, e.g. code-switched "OK啦"
+pub struct NoLatin;
+
+impl ParaFilter for NoLatin {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if para.chars().any(|c| c.is_ascii_alphabetic()) {
+            Err(RejectReason::Latin)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Chars written in Cantonese but rarely in standard written Chinese
+pub const CANTONESE_MARKERS: &str =
+    "嘅咗哋唔喺嘢啲乜冇嚟佢諗咩喎囉噉畀俾嗰搵揾攞嘥嗌啱嘞吖啫咋咁睇";
+
+// Rejects paragraphs without any of the `CANTONESE_MARKERS`, which are
+// likely standard written Chinese
+pub struct CantoneseMarkers;
+
+impl ParaFilter for CantoneseMarkers {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        if para.chars().any(|c| CANTONESE_MARKERS.contains(c)) {
+            Ok(())
+        } else {
+            Err(RejectReason::NotCantonese)
+        }
+    }
+}
+
+// Filters run in order and the first rejection wins
+pub struct FilterChain {
+    filters: Vec<Box<dyn ParaFilter>>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        FilterChain {
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &ParaConfig) -> Self {
+        let mut chain = FilterChain::new().with(DeletionMarkers::new(&config.deletion_markers));
+        // ahead of the length rules, so symbol spam is reported as such
+        if let Some(max_fraction) = config.max_symbol_fraction {
+            chain = chain.with(SymbolSpam { max_fraction });
+        }
+        chain = chain.with_base_rules(Length {
+            min: config.min_len,
+            max: config.max_len,
+            unit: config.length_unit,
+            allow_short_cjk: config.allow_short_cjk,
+        });
+        if let Some(max_run) = config.max_letter_run {
+            chain = chain.with(LetterRun { max_run });
+        }
+        // truncated runs are shortened by `Extractor::normalize_para` instead
+        if let (Some(max_run), false) = (config.max_latin_run, config.truncate_latin_run) {
+            chain = chain.with(LatinRun { max_run });
+        }
+        if let Some(max_fraction) = config.max_bigram_fraction {
+            chain = chain.with(RepeatedBigram { max_fraction });
+        }
+        if let Some(min_tokens) = config.min_distinct_tokens {
+            chain = chain.with(DistinctTokens { min_tokens });
+        }
+        if config.reject_latin {
+            chain = chain.with(NoLatin);
+        }
+        if config.require_cantonese {
+            chain = chain.with(CantoneseMarkers);
+        }
+        chain
+    }
+
+    // The rules every paragraph goes through, in order: shared footers,
+    // length, URLs, English only, dates and times, then repeated chars
+    pub fn with_base_rules(self, length: Length) -> Self {
+        let unit = length.unit;
+        self.with(SharedFooter)
+            .with(length)
+            .with(Url)
+            .with(EnglishOnly)
+            .with(DateTime)
+            .with(RepeatedChars {
+                bytes_per_distinct: BYTES_PER_DISTINCT,
+                unit,
+            })
+    }
+
+    pub fn with(mut self, filter: impl ParaFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn check(&self, para: &str) -> Result<(), RejectReason> {
+        self.filters
+            .iter()
+            .try_for_each(|filter| filter.check(para))
+    }
+
+    // The verdict of every filter, past the first rejection too
+    pub fn trace(&self, para: &str) -> Vec<(&'static str, Result<(), RejectReason>)> {
+        self.filters
+            .iter()
+            .map(|filter| (filter.name(), filter.check(para)))
+            .collect()
+    }
+}
+
+// A chain is a filter too, rejecting as its first rejecting filter
+impl ParaFilter for FilterChain {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        FilterChain::check(self, para)
+    }
+}
+
+impl Default for FilterChain {
+    fn default() -> Self {
+        FilterChain::from_config(&ParaConfig::default())
+    }
+}
+
+// What a `ParaValidator` gives for a paragraph
+pub type ValidationResult = Result<(), RejectReason>;
+
+/// Paragraph rules for library users to compose. Every `ParaFilter` is a
+/// validator, and the validators below are the base rules with plain
+/// parameters.
+///
+/// ```
+/// use lihkg::filters::{AndValidator, LengthValidator, ParaValidator, RejectReason, UrlValidator};
+///
+/// let validator = AndValidator(vec![
+///     Box::new(LengthValidator { min: 2, max: 20 }),
+///     Box::new(UrlValidator),
+/// ]);
+/// assert_eq!(validator.is_valid("今日好熱"), Ok(()));
+/// assert_eq!(validator.is_valid("睇 https://lihkg.com/thread/1"), Err(RejectReason::Length));
+/// assert_eq!(validator.is_valid("睇 http://a.hk"), Err(RejectReason::Url));
+/// ```
+pub trait ParaValidator: Send + Sync {
+    fn is_valid(&self, para: &str) -> ValidationResult;
+}
+
+impl<F: ParaFilter> ParaValidator for F {
+    fn is_valid(&self, para: &str) -> ValidationResult {
+        self.check(para)
+    }
+}
+
+// The rules of `is_valid_para`: deletion markers and the base rules of the
+// default config
+#[derive(Default)]
+pub struct DefaultValidator(FilterChain);
+
+impl ParaFilter for DefaultValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        self.0.check(para)
+    }
+}
+
+// `Length` in chars
+pub struct LengthValidator {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl ParaFilter for LengthValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        Length {
+            min: self.min,
+            max: self.max,
+            unit: LengthUnit::Chars,
+            allow_short_cjk: false,
+        }
+        .check(para)
+    }
+}
+
+// `CjkRatio` in chars
+pub struct CjkRatioValidator {
+    pub min_ratio: f32,
+}
+
+impl ParaFilter for CjkRatioValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        CjkRatio {
+            min_ratio: self.min_ratio as f64,
+            unit: LengthUnit::Chars,
+            latin_as_word: false,
+            allow_short_cjk: false,
+        }
+        .check(para)
+    }
+}
+
+pub struct UrlValidator;
+
+impl ParaFilter for UrlValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        Url.check(para)
+    }
+}
+
+pub struct DateTimeValidator;
+
+impl ParaFilter for DateTimeValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        DateTime.check(para)
+    }
+}
+
+// Rejects paragraphs with fewer distinct chars per byte than `threshold`,
+// 0.2 in the base rules
+pub struct RepeatedCharValidator {
+    pub threshold: f32,
+}
+
+impl ParaFilter for RepeatedCharValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        let unit = LengthUnit::Chars;
+        if (unit.distinct(para) as f32) < self.threshold * unit.bytes(para) as f32 {
+            Err(RejectReason::RepeatedChars)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Validators run in order and the first rejection wins, as in `FilterChain`
+pub struct AndValidator(pub Vec<Box<dyn ParaValidator>>);
+
+impl ParaFilter for AndValidator {
+    fn check(&self, para: &str) -> Result<(), RejectReason> {
+        self.0
+            .iter()
+            .try_for_each(|validator| validator.is_valid(para))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spam_chain() -> FilterChain {
+        FilterChain::from_config(&ParaConfig {
+            max_bigram_fraction: Some(0.5),
+            max_letter_run: Some(4),
+            min_distinct_tokens: Some(4),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn base_rules_compose() {
+        let length = Length {
+            min: 5,
+            max: 20,
+            unit: LengthUnit::Chars,
+            allow_short_cjk: true,
+        };
+        assert_eq!(length.check(""), Err(RejectReason::Empty));
+        assert_eq!(length.check("好"), Ok(()));
+        assert_eq!(length.check("OK"), Err(RejectReason::Length));
+        assert_eq!(DateTime.check("2024/01/31"), Err(RejectReason::Date));
+        assert_eq!(DateTime.check("12:30:45"), Err(RejectReason::Time));
+        // chains nest, the outer one stopping at the inner one's rejection
+        let chain = FilterChain::new()
+            .with(FilterChain::new().with(Url).with(EnglishOnly))
+            .with(DateTime);
+        assert_eq!(chain.check("hello world"), Err(RejectReason::EnglishOnly));
+        assert_eq!(chain.check("2024-01-31"), Err(RejectReason::Date));
+        assert_eq!(chain.check("我哋今日去咗飲茶"), Ok(()));
+        let names: Vec<&str> = FilterChain::default()
+            .trace("哈哈哈哈哈哈")
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "DeletionMarkers",
+                "SharedFooter",
+                "Length",
+                "Url",
+                "EnglishOnly",
+                "DateTime",
+                "RepeatedChars"
+            ]
+        );
+        let cjk = CjkRatio::from_config(&ParaConfig::default());
+        assert_eq!(cjk.check("OK啦我聽日再嚟過"), Err(RejectReason::CjkRatio));
+    }
+
+    #[test]
+    fn repeated_bigram() {
+        assert_eq!(
+            spam_chain().check("恭喜恭喜恭喜大家發財"),
+            Err(RejectReason::RepeatedBigram)
+        );
+        assert_eq!(
+            spam_chain().check("多謝多謝多謝你呀大佬"),
+            Err(RejectReason::RepeatedBigram)
+        );
+        assert_eq!(spam_chain().check("今日天氣好好呀各位"), Ok(()));
+    }
+
+    #[test]
+    fn overlapping_bigrams_are_counted_once() {
+        assert_eq!(bigram_coverage("哈哈哈好"), 0.75);
+        assert_eq!(bigram_coverage("恭喜恭喜"), 1.0);
+        assert_eq!(bigram_coverage("今日天氣好"), 0.0);
+    }
+
+    #[test]
+    fn letter_run() {
+        assert_eq!(
+            spam_chain().check("LOLLLLLLLL好正"),
+            Err(RejectReason::LetterRun)
+        );
+        assert_eq!(
+            spam_chain().check("好正呀Ahhhhhh真係"),
+            Err(RejectReason::LetterRun)
+        );
+        assert_eq!(spam_chain().check("check吓先ok喎"), Ok(()));
+        assert_eq!(longest_letter_run("aAaA好"), 4);
+    }
+
+    #[test]
+    fn latin_runs() {
+        let chain = FilterChain::from_config(&ParaConfig {
+            max_latin_run: Some(8),
+            ..Default::default()
+        });
+        // borrowed English words
+        assert_eq!(chain.check("check吓先ok喎"), Ok(()));
+        assert_eq!(chain.check("今晚去party好唔好"), Ok(()));
+        // pasted identifiers and hashes
+        assert_eq!(
+            chain.check("個commit係3f9a2c7b1e嗰個"),
+            Err(RejectReason::LatinRun)
+        );
+        assert_eq!(
+            chain.check("帳號係user20231031呀"),
+            Err(RejectReason::LatinRun)
+        );
+        assert_eq!(longest_latin_run("呢個site係www後面"), 4);
+        assert_eq!(
+            truncate_latin_runs("個commit係3f9a2c7b1e嗰個", 8),
+            "個commit係3f9a2c7b嗰個"
+        );
+        assert_eq!(truncate_latin_runs("check吓", 8), "check吓");
+        // a run counts once in the CJK ratio
+        assert_eq!(
+            len_latin_as_word("呢個site係www後面嗰part壞咗", LengthUnit::Chars),
+            11
+        );
+    }
+
+    #[test]
+    fn distinct_tokens() {
+        assert_eq!(
+            spam_chain().check("推推推推推"),
+            Err(RejectReason::RepeatedChars)
+        );
+        assert_eq!(
+            FilterChain::new()
+                .with(DistinctTokens { min_tokens: 4 })
+                .check("好嘢好嘢好"),
+            Err(RejectReason::FewTokens)
+        );
+        assert_eq!(spam_chain().check("我今日好攰"), Ok(()));
+    }
+
+    #[test]
+    fn pure_cantonese() {
+        let chain = FilterChain::from_config(&ParaConfig {
+            reject_latin: true,
+            require_cantonese: true,
+            ..Default::default()
+        });
+        assert_eq!(chain.check("OK啦我聽日再嚟過"), Err(RejectReason::Latin));
+        assert_eq!(chain.check("點心好好食"), Err(RejectReason::NotCantonese));
+        assert_eq!(chain.check("我哋今日去咗飲茶"), Ok(()));
+    }
+
+    #[test]
+    fn length_bounds() {
+        let chain = FilterChain::from_config(&ParaConfig {
+            max_len: 30,
+            ..Default::default()
+        });
+        let para = "我哋今日去咗飲茶然後再去行街睇戲食飯返屋企";
+        assert_eq!(
+            FilterChain::default().check(para),
+            Err(RejectReason::Length)
+        );
+        assert_eq!(chain.check(para), Ok(()));
+    }
+
+    #[test]
+    fn validators_match_the_base_rules() {
+        let default = DefaultValidator::default();
+        let repeated = RepeatedCharValidator { threshold: 0.2 };
+        for para in [
+            "我哋今日去咗飲茶",
+            "哈哈哈哈哈哈",
+            "2024/01/31",
+            "此回覆已被刪除",
+            "",
+        ] {
+            assert_eq!(
+                default.is_valid(para),
+                crate::validate_para(para),
+                "{}",
+                para
+            );
+            let base = RepeatedChars {
+                bytes_per_distinct: BYTES_PER_DISTINCT,
+                unit: LengthUnit::Chars,
+            };
+            assert_eq!(repeated.is_valid(para), base.check(para), "{}", para);
+        }
+        let validator = AndValidator(vec![
+            Box::new(DateTimeValidator),
+            Box::new(CjkRatioValidator { min_ratio: 0.8 }),
+        ]);
+        assert_eq!(validator.is_valid("12:30:45"), Err(RejectReason::Time));
+        assert_eq!(validator.is_valid("OK OK 好"), Err(RejectReason::CjkRatio));
+        assert_eq!(validator.is_valid("我哋今日去咗飲茶"), Ok(()));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        assert_eq!(FilterChain::default().check("LOLLLLLLLL好正"), Ok(()));
+    }
+
+    #[test]
+    fn symbol_spam() {
+        let chain = FilterChain::from_config(&ParaConfig {
+            max_symbol_fraction: Some(0.3),
+            ..Default::default()
+        });
+        for para in [
+            "(╯°□°）╯︵ ┻━┻",
+            "┏━━━━━━━━┓",
+            "┃ 頂 ┃ 頂 ┃",
+            "▇▇▇▇▆▅▃▂",
+            "ヽ(✿ﾟ▽ﾟ)ノ♪♪♪",
+            "😂😂😂😂😂😂",
+        ] {
+            assert_eq!(chain.check(para), Err(RejectReason::Symbols), "{}", para);
+        }
+        assert_eq!(chain.check("我哋今日去咗飲茶😂"), Ok(()));
+        assert_eq!(FilterChain::default().check("我哋今日去咗飲茶😂"), Ok(()));
+        assert_eq!(symbol_fraction("   "), 0.0);
+    }
+}
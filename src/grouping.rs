@@ -0,0 +1,204 @@
+use dashmap::DashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// One output file per key in `dir`, with at most `max_open` of them open at
+// once. Making room flushes and closes the least recently written file; a
+// key written again after that is appended to. Lines are buffered by `push`
+// and written by `flush`.
+pub struct BucketFiles<K> {
+    dir: PathBuf,
+    extension: &'static str,
+    // append to existing files, as watch mode does, instead of replacing them
+    append: bool,
+    max_open: usize,
+    // open files with the tick they were last written at
+    open: DashMap<K, (BufWriter<File>, u64)>,
+    // keys whose file was created by this run
+    created: HashSet<K>,
+    pending: Vec<(K, String)>,
+    tick: u64,
+}
+
+// A file per thread id, for --group-by-thread
+pub type ThreadFiles = BucketFiles<u64>;
+
+impl<K: Clone + Eq + Hash + Display> BucketFiles<K> {
+    pub fn new(
+        dir: &Path,
+        extension: &'static str,
+        append: bool,
+        max_open: usize,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(BucketFiles {
+            dir: dir.to_path_buf(),
+            extension,
+            append,
+            max_open: max_open.max(1),
+            open: DashMap::new(),
+            created: HashSet::new(),
+            pending: Vec::new(),
+            tick: 0,
+        })
+    }
+
+    pub fn path(&self, key: &K) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, self.extension))
+    }
+
+    pub fn push(&mut self, key: K, line: String) {
+        self.pending.push((key, line));
+    }
+
+    pub fn open_files(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (key, line) in std::mem::take(&mut self.pending) {
+            self.tick += 1;
+            if !self.open.contains_key(&key) {
+                if self.open.len() >= self.max_open {
+                    self.close_oldest()?;
+                }
+                let file = self.open_file(&key)?;
+                self.open.insert(key.clone(), (BufWriter::new(file), 0));
+            }
+            let mut entry = self.open.get_mut(&key).unwrap();
+            let (file, last_written) = entry.value_mut();
+            *last_written = self.tick;
+            file.write_all(line.as_bytes())?;
+        }
+        for mut entry in self.open.iter_mut() {
+            entry.value_mut().0.flush()?;
+        }
+        Ok(())
+    }
+
+    fn open_file(&mut self, key: &K) -> io::Result<File> {
+        let path = self.path(key);
+        if self.append || !self.created.insert(key.clone()) {
+            OpenOptions::new().create(true).append(true).open(path)
+        } else {
+            File::create(path)
+        }
+    }
+
+    fn close_oldest(&mut self) -> io::Result<()> {
+        let oldest = self
+            .open
+            .iter()
+            .min_by_key(|entry| entry.value().1)
+            .map(|entry| entry.key().clone());
+        if let Some((_, (mut file, _))) = oldest.and_then(|key| self.open.remove(&key)) {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Output files named after archive entries, one per entry. Path separators
+// in entry names are replaced so every file lands directly in `dir`, and a
+// name already taken by another entry gets a numbered suffix, so no two
+// entries share a file.
+pub struct EntryFiles {
+    dir: PathBuf,
+    extension: &'static str,
+    used: HashSet<String>,
+    // the file of the last entry, taking its later parts
+    last: Option<File>,
+}
+
+impl EntryFiles {
+    pub fn new(dir: &Path, extension: &'static str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(EntryFiles {
+            dir: dir.to_path_buf(),
+            extension,
+            used: HashSet::new(),
+            last: None,
+        })
+    }
+
+    // Creates the file of an entry, empty until written to
+    pub fn create(&mut self, entry: &str) -> io::Result<File> {
+        let base = entry_file_stem(entry);
+        let mut stem = base.clone();
+        let mut n = 1;
+        while !self.used.insert(stem.clone()) {
+            n += 1;
+            stem = format!("{}-{}", base, n);
+        }
+        File::create(self.dir.join(format!("{}.{}", stem, self.extension)))
+    }
+
+    // The file of an entry, a new one unless this continues the last entry
+    pub fn file(&mut self, entry: &str, continued: bool) -> io::Result<&mut File> {
+        if !continued || self.last.is_none() {
+            self.last = Some(self.create(entry)?);
+        }
+        Ok(self.last.as_mut().unwrap())
+    }
+}
+
+pub fn entry_file_stem(entry: &str) -> String {
+    let stem: String = entry
+        .trim_start_matches(['/', '\\'])
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\') { '_' } else { c })
+        .collect();
+    // never a hidden file or one of the . and .. entries
+    if stem.is_empty() || stem.starts_with('.') {
+        format!("_{}", stem)
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_written_files_are_closed() {
+        let dir = std::env::temp_dir().join(format!("lihkg-threads-{}", std::process::id()));
+        let mut files = ThreadFiles::new(&dir, "txt", false, 2).unwrap();
+        for (thread_id, line) in [(1, "一\n"), (2, "二\n"), (1, "三\n"), (3, "四\n")] {
+            files.push(thread_id, line.to_string());
+        }
+        files.flush().unwrap();
+        // 2 was written before the last write to 1
+        assert_eq!(files.open_files(), 2);
+        assert!(!files.open.contains_key(&2));
+        files.push(2, "五\n".to_string());
+        files.flush().unwrap();
+        let read = |thread_id| fs::read_to_string(files.path(&thread_id)).unwrap();
+        assert_eq!(read(1), "一\n三\n");
+        // reopened files are appended to, not replaced
+        assert_eq!(read(2), "二\n五\n");
+        assert_eq!(read(3), "四\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_get_their_own_files() {
+        assert_eq!(entry_file_stem("dump/3300000.csv"), "dump_3300000.csv");
+        assert_eq!(
+            entry_file_stem("/data/3300000.csv.xz"),
+            "data_3300000.csv.xz"
+        );
+        assert_eq!(entry_file_stem("../x"), "_.._x");
+        let dir = std::env::temp_dir().join(format!("lihkg-entries-{}", std::process::id()));
+        let mut files = EntryFiles::new(&dir, "txt").unwrap();
+        files.create("a/b.csv").unwrap();
+        files.create("a_b.csv").unwrap();
+        assert!(dir.join("a_b.csv.txt").exists());
+        assert!(dir.join("a_b.csv-2.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
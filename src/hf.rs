@@ -0,0 +1,308 @@
+use crate::sink::SinkFile;
+use crate::{RecordKind, SentenceRecord};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh64::xxh64;
+
+// Name of the metadata file, as the datasets library reads it
+pub const DATASET_INFOS: &str = "dataset_infos.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    Train,
+    Validation,
+    Test,
+}
+
+impl Split {
+    pub const ALL: [Split; 3] = [Split::Train, Split::Validation, Split::Test];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Split::Train => "train",
+            Split::Validation => "validation",
+            Split::Test => "test",
+        }
+    }
+}
+
+// Assigns whole threads to splits by a seeded hash of the thread id, so no
+// thread is spread over train and test and the same seed gives the same
+// split. Sentences without a thread are assigned by their text.
+#[derive(Debug, Clone, Copy)]
+pub struct SplitFractions {
+    pub validation: f64,
+    pub test: f64,
+    pub seed: u64,
+}
+
+impl SplitFractions {
+    pub fn check(&self) -> Result<(), String> {
+        let valid = |f: f64| (0.0..=1.0).contains(&f);
+        if !valid(self.validation) || !valid(self.test) || self.validation + self.test > 1.0 {
+            return Err(format!(
+                "validation and test fractions must be in [0, 1] and sum to at most 1, got {} and {}",
+                self.validation, self.test
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn split(&self, record: &SentenceRecord) -> Split {
+        let hash = match record.thread_id {
+            Some(thread_id) => xxh64(&thread_id.to_le_bytes(), self.seed),
+            None => xxh64(record.text.as_bytes(), self.seed),
+        };
+        // the top 53 bits as a uniform draw in [0, 1)
+        let draw = (hash >> 11) as f64 / (1u64 << 53) as f64;
+        if draw < self.test {
+            Split::Test
+        } else if draw < self.test + self.validation {
+            Split::Validation
+        } else {
+            Split::Train
+        }
+    }
+
+    // Train always, the others only when given a share
+    fn splits(&self) -> Vec<Split> {
+        Split::ALL
+            .into_iter()
+            .filter(|split| match split {
+                Split::Train => true,
+                Split::Validation => self.validation > 0.0,
+                Split::Test => self.test > 0.0,
+            })
+            .collect()
+    }
+}
+
+// A row of the jsonl files. Every column is written on every row, missing
+// values as null, so all rows share one schema.
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    text: &'a str,
+    thread_id: Option<u64>,
+    post_id: Option<&'a str>,
+    msg_num: Option<u64>,
+    reply_time: Option<i64>,
+    post_score: Option<i64>,
+    like_count: Option<i64>,
+    dislike_count: Option<i64>,
+    user_id: Option<&'a str>,
+    score: Option<f64>,
+    kind: Option<RecordKind>,
+}
+
+// The columns of `Row` with their datasets feature types
+const FEATURES: &[(&str, &str)] = &[
+    ("text", "string"),
+    ("thread_id", "int64"),
+    ("post_id", "string"),
+    ("msg_num", "int64"),
+    ("reply_time", "int64"),
+    ("post_score", "int64"),
+    ("like_count", "int64"),
+    ("dislike_count", "int64"),
+    ("user_id", "string"),
+    ("score", "float64"),
+    ("kind", "string"),
+];
+
+struct SplitOutput {
+    split: Split,
+    file: SinkFile,
+    // rows not yet written
+    buffer: String,
+    examples: u64,
+    // uncompressed bytes of the jsonl
+    bytes: u64,
+}
+
+// A directory `load_dataset("json", data_dir=...)` reads as is: one
+// <split>.jsonl file per split, zstd compressed if asked, and the feature
+// names and split sizes in dataset_infos.json. Rows are buffered by `push`
+// and written by `flush`.
+pub struct HfLayout {
+    dir: PathBuf,
+    fractions: SplitFractions,
+    outputs: Vec<SplitOutput>,
+}
+
+impl HfLayout {
+    pub fn create(dir: &Path, fractions: SplitFractions, zstd: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut outputs = Vec::new();
+        for split in fractions.splits() {
+            let extension = if zstd { "jsonl.zst" } else { "jsonl" };
+            let path = dir.join(format!("{}.{}", split.name(), extension));
+            let file = SinkFile::create(&path, zstd)?;
+            outputs.push(SplitOutput {
+                split,
+                file,
+                buffer: String::new(),
+                examples: 0,
+                bytes: 0,
+            });
+        }
+        Ok(HfLayout {
+            dir: dir.to_path_buf(),
+            fractions,
+            outputs,
+        })
+    }
+
+    pub fn push(&mut self, record: &SentenceRecord) {
+        let row = Row {
+            text: &record.text,
+            thread_id: record.thread_id,
+            post_id: record.post_id.as_deref(),
+            msg_num: record.msg_num,
+            reply_time: record.reply_time,
+            post_score: record.post_score,
+            like_count: record.like_count,
+            dislike_count: record.dislike_count,
+            user_id: record.user_id.as_deref(),
+            score: record.score,
+            kind: record.kind,
+        };
+        let mut line = serde_json::to_string(&row).unwrap();
+        line.push('\n');
+        let split = self.fractions.split(record);
+        // splits without a file have a zero fraction and get no rows
+        let output = self
+            .outputs
+            .iter_mut()
+            .find(|output| output.split == split)
+            .unwrap();
+        output.examples += 1;
+        output.bytes += line.len() as u64;
+        output.buffer.push_str(&line);
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for output in &mut self.outputs {
+            output.file.writer().write_all(output.buffer.as_bytes())?;
+            output.buffer.clear();
+        }
+        Ok(())
+    }
+
+    // Closes the split files and writes the metadata
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        let mut splits = Vec::new();
+        for output in self.outputs {
+            output.file.finish()?;
+            splits.push((
+                output.split.name(),
+                SplitInfo {
+                    name: output.split.name(),
+                    num_bytes: output.bytes,
+                    num_examples: output.examples,
+                    dataset_name: "lihkg",
+                },
+            ));
+        }
+        let features: Vec<(&str, Feature)> = FEATURES
+            .iter()
+            .map(|&(name, dtype)| {
+                (
+                    name,
+                    Feature {
+                        dtype,
+                        kind: "Value",
+                    },
+                )
+            })
+            .collect();
+        let info = DatasetInfo {
+            description: "Cantonese sentences extracted from LIHKG",
+            features: Ordered(&features),
+            builder_name: "json",
+            config_name: "default",
+            dataset_size: splits.iter().map(|(_, split)| split.num_bytes).sum(),
+            splits: Ordered(&splits),
+        };
+        let file = File::create(self.dir.join(DATASET_INFOS))?;
+        serde_json::to_writer_pretty(file, &Ordered(&[("default", info)]))?;
+        Ok(())
+    }
+}
+
+// A map serialized in the order of its entries, keeping the columns in
+// the order of the rows
+struct Ordered<'a, V>(&'a [(&'a str, V)]);
+
+impl<V: Serialize> Serialize for Ordered<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Serialize)]
+struct DatasetInfo<'a> {
+    description: &'static str,
+    features: Ordered<'a, Feature>,
+    builder_name: &'static str,
+    config_name: &'static str,
+    splits: Ordered<'a, SplitInfo>,
+    dataset_size: u64,
+}
+
+#[derive(Serialize)]
+struct Feature {
+    dtype: &'static str,
+    #[serde(rename = "_type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct SplitInfo {
+    name: &'static str,
+    num_bytes: u64,
+    num_examples: u64,
+    dataset_name: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_stay_in_one_split() {
+        let fractions = SplitFractions {
+            validation: 0.1,
+            test: 0.2,
+            seed: 42,
+        };
+        let mut counts = [0; 3];
+        for thread_id in 0..10_000 {
+            let record = |text: &str| SentenceRecord {
+                text: text.to_string(),
+                thread_id: Some(thread_id),
+                ..Default::default()
+            };
+            let split = fractions.split(&record("一"));
+            assert_eq!(fractions.split(&record("二")), split);
+            counts[split as usize] += 1;
+        }
+        assert!((6700..7300).contains(&counts[0]), "{:?}", counts);
+        assert!((800..1200).contains(&counts[1]), "{:?}", counts);
+        assert!((1800..2200).contains(&counts[2]), "{:?}", counts);
+        assert!(SplitFractions {
+            validation: 0.6,
+            test: 0.6,
+            seed: 0
+        }
+        .check()
+        .is_err());
+    }
+}
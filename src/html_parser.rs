@@ -0,0 +1,136 @@
+use crate::config::HtmlParserKind;
+use crate::{convert_post, Markup, PostContent};
+use std::io;
+
+// Turns the HTML of a post into text, as `convert_post` does. Both backends
+// share the html5ever tokenizer and differ in the tree they build from it.
+pub trait HtmlParser: Send + Sync {
+    fn to_text(&self, html: &str, markup: Markup) -> (String, PostContent);
+}
+
+// The backend of `kind`, an error for one left out of the build
+pub fn html_parser(kind: HtmlParserKind) -> io::Result<Box<dyn HtmlParser>> {
+    match kind {
+        HtmlParserKind::Scraper => Ok(Box::new(ScraperParser)),
+        #[cfg(feature = "kuchiki")]
+        HtmlParserKind::Kuchiki => Ok(Box::new(kuchiki::KuchikiParser)),
+        #[cfg(not(feature = "kuchiki"))]
+        HtmlParserKind::Kuchiki => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the kuchiki html parser needs the kuchiki feature",
+        )),
+    }
+}
+
+pub struct ScraperParser;
+
+impl HtmlParser for ScraperParser {
+    fn to_text(&self, html: &str, markup: Markup) -> (String, PostContent) {
+        convert_post(html, markup)
+    }
+}
+
+#[cfg(feature = "kuchiki")]
+mod kuchiki {
+    use super::HtmlParser;
+    use crate::config::{SpoilerMode, StrikethroughMode};
+    use crate::{push_run, Markup, PostContent, QUOTE_CLASSES, SPOILER_CLOSE, SPOILER_OPEN};
+    use kuchikiki::traits::TendrilSink;
+    use kuchikiki::NodeRef;
+
+    pub struct KuchikiParser;
+
+    impl HtmlParser for KuchikiParser {
+        fn to_text(&self, html: &str, markup: Markup) -> (String, PostContent) {
+            let document = kuchikiki::parse_html().one(html);
+            let mut text = String::new();
+            let mut content = PostContent::default();
+            push_text(&document, markup, &mut text, &mut false, &mut content);
+            (text, content)
+        }
+    }
+
+    // `crate::push_text` over kuchiki's tree
+    fn push_text(
+        node: &NodeRef,
+        markup: Markup,
+        text: &mut String,
+        paragraph_break: &mut bool,
+        content: &mut PostContent,
+    ) {
+        for child in node.children() {
+            if let Some(run) = child.as_text() {
+                push_run(&run.borrow(), text, paragraph_break);
+                continue;
+            }
+            let Some(element) = child.as_element() else {
+                continue;
+            };
+            let name = &*element.name.local;
+            let attributes = element.attributes.borrow();
+            let has_class = |wanted: &[&str]| {
+                attributes
+                    .get("class")
+                    .is_some_and(|classes| classes.split_whitespace().any(|c| wanted.contains(&c)))
+            };
+            let strikethrough = matches!(name, "del" | "s" | "strike");
+            let spoiler = has_class(&["spoiler"]);
+            if name == "br" {
+                text.push('\n');
+            } else if name == "blockquote" || has_class(QUOTE_CLASSES) {
+                content.quotes += 1;
+            } else if (strikethrough && markup.strikethrough == StrikethroughMode::Drop)
+                || (spoiler && markup.spoiler == SpoilerMode::Drop)
+            {
+            } else {
+                content.images += (name == "img") as usize;
+                let paragraph = name == "p";
+                let token = spoiler && markup.spoiler == SpoilerMode::Token;
+                *paragraph_break |= paragraph;
+                if token {
+                    push_run(SPOILER_OPEN, text, paragraph_break);
+                }
+                push_text(&child, markup, text, paragraph_break, content);
+                if token {
+                    push_run(SPOILER_CLOSE, text, paragraph_break);
+                }
+                *paragraph_break |= paragraph;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::convert_post;
+
+        #[test]
+        fn matches_the_scraper_backend() {
+            let markups = [
+                Markup::default(),
+                Markup {
+                    strikethrough: StrikethroughMode::Drop,
+                    spoiler: SpoilerMode::Token,
+                },
+            ];
+            let posts = [
+                "我哋今日去咗飲茶<br />點心好好食呀",
+                "<p>第一段</p><p>第二段<br></p>尾",
+                "<blockquote>引用<img src=\"a.gif\" /></blockquote>回覆",
+                "<div class=\"post-quote\">引用</div><img src=\"b.gif\" />",
+                "老細話今年有花紅<del>係得個講字</del><span class=\"spoiler\">主角死咗</span>",
+                "&lt;tag&gt; &amp; &#13;&#10;<b>粗<i>斜</i></b>",
+            ];
+            for markup in markups {
+                for post in posts {
+                    assert_eq!(
+                        KuchikiParser.to_text(post, markup),
+                        convert_post(post, markup),
+                        "{}",
+                        post
+                    );
+                }
+            }
+        }
+    }
+}
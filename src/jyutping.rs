@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// The weight of a reading given none, that of one taking every use
+const DEFAULT_WEIGHT: f64 = 100.0;
+
+// Jyutping readings of characters and of the words giving some of them
+// another reading, as in 行 of 銀行. A sentence takes the longest word at
+// each character, else the heaviest reading of the character.
+#[derive(Debug, Default)]
+pub struct Jyutping {
+    chars: HashMap<char, (String, f64)>,
+    words: HashMap<String, Vec<String>>,
+    // in chars
+    longest_word: usize,
+}
+
+impl Jyutping {
+    // The built-in table with the dict files of `dicts` added in turn
+    pub fn load(dicts: &[PathBuf]) -> io::Result<Self> {
+        let mut jyutping = Jyutping::builtin()?;
+        for path in dicts {
+            jyutping.add_file(path)?;
+        }
+        Ok(jyutping)
+    }
+
+    // The chars and words of rime-cantonese, gathered by build.rs
+    #[cfg(feature = "jyutping")]
+    pub fn builtin() -> io::Result<Self> {
+        let mut jyutping = Jyutping::default();
+        jyutping.add(include_str!(concat!(env!("OUT_DIR"), "/jyutping.tsv")))?;
+        Ok(jyutping)
+    }
+
+    #[cfg(not(feature = "jyutping"))]
+    pub fn builtin() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "jyutping needs the jyutping feature",
+        ))
+    }
+
+    pub fn add_file(&mut self, path: &Path) -> io::Result<()> {
+        let dict = std::fs::read_to_string(path)?;
+        self.add(&dict)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    // Adds the entries of a dict, its lines after the yaml header ending
+    // with `...` if it has one. A word replaces an earlier reading of it,
+    // lines without a reading and words whose syllables do not match their
+    // characters are skipped.
+    pub fn add(&mut self, dict: &str) -> io::Result<()> {
+        let body = match dict.split_once("\n...\n") {
+            Some((_, body)) => body,
+            None => dict.strip_prefix("...\n").unwrap_or(dict),
+        };
+        for line in body.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split('\t');
+            let text = columns.next().unwrap_or_default();
+            let Some(reading) = columns.next().map(str::trim).filter(|r| !r.is_empty()) else {
+                continue;
+            };
+            let weight = match columns.next().map(str::trim).filter(|w| !w.is_empty()) {
+                Some(weight) => parse_weight(weight).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad weight {:?} of {:?}", weight, text),
+                    )
+                })?,
+                None => DEFAULT_WEIGHT,
+            };
+            let syllables: Vec<String> = reading.split_whitespace().map(String::from).collect();
+            let chars = text.chars().count();
+            if chars != syllables.len() {
+                continue;
+            }
+            if chars == 1 {
+                let c = text.chars().next().unwrap();
+                match self.chars.get(&c) {
+                    Some((_, heaviest)) if *heaviest >= weight => {}
+                    _ => {
+                        self.chars.insert(c, (reading.to_string(), weight));
+                    }
+                }
+            } else {
+                self.longest_word = self.longest_word.max(chars);
+                self.words.insert(text.to_string(), syllables);
+            }
+        }
+        Ok(())
+    }
+
+    // A syllable per character of `text` but whitespace, `?` for those
+    // without a reading
+    pub fn annotate(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut syllables: Vec<&str> = Vec::with_capacity(chars.len());
+        let mut i = 0;
+        'chars: while i < chars.len() {
+            for len in (2..=self.longest_word.min(chars.len() - i)).rev() {
+                let word: String = chars[i..i + len].iter().collect();
+                if let Some(reading) = self.words.get(&word) {
+                    syllables.extend(reading.iter().map(String::as_str));
+                    i += len;
+                    continue 'chars;
+                }
+            }
+            syllables.push(self.chars.get(&chars[i]).map_or("?", |(r, _)| r.as_str()));
+            i += 1;
+        }
+        syllables.join(" ")
+    }
+}
+
+// A count, or a percentage as in `70%`
+fn parse_weight(weight: &str) -> Option<f64> {
+    weight
+        .strip_suffix('%')
+        .unwrap_or(weight)
+        .parse()
+        .ok()
+        .filter(|w: &f64| w.is_finite() && *w >= 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DICT: &str = "\
+---
+name: jyut6ping3
+...
+# chars
+行\thang4\t25%
+行\thaang4\t60%
+行\thong4\t15%
+銀\tngan4
+山\tsaan1
+我\tngo5
+去\theoi3
+咗\tzo2
+OK\tou1 kei1
+銀行\tngan4 hong4
+";
+
+    #[test]
+    fn words_give_characters_their_reading_in_them() {
+        let mut jyutping = Jyutping::default();
+        jyutping.add(DICT).unwrap();
+        assert_eq!(
+            jyutping.annotate("我去咗銀行"),
+            "ngo5 heoi3 zo2 ngan4 hong4"
+        );
+        // the heaviest reading outside a word
+        assert_eq!(jyutping.annotate("去行山"), "heoi3 haang4 saan1");
+        assert_eq!(jyutping.annotate("OK 我去 喇"), "ou1 kei1 ngo5 heoi3 ?");
+        assert!(jyutping.add("行\thaang4\tmost").is_err());
+    }
+
+    #[cfg(feature = "jyutping")]
+    #[test]
+    fn builtin_table_reads_common_words() {
+        let jyutping = Jyutping::builtin().unwrap();
+        assert_eq!(
+            jyutping.annotate("我去咗銀行"),
+            "ngo5 heoi3 zo2 ngan4 hong4"
+        );
+        assert_eq!(jyutping.annotate("去行山"), "heoi3 haang4 saan1");
+    }
+}
@@ -0,0 +1,2120 @@
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::node::Element;
+use scraper::{ElementRef, Html, Node};
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use xxhash_rust::xxh64::xxh64;
+
+pub mod anonymize;
+pub mod arrow_output;
+pub mod ban_list;
+pub mod bert;
+pub mod bloom;
+pub mod config;
+pub mod corpus_stats;
+pub mod dedup;
+pub mod encoding;
+pub mod english;
+#[cfg(feature = "fetch")]
+pub mod fetch;
+pub mod filters;
+pub mod grouping;
+pub mod hf;
+pub mod html_parser;
+pub mod jyutping;
+pub mod manifest;
+pub mod memory;
+pub mod merge;
+pub mod metrics;
+pub mod months;
+pub mod news;
+pub mod parquet_output;
+pub mod pipeline;
+pub mod post_process;
+pub mod profanity;
+#[cfg(feature = "python")]
+mod python;
+pub mod quality;
+pub mod rare_chars;
+pub mod reply_graph;
+pub mod reply_tree;
+pub mod sampling;
+pub mod scorer;
+#[cfg(target_arch = "x86_64")]
+mod simd;
+pub mod sink;
+pub mod spm;
+pub mod sqlite;
+pub mod stats;
+pub mod substrings;
+pub mod thread_meta;
+pub mod throttle;
+pub mod trace;
+pub mod two_pass;
+pub mod watch;
+
+use anonymize::Anonymizer;
+use ban_list::BanList;
+use config::{ExtractorConfig, NewsMode, ParaConfig, SpoilerMode, StrikethroughMode};
+use encoding::NonUtf8Line;
+use english::EnglishFilter;
+use filters::{CjkRatio, DefaultValidator, FilterChain, ParaFilter, ParaValidator, RejectReason};
+use html_parser::{html_parser, HtmlParser};
+use news::NewsDetector;
+use post_process::{PostProcessor, PostProcessorChain};
+use profanity::{Profanity, ProfanityMode};
+use quality::{Quality, QualityScorer};
+use reply_graph::{quote_key, PostQuote};
+use stats::{Stats, ThreadStats};
+use thread_meta::ThreadMeta;
+use throttle::ReadLimit;
+
+lazy_static! {
+    static ref DEFAULT_RULES: DefaultValidator = DefaultValidator::default();
+    pub static ref CJK_REGEX: Regex = Regex::new(r"\p{Unified_Ideograph}").unwrap();
+    static ref EMOJI_REGEX: Regex = Regex::new(r"\p{Extended_Pictographic}").unwrap();
+    static ref URL_REGEX: Regex = Regex::new(r"https?://[!-~]+").unwrap();
+    pub static ref WORD_REGEX: Regex =
+        Regex::new(r"[[:alnum:]]+|\p{Unified_Ideograph}|\p{Punct}+").unwrap();
+    static ref PUNCS: HashSet<char> = {
+        SHARED_PUNCS
+            .union(&ENGLISH_PUNCS)
+            .copied()
+            .collect::<HashSet<char>>()
+            .union(&CHINESE_PUNCS)
+            .copied()
+            .collect()
+    };
+    static ref SHARED_PUNCS: HashSet<char> =
+        HashSet::from(['@', '#', '$', '%', '^', '&', '*', '·', '…', '‥', '—', '～']);
+    static ref ENGLISH_PUNCS: HashSet<char> = {
+        HashSet::from([
+            '~', '`', '!', '(', ')', '-', '_', '{', '}', '[', ']', '|', '\\', ':', ';', '"', '\'',
+            '<', '>', ',', '.', '?', '/',
+        ])
+    };
+    static ref CHINESE_PUNCS: HashSet<char> = {
+        HashSet::from([
+            '！', '：', '；', '“', '”', '‘', '’', '【', '】', '（', '）', '「', '」', '﹁', '﹂',
+            '『', '』', '《', '》', '？', '，', '。', '、', '／', '＋', '〈', '〉', '︿', '﹀',
+            '［', '］', '‧',
+        ])
+    };
+}
+
+pub fn filter_irrelevant_chars(text: &str) -> String {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports SSE4.2
+        return unsafe { simd::filter_irrelevant_chars(text) };
+    }
+    filter_irrelevant_chars_scalar(text)
+}
+
+// The reference for the SIMD version, one char at a time
+pub fn filter_irrelevant_chars_scalar(text: &str) -> String {
+    text.chars().filter(|c| is_relevant_char(*c)).collect()
+}
+
+fn is_relevant_char(c: char) -> bool {
+    CJK_REGEX.is_match(&c.to_string()) || is_punc(c) || c.is_ascii_alphanumeric()
+}
+
+// Like `filter_irrelevant_chars`, keeping emoji as well
+pub fn filter_irrelevant_chars_keeping_emoji(text: &str) -> String {
+    text.chars()
+        .filter(|c| {
+            let s = c.to_string();
+            CJK_REGEX.is_match(&s)
+                || EMOJI_REGEX.is_match(&s)
+                || is_punc(*c)
+                || c.is_ascii_alphanumeric()
+        })
+        .collect()
+}
+
+// Written in place of each URL with `ExtractorConfig::urls_as_tokens`
+pub const URL_TOKEN: &str = "<url>";
+
+pub fn replace_urls(text: &str) -> Cow<'_, str> {
+    URL_REGEX.replace_all(text, URL_TOKEN)
+}
+
+// Shortens every run of the same char to at most `max` chars
+pub fn collapse_repeats(text: &str, max: usize) -> String {
+    collapse_runs(text, max, |_| true)
+}
+
+// Repetitions of a lengthened char kept by `normalize_elongation`
+pub const MAX_ELONGATION: usize = 2;
+
+// Shortens runs of the `chars` to `MAX_ELONGATION`, leaving other runs
+// alone, so expressive lengthening like "正呀呀呀呀" goes but words repeated
+// on purpose like "好好食" stay
+pub fn normalize_elongation(text: &str, chars: &str) -> String {
+    collapse_runs(text, MAX_ELONGATION, |c| chars.contains(c))
+}
+
+fn collapse_runs(text: &str, max: usize, collapsible: impl Fn(char) -> bool) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut prev = None;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if prev == Some(c) { run + 1 } else { 1 };
+        prev = Some(c);
+        if run <= max || !collapsible(c) {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+// Punctuation ending a finished sentence, with the ASCII forms posters type
+const SENTENCE_END_PUNCS: &str = "。！？…～.!?~";
+// Closing brackets and quotes left after the end of a sentence
+const CLOSING_PUNCS: &str = "」』）】》〉﹂］)]\"'”’";
+
+// Whether a sentence ends in sentence-final punctuation or one of the
+// `particles`, ignoring closing brackets and quotes after it
+pub fn ends_sentence(sentence: &str, particles: &str) -> bool {
+    sentence
+        .trim_end_matches(|c| CLOSING_PUNCS.contains(c))
+        .chars()
+        .next_back()
+        .is_some_and(|c| SENTENCE_END_PUNCS.contains(c) || particles.contains(c))
+}
+
+pub fn is_punc(c: char) -> bool {
+    PUNCS.contains(&c)
+}
+
+pub fn count_matching_chars(text: &str, regex: &Regex) -> usize {
+    text.chars()
+        .filter(|c| regex.is_match(&c.to_string()))
+        .count()
+}
+
+// Share of CJK chars, 0 for empty text
+pub fn cjk_ratio(text: &str) -> f64 {
+    let len = text.chars().count();
+    count_matching_chars(text, &CJK_REGEX) as f64 / len.max(1) as f64
+}
+
+// Whether every char is a CJK ideograph, false for empty text
+pub fn is_all_cjk(text: &str) -> bool {
+    !text.is_empty() && count_matching_chars(text, &CJK_REGEX) == text.chars().count()
+}
+
+pub fn is_valid_para(para: &str) -> bool {
+    validate_para(para).is_ok()
+}
+
+pub fn validate_para(para: &str) -> Result<(), RejectReason> {
+    DEFAULT_RULES.is_valid(para)
+}
+
+// The entries of a list file, one per line, with blank lines and lines
+// starting with '#' ignored
+pub fn list_entries(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+pub fn convert_html_to_text(html: &str) -> String {
+    convert_post(html, Markup::default()).0
+}
+
+// Written around spoiler text with `SpoilerMode::Token`
+pub const SPOILER_OPEN: &str = "<spoiler>";
+pub const SPOILER_CLOSE: &str = "</spoiler>";
+
+// How struck through and spoiler text is converted, kept as plain text by
+// default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Markup {
+    pub strikethrough: StrikethroughMode,
+    pub spoiler: SpoilerMode,
+}
+
+// What the HTML of a post held besides its own text, quotes nested in
+// quotes and the images in them not counted
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PostContent {
+    pub images: usize,
+    pub quotes: usize,
+}
+
+// Converts to text, with line breaks for <br> and between paragraphs.
+// Quoted posts are skipped.
+pub fn convert_post(html: &str, markup: Markup) -> (String, PostContent) {
+    let document = Html::parse_fragment(html);
+    let mut text = String::new();
+    let mut content = PostContent::default();
+    push_text(
+        document.root_element(),
+        markup,
+        &mut text,
+        &mut false,
+        &mut content,
+    );
+    (text, content)
+}
+
+// Classes of the elements LIHKG has wrapped quoted posts in besides
+// <blockquote>, across its redesigns
+pub const QUOTE_CLASSES: &[&str] = &["quote", "post-quote", "quote-post", "quoted-post"];
+
+fn is_quote(element: &Element) -> bool {
+    element.name() == "blockquote"
+        || element
+            .classes()
+            .any(|class| QUOTE_CLASSES.contains(&class))
+}
+
+fn is_strikethrough(element: &Element) -> bool {
+    matches!(element.name(), "del" | "s" | "strike")
+}
+
+fn is_spoiler(element: &Element) -> bool {
+    element.classes().any(|class| class == "spoiler")
+}
+
+// A post split into its own text and the chain of posts it quotes
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotedReply {
+    // the quoted post, the post that one quotes and so on, each without the
+    // quotes nested in it
+    pub quotes: Vec<String>,
+    pub reply: String,
+    pub content: PostContent,
+}
+
+// Follows the chain of quotes down at most `depth` levels, deeper quotes
+// are dropped with the level they are nested in
+pub fn split_quote(html: &str, depth: usize, markup: Markup) -> QuotedReply {
+    let document = Html::parse_fragment(html);
+    let root = document.root_element();
+    let mut reply = String::new();
+    let mut content = PostContent::default();
+    push_text(root, markup, &mut reply, &mut false, &mut content);
+    let mut quotes = Vec::new();
+    let mut element = root;
+    while quotes.len() < depth {
+        // in document order the first quote is never nested in another
+        let Some(quote) = element
+            .descendants()
+            .filter_map(ElementRef::wrap)
+            .find(|e| e.id() != element.id() && is_quote(e.value()))
+        else {
+            break;
+        };
+        let mut text = String::new();
+        push_text(
+            quote,
+            markup,
+            &mut text,
+            &mut false,
+            &mut PostContent::default(),
+        );
+        quotes.push(text);
+        element = quote;
+    }
+    QuotedReply {
+        quotes,
+        reply,
+        content,
+    }
+}
+
+// `paragraph_break` is set after a <p> and turns into a line break before
+// the next text, so paragraphs never leave leading or trailing ones
+fn push_text(
+    element: ElementRef,
+    markup: Markup,
+    text: &mut String,
+    paragraph_break: &mut bool,
+    content: &mut PostContent,
+) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => push_run(t, text, paragraph_break),
+            Node::Element(e) if e.name() == "br" => text.push('\n'),
+            Node::Element(e) if is_quote(e) => content.quotes += 1,
+            Node::Element(e)
+                if is_strikethrough(e) && markup.strikethrough == StrikethroughMode::Drop => {}
+            Node::Element(e) if is_spoiler(e) && markup.spoiler == SpoilerMode::Drop => {}
+            Node::Element(e) => {
+                content.images += (e.name() == "img") as usize;
+                let paragraph = e.name() == "p";
+                let token = is_spoiler(e) && markup.spoiler == SpoilerMode::Token;
+                *paragraph_break |= paragraph;
+                if token {
+                    push_run(SPOILER_OPEN, text, paragraph_break);
+                }
+                push_text(
+                    ElementRef::wrap(child).unwrap(),
+                    markup,
+                    text,
+                    paragraph_break,
+                    content,
+                );
+                if token {
+                    push_run(SPOILER_CLOSE, text, paragraph_break);
+                }
+                *paragraph_break |= paragraph;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn push_run(run: &str, text: &mut String, paragraph_break: &mut bool) {
+    if *paragraph_break && !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    *paragraph_break = false;
+    text.push_str(&normalize_line_endings(run));
+}
+
+// Windows "\r\n" and lone "\r" line endings as "\n". The HTML parser already
+// does this for raw text but not for a "&#13;", and poll texts are no HTML.
+pub fn normalize_line_endings(text: &str) -> Cow<'_, str> {
+    if text.contains('\r') {
+        Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+// The error code of a response without success as text, "none" without one
+pub fn api_error_code(obj: &Value) -> String {
+    match value_as_i64(&obj["error_code"]) {
+        Some(code) => code.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+// Lenient integer lookup, the API encodes some numbers as strings
+pub fn value_as_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+// Posts per page of a thread, both on the site and in the API
+pub const POSTS_PER_PAGE: u64 = 25;
+
+// The thread page showing post number `msg_num`, counted from 1
+pub fn thread_url(thread_id: u64, msg_num: u64) -> String {
+    let page = msg_num.saturating_sub(1) / POSTS_PER_PAGE + 1;
+    format!("https://lihkg.com/thread/{}/page/{}", thread_id, page)
+}
+
+// An accepted sentence with the metadata of the post it came from
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SentenceRecord {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<u64>,
+    // only written to the --index file
+    #[serde(skip)]
+    pub post_id: Option<String>,
+    #[serde(skip)]
+    pub msg_num: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_time: Option<i64>,
+    // likes minus dislikes of the post
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_score: Option<i64>,
+    // set with --include-votes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub like_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dislike_count: Option<i64>,
+    // pseudonym of the poster with --anonymize-users, raw ids are never kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    // set by the external scorer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    // set for text other than post paragraphs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<RecordKind>,
+    // from a post pasting a news article, with --news-posts tag
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub news_like: bool,
+    // the trimmed paragraph before character filtering, with --keep-raw
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+    // space separated syllables of the text, with --jyutping
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jyutping: Option<String>,
+    // score and features with --score-all
+    #[serde(flatten)]
+    pub quality: Option<Quality>,
+    // xxh64 of the post's html and the index of the blank line separated
+    // block in it, telling posts and paragraphs apart when grouping output
+    #[serde(skip)]
+    pub post_hash: u64,
+    #[serde(skip)]
+    pub block: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    PollQuestion,
+    PollOption,
+}
+
+// The question and option labels of a vote thread's poll. Depending on the
+// API era the poll is under vote_info or display_vote, and its options are
+// an array or a map by option id, of strings or of objects.
+pub fn poll_texts(response: &Value) -> Vec<(RecordKind, &str)> {
+    let Some(poll) = ["vote_info", "display_vote"]
+        .iter()
+        .map(|key| &response[*key])
+        .find(|poll| poll.is_object())
+    else {
+        return Vec::new();
+    };
+    let mut texts = Vec::new();
+    if let Some(question) = ["question", "title"]
+        .iter()
+        .find_map(|key| poll[*key].as_str())
+    {
+        texts.push((RecordKind::PollQuestion, question));
+    }
+    let options: Vec<&Value> = match ["options", "vote_options"]
+        .iter()
+        .map(|key| &poll[*key])
+        .find(|options| !options.is_null())
+    {
+        Some(Value::Array(options)) => options.iter().collect(),
+        Some(Value::Object(options)) => options.values().collect(),
+        _ => Vec::new(),
+    };
+    for option in options {
+        let label = match option {
+            Value::String(label) => Some(label.as_str()),
+            _ => ["text", "title", "option", "name"]
+                .iter()
+                .find_map(|key| option[*key].as_str()),
+        };
+        if let Some(label) = label {
+            texts.push((RecordKind::PollOption, label));
+        }
+    }
+    texts
+}
+
+// Names of the accounts posting announcements, and what deleted accounts
+// are shown as
+pub const SYSTEM_NICKNAMES: &[&str] = &[
+    "LIHKG",
+    "LIHKG討論區",
+    "連登",
+    "連登討論區",
+    "系統",
+    "管理員",
+    "已刪除",
+    "已刪除用戶",
+    "[deleted]",
+];
+
+// The nickname of the user who wrote an item, unless the account is deleted
+// or a system account
+pub fn nickname(item: &Value) -> Option<&str> {
+    let user = &item["user"];
+    let nickname = user["nickname"]
+        .as_str()
+        .or_else(|| item["user_nickname"].as_str())?
+        .trim();
+    let deleted = value_as_i64(&user["user_id"]).is_some_and(|id| id <= 0);
+    if nickname.is_empty() || deleted || SYSTEM_NICKNAMES.contains(&nickname) {
+        return None;
+    }
+    Some(nickname)
+}
+
+// Output of processing a slice of lines, merged across rayon workers
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub records: Vec<SentenceRecord>,
+    // (quoted, reply) sentence pairs, set when extracting pairs
+    pub pairs: Vec<(String, String)>,
+    // posts per nickname, set when collecting nicknames
+    pub nicknames: HashMap<String, u64>,
+    // (error code, thread id) of the responses without success, set when
+    // collecting api errors
+    pub api_errors: Vec<(String, u64)>,
+    // posts of threads in input order, set when collecting the reply graph
+    pub quotes: Vec<PostQuote>,
+    // (thread id, post) of the posts of threads, set when collecting reply
+    // trees
+    pub thread_posts: Vec<(u64, Value)>,
+    // English paragraphs passing `english::EnglishFilter`, set with
+    // --english-out
+    pub english: Vec<String>,
+    // lines of the entry that were not UTF-8, set when detecting encodings
+    pub non_utf8: Vec<NonUtf8Line>,
+    // what each response says of its thread, set when collecting thread
+    // metadata
+    pub threads: Vec<ThreadMeta>,
+    pub stats: Stats,
+}
+
+// Where a written sentence came from, a line of the --index file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndexEntry<'a> {
+    pub thread_id: Option<u64>,
+    pub post_id: Option<&'a str>,
+    pub msg_num: Option<u64>,
+    pub url: Option<String>,
+}
+
+impl SentenceRecord {
+    pub fn index_entry(&self) -> IndexEntry<'_> {
+        IndexEntry {
+            thread_id: self.thread_id,
+            post_id: self.post_id.as_deref(),
+            msg_num: self.msg_num,
+            url: self
+                .thread_id
+                .map(|thread_id| thread_url(thread_id, self.msg_num.unwrap_or(1))),
+        }
+    }
+}
+
+impl Batch {
+    pub fn merge(&mut self, mut other: Batch) {
+        self.records.append(&mut other.records);
+        self.pairs.append(&mut other.pairs);
+        self.api_errors.append(&mut other.api_errors);
+        self.quotes.append(&mut other.quotes);
+        self.thread_posts.append(&mut other.thread_posts);
+        self.english.append(&mut other.english);
+        self.non_utf8.append(&mut other.non_utf8);
+        self.threads.append(&mut other.threads);
+        for (nickname, count) in other.nicknames {
+            *self.nicknames.entry(nickname).or_default() += count;
+        }
+        self.stats.merge(other.stats);
+    }
+}
+
+pub struct Extractor {
+    chain: FilterChain,
+    cjk_ratio: CjkRatio,
+    // the paragraph rules in effect, shared by the rayon workers through the
+    // extractor and handed out as a pointer copy
+    para: Arc<ParaConfig>,
+    collapse_repeats: Option<usize>,
+    // set when normalizing elongation
+    elongation_chars: Option<String>,
+    // the particles of --require-sentence-end when set
+    sentence_end_particles: Option<String>,
+    post_processors: Option<PostProcessorChain>,
+    urls_as_tokens: bool,
+    keep_emoji: bool,
+    keep_raw: bool,
+    min_replies: Option<u64>,
+    min_likes: Option<u64>,
+    // accounts whose posts are skipped, see `BanList`
+    ban_list: Option<BanList>,
+    include_votes: bool,
+    anonymizer: Option<Anonymizer>,
+    profanity: Option<(ProfanityMode, Profanity)>,
+    news: Option<(NewsMode, NewsDetector)>,
+    english: Option<EnglishFilter>,
+    // set with --score-all, which writes rejected sentences too
+    quality: Option<QualityScorer>,
+    seen_posts: Option<DashMap<u64, ()>>,
+    // poll texts by thread, set when extracting polls
+    seen_poll_texts: Option<DashMap<(u64, u64), ()>>,
+    extract_pairs: bool,
+    quote_depth: usize,
+    markup: Markup,
+    html: Box<dyn HtmlParser>,
+    collect_nicknames: bool,
+    collect_api_errors: bool,
+    collect_post_sentences: bool,
+    collect_reply_graph: bool,
+    collect_reply_trees: bool,
+    collect_thread_meta: bool,
+    thread_stats: Option<DashMap<u64, ThreadStats>>,
+    // lines per rayon job, see `pipeline::process_archive`
+    batch_size: Option<usize>,
+    pub max_memory_mb: Option<u64>,
+    // read lines that are not UTF-8 in the encoding detected for them, see
+    // `pipeline::read_entry`
+    pub detect_encoding: bool,
+    // shared by the readers of the input files, see `throttle::Throttled`
+    pub read_limit: Option<ReadLimit>,
+}
+
+impl Extractor {
+    pub fn new(config: &ExtractorConfig) -> std::io::Result<Self> {
+        let profanity = match config.profanity {
+            ProfanityMode::Keep => None,
+            mode => {
+                let words = match &config.profanity_list {
+                    Some(path) => Profanity::from_file(path)?,
+                    None => Profanity::builtin(),
+                };
+                Some((mode, words))
+            }
+        };
+        let anonymizer = match (config.anonymize_users, &config.anonymize_key) {
+            (false, _) => None,
+            (true, Some(key)) => Some(Anonymizer::from_hex(key)?),
+            (true, None) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "anonymizing users needs --anonymize-key or {}",
+                        anonymize::ANON_KEY_ENV
+                    ),
+                ))
+            }
+        };
+        let quality = if config.score_all {
+            let blocklist = match &config.profanity_list {
+                Some(path) => Profanity::from_file(path)?,
+                None => Profanity::builtin(),
+            };
+            let lengths = config.para.min_len..=config.para.max_len;
+            Some(QualityScorer::new(lengths, blocklist))
+        } else {
+            None
+        };
+        let mut para = config.para.clone();
+        if let Some(path) = &config.deleted_patterns {
+            let contents = std::fs::read_to_string(path)?;
+            para.deletion_markers = list_entries(&contents)
+                .into_iter()
+                .map(String::from)
+                .collect();
+        }
+        let post_processors = match &config.post_processors {
+            Some(spec) => Some(
+                PostProcessorChain::parse(spec)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            ),
+            None => None,
+        };
+        Ok(Extractor {
+            chain: FilterChain::from_config(&para),
+            cjk_ratio: CjkRatio::from_config(&para),
+            para: Arc::new(para),
+            collapse_repeats: config.collapse_repeats,
+            elongation_chars: config
+                .normalize_elongation
+                .then(|| config.elongation_chars.clone()),
+            sentence_end_particles: config
+                .require_sentence_end
+                .then(|| config.sentence_end_particles.clone()),
+            post_processors,
+            urls_as_tokens: config.urls_as_tokens,
+            keep_emoji: config.keep_emoji,
+            keep_raw: config.keep_raw,
+            min_replies: config.min_replies,
+            min_likes: config.min_likes,
+            ban_list: match &config.ban_users {
+                Some(path) => Some(BanList::from_file(path)?),
+                None => None,
+            },
+            include_votes: config.include_votes,
+            anonymizer,
+            profanity,
+            news: (config.news_posts != NewsMode::Keep)
+                .then(|| (config.news_posts, NewsDetector::new(config))),
+            english: config.collect_english.then_some(EnglishFilter {
+                min_words: config.english_min_words,
+                max_words: config.english_max_words,
+            }),
+            quality,
+            seen_posts: config.dedup_posts.then(DashMap::new),
+            seen_poll_texts: config.extract_polls.then(DashMap::new),
+            extract_pairs: config.extract_pairs,
+            quote_depth: config.quote_depth,
+            markup: Markup {
+                strikethrough: config.strikethrough,
+                spoiler: config.spoiler,
+            },
+            html: html_parser(config.html_parser)?,
+            collect_nicknames: config.collect_nicknames,
+            collect_api_errors: config.collect_api_errors,
+            collect_post_sentences: config.collect_post_sentences,
+            collect_reply_graph: config.collect_reply_graph,
+            collect_reply_trees: config.collect_reply_trees,
+            collect_thread_meta: config.collect_thread_meta,
+            thread_stats: config.collect_thread_stats.then(DashMap::new),
+            batch_size: config.batch_size,
+            max_memory_mb: config.max_memory_mb,
+            detect_encoding: config.detect_encoding,
+            read_limit: config.max_read_mbps.map(ReadLimit::new),
+        })
+    }
+
+    // Forgets the posts, polls and thread totals seen so far, so another pass
+    // over the same input is not taken for duplicates or counted twice
+    pub fn reset_seen_posts(&self) {
+        if let Some(seen_posts) = &self.seen_posts {
+            seen_posts.clear();
+        }
+        if let Some(seen_poll_texts) = &self.seen_poll_texts {
+            seen_poll_texts.clear();
+        }
+        if let Some(thread_stats) = &self.thread_stats {
+            thread_stats.clear();
+        }
+    }
+
+    pub fn para_config(&self) -> Arc<ParaConfig> {
+        self.para.clone()
+    }
+
+    pub fn check_para(&self, para: &str) -> Result<(), RejectReason> {
+        self.chain.check(para)?;
+        self.cjk_ratio.check(para)
+    }
+
+    // Rewrites a paragraph before it is checked
+    pub fn normalize_para<'a>(&self, para: &'a str) -> Cow<'a, str> {
+        let mut para = Cow::Borrowed(para);
+        if self.urls_as_tokens {
+            para = Cow::Owned(replace_urls(&para).into_owned());
+        }
+        if let Some(chars) = &self.elongation_chars {
+            para = Cow::Owned(normalize_elongation(&para, chars));
+        }
+        if let Some(max) = self.collapse_repeats {
+            para = Cow::Owned(collapse_repeats(&para, max));
+        }
+        if let (Some(max_run), true) = (self.para.max_latin_run, self.para.truncate_latin_run) {
+            para = Cow::Owned(filters::truncate_latin_runs(&para, max_run).into_owned());
+        }
+        para
+    }
+
+    fn strip_para(&self, para: &str) -> String {
+        if self.keep_emoji {
+            filter_irrelevant_chars_keeping_emoji(para)
+        } else {
+            filter_irrelevant_chars(para)
+        }
+    }
+
+    // Turns an accepted paragraph into the emitted sentence. The sentence
+    // end is checked last, so its rejections count sentences valid otherwise.
+    fn clean_para(&self, para: &str, stats: &mut Stats) -> Result<String, RejectReason> {
+        let sentence = self.mask_profanity(self.strip_para(para), stats)?;
+        if let Some(particles) = &self.sentence_end_particles {
+            if !ends_sentence(&sentence, particles) {
+                return Err(RejectReason::SentenceEnd);
+            }
+        }
+        match &self.post_processors {
+            // rewritten to nothing
+            Some(chain) => Some(chain.process(sentence))
+                .filter(|sentence| !sentence.is_empty())
+                .ok_or(RejectReason::PostProcess),
+            None => Ok(sentence),
+        }
+    }
+
+    fn mask_profanity(&self, para: String, stats: &mut Stats) -> Result<String, RejectReason> {
+        let Some((mode, profanity)) = &self.profanity else {
+            return Ok(para);
+        };
+        let spans = profanity.find(&para);
+        if spans.is_empty() {
+            return Ok(para);
+        }
+        for (_, _, word) in &spans {
+            *stats.profanity.entry(word.clone()).or_default() += 1;
+        }
+        match mode {
+            ProfanityMode::Drop => Err(RejectReason::Profanity),
+            _ => Ok(Profanity::mask(&para, &spans)),
+        }
+    }
+
+    // The last line of a quote and the first line of the reply to it, if both
+    // pass the checks on their own
+    fn pair(&self, quote: &str, reply: &str) -> Option<(String, String)> {
+        let quote = quote
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())?;
+        let reply = reply.lines().map(str::trim).find(|line| !line.is_empty())?;
+        let side = |para: &str| {
+            let para = self.normalize_para(para);
+            self.check_para(&para).ok()?;
+            // profanity is counted once, with the sentences
+            self.clean_para(&para, &mut Stats::default()).ok()
+        };
+        Some((side(quote)?, side(reply)?))
+    }
+
+    // Hands a post of a thread over to the reply graph, see
+    // `reply_graph::ReplyGraph`
+    fn push_quote(
+        &self,
+        source: &SentenceRecord,
+        text: &str,
+        quote: Option<&str>,
+        batch: &mut Batch,
+    ) {
+        if let Some(thread_id) = source.thread_id {
+            batch.quotes.push(PostQuote {
+                thread_id,
+                post_id: source.post_id.clone(),
+                key: quote_key(text),
+                quote: quote.map(quote_key),
+            });
+        }
+    }
+
+    // Keeps the fields of a post its reply tree needs, see
+    // `reply_tree::thread_tree`, with its text in place of its html
+    fn push_tree_post(
+        &self,
+        source: &SentenceRecord,
+        item: &Value,
+        text: String,
+        batch: &mut Batch,
+    ) {
+        let Some(thread_id) = source.thread_id else {
+            return;
+        };
+        let mut post = serde_json::Map::new();
+        for key in ["post_id", "msg_num", "reply_id", "parent_id", "reply_time"] {
+            if !item[key].is_null() {
+                post.insert(key.to_string(), item[key].clone());
+            }
+        }
+        // raw user ids are never kept
+        if let Some(user_id) = &source.user_id {
+            post.insert("user_id".to_string(), Value::from(user_id.as_str()));
+        }
+        post.insert("text".to_string(), Value::from(text));
+        batch.thread_posts.push((thread_id, Value::Object(post)));
+    }
+
+    // Checks each line of `text` and adds the accepted ones as records with
+    // the metadata of `source`
+    fn process_text(&self, text: &str, source: &SentenceRecord, batch: &mut Batch) {
+        let text = normalize_line_endings(text);
+        // gathered here and added to the thread once, keeping the map's
+        // locks out of the loop
+        let mut thread = match (&self.thread_stats, source.thread_id) {
+            (Some(_), Some(_)) => Some(ThreadStats::default()),
+            _ => None,
+        };
+        // the whole post is dropped or tagged, whatever its paragraphs
+        let news = match &self.news {
+            Some((mode, detector)) if detector.is_news(&text) => {
+                batch.stats.news_posts += 1;
+                Some(*mode)
+            }
+            _ => None,
+        };
+        let mut block = 0;
+        let mut blank = false;
+        let mut deleted = false;
+        for para in text.split('\n') {
+            let para = para.trim();
+            let raw = self.keep_raw.then(|| para.to_string());
+            // a run of blank lines ends a block
+            if para.is_empty() && !blank {
+                block += 1;
+            }
+            blank = para.is_empty();
+            let len = para.chars().count();
+            batch.stats.paragraphs += 1;
+            if len > 0 {
+                batch.stats.considered_lengths.add(len);
+                if let Some(thread) = &mut thread {
+                    thread.paragraphs += 1;
+                    thread.cjk_ratio_sum +=
+                        count_matching_chars(para, &CJK_REGEX) as f64 / len as f64;
+                }
+            }
+            let para = self.normalize_para(para);
+            // set aside whatever the checks below make of it
+            if let Some(english) = &self.english {
+                if english::is_english_only(&para) {
+                    batch.stats.english_paragraphs += 1;
+                    if english.check(&para).is_ok() {
+                        batch.english.push(para.to_string());
+                        batch.stats.english_sentences += 1;
+                    }
+                }
+            }
+            let result = self
+                .check_para(&para)
+                .and(match news {
+                    Some(NewsMode::Drop) => Err(RejectReason::NewsLike),
+                    _ => Ok(()),
+                })
+                .and_then(|()| self.clean_para(&para, &mut batch.stats));
+            // deleted posts and blank lines are no candidates
+            let quality = match (&self.quality, &result) {
+                (Some(_), Err(RejectReason::Deleted | RejectReason::Empty)) | (None, _) => None,
+                (Some(scorer), result) => Some(scorer.score(&para, result.is_ok())),
+            };
+            match result {
+                Ok(text) => {
+                    batch.records.push(SentenceRecord {
+                        text,
+                        block,
+                        quality,
+                        news_like: news.is_some(),
+                        raw,
+                        ..source.clone()
+                    });
+                    batch.stats.sentences += 1;
+                    batch.stats.accepted_lengths.add(len);
+                    if let Some(thread) = &mut thread {
+                        thread.sentences += 1;
+                    }
+                }
+                Err(reason) => {
+                    deleted |= reason == RejectReason::Deleted;
+                    batch.stats.reject(reason);
+                    // written anyway with its score, masked but not dropped
+                    // for profanity
+                    if quality.is_some() {
+                        let mut text = self.strip_para(&para);
+                        if let Some((ProfanityMode::Mask, profanity)) = &self.profanity {
+                            text = Profanity::mask(&text, &profanity.find(&text));
+                        }
+                        if !text.is_empty() {
+                            batch.records.push(SentenceRecord {
+                                text,
+                                block,
+                                quality,
+                                news_like: news.is_some(),
+                                raw,
+                                ..source.clone()
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        // a post is deleted once, however many paragraphs show it
+        if deleted {
+            batch.stats.deleted_posts += 1;
+            if let Some(thread) = &mut thread {
+                thread.deleted += 1;
+            }
+        }
+        if let (Some(threads), Some(thread_id), Some(mut thread)) =
+            (&self.thread_stats, source.thread_id, thread)
+        {
+            // poll texts are not posts
+            if source.kind.is_none() {
+                thread.posts = 1;
+                if let Some(reply_time) = source.reply_time {
+                    thread.observe_reply_time(reply_time);
+                }
+            }
+            threads.entry(thread_id).or_default().merge(&thread);
+        }
+    }
+
+    // The accepted sentences of a post's html, as a post of no thread
+    pub fn extract_paragraphs(&self, html: &str) -> Vec<String> {
+        let mut batch = Batch::default();
+        let source = SentenceRecord::default();
+        let (text, _) = self.html.to_text(html, self.markup);
+        self.process_text(&text, &source, &mut batch);
+        batch
+            .records
+            .into_iter()
+            .map(|record| record.text)
+            .collect()
+    }
+
+    // Reads the --ban-users file again if it changed, true if it did
+    pub fn reload_ban_list(&self) -> std::io::Result<bool> {
+        match &self.ban_list {
+            Some(ban_list) => ban_list.reload(),
+            None => Ok(false),
+        }
+    }
+
+    // Totals of every thread seen, set when collecting thread stats
+    pub fn thread_stats(&self) -> Vec<(u64, ThreadStats)> {
+        self.thread_stats
+            .iter()
+            .flat_map(|threads| threads.iter())
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    pub fn process_line(&self, line: &str, batch: &mut Batch) -> Result<(), serde_json::Error> {
+        batch.stats.lines += 1;
+        // the "\r" of a Windows line ending read by something other than
+        // `pipeline::process_archive`
+        let line = line.trim_end_matches('\r');
+        let mut columns = line.split('\t');
+        let line_thread_id = columns.next().and_then(|id| id.trim().parse::<u64>().ok());
+        // a line without the json column fails to parse like any other bad json
+        let line = columns.nth(1).unwrap_or_default();
+        let obj: Value = serde_json::from_str(line)?;
+
+        if obj["success"].as_i64() == Some(1) {
+            let response = &obj["response"];
+            if self.collect_thread_meta {
+                let thread_id = value_as_i64(&response["thread_id"])
+                    .map(|id| id as u64)
+                    .or(line_thread_id);
+                if let Some(thread_id) = thread_id {
+                    batch
+                        .threads
+                        .push(ThreadMeta::from_response(thread_id, response));
+                }
+            }
+            if let Some(min_replies) = self.min_replies {
+                let replies = value_as_i64(&response["total_replies"])
+                    .or_else(|| value_as_i64(&response["no_of_reply"]));
+                if replies.is_some_and(|replies| replies < min_replies as i64) {
+                    batch.stats.thread_too_small += 1;
+                    return Ok(());
+                }
+            }
+            if let Some(item_data) = response["item_data"].as_array() {
+                for item in item_data {
+                    batch.stats.items += 1;
+                    if let Some(msg) = item["msg"].as_str() {
+                        if let Some(entry) = self.ban_list.as_ref().and_then(|b| b.matching(item)) {
+                            *batch.stats.banned_posts.entry(entry).or_default() += 1;
+                            continue;
+                        }
+                        let like_count = value_as_i64(&item["like_count"]);
+                        let dislike_count = value_as_i64(&item["dislike_count"]);
+                        if let Some(min_likes) = self.min_likes {
+                            // posts without a like count have none
+                            if like_count.unwrap_or(0) < min_likes as i64 {
+                                batch.stats.posts_too_few_likes += 1;
+                                continue;
+                            }
+                        }
+                        let post_hash = xxh64(msg.as_bytes(), 0);
+                        if let Some(seen_posts) = &self.seen_posts {
+                            if seen_posts.insert(post_hash, ()).is_some() {
+                                batch.stats.duplicate_posts += 1;
+                                continue;
+                            }
+                        }
+                        if self.collect_nicknames {
+                            if let Some(nickname) = nickname(item) {
+                                *batch.nicknames.entry(nickname.to_string()).or_default() += 1;
+                            }
+                        }
+                        let thread_id = value_as_i64(&response["thread_id"])
+                            .or_else(|| value_as_i64(&item["thread_id"]))
+                            .map(|id| id as u64);
+                        let post_id = match &item["post_id"] {
+                            Value::String(id) => Some(id.clone()),
+                            Value::Number(id) => Some(id.to_string()),
+                            _ => None,
+                        };
+                        let msg_num = value_as_i64(&item["msg_num"]).map(|n| n as u64);
+                        let user_id = match &item["user"]["user_id"] {
+                            Value::Null => &item["user_id"],
+                            user_id => user_id,
+                        };
+                        let user_id = match (&self.anonymizer, user_id) {
+                            (Some(anonymizer), Value::String(id)) => Some(anonymizer.pseudonym(id)),
+                            (Some(anonymizer), Value::Number(id)) => {
+                                Some(anonymizer.pseudonym(&id.to_string()))
+                            }
+                            _ => None,
+                        };
+                        let reply_time = value_as_i64(&item["reply_time"]);
+                        let post_score = like_count.map(|likes| likes - dislike_count.unwrap_or(0));
+                        let source = SentenceRecord {
+                            thread_id,
+                            post_id,
+                            msg_num,
+                            reply_time,
+                            post_score,
+                            user_id,
+                            post_hash,
+                            like_count: like_count.filter(|_| self.include_votes),
+                            dislike_count: dislike_count.filter(|_| self.include_votes),
+                            ..Default::default()
+                        };
+                        let sentences = batch.stats.sentences;
+                        // the text of the post without its quotes
+                        let text = if !self.extract_pairs {
+                            let (text, content) = self.html.to_text(msg, self.markup);
+                            if text.trim().is_empty() {
+                                batch.stats.non_text_posts.count(content);
+                            }
+                            self.process_text(&text, &source, batch);
+                            if self.collect_reply_graph {
+                                let quote = match content.quotes {
+                                    0 => None,
+                                    _ => split_quote(msg, 1, self.markup).quotes.into_iter().next(),
+                                };
+                                self.push_quote(&source, &text, quote.as_deref(), batch);
+                            }
+                            text
+                        } else {
+                            let post = split_quote(msg, self.quote_depth, self.markup);
+                            if post.reply.trim().is_empty() {
+                                batch.stats.non_text_posts.count(post.content);
+                            }
+                            // each post of the chain pairs with the one it quotes
+                            let mut reply = post.reply.as_str();
+                            for quote in &post.quotes {
+                                if let Some(pair) = self.pair(quote, reply) {
+                                    batch.pairs.push(pair);
+                                }
+                                reply = quote;
+                            }
+                            self.process_text(&post.reply, &source, batch);
+                            if self.collect_reply_graph {
+                                let quote = post.quotes.first().map(String::as_str);
+                                self.push_quote(&source, &post.reply, quote, batch);
+                            }
+                            post.reply
+                        };
+                        if self.collect_reply_trees {
+                            self.push_tree_post(&source, item, text, batch);
+                        }
+                        if self.collect_post_sentences {
+                            let sentences = batch.stats.sentences - sentences;
+                            *batch.stats.sentences_per_post.entry(sentences).or_default() += 1;
+                        }
+                    }
+                }
+            }
+            if let Some(seen_poll_texts) = &self.seen_poll_texts {
+                let thread_id = value_as_i64(&response["thread_id"]).map(|id| id as u64);
+                for (kind, text) in poll_texts(response) {
+                    // the poll comes with every page of the thread
+                    let key = (thread_id.unwrap_or(0), xxh64(text.as_bytes(), 0));
+                    if seen_poll_texts.insert(key, ()).is_some() {
+                        continue;
+                    }
+                    let source = SentenceRecord {
+                        thread_id,
+                        kind: Some(kind),
+                        ..Default::default()
+                    };
+                    self.process_text(text, &source, batch);
+                }
+            }
+        } else {
+            let code = api_error_code(&obj);
+            let message = obj["error_message"].as_str().unwrap_or_default();
+            batch
+                .stats
+                .api_errors
+                .entry(code.clone())
+                .or_default()
+                .count(message);
+            if let (true, Some(thread_id)) = (self.collect_api_errors, line_thread_id) {
+                batch.api_errors.push((code, thread_id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        // only a profanity list file can fail to load
+        Extractor::new(&ExtractorConfig::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::LengthUnit;
+    use filters::Length;
+
+    #[test]
+    fn valid_paras() {
+        assert!(is_valid_para("我哋今日去咗飲茶"));
+        assert!(is_valid_para("OK啦我聽日再嚟過"));
+        assert!(!is_valid_para("此回覆已被刪除"));
+        assert!(!is_valid_para("睇下 https://lih.kg/1"));
+        assert!(!is_valid_para("hello world"));
+    }
+
+    fn rejects(para: &str, reason: RejectReason) {
+        assert!(!is_valid_para(para), "{:?}", para);
+        assert_eq!(validate_para(para), Err(reason), "{:?}", para);
+    }
+
+    #[test]
+    fn rejects_empty() {
+        rejects("", RejectReason::Empty);
+    }
+
+    #[test]
+    fn rejects_deleted() {
+        rejects("此回覆已被刪除", RejectReason::Deleted);
+        rejects("此回覆已被刪除。", RejectReason::Deleted);
+        rejects(" 此帖已被刪除\u{3000}", RejectReason::Deleted);
+        rejects("此回覆已被管理員刪除", RejectReason::Deleted);
+        let path = std::env::temp_dir().join(format!("lihkg-deleted-{}.txt", std::process::id()));
+        std::fs::write(&path, "# moderation\n\n[已刪除]\n").unwrap();
+        let extractor = Extractor::new(&ExtractorConfig {
+            deleted_patterns: Some(path.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            extractor.check_para("呢個post[已刪除]"),
+            Err(RejectReason::Deleted)
+        );
+        // the file replaces the built-in patterns
+        assert_eq!(extractor.check_para("佢話此回覆已被刪除喎"), Ok(()));
+        let mut batch = Batch::default();
+        let line = serde_json::json!({"success": 1, "response": {"item_data": [
+            {"msg": "[已刪除]<br />[已刪除]"},
+            {"msg": "我哋今日去咗飲茶"},
+        ]}});
+        extractor
+            .process_line(&format!("1\t1\t{}", line), &mut batch)
+            .unwrap();
+        assert_eq!(batch.stats.deleted_posts, 1);
+        assert_eq!(batch.stats.rejected[&RejectReason::Deleted], 2);
+    }
+
+    #[test]
+    fn rejects_shared() {
+        rejects("分享自 LIHKG 討論區", RejectReason::Shared);
+    }
+
+    #[test]
+    fn rejects_4_chars() {
+        rejects("我哋飲茶", RejectReason::Length);
+    }
+
+    #[test]
+    fn short_cjk_labels_are_allowed() {
+        let mut config = ExtractorConfig::default();
+        config.para.allow_short_cjk = true;
+        let extractor = Extractor::new(&config).unwrap();
+        let html = "正評<br />好<br />OK<br />正 評<br />我哋今日去咗飲茶";
+        assert_eq!(
+            extractor.extract_paragraphs(html),
+            ["正評", "好", "我哋今日去咗飲茶"]
+        );
+        assert_eq!(extractor.check_para("正評！"), Err(RejectReason::Length));
+    }
+
+    #[test]
+    fn rejects_21_chars() {
+        rejects(
+            "我哋今日去咗飲茶然後再去行街睇戲食飯返屋企",
+            RejectReason::Length,
+        );
+    }
+
+    #[test]
+    fn accepts_5_chars() {
+        assert!(is_valid_para("我哋去飲茶"));
+    }
+
+    #[test]
+    fn accepts_20_chars() {
+        assert!(is_valid_para("我哋今日去咗飲茶然後再去行街睇戲食飯返屋"));
+    }
+
+    #[test]
+    fn rejects_http() {
+        rejects("睇下http://lih.kg", RejectReason::Url);
+    }
+
+    #[test]
+    fn rejects_https() {
+        rejects("睇下https://lih.kg", RejectReason::Url);
+    }
+
+    #[test]
+    fn rejects_english_only() {
+        rejects("hello world", RejectReason::EnglishOnly);
+    }
+
+    #[test]
+    fn rejects_date() {
+        rejects("2023.10.15", RejectReason::Date);
+    }
+
+    #[test]
+    fn rejects_time() {
+        rejects("12:34:56", RejectReason::Time);
+    }
+
+    #[test]
+    fn rejects_repeated_chars() {
+        rejects("哈哈哈哈哈", RejectReason::RepeatedChars);
+    }
+
+    #[test]
+    fn irrelevant_chars_are_dropped() {
+        assert_eq!(filter_irrelevant_chars("笑死我😂 OK啦！"), "笑死我OK啦！");
+        assert_eq!(filter_irrelevant_chars("\u{200b}早晨\t"), "早晨");
+        assert_eq!(filter_irrelevant_chars("ａｂｃ abc"), "abc");
+    }
+
+    #[test]
+    fn html_to_text_passes_plain_text() {
+        assert_eq!(convert_html_to_text("我哋今日去咗飲茶"), "我哋今日去咗飲茶");
+        assert_eq!(convert_html_to_text("第一行\n第二行"), "第一行\n第二行");
+    }
+
+    #[test]
+    fn html_to_text_drops_quotes() {
+        assert_eq!(
+            convert_html_to_text("<blockquote>引用</blockquote>回覆"),
+            "回覆"
+        );
+    }
+
+    #[test]
+    fn html_to_text_drops_nested_quotes() {
+        assert_eq!(
+            convert_html_to_text("<blockquote><blockquote>一</blockquote>二</blockquote>三"),
+            "三"
+        );
+    }
+
+    #[test]
+    fn quotes_are_split_from_the_reply() {
+        let html = "<blockquote><blockquote>一</blockquote>二<br>三</blockquote>四<blockquote>五</blockquote>";
+        assert_eq!(
+            split_quote(html, 1, Markup::default()),
+            QuotedReply {
+                quotes: vec!["二\n三".to_string()],
+                reply: "四".to_string(),
+                content: PostContent {
+                    images: 0,
+                    quotes: 2
+                },
+            }
+        );
+        assert_eq!(
+            split_quote(html, 3, Markup::default()).quotes,
+            ["二\n三", "一"]
+        );
+        assert!(split_quote("回覆", 1, Markup::default()).quotes.is_empty());
+    }
+
+    #[test]
+    fn html_to_text_drops_quote_wrappers() {
+        assert_eq!(
+            convert_html_to_text(r#"<div class="quote">引用</div>回覆"#),
+            "回覆"
+        );
+        assert_eq!(
+            convert_html_to_text(
+                r#"<div class="post-quote"><div class="quote">一</div>二</div>三"#
+            ),
+            "三"
+        );
+        assert_eq!(
+            convert_html_to_text(r#"<div class="quoted">保留</div>"#),
+            "保留"
+        );
+    }
+
+    #[test]
+    fn triple_nested_quotes() {
+        let html = include_str!("../tests/fixtures/triple_quote.html");
+        assert_eq!(
+            convert_html_to_text(html),
+            "我上次排咗半個鐘\n建議你兩點後先去，嗰陣冇乜人\n\n唔知係咪真"
+        );
+        let response =
+            serde_json::json!({"success": 1, "response": {"item_data": [{"msg": html}]}});
+        let mut batch = Batch::default();
+        Extractor::default()
+            .process_line(&format!("1\t1\t{}", response), &mut batch)
+            .unwrap();
+        let texts: Vec<&str> = batch.records.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "我上次排咗半個鐘",
+                "建議你兩點後先去，嗰陣冇乜人",
+                "唔知係咪真"
+            ]
+        );
+        let post = split_quote(html, 1, Markup::default());
+        assert_eq!(post.quotes, ["排幾耐呀？我驚食晏唔夠鐘"]);
+        let post = split_quote(html, 5, Markup::default());
+        assert_eq!(
+            post.quotes,
+            [
+                "排幾耐呀？我驚食晏唔夠鐘",
+                "試過，啲汁好濃，不過排好耐隊",
+                "有冇人試過旺角新開嗰間車仔麵？\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn html_to_text_breaks_lines_at_br() {
+        assert_eq!(convert_html_to_text("回覆<br /><b>粗體</b>"), "回覆\n粗體");
+        assert_eq!(convert_html_to_text("一<br>二<br><br>三"), "一\n二\n\n三");
+    }
+
+    #[test]
+    fn html_to_text_breaks_lines_between_paragraphs() {
+        assert_eq!(convert_html_to_text("<p>一</p><p>二</p>"), "一\n二");
+        assert_eq!(convert_html_to_text("前<p>中</p>後"), "前\n中\n後");
+        assert_eq!(convert_html_to_text("<p><b>一</b></p>"), "一");
+    }
+
+    #[test]
+    fn html_to_text_drops_images() {
+        assert_eq!(
+            convert_html_to_text(
+                r#"好笑<img src="/assets/faces/normal/smile.gif" class="hkgmoji" />"#
+            ),
+            "好笑"
+        );
+    }
+
+    #[test]
+    fn html_to_text_keeps_link_text() {
+        assert_eq!(
+            convert_html_to_text(
+                r#"睇<a href="https://lih.kg/3312345" target="_blank">呢個post</a>"#
+            ),
+            "睇呢個post"
+        );
+    }
+
+    #[test]
+    fn html_to_text_decodes_entities() {
+        assert_eq!(convert_html_to_text("a &amp; b"), "a & b");
+        assert_eq!(convert_html_to_text("1 &lt; 2 &gt; 0"), "1 < 2 > 0");
+    }
+
+    #[test]
+    fn struck_through_and_spoiler_text() {
+        let html = r#"<p>老細話今年有花紅<del>係得個講字</del></p><p><span class="spoiler">主角死咗</span>估唔到啩</p>"#;
+        let convert = |strikethrough, spoiler| {
+            let markup = Markup {
+                strikethrough,
+                spoiler,
+            };
+            convert_post(html, markup).0
+        };
+        assert_eq!(
+            convert(StrikethroughMode::Keep, SpoilerMode::Keep),
+            "老細話今年有花紅係得個講字\n主角死咗估唔到啩"
+        );
+        assert_eq!(
+            convert(StrikethroughMode::Drop, SpoilerMode::Drop),
+            "老細話今年有花紅\n估唔到啩"
+        );
+        assert_eq!(
+            convert(StrikethroughMode::Keep, SpoilerMode::Token),
+            "老細話今年有花紅係得個講字\n<spoiler>主角死咗</spoiler>估唔到啩"
+        );
+        assert_eq!(
+            convert_post(
+                "<s>成句</s><strike>刪晒</strike>",
+                Markup {
+                    strikethrough: StrikethroughMode::Drop,
+                    ..Default::default()
+                }
+            )
+            .0,
+            ""
+        );
+    }
+
+    #[test]
+    fn html_to_text_of_a_lihkg_post() {
+        let html = concat!(
+            r#"<blockquote><blockquote>呢間茶記好食過隔離嗰間</blockquote>真係咁好食？</blockquote>"#,
+            r#"我上個禮拜先去過，菠蘿油一般啦<br />"#,
+            r#"<img src="/assets/faces/normal/smile.gif" class="hkgmoji" /> 奶茶就真係唔錯<br />"#,
+            r#"<br />"#,
+            r#"<a href="https://lih.kg/3312345" target="_blank">https://lih.kg/3312345</a><br />"#,
+            r#"<strong>上次有巴打話佢哋改咗餐牌</strong>，唔知係咪真"#,
+        );
+        assert_eq!(
+            convert_html_to_text(html),
+            "我上個禮拜先去過，菠蘿油一般啦\n 奶茶就真係唔錯\n\nhttps://lih.kg/3312345\n上次有巴打話佢哋改咗餐牌，唔知係咪真"
+        );
+    }
+
+    #[test]
+    fn repeats_are_collapsed() {
+        assert_eq!(collapse_repeats("哈哈哈哈哈好好笑", 3), "哈哈哈好好笑");
+        assert_eq!(collapse_repeats("好好好好", 1), "好");
+    }
+
+    #[test]
+    fn elongation_is_normalized() {
+        let chars = config::DEFAULT_ELONGATION_CHARS;
+        // at the end, in the middle and of chars outside the set
+        assert_eq!(normalize_elongation("好正呀呀呀呀", chars), "好正呀呀");
+        assert_eq!(
+            normalize_elongation("哈哈哈哈哈好好笑", chars),
+            "哈哈好好笑"
+        );
+        assert_eq!(
+            normalize_elongation("好好好好好正呀呀呀呀", chars),
+            "好好好好好正呀呀"
+        );
+        assert_eq!(normalize_elongation("笑死wwwwww", chars), "笑死ww");
+        assert_eq!(normalize_elongation("嘻嘻", "呀"), "嘻嘻");
+
+        let para = "我哋今日去咗飲茶呀呀呀呀呀呀呀呀呀呀呀呀呀";
+        let line = |para: &str| {
+            let response =
+                serde_json::json!({"success": 1, "response": {"item_data": [{"msg": para}]}});
+            format!("1\t1\t{}", response)
+        };
+        let mut batch = Batch::default();
+        Extractor::default()
+            .process_line(&line(para), &mut batch)
+            .unwrap();
+        assert!(batch.records.is_empty());
+        let extractor = Extractor::new(&ExtractorConfig {
+            normalize_elongation: true,
+            ..Default::default()
+        })
+        .unwrap();
+        extractor.process_line(&line(para), &mut batch).unwrap();
+        assert_eq!(batch.records[0].text, "我哋今日去咗飲茶呀呀");
+    }
+
+    #[test]
+    fn sentence_end_is_required() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            require_sentence_end: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let html = "我哋今日去咗飲茶<br />我哋今日去咗飲茶呀」<br />我哋今日好開心!<br />我哋今日好開心,<br />呢度啲點心好好食。<br />呢間茶記";
+        let response =
+            serde_json::json!({"success": 1, "response": {"item_data": [{"msg": html}]}});
+        let mut batch = Batch::default();
+        extractor
+            .process_line(&format!("1\t1\t{}", response), &mut batch)
+            .unwrap();
+        let texts: Vec<&str> = batch.records.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "我哋今日去咗飲茶呀」",
+                "我哋今日好開心!",
+                "呢度啲點心好好食。"
+            ]
+        );
+        // the last one is too short anyway
+        assert_eq!(batch.stats.rejected[&RejectReason::SentenceEnd], 2);
+    }
+
+    #[test]
+    fn english_paragraphs_are_set_aside() {
+        let html =
+            "I love Hong Kong milk tea<br />WHY IS IT SO HOT<br />ok la<br />我哋今日去咗飲茶";
+        let response =
+            serde_json::json!({"success": 1, "response": {"item_data": [{"msg": html}]}});
+        let line = format!("1\t1\t{}", response);
+        let mut plain = Batch::default();
+        Extractor::default()
+            .process_line(&line, &mut plain)
+            .unwrap();
+        let mut batch = Batch::default();
+        Extractor::new(&ExtractorConfig {
+            collect_english: true,
+            ..Default::default()
+        })
+        .unwrap()
+        .process_line(&line, &mut batch)
+        .unwrap();
+        assert_eq!(batch.english, ["I love Hong Kong milk tea"]);
+        assert_eq!(
+            (
+                batch.stats.english_paragraphs,
+                batch.stats.english_sentences
+            ),
+            (3, 1)
+        );
+        assert_eq!(batch.records, plain.records);
+    }
+
+    #[test]
+    fn urls_become_tokens() {
+        assert_eq!(
+            replace_urls("睇下 https://lih.kg/3300001 好正"),
+            "睇下 <url> 好正"
+        );
+        assert_eq!(replace_urls("http://example.com，正"), "<url>，正");
+    }
+
+    #[test]
+    fn emoji_can_be_kept() {
+        assert_eq!(
+            filter_irrelevant_chars_keeping_emoji("笑死我😂 OK啦！"),
+            "笑死我😂OK啦！"
+        );
+    }
+
+    #[test]
+    fn urls_point_at_the_page_of_the_post() {
+        assert_eq!(
+            thread_url(3300001, 1),
+            "https://lihkg.com/thread/3300001/page/1"
+        );
+        assert_eq!(
+            thread_url(3300001, 25),
+            "https://lihkg.com/thread/3300001/page/1"
+        );
+        assert_eq!(
+            thread_url(3300001, 26),
+            "https://lihkg.com/thread/3300001/page/2"
+        );
+    }
+
+    #[test]
+    fn banned_users_posts_are_skipped_and_tallied() {
+        let path = std::env::temp_dir().join(format!("lihkg-banned-{}.txt", std::process::id()));
+        std::fs::write(&path, "# bots\n999\n廣告王\n").unwrap();
+        let extractor = Extractor::new(&ExtractorConfig {
+            ban_users: Some(path.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut batch = Batch::default();
+        for user in [
+            r#"{"user_id": "999", "nickname": "六合彩"}"#,
+            r#"{"user_id": "5", "nickname": "廣告王"}"#,
+            r#"{"user_id": "999", "nickname": "六合彩"}"#,
+            r#"{"user_id": "6", "nickname": "巴打"}"#,
+        ] {
+            let line = format!(
+                "1\t1\t{{\"success\":1,\"response\":{{\"item_data\":[{{\"msg\":\"我哋今日去咗飲茶\",\"user\":{}}}]}}}}",
+                user
+            );
+            extractor.process_line(&line, &mut batch).unwrap();
+        }
+        assert_eq!(batch.records.len(), 1);
+        assert_eq!(
+            batch.stats.banned_posts,
+            std::collections::BTreeMap::from([("999".to_string(), 2), ("廣告王".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn raw_paragraphs_are_kept_beside_the_text() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            keep_raw: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut batch = Batch::default();
+        let line = "1\t1\t{\"success\":1,\"response\":{\"item_data\":[{\"msg\":\" 我哋今日去咗飲茶好開心呀 😂 <br />\"}]}}";
+        extractor.process_line(line, &mut batch).unwrap();
+        assert_eq!(batch.records[0].text, "我哋今日去咗飲茶好開心呀");
+        assert_eq!(
+            batch.records[0].raw.as_deref(),
+            Some("我哋今日去咗飲茶好開心呀 😂")
+        );
+        let json = serde_json::to_value(&batch.records[0]).unwrap();
+        assert_eq!(json["raw"], "我哋今日去咗飲茶好開心呀 😂");
+
+        let mut batch = Batch::default();
+        Extractor::new(&ExtractorConfig::default())
+            .unwrap()
+            .process_line(line, &mut batch)
+            .unwrap();
+        assert_eq!(batch.records[0].raw, None);
+    }
+
+    #[test]
+    fn votes_filter_and_annotate_posts() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            min_likes: Some(3),
+            include_votes: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut batch = Batch::default();
+        for votes in [
+            r#""like_count": "5", "dislike_count": "1""#,
+            r#""like_count": 2"#,
+            r#""dislike_count": 9"#,
+        ] {
+            let line = format!(
+                "1\t1\t{{\"success\":1,\"response\":{{\"item_data\":[{{\"msg\":\"我哋今日去咗飲茶\",{}}}]}}}}",
+                votes
+            );
+            extractor.process_line(&line, &mut batch).unwrap();
+        }
+        assert_eq!(batch.stats.posts_too_few_likes, 2);
+        assert_eq!(
+            serde_json::to_string(&batch.records[0]).unwrap(),
+            r#"{"text":"我哋今日去咗飲茶","post_score":4,"like_count":5,"dislike_count":1}"#
+        );
+    }
+
+    #[test]
+    fn api_errors_are_tallied_per_code() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            collect_api_errors: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut batch = Batch::default();
+        for line in [
+            r#"3300010	1	{"success":0,"error_code":100,"error_message":"標題不存在"}"#,
+            r#"3300011	1	{"success":0,"error_code":"100","error_message":"標題不存在"}"#,
+            r#"3300012	1	{"success":0,"error_code":998,"error_message":"請求過於頻繁"}"#,
+            r#"x	1	{"success":0}"#,
+        ] {
+            extractor.process_line(line, &mut batch).unwrap();
+        }
+        let errors = &batch.stats.api_errors;
+        assert_eq!(
+            (errors["100"].count, errors["100"].message.as_str()),
+            (2, "標題不存在")
+        );
+        assert_eq!(errors["998"].count, 1);
+        assert_eq!(errors["none"].count, 1);
+        assert_eq!(
+            batch.api_errors,
+            [
+                ("100".to_string(), 3300010),
+                ("100".to_string(), 3300011),
+                ("998".to_string(), 3300012)
+            ]
+        );
+    }
+
+    #[test]
+    fn posts_without_text_are_told_apart() {
+        let msgs = [
+            r#"<img src="/assets/faces/normal/smile.gif" class="hkgmoji" />"#,
+            r#"<blockquote>引用<img src="a.jpg" /></blockquote>"#,
+            r#"<blockquote>引用</blockquote><br /><img src="a.jpg" />"#,
+            " <br /> ",
+            "我哋今日去咗飲茶<img src=\"a.jpg\" />",
+        ];
+        let item_data: Vec<Value> = msgs
+            .iter()
+            .map(|msg| serde_json::json!({ "msg": msg }))
+            .collect();
+        let response = serde_json::json!({"success": 1, "response": {"item_data": item_data}});
+        let line = format!("1\t1\t{}", response);
+        for extract_pairs in [false, true] {
+            let extractor = Extractor::new(&ExtractorConfig {
+                extract_pairs,
+                ..Default::default()
+            })
+            .unwrap();
+            let mut batch = Batch::default();
+            extractor.process_line(&line, &mut batch).unwrap();
+            let non_text = &batch.stats.non_text_posts;
+            assert_eq!(
+                (non_text.image_only, non_text.quote_only, non_text.empty),
+                (2, 1, 1)
+            );
+        }
+    }
+
+    #[test]
+    fn latin_runs_count_as_words_or_are_cut() {
+        let mut config = ExtractorConfig::default();
+        config.para.max_len = 30;
+        let para = "我哋今日返工check吓個email先知道";
+        let extractor = Extractor::new(&config).unwrap();
+        assert_eq!(extractor.check_para(para), Err(RejectReason::CjkRatio));
+        config.para.ratio_latin_as_word = true;
+        let extractor = Extractor::new(&config).unwrap();
+        assert_eq!(extractor.check_para(para), Ok(()));
+        // a pasted hash is still one word but no borrowing
+        config.para.max_latin_run = Some(8);
+        let extractor = Extractor::new(&config).unwrap();
+        let hashed = "我哋今日返工check吓個3f9a2c7b1e先知道";
+        assert_eq!(extractor.check_para(hashed), Err(RejectReason::LatinRun));
+        config.para.truncate_latin_run = true;
+        let extractor = Extractor::new(&config).unwrap();
+        let cut = extractor.normalize_para(hashed);
+        assert_eq!(cut, "我哋今日返工check吓個3f9a2c7b先知道");
+        assert_eq!(extractor.check_para(&cut), Ok(()));
+    }
+
+    #[test]
+    fn graphemes_count_emoji_sequences_once() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+        assert_eq!(LengthUnit::Chars.count(family), 7);
+        assert_eq!(LengthUnit::Graphemes.count(family), 1);
+        let mut config = ExtractorConfig::default();
+        // 25 chars but 13 graphemes, past --max-len only in chars
+        let trip = format!("我哋一家人去旅行好開心{}{}", family, family);
+        // the skin tone makes the CJK share 8 of 10 chars or 8 of 9 graphemes
+        let thumbs = "呢間餐廳真係好正\u{1F44D}\u{1F3FB}";
+        let extractor = Extractor::new(&config).unwrap();
+        assert_eq!(extractor.check_para(&trip), Err(RejectReason::Length));
+        assert_eq!(extractor.check_para(thumbs), Err(RejectReason::CjkRatio));
+        config.para.length_unit = LengthUnit::Graphemes;
+        let extractor = Extractor::new(&config).unwrap();
+        assert_eq!(extractor.check_para(&trip), Ok(()));
+        assert_eq!(extractor.check_para(thumbs), Ok(()));
+        // thumbs of five skin tones share their first char as chars, not as
+        // graphemes
+        let toned = "好正\u{1F44D}\u{1F3FB}\u{1F44D}\u{1F3FC}\u{1F44D}\u{1F3FD}\u{1F44D}\u{1F3FE}\u{1F44D}\u{1F3FF}";
+        let base_rules = |unit| {
+            FilterChain::new().with_base_rules(Length {
+                min: 1,
+                max: 20,
+                unit,
+                allow_short_cjk: false,
+            })
+        };
+        assert_eq!(
+            base_rules(LengthUnit::Chars).check(toned),
+            Err(RejectReason::RepeatedChars)
+        );
+        assert_eq!(base_rules(LengthUnit::Graphemes).check(toned), Ok(()));
+    }
+
+    #[test]
+    fn ascii_art_is_rejected_as_symbols() {
+        let mut config = ExtractorConfig::default();
+        config.para.min_cjk_ratio = 0.3;
+        config.para.max_symbol_fraction = Some(0.3);
+        let html = include_str!("../tests/fixtures/ascii_art.html");
+        let response =
+            serde_json::json!({"success": 1, "response": {"item_data": [{"msg": html}]}});
+        let mut batch = Batch::default();
+        Extractor::new(&config)
+            .unwrap()
+            .process_line(&format!("1\t1\t{}", response), &mut batch)
+            .unwrap();
+        let texts: Vec<&str> = batch.records.iter().map(|r| r.text.as_str()).collect();
+        // the framed line is mostly words and survives as its words
+        assert_eq!(texts, ["巴打們頂呢個post", "呢個post真係好正"]);
+        assert_eq!(batch.stats.rejected[&RejectReason::Symbols], 4);
+    }
+
+    #[test]
+    fn news_posts_are_dropped_or_tagged() {
+        let mut config = ExtractorConfig::default();
+        config.para.max_len = 100;
+        let response = |html: &str| {
+            let response =
+                serde_json::json!({"success": 1, "response": {"item_data": [{"msg": html}]}});
+            format!("1\t1\t{}", response)
+        };
+        let article = response(include_str!("../tests/fixtures/news_article.html"));
+        let rant = response(include_str!("../tests/fixtures/long_rant.html"));
+        for mode in [NewsMode::Drop, NewsMode::Tag] {
+            config.news_posts = mode;
+            let extractor = Extractor::new(&config).unwrap();
+            let mut batch = Batch::default();
+            extractor.process_line(&article, &mut batch).unwrap();
+            extractor.process_line(&rant, &mut batch).unwrap();
+            assert_eq!(batch.stats.news_posts, 1);
+            let news: Vec<bool> = batch.records.iter().map(|r| r.news_like).collect();
+            match mode {
+                NewsMode::Drop => {
+                    assert_eq!(news, [false; 5]);
+                    assert_eq!(batch.stats.rejected[&RejectReason::NewsLike], 5);
+                }
+                _ => assert_eq!(news, [[true; 5], [false; 5]].concat()),
+            }
+        }
+    }
+
+    #[test]
+    fn user_ids_are_pseudonymized() {
+        let config = ExtractorConfig {
+            anonymize_users: true,
+            anonymize_key: Some("00ff".to_string()),
+            ..Default::default()
+        };
+        let extractor = Extractor::new(&config).unwrap();
+        let mut batch = Batch::default();
+        for (msg, user) in [
+            ("我哋今日去咗飲茶", r#""user":{"user_id":"91234"}"#),
+            ("呢間茶記好食過隔離嗰間", r#""user_id":91234"#),
+        ] {
+            let line = format!(
+                "1\t1\t{{\"success\":1,\"response\":{{\"item_data\":[{{\"msg\":\"{}\",{}}}]}}}}",
+                msg, user
+            );
+            extractor.process_line(&line, &mut batch).unwrap();
+        }
+        let pseudonym = batch.records[0].user_id.clone().unwrap();
+        assert_eq!(pseudonym.len(), 16);
+        assert_eq!(batch.records[1].user_id.as_ref(), Some(&pseudonym));
+        assert!(!serde_json::to_string(&batch.records[0])
+            .unwrap()
+            .contains("91234"));
+        // the key is never written out, e.g. to the --stats-file settings
+        assert!(!serde_json::to_string(&config).unwrap().contains("00ff"));
+        let without_key = ExtractorConfig {
+            anonymize_key: None,
+            ..config
+        };
+        assert!(Extractor::new(&without_key).is_err());
+    }
+
+    #[test]
+    fn small_threads_are_skipped() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            min_replies: Some(3),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut batch = Batch::default();
+        for replies in [
+            r#""total_replies": 2"#,
+            r#""no_of_reply": "2""#,
+            r#""total_replies": "3""#,
+            r#""no_of_reply": 10"#,
+            r#""thread_id": "1""#,
+        ] {
+            let line = format!(
+                "1\t1\t{{\"success\":1,\"response\":{{{},\"item_data\":[{{\"msg\":\"我哋今日去咗飲茶\"}}]}}}}",
+                replies
+            );
+            extractor.process_line(&line, &mut batch).unwrap();
+        }
+        assert_eq!(batch.stats.thread_too_small, 2);
+        assert_eq!(batch.stats.sentences, 3);
+    }
+
+    #[test]
+    fn poll_shapes() {
+        let array = serde_json::json!({"vote_info": {
+            "question": "呢套戲好唔好睇呀",
+            "options": [{"option_id": 1, "text": "好睇"}, {"option_id": 2, "text": "唔好睇"}],
+        }});
+        let map = serde_json::json!({"display_vote": {
+            "title": "呢套戲好唔好睇呀",
+            "vote_options": {"1": "好睇", "2": {"name": "唔好睇"}},
+        }});
+        let expected = vec![
+            (RecordKind::PollQuestion, "呢套戲好唔好睇呀"),
+            (RecordKind::PollOption, "好睇"),
+            (RecordKind::PollOption, "唔好睇"),
+        ];
+        assert_eq!(poll_texts(&array), expected);
+        assert_eq!(poll_texts(&map), expected);
+        assert!(poll_texts(&serde_json::json!({"display_vote": false})).is_empty());
+    }
+
+    #[test]
+    fn polls_are_extracted_once_per_thread() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            extract_polls: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut batch = Batch::default();
+        for (thread_id, page) in [(1, 1), (1, 2), (2, 1)] {
+            let response = serde_json::json!({"success": 1, "response": {
+                "thread_id": thread_id.to_string(),
+                "vote_info": {"question": "呢套戲好唔好睇呀", "options": ["好睇到喊咗出嚟", "唔好睇浪費錢"]},
+                "item_data": [],
+            }});
+            let line = format!("{}\t{}\t{}", thread_id, page, response);
+            extractor.process_line(&line, &mut batch).unwrap();
+        }
+        let texts: Vec<(Option<u64>, &str)> = batch
+            .records
+            .iter()
+            .map(|r| (r.thread_id, r.text.as_str()))
+            .collect();
+        assert_eq!(texts.len(), 6);
+        assert_eq!(
+            texts[..3],
+            [
+                (Some(1), "呢套戲好唔好睇呀"),
+                (Some(1), "好睇到喊咗出嚟"),
+                (Some(1), "唔好睇浪費錢")
+            ]
+        );
+        assert_eq!(batch.records[1].kind, Some(RecordKind::PollOption));
+    }
+
+    #[test]
+    fn pairs_need_both_sides_valid() {
+        let extractor = Extractor::new(&ExtractorConfig {
+            extract_pairs: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut batch = Batch::default();
+        for msg in [
+            "<blockquote>唔記得咗<br>呢間茶記好食過隔離嗰間</blockquote>真係咁好食呀，我都想去試下<br>我聽日去試下先",
+            "<blockquote>呢間茶記好食過隔離嗰間</blockquote>係咩",
+            "<blockquote>好</blockquote>真係咁好食呀，我都想去試下",
+            "冇引用嘅回覆係唔會有對",
+        ] {
+            let response = serde_json::json!({"success": 1, "response": {"item_data": [{"msg": msg}]}});
+            extractor
+                .process_line(&format!("1\t1\t{}", response), &mut batch)
+                .unwrap();
+        }
+        assert_eq!(
+            batch.pairs,
+            [(
+                "呢間茶記好食過隔離嗰間".to_string(),
+                "真係咁好食呀，我都想去試下".to_string()
+            )]
+        );
+        // the quotes are not sentences of the replies
+        assert!(batch.records.iter().all(|r| !r.text.contains("茶記")));
+    }
+
+    #[test]
+    fn nicknames_skip_system_and_deleted_accounts() {
+        let item = |user: Value| serde_json::json!({ "user": user });
+        let nickname_of = |user| nickname(&item(user)).map(str::to_string);
+        assert_eq!(
+            nickname_of(serde_json::json!({"user_id": "123", "nickname": " 潮爆巴打 "})),
+            Some("潮爆巴打".to_string())
+        );
+        assert_eq!(
+            nickname_of(serde_json::json!({"user_id": "1", "nickname": "LIHKG"})),
+            None
+        );
+        assert_eq!(
+            nickname_of(serde_json::json!({"user_id": "0", "nickname": "某人"})),
+            None
+        );
+        assert_eq!(nickname_of(serde_json::json!({"nickname": ""})), None);
+        assert_eq!(
+            nickname(&serde_json::json!({"user_nickname": "舊API"})),
+            Some("舊API")
+        );
+    }
+}
@@ -1,182 +1,2855 @@
-use html5ever::tree_builder::TreeSink;
-use lazy_static::lazy_static;
-use rayon::prelude::*;
-use regex::Regex;
-use scraper::{Html, Selector};
-use serde_json::Value;
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use tar::Archive;
-use xz2::read::XzDecoder;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use lihkg::anonymize::ANON_KEY_ENV;
+use lihkg::arrow_output::ArrowOutput;
+use lihkg::bert::BertScorer;
+use lihkg::bloom::{bloom_params, optimal_hashes, BloomFilter};
+use lihkg::config::{
+    ExtractorConfig, HtmlParserKind, LengthUnit, NewsMode, OutputFormat, OutputMode, Profile,
+    RareCharsMode, Settings, SpoilerMode, StrikethroughMode, DEFAULT_BERT_BATCH_SIZE,
+    DEFAULT_BLOOM_FP_RATE, DEFAULT_BLOOM_ITEMS, DEFAULT_CORPUS_STATS, DEFAULT_DOC_SEPARATOR,
+    DEFAULT_ELONGATION_CHARS, DEFAULT_ENGLISH_MAX_WORDS, DEFAULT_ENGLISH_MIN_WORDS, DEFAULT_INPUT,
+    DEFAULT_MAX_LEN, DEFAULT_MAX_OPEN_FILES, DEFAULT_MIN_CJK_RATIO, DEFAULT_MIN_LEN,
+    DEFAULT_NEWS_MAX_CANTONESE_DENSITY, DEFAULT_NEWS_MIN_AVG_LEN, DEFAULT_NEWS_MIN_PARAGRAPHS,
+    DEFAULT_NGRAM_N, DEFAULT_OUTPUT, DEFAULT_PARQUET_ROW_GROUP_SIZE, DEFAULT_QUOTE_DEPTH,
+    DEFAULT_RARE_CHAR_TOKEN, DEFAULT_RARE_THRESHOLD, DEFAULT_REPLY_GRAPH_WINDOW,
+    DEFAULT_SENTENCE_END_PARTICLES, DEFAULT_SETTLE_SECS, DEFAULT_SPM_OUTPUT,
+    DEFAULT_SPM_VOCAB_SIZE, DEFAULT_WATCH_MANIFEST, DEFAULT_WEIGHT_EXPONENT,
+    DEFAULT_WRITE_BUFFER_MB,
+};
+use lihkg::corpus_stats::{counts_tsv, length_histogram_tsv, CorpusStats, NgramCounts};
+use lihkg::dedup::{Dedup, DedupHash, DedupKey, DedupState};
+use lihkg::filters::RejectReason;
+use lihkg::grouping::{BucketFiles, EntryFiles, ThreadFiles};
+use lihkg::hf::{HfLayout, SplitFractions};
+use lihkg::jyutping::Jyutping;
+use lihkg::manifest::{
+    auto_output_name, iso8601, CorpusMeta, HashingWriter, InputFile, RunManifest,
+};
+use lihkg::merge::{Merge, MergeCounts, MergeDedup};
+use lihkg::metrics::Metrics;
+use lihkg::months::{month_key, months_tsv, MONTHS_TSV};
+use lihkg::parquet_output::ParquetOutput;
+use lihkg::pipeline::{process_archive, verify_archive, EntryInfo, Shard};
+use lihkg::post_process::PostProcessCommand;
+use lihkg::profanity::ProfanityMode;
+use lihkg::rare_chars::RareChars;
+use lihkg::reply_graph::{ReplyEdge, ReplyGraph};
+use lihkg::reply_tree::thread_tree;
+use lihkg::sampling::{Reservoir, WeightedSampler};
+use lihkg::scorer::ExternalScorer;
+use lihkg::sink::{format_line, SentenceSink};
+use lihkg::spm::{BpeTrainer, SpmModel};
+use lihkg::sqlite::SentenceDb;
+use lihkg::stats::{post_sentences_tsv, thread_stats_tsv, DedupStats, EntryStats, Stats};
+use lihkg::substrings::contained_in_longer;
+use lihkg::thread_meta::ThreadTable;
+use lihkg::throttle::{set_io_priority, IoNice, ReadLimit};
+use lihkg::two_pass::{Pass1Collector, Pass1State, Pruner};
+use lihkg::watch::watch;
+use lihkg::{Batch, Extractor, SentenceRecord};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-lazy_static! {
-    static ref CJK_REGEX: Regex = Regex::new(r"\p{Unified_Ideograph}").unwrap();
-    static ref WORD_REGEX: Regex =
-        Regex::new(r"[[:alnum:]]+|\p{Unified_Ideograph}|\p{Punct}+").unwrap();
-    static ref PUNCS: HashSet<char> = {
-        SHARED_PUNCS
-            .union(&ENGLISH_PUNCS)
-            .copied()
-            .collect::<HashSet<char>>()
-            .union(&CHINESE_PUNCS)
-            .copied()
-            .collect()
-    };
-    static ref SHARED_PUNCS: HashSet<char> =
-        HashSet::from(['@', '#', '$', '%', '^', '&', '*', '·', '…', '‥', '—', '～']);
-    static ref ENGLISH_PUNCS: HashSet<char> = {
-        HashSet::from([
-            '~', '`', '!', '(', ')', '-', '_', '{', '}', '[', ']', '|', '\\', ':', ';', '"', '\'',
-            '<', '>', ',', '.', '?', '/',
-        ])
-    };
-    static ref CHINESE_PUNCS: HashSet<char> = {
-        HashSet::from([
-            '！', '：', '；', '“', '”', '‘', '’', '【', '】', '（', '）', '「', '」', '﹁', '﹂',
-            '『', '』', '《', '》', '？', '，', '。', '、', '／', '＋', '〈', '〉', '︿', '﹀',
-            '［', '］', '‧',
-        ])
-    };
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Extract Cantonese sentences from LIHKG dumps",
+    args_conflicts_with_subcommands = true,
+    propagate_version = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+
+    /// Least severe messages logged to stderr
+    #[arg(long, value_enum, default_value_t = LogLevel::Info, global = true)]
+    log_level: LogLevel,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn level(self) -> tracing::Level {
+        match self {
+            LogLevel::Trace => tracing::Level::TRACE,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Error => tracing::Level::ERROR,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract sentences from the inputs, as when no subcommand is given
+    Extract(Box<Args>),
+
+    /// Extract the sentences of the inputs without writing them, reporting
+    /// the corpus statistics of what would be written
+    Stats(StatsArgs),
+
+    /// Write a uniform sample of the extracted sentences, kept in memory by
+    /// reservoir sampling
+    Sample(SampleArgs),
+
+    /// Merge the --dedup-state files of several runs or shards into one
+    MergeDedup(MergeDedupArgs),
+
+    /// Merge the sentence files of several runs or shards into one without
+    /// repeats, with their dedup states and stats files
+    Merge(MergeArgs),
+
+    /// Run the conversion and checks on the html of one post, printing what
+    /// each step made of it
+    DebugMsg(DebugMsgArgs),
+
+    /// Download threads from the LIHKG API into a file of dump lines that
+    /// can be extracted like an archive
+    #[cfg(feature = "fetch")]
+    Fetch(FetchArgs),
+}
+
+#[derive(clap::Args)]
+struct MergeDedupArgs {
+    /// Dedup state files to merge
+    #[arg(required = true)]
+    states: Vec<PathBuf>,
+
+    /// Merged state file
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct MergeArgs {
+    /// Text or jsonl sentence files, read through zstd or xz for a .zst or
+    /// .xz extension. Jsonl lines are compared by their text
+    #[arg(required_unless_present = "input")]
+    inputs: Vec<PathBuf>,
+
+    /// More input files, read after the others
+    #[arg(long, value_name = "FILE")]
+    input: Vec<PathBuf>,
+
+    /// Merged sentence file, zstd compressed for a .zst extension
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Drop repeated sentences by a Bloom filter in fixed memory, which
+    /// drops about --bloom-fp-rate of the sentences never seen. Without it
+    /// the inputs are concatenated
+    #[arg(long)]
+    deduplicate: bool,
+
+    /// Distinct sentences the --deduplicate filter is sized for
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_BLOOM_ITEMS)]
+    bloom_items: u64,
+
+    /// False positive rate the --deduplicate filter is sized for
+    #[arg(long, value_name = "RATE", default_value_t = DEFAULT_BLOOM_FP_RATE)]
+    bloom_fp_rate: f64,
+
+    /// Drop repeated sentences by their exact hashes instead, held in
+    /// memory, and merge the dedup states of the runs
+    #[arg(long, conflicts_with = "deduplicate")]
+    dedup_exact: bool,
+
+    /// Dedup state files of the inputs, merged into --state-output
+    #[arg(
+        long = "state",
+        value_name = "FILE",
+        requires_all = ["state_output", "dedup_exact"]
+    )]
+    states: Vec<PathBuf>,
+
+    /// Dedup state of the merged sentences and the --state files
+    #[arg(long, value_name = "FILE", requires = "dedup_exact")]
+    state_output: Option<PathBuf>,
+
+    /// How sentences are keyed, as the --dedup-hash of the runs
+    #[arg(long, value_enum, default_value_t = DedupHash::Xxhash)]
+    dedup_hash: DedupHash,
+
+    /// Passes of --dedup-exact over the inputs, each holding the hashes of a
+    /// part of the sentences, for inputs with more sentences than fit in
+    /// memory. The output then holds each part in turn
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "dedup_exact"
+    )]
+    partitions: u64,
+
+    /// Write the merged sentences in a random order, shuffled through
+    /// temporary files beside the output so they are not all held at once
+    #[arg(long)]
+    shuffle: bool,
+
+    /// Seed of --shuffle, the same inputs and seed giving the same order
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "shuffle")]
+    seed: u64,
+
+    /// --stats-file reports of the runs, added up into --stats-output
+    #[arg(long = "stats", value_name = "FILE", requires = "stats_output")]
+    stats: Vec<PathBuf>,
+
+    /// Combined stats report
+    #[arg(long, value_name = "FILE")]
+    stats_output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct StatsArgs {
+    /// Input archives or files of dump lines, as for extracting
+    #[arg(default_value = DEFAULT_INPUT, num_args = 1..)]
+    input: Vec<PathBuf>,
+
+    /// TOML file of the extraction settings, as for extracting
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Preset of the extraction settings, overriding the one of --config
+    #[arg(long, value_enum)]
+    profile: Option<Profile>,
+
+    /// Corpus statistics of the sentences as JSON
+    #[arg(short, long, default_value = DEFAULT_CORPUS_STATS)]
+    output: PathBuf,
+
+    /// Also write the counts of the run, as --stats-file
+    #[arg(long, value_name = "FILE")]
+    stats_file: Option<PathBuf>,
+
+    /// Worker threads, all logical CPUs by default
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+}
+
+#[derive(clap::Args)]
+struct SampleArgs {
+    /// Input archives or files of dump lines, as for extracting
+    #[arg(default_value = DEFAULT_INPUT, num_args = 1..)]
+    input: Vec<PathBuf>,
+
+    /// TOML file of the extraction settings, as for extracting
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Preset of the extraction settings, overriding the one of --config
+    #[arg(long, value_enum)]
+    profile: Option<Profile>,
+
+    /// Sampled sentences, one per line
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Sentences in the sample
+    #[arg(short = 'n', long, value_name = "N")]
+    size: usize,
+
+    /// Seed of the sampling, the same inputs and seed giving the same sample
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    seed: u64,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Worker threads, all logical CPUs by default
+    #[arg(long, value_name = "N")]
+    threads: Option<usize>,
+}
+
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+struct DebugMsgSource {
+    /// Html of the post
+    #[arg(long, value_name = "HTML")]
+    html: Option<String>,
+
+    /// Read the html of the post from stdin
+    #[arg(long)]
+    stdin: bool,
+}
+
+#[derive(clap::Args)]
+struct DebugMsgArgs {
+    #[command(flatten)]
+    source: DebugMsgSource,
+
+    /// Print the trace as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// TOML file of the extraction settings, as for extracting
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Preset of the extraction settings, overriding the one of --config
+    #[arg(long, value_enum)]
+    profile: Option<Profile>,
 }
 
-fn filter_irrelevant_chars(text: &str) -> String {
-    text.chars()
-        .filter(|c| CJK_REGEX.is_match(&c.to_string()) || is_punc(*c) || c.is_ascii_alphanumeric())
-        .collect()
+#[cfg(feature = "fetch")]
+#[derive(clap::Args)]
+struct FetchArgs {
+    /// First thread id
+    #[arg(long)]
+    from: u64,
+
+    /// Thread id to stop before
+    #[arg(long)]
+    to: u64,
+
+    /// Output file, appended to
+    #[arg(long)]
+    out: PathBuf,
+
+    /// Completed thread ids, appended to and skipped when rerun
+    #[arg(long, default_value = "fetch-progress.txt")]
+    progress: PathBuf,
+
+    /// Threads downloaded in parallel
+    #[arg(long, default_value_t = 2)]
+    concurrency: usize,
+
+    /// Requests per second across all workers, at most 5
+    #[arg(long, default_value_t = 1.0)]
+    rate: f64,
+
+    /// Retries of a request answered with 429, 5xx or a network error
+    #[arg(long, default_value_t = 5)]
+    max_retries: u32,
+
+    #[arg(long, default_value = lihkg::fetch::DEFAULT_BASE_URL, hide = true)]
+    base_url: String,
+}
+
+#[derive(clap::Args)]
+struct Args {
+    /// TOML file with any of the settings below, flags given on the command
+    /// line take precedence
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Print the effective settings as TOML and exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// Preset of the extraction settings, overridden by --config and the
+    /// flags below
+    #[arg(long, value_enum, default_value_t = Profile::Default)]
+    profile: Profile,
+
+    /// Print the extraction settings of each profile as TOML and exit
+    #[arg(long)]
+    list_profiles: bool,
+
+    /// Input .tar.xz archives of LIHKG csv dumps, or plain files of dump
+    /// lines such as the output of fetch, processed in the order given
+    #[arg(default_value = DEFAULT_INPUT, num_args = 1..)]
+    input: Vec<PathBuf>,
+
+    /// With several inputs, process them at once and write their posts in
+    /// turn, mixing the output without a full shuffle. The order varies
+    /// between runs.
+    #[arg(long, conflicts_with_all = ["per_entry_output", "drop_substrings", "watch"])]
+    interleave: bool,
+
+    /// Output file, one sentence per line
+    #[arg(short, long, default_value = DEFAULT_OUTPUT)]
+    output: PathBuf,
+
+    /// Add to the output files of an earlier run instead of replacing them,
+    /// to build a corpus from several runs
+    #[arg(long, conflicts_with_all = ["output_auto", "run_manifest", "hf_layout"])]
+    append: bool,
+
+    /// Run the whole extraction, deduplication included, without opening
+    /// any output file, then print the statistics report as JSON to stdout.
+    /// --dedup-state is read but not saved.
+    #[arg(long, conflicts_with = "watch")]
+    dry_run: bool,
+
+    /// Megabytes of output buffered before they are written to the output
+    /// file, which takes the sentences of small entries in fewer writes
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_WRITE_BUFFER_MB)]
+    write_buffer_mb: usize,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// What an output line holds: a sentence, the sentences of a paragraph
+    /// of a post or of a whole post. Grouped jsonl lines have a sentences
+    /// array instead of the text. Post writes the sentences of a post one
+    /// per line with a blank line after each post, a jsonl line per post
+    /// like document
+    #[arg(long, value_enum, default_value_t = OutputMode::Sentence)]
+    output_mode: OutputMode,
+
+    /// Joins the sentences of a paragraph or document line, post text
+    /// output joins them with line breaks
+    #[arg(long, value_name = "SEP", default_value = DEFAULT_DOC_SEPARATOR)]
+    doc_separator: String,
+
+    /// Write a JSON line per output line with the thread, post and page URL
+    /// the sentence came from
+    #[arg(long, value_name = "FILE")]
+    index: Option<PathBuf>,
+
+    /// Write the sentences of each thread to their own file in --output-dir,
+    /// named by the thread id; sentences without a thread go to the output
+    #[arg(long, requires = "output_dir")]
+    group_by_thread: bool,
+
+    /// Directory of the per-thread files of --group-by-thread
+    #[arg(long, value_name = "DIR", requires = "group_by_thread")]
+    output_dir: Option<PathBuf>,
+
+    /// Per-thread or per-month files kept open at once, the least recently
+    /// written one is closed to open another
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_MAX_OPEN_FILES,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_open_files: usize,
+
+    /// Write the sentences of each archive entry to a file named after it in
+    /// this directory instead of the output, empty if it has none
+    #[arg(long, value_name = "DIR", conflicts_with = "drop_substrings")]
+    per_entry_output: Option<PathBuf>,
+
+    /// Also write the sentences to a file per month of their post time in
+    /// this directory, named YYYY-MM in Hong Kong time, or unknown without
+    /// one, and their counts per month to months.tsv
+    #[arg(long, value_name = "DIR", conflicts_with = "hf_layout")]
+    by_month: Option<PathBuf>,
+
+    /// Write the sentences as a dataset directory `load_dataset` reads as
+    /// is, instead of the output: train.jsonl, plus validation.jsonl and
+    /// test.jsonl with their fractions set, and dataset_infos.json with the
+    /// columns and split sizes
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["group_by_thread", "per_entry_output", "index", "polls"]
+    )]
+    hf_layout: Option<PathBuf>,
+
+    /// Also write the sentences to the `sentences` table of this SQLite
+    /// database, with their source entry, CJK ratio, length in chars and
+    /// post time, committing once per archive entry
+    #[arg(long, value_name = "FILE", conflicts_with = "interleave")]
+    output_sqlite: Option<PathBuf>,
+
+    /// Also write the sentences to this Parquet file, with their source
+    /// entry, CJK ratio and length in chars
+    #[arg(long, value_name = "FILE", conflicts_with = "interleave")]
+    output_parquet: Option<PathBuf>,
+
+    /// With --output-parquet, rows per row group
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_PARQUET_ROW_GROUP_SIZE,
+        requires = "output_parquet"
+    )]
+    parquet_row_group_size: usize,
+
+    /// Also write the sentences to this Arrow IPC stream, with the columns
+    /// of --output-parquet and one record batch per archive entry
+    #[arg(long, value_name = "FILE", conflicts_with = "interleave")]
+    output_arrow: Option<PathBuf>,
+
+    /// With --hf-layout, compress the jsonl files with zstd
+    #[arg(long, requires = "hf_layout")]
+    hf_zstd: bool,
+
+    /// With --hf-layout, fraction of the threads going to the validation
+    /// split. Threads are assigned by a hash of their id seeded by --seed.
+    #[arg(long, value_name = "F", default_value_t = 0.0, requires = "hf_layout")]
+    validation_fraction: f64,
+
+    /// With --hf-layout, fraction of the threads going to the test split
+    #[arg(long, value_name = "F", default_value_t = 0.0, requires = "hf_layout")]
+    test_fraction: f64,
+
+    /// Write processing statistics as JSON
+    #[arg(long)]
+    stats_file: Option<PathBuf>,
+
+    /// Write what the run read, its settings, what it wrote, when, and the
+    /// SHA-256 of the --output file as JSON, to build the same corpus again
+    #[arg(long, value_name = "FILE")]
+    run_manifest: Option<PathBuf>,
+
+    /// Write the output to DIR as corpus-<confighash>-<inputhash>.txt, named
+    /// by the settings and by the names, sizes and modification times of the
+    /// inputs, with its settings, inputs and stats in a .meta.json beside it.
+    /// A corpus built already is not built again.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "hf_layout"])]
+    output_auto: Option<PathBuf>,
+
+    /// With --output-auto, build the corpus even if it was built already
+    #[arg(long, requires = "output_auto")]
+    rebuild: bool,
+
+    /// Serve Prometheus metrics of the run at http://<host>:PORT/metrics
+    /// while processing: lines, sentences, errors and duplicates so far
+    #[arg(long, value_name = "PORT")]
+    metrics_port: Option<u16>,
+
+    /// Print the paragraph length histogram and per-entry yield to stderr
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Process the .xz dumps in this directory, then keep processing new ones
+    /// as they appear until interrupted, appending to the output. Sentence
+    /// deduplication only spans the files seen by this process.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = [
+            "two_pass", "drop_substrings", "weight_by_score", "reservoir", "stats_file", "run_manifest",
+            "corpus_stats", "cjk_coverage", "length_histogram", "rare_chars_report", "ngrams",
+            "train_spm", "nicknames", "thread_stats", "post_sentence_hist", "hf_layout", "errors_jsonl",
+            "output_parquet", "output_arrow", "by_month", "output_tree", "threads_out",
+            "output_auto", "unicode_block_stats",
+        ]
+    )]
+    watch: Option<PathBuf>,
+
+    /// With --watch, processed files, skipped when watching again
+    #[arg(long, value_name = "FILE", default_value = DEFAULT_WATCH_MANIFEST)]
+    manifest: PathBuf,
+
+    /// With --watch, seconds a file's size must stay unchanged before it is
+    /// processed
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_SETTLE_SECS)]
+    settle_secs: u64,
+
+    /// Read the whole input once before extracting and report entries that
+    /// cannot be read; extraction skips them either way
+    #[arg(long)]
+    verify: bool,
+
+    /// Append a JSON line of statistics per archive entry as it completes
+    #[arg(long, value_name = "FILE")]
+    per_entry_stats: Option<PathBuf>,
+
+    /// Write character and length statistics of the written sentences as JSON
+    #[arg(long, value_name = "FILE")]
+    corpus_stats: Option<PathBuf>,
+
+    /// Write a TSV of every CJK character written with its frequency and block
+    #[arg(long, value_name = "FILE")]
+    cjk_coverage: Option<PathBuf>,
+
+    /// Write a TSV of the written characters per Unicode block with their
+    /// share of all written characters, the most common block first
+    #[arg(long, value_name = "FILE")]
+    unicode_block_stats: Option<PathBuf>,
+
+    /// Write a length\tcount TSV of the written sentence lengths
+    #[arg(long, value_name = "FILE")]
+    length_histogram: Option<PathBuf>,
+
+    /// Bin width for --length-histogram
+    #[arg(long, default_value_t = 1)]
+    hist_bin_width: usize,
+
+    /// Write a tsv row per char written fewer than --rare-threshold times
+    /// and per private use char, with its first sentences
+    #[arg(long, value_name = "FILE")]
+    rare_chars_report: Option<PathBuf>,
+
+    /// Count below which --rare-chars-report lists a char
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_RARE_THRESHOLD)]
+    rare_threshold: u64,
+
+    /// Write an ngram\tcount TSV of the word n-grams in the written
+    /// sentences, most frequent first
+    #[arg(long, value_name = "FILE")]
+    ngrams: Option<PathBuf>,
+
+    /// Tokens per n-gram for --ngrams
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_NGRAM_N,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    ngram_n: usize,
+
+    /// Split each written sentence into the pieces of this SentencePiece
+    /// model, separated by spaces
+    #[arg(long, value_name = "MODEL_FILE")]
+    tokenize_spm: Option<PathBuf>,
+
+    /// Write the Jyutping of each sentence as a jyutping field, a syllable
+    /// per character with ? for those without a reading. Needs --format
+    /// jsonl and the jyutping feature.
+    #[arg(long)]
+    jyutping: bool,
+
+    /// Rime dict files adding to the built-in readings of --jyutping, such as
+    /// the dicts of a newer rime-cantonese or words of your own
+    #[arg(long, value_name = "FILE", num_args = 1.., requires = "jyutping")]
+    jyutping_dict: Vec<PathBuf>,
+
+    /// Train a SentencePiece BPE model on the written sentences after the run
+    #[arg(long)]
+    train_spm: bool,
+
+    /// Pieces of the --train-spm model, including the meta pieces and every
+    /// char written
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_SPM_VOCAB_SIZE,
+        requires = "train_spm"
+    )]
+    spm_vocab_size: usize,
+
+    /// Where --train-spm writes the model
+    #[arg(
+        long,
+        value_name = "FILE",
+        default_value = DEFAULT_SPM_OUTPUT,
+        requires = "train_spm"
+    )]
+    spm_output: PathBuf,
+
+    /// What to do with sentences containing profanity
+    #[arg(long, value_enum, default_value_t = ProfanityMode::Keep)]
+    profanity: ProfanityMode,
+
+    /// Profanity word list replacing the built-in one, one entry per line
+    #[arg(long)]
+    profanity_list: Option<PathBuf>,
+
+    /// Write rejected sentences too, except those of deleted posts, each with
+    /// a 0-1 quality score and the features it combines: length,
+    /// cjk_fraction, repetition, has_url, blocklist_hits and
+    /// cantonese_markers. Sentences scoring at least 0.5 are the ones written
+    /// without it. The scores are in jsonl and Parquet output.
+    #[arg(long)]
+    score_all: bool,
+
+    /// Skip the pages of threads with fewer than N replies, as given by
+    /// total_replies or no_of_reply
+    #[arg(long, value_name = "N")]
+    min_replies: Option<u64>,
+
+    /// Skip posts with fewer than N likes, posts without a like count
+    /// included
+    #[arg(long, value_name = "N")]
+    min_likes: Option<u64>,
+
+    /// Skip the posts of the accounts listed in FILE, a user_id or nickname
+    /// per line with # comments, tallied per entry in the stats. A watch
+    /// reads the file again when it changes
+    #[arg(long, value_name = "FILE")]
+    ban_users: Option<PathBuf>,
+
+    /// Add the like_count and dislike_count of the post to each jsonl record
+    #[arg(long)]
+    include_votes: bool,
+
+    /// Add the poster's user id to each jsonl record as a pseudonym, the
+    /// first 16 hex digits of its HMAC-SHA256 under --anonymize-key
+    #[arg(long)]
+    anonymize_users: bool,
+
+    /// Hex key of --anonymize-users, read from LIHKG_ANON_KEY if not given
+    #[arg(long, value_name = "HEX")]
+    anonymize_key: Option<String>,
+
+    /// Also extract the question and options of vote threads' polls, once
+    /// per thread; tagged with a kind in jsonl output
+    #[arg(long)]
+    extract_polls: bool,
+
+    /// Write poll texts to this file in the output format instead of the
+    /// output, implies --extract-polls
+    #[arg(long, value_name = "FILE")]
+    polls: Option<PathBuf>,
+
+    /// Write the last line of each quoted post and the first line of the
+    /// reply quoting it as a tab separated pair, when both sides are valid
+    #[arg(long, value_name = "FILE")]
+    output_pairs: Option<PathBuf>,
+
+    /// Write a json line per post quoting another, with its thread id, post
+    /// id and the id of the post it quotes. Quotes are matched by the start
+    /// of their text against the last posts of the thread, null when none
+    /// matches
+    #[arg(long, value_name = "FILE")]
+    reply_graph: Option<PathBuf>,
+
+    /// Posts of each thread kept for matching the quotes of --reply-graph
+    #[arg(long, value_name = "K", default_value_t = DEFAULT_REPLY_GRAPH_WINDOW)]
+    reply_graph_window: usize,
+
+    /// Write a json line per thread nesting its posts under the posts they
+    /// reply to by reply_id or parent_id, each with its text and a children
+    /// array. Holds the posts of every thread until the end of the run
+    #[arg(long, value_name = "FILE")]
+    output_tree: Option<PathBuf>,
+
+    /// Write paragraphs of English only to this file, one per line, when
+    /// they pass checks of their own: --english-min-words to
+    /// --english-max-words words, no URL and not mostly uppercase. The
+    /// output still rejects them
+    #[arg(long, value_name = "FILE")]
+    english_out: Option<PathBuf>,
+
+    /// Fewest words of a paragraph of --english-out
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_ENGLISH_MIN_WORDS)]
+    english_min_words: usize,
+
+    /// Most words of a paragraph of --english-out
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_ENGLISH_MAX_WORDS)]
+    english_max_words: usize,
+
+    /// Levels of nested quotes followed for --output-pairs, pairing each
+    /// quoted post with the one it quotes; deeper quotes are dropped. Quotes
+    /// never reach the output
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_QUOTE_DEPTH,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    quote_depth: usize,
+
+    /// What to do with struck through text of posts, in <del>, <s> or
+    /// <strike>
+    #[arg(long, value_enum, default_value_t = StrikethroughMode::Keep)]
+    strikethrough: StrikethroughMode,
+
+    /// What to do with spoiler text of posts; token writes it between
+    /// <spoiler> and </spoiler>, which count towards the length and CJK ratio
+    #[arg(long, value_enum, default_value_t = SpoilerMode::Keep)]
+    spoiler: SpoilerMode,
+
+    /// Tree the html of posts is parsed into, kuchiki for posts the default
+    /// handles badly. Quotes are split with scraper whatever this is, and
+    /// kuchiki needs a build with the kuchiki feature
+    #[arg(long, value_enum, default_value_t = HtmlParserKind::Scraper)]
+    html_parser: HtmlParserKind,
+
+    /// What to do with posts pasting a news article: drop all their
+    /// sentences or tag them news_like in jsonl records. A post is taken for
+    /// one with enough long paragraphs, next to no Cantonese and a newswire
+    /// phrase such as 【 or 記者
+    #[arg(long, value_enum, default_value_t = NewsMode::Keep)]
+    news_posts: NewsMode,
+
+    /// Fewest non-blank paragraphs of a news-like post
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_NEWS_MIN_PARAGRAPHS)]
+    news_min_paragraphs: usize,
+
+    /// Fewest chars per paragraph on average of a news-like post
+    #[arg(long, value_name = "M", default_value_t = DEFAULT_NEWS_MIN_AVG_LEN)]
+    news_min_avg_len: usize,
+
+    /// Most Cantonese marker chars per non-space char of a news-like post
+    #[arg(long, value_name = "DENSITY", default_value_t = DEFAULT_NEWS_MAX_CANTONESE_DENSITY)]
+    news_max_cantonese_density: f64,
+
+    /// Write the number of posts per nickname as tsv, most posts first,
+    /// leaving out deleted and system accounts
+    #[arg(long, value_name = "FILE")]
+    nicknames: Option<PathBuf>,
+
+    /// Write a json line per API error code of the responses without
+    /// success, e.g. 100 for threads not found, with its message and the
+    /// ids of the threads it came back for
+    #[arg(long, value_name = "FILE")]
+    errors_jsonl: Option<PathBuf>,
+
+    /// Write a tsv row per thread with its posts, valid sentences, average
+    /// CJK ratio of its paragraphs and first and last reply time
+    #[arg(long, value_name = "FILE")]
+    thread_stats: Option<PathBuf>,
+
+    /// Write a row per thread with its title, category, creation time and
+    /// total replies as its responses first give them, and its written
+    /// sentences, as csv or as json lines for a .jsonl path
+    #[arg(long, value_name = "FILE")]
+    threads_out: Option<PathBuf>,
+
+    /// With --threads-out, also list the threads giving no written sentence
+    #[arg(long, requires = "threads_out")]
+    threads_out_all: bool,
+
+    /// Write a tsv row per number of valid sentences a post gave with the
+    /// posts giving that many, counted before deduplication
+    #[arg(long, value_name = "FILE")]
+    post_sentence_hist: Option<PathBuf>,
+
+    /// Skip posts whose raw html was already seen in this run
+    #[arg(long)]
+    dedup_posts: bool,
+
+    /// Drop sentences already written in this run
+    #[arg(long)]
+    deduplicate: bool,
+
+    /// Only drop a repeated sentence if it was written within this many days
+    /// before the post, implies --deduplicate
+    #[arg(long, value_name = "N")]
+    dedup_window_days: Option<u32>,
+
+    /// Sentences written by earlier runs, loaded if the file exists and saved
+    /// back with this run's sentences, implies --deduplicate. When sharding,
+    /// give each shard its own file and combine them with merge-dedup.
+    #[arg(long, value_name = "FILE")]
+    dedup_state: Option<PathBuf>,
+
+    /// How sentences are keyed for --deduplicate: xxhash (xxh3) or siphash
+    /// keep 8 bytes per sentence, identity keeps its text and never
+    /// collides but cannot be saved with --dedup-state
+    #[arg(long, value_enum, default_value_t = DedupHash::Xxhash)]
+    dedup_hash: DedupHash,
+
+    /// The field --deduplicate keys on: the written text, or with
+    /// --keep-raw the raw paragraph, so sentences filtered to the same text
+    /// are kept while their surface forms differ
+    #[arg(long, value_enum, default_value_t = DedupKey::Text)]
+    dedup_key: DedupKey,
+
+    /// Keep sentence keys in a Bloom filter of fixed memory instead, which
+    /// drops about the reported bloom_false_positive_rate of the sentences
+    /// never seen. Implies --deduplicate; its --dedup-state only loads into
+    /// filters of the same size.
+    #[arg(long, conflicts_with = "dedup_window_days")]
+    dedup_bloom: bool,
+
+    /// Bits of the --dedup-bloom filter, from --bloom-items and
+    /// --bloom-fp-rate unless given
+    #[arg(long, value_name = "N")]
+    bloom_bits: Option<u64>,
+
+    /// Hashes per key of the --dedup-bloom filter, the best for the bits and
+    /// --bloom-items unless given
+    #[arg(long, value_name = "K")]
+    bloom_hashes: Option<u32>,
+
+    /// Distinct sentences the --dedup-bloom filter is sized for
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_BLOOM_ITEMS)]
+    bloom_items: u64,
+
+    /// False positive rate the --dedup-bloom filter is sized for, reached
+    /// once --bloom-items sentences are in it
+    #[arg(long, value_name = "RATE", default_value_t = DEFAULT_BLOOM_FP_RATE)]
+    bloom_fp_rate: f64,
+
+    /// Only process shard I of --shard-count. Archive entries are assigned by
+    /// a hash of their path and single-entry inputs by line number, so the
+    /// shards of a given input are disjoint and together cover all of it.
+    #[arg(long, value_name = "I", requires = "shard_count")]
+    shard_index: Option<u64>,
+
+    /// Number of shards the input is split into
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "shard_index",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    shard_count: Option<u64>,
+
+    /// Lines of an entry each worker takes at once, between N and 4N,
+    /// instead of letting rayon split entries down to single lines. Pages
+    /// are a few KB, so 100 to 1000 suits a laptop and 1000 or more a many
+    /// core server, where fewer and larger jobs cut the scheduling and merge
+    /// overhead; entries shorter than N lines then run on one core
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    batch_size: Option<usize>,
+
+    /// Read entries 10000 lines at a time instead of whole, checking the
+    /// resident memory after each: warn when it nears N MB and past N MB
+    /// write the entry's sentences so far before going on, reporting the
+    /// entry in parts. Linux only, elsewhere there is no check.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_memory_mb: Option<u64>,
+
+    /// Read the lines of an entry that are not UTF-8, such as Big5 or
+    /// GB18030 in older dumps, in the encoding detected for them instead of
+    /// skipping the entry as corrupt. Lines failing to decode are dropped
+    /// and counted as encoding_errors
+    #[arg(long)]
+    detect_encoding: bool,
+
+    /// With --detect-encoding, write a json line per line that was not
+    /// UTF-8 with its entry, line number, the encoding it was read in and
+    /// its text with replacement chars
+    #[arg(long, value_name = "FILE", requires = "detect_encoding")]
+    error_log: Option<PathBuf>,
+
+    /// Worker threads, all logical CPUs by default. Fewer leave cores free
+    /// for other processes, or avoid sharing cores between hyperthreads
+    /// when decompression dominates
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    threads: Option<usize>,
+
+    /// Read the input files at most N MB per second as stored, before
+    /// decompression, over all inputs read at once. The summary reports the
+    /// rate read.
+    #[arg(long, value_name = "N")]
+    max_read_mbps: Option<f64>,
+
+    /// Put the process in this I/O scheduling class, as ionice does. Linux
+    /// only; local disk schedulers honor it, network mounts do not.
+    #[arg(long, value_enum, value_name = "CLASS")]
+    io_nice: Option<IoNice>,
+
+    /// Run a first pass collecting corpus-wide counts used for pruning
+    #[arg(long)]
+    two_pass: bool,
+
+    /// With --two-pass, drop sentences containing a character seen fewer than K times
+    #[arg(long, value_name = "K", requires = "two_pass")]
+    min_char_count: Option<u64>,
+
+    /// With --two-pass, count private use chars as rare, where HKSCS chars
+    /// of legacy fonts land
+    #[arg(long, requires = "two_pass")]
+    rare_pua: bool,
+
+    /// What --min-char-count and --rare-pua do with rare chars: drop their
+    /// sentence or replace each with --rare-char-token
+    #[arg(long, value_enum, default_value_t = RareCharsMode::DropSentence)]
+    rare_chars: RareCharsMode,
+
+    /// Replacement of rare chars with --rare-chars replace
+    #[arg(long, value_name = "TOKEN", default_value = DEFAULT_RARE_CHAR_TOKEN)]
+    rare_char_token: String,
+
+    /// With --two-pass, drop sentences appearing in more than M distinct threads
+    #[arg(long, value_name = "M", requires = "two_pass")]
+    max_threads_per_sentence: Option<u32>,
+
+    /// Pass one state file, reused if it exists and written otherwise
+    #[arg(long)]
+    pass1_state: Option<PathBuf>,
+
+    /// Drop sentences contained in a longer written sentence, holding all
+    /// sentences in memory until the end of the run
+    #[arg(long)]
+    drop_substrings: bool,
+
+    /// Command scoring sentences, reading one per line on stdin and writing
+    /// one float per line on stdout; scores appear in jsonl output
+    #[arg(long, value_name = "CMD")]
+    score_cmd: Option<String>,
+
+    /// With --score-cmd, drop sentences scoring below this
+    #[arg(long, value_name = "X", requires = "score_cmd")]
+    score_threshold: Option<f64>,
+
+    /// Directory of a BERT model converted for rust-bert, with its
+    /// config.json, vocab.txt and rust_model.ot, scoring each sentence by
+    /// masked language model pseudo-perplexity. Slow, a forward pass per
+    /// token. Needs a build with the bert-filter feature.
+    #[arg(long, value_name = "DIR", requires = "bert_threshold")]
+    bert_model: Option<PathBuf>,
+
+    /// With --bert-model, drop sentences scoring below this, 1 over the
+    /// pseudo-perplexity between 0 and 1
+    #[arg(long, value_name = "FLOAT", requires = "bert_model")]
+    bert_threshold: Option<f64>,
+
+    /// Masked copies of sentences --bert-model runs through the model at a time
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_BERT_BATCH_SIZE)]
+    bert_batch_size: usize,
+
+    /// Command rewriting sentences, reading one per line on stdin and writing
+    /// the replacement of each as a line on stdout, before scoring. An empty
+    /// line drops the sentence, and so does the command exiting, which is
+    /// logged before starting it again for the next sentences.
+    #[arg(long, value_name = "CMD")]
+    post_process: Option<String>,
+
+    /// Built-in rewrites of each sentence, applied in the given order: norm
+    /// (fullwidth ASCII to ASCII), dedup-chars[=N] (runs of a char cut to N,
+    /// 2 by default), unescape (html entities), whitespace (runs of it as one
+    /// space) and numbers[=TOKEN] (numbers as TOKEN, <num> by default). A
+    /// sentence rewritten to nothing is dropped.
+    #[arg(long, value_name = "LIST")]
+    post_processors: Option<String>,
+
+    /// Keep each sentence with probability (1 + max(likes - dislikes, 0))^a
+    /// relative to the best scored post, found by an extra pass over the input.
+    /// The same input and seed always produce the same output.
+    #[arg(long)]
+    weight_by_score: bool,
+
+    /// Exponent a of the --weight-by-score weight
+    #[arg(
+        long,
+        value_name = "A",
+        default_value_t = DEFAULT_WEIGHT_EXPONENT,
+        requires = "weight_by_score"
+    )]
+    weight_exponent: f64,
+
+    /// Write a uniform random sample of N of the accepted sentences instead
+    /// of all of them, in input order at the end of the run. Only the sample
+    /// is held in memory. The same input and seed always give the same sample.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["per_entry_output", "drop_substrings", "interleave"]
+    )]
+    reservoir: Option<usize>,
+
+    /// Seed of the --weight-by-score and --reservoir sampling
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    seed: u64,
+
+    /// Reject paragraphs where a single repeated bigram covers more than this fraction
+    #[arg(long)]
+    max_bigram_fraction: Option<f32>,
+
+    /// Reject paragraphs repeating a single Latin letter more than this many times in a row
+    #[arg(long)]
+    max_letter_run: Option<usize>,
+
+    /// Reject paragraphs with an uninterrupted run of Latin letters and
+    /// digits longer than N, such as a pasted id or hash, keeping short
+    /// English borrowings like check吓 or ok喎
+    #[arg(long, value_name = "N")]
+    max_latin_run: Option<usize>,
+
+    /// Shorten the runs longer than --max-latin-run to N chars instead of
+    /// rejecting their paragraphs
+    #[arg(long, requires = "max_latin_run")]
+    truncate_latin_run: bool,
+
+    /// Count each run of Latin letters and digits as one char in the
+    /// --min-cjk-ratio check, so code-switched English words weigh like one
+    /// Chinese word rather than a char each
+    #[arg(long)]
+    ratio_latin_as_word: bool,
+
+    /// Reject paragraphs with fewer distinct word tokens than this
+    #[arg(long)]
+    min_distinct_tokens: Option<usize>,
+
+    /// Reject paragraphs where symbols, emoji and box drawing chars make up
+    /// more than this fraction of the non-whitespace chars
+    #[arg(long, value_name = "FRACTION")]
+    max_symbol_fraction: Option<f64>,
+
+    /// Reject paragraphs shorter than this many chars
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MIN_LEN)]
+    min_len: usize,
+
+    /// Reject paragraphs longer than this many chars
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_LEN)]
+    max_len: usize,
+
+    /// What --min-len, --max-len, --min-cjk-ratio and the repeated chars
+    /// rule count. Graphemes take an emoji sequence or a letter with
+    /// combining marks for one char where chars count each code point.
+    /// Chars by default, as the bounds were set in
+    #[arg(long, value_enum, default_value_t = LengthUnit::Chars)]
+    length_unit: LengthUnit,
+
+    /// Accept paragraphs shorter than --min-len when every char is a CJK
+    /// ideograph, for short labels and tags such as 正評 or 政治. They still
+    /// go through the deleted, URL, date and time checks
+    #[arg(long)]
+    allow_short_cjk: bool,
+
+    /// Reject paragraphs where CJK chars are not more than this fraction
+    #[arg(long, value_name = "X", default_value_t = DEFAULT_MIN_CJK_RATIO)]
+    min_cjk_ratio: f64,
+
+    /// Reject paragraphs containing Latin letters
+    #[arg(long)]
+    reject_latin: bool,
+
+    /// Reject paragraphs without any char particular to written Cantonese,
+    /// such as 嘅, 咗 or 唔
+    #[arg(long)]
+    require_cantonese: bool,
+
+    /// Reject paragraphs containing any pattern of this file as deleted,
+    /// instead of the built-in markers of removed posts like 此回覆已被刪除.
+    /// One pattern per line, blank lines and lines starting with '#' are
+    /// ignored.
+    #[arg(long, value_name = "FILE")]
+    deleted_patterns: Option<PathBuf>,
+
+    /// Shorten runs of the same char to N before filtering
+    #[arg(long, value_name = "N")]
+    collapse_repeats: Option<usize>,
+
+    /// Shorten runs of a char posters lengthen for emphasis, e.g. 呀 or 哈,
+    /// to two before filtering
+    #[arg(long)]
+    normalize_elongation: bool,
+
+    /// The chars of --normalize-elongation
+    #[arg(long, value_name = "CHARS", default_value = DEFAULT_ELONGATION_CHARS)]
+    elongation_chars: String,
+
+    /// Drop sentences not ending in 。！？…～, their ASCII forms or a
+    /// sentence-final particle, ignoring closing brackets and quotes after
+    /// it. Counted as sentence_end among the rejections
+    #[arg(long)]
+    require_sentence_end: bool,
+
+    /// The particles of --require-sentence-end
+    #[arg(long, value_name = "CHARS", default_value = DEFAULT_SENTENCE_END_PARTICLES)]
+    sentence_end_particles: String,
+
+    /// Replace URLs with <url> instead of rejecting paragraphs containing them
+    #[arg(long)]
+    urls_as_tokens: bool,
+
+    /// Keep emoji in the written sentences
+    #[arg(long)]
+    keep_emoji: bool,
+
+    /// Write each sentence's paragraph as it was before character
+    /// filtering, with its emoji, spaces and symbols, as a raw field beside
+    /// the text. Needs --format jsonl.
+    #[arg(long)]
+    keep_raw: bool,
+}
+
+impl Args {
+    // The profile overridden by the --config file, overridden by every flag
+    // given on the command line
+    fn settings(&self, matches: &ArgMatches) -> std::io::Result<Settings> {
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let profile = given("profile").then_some(self.profile);
+        let mut settings = match &self.config {
+            Some(path) => Settings::load(path, profile)?,
+            None => profile.unwrap_or_default().settings(),
+        };
+        macro_rules! set {
+            ($($id:ident => $target:expr),* $(,)?) => {
+                $(if given(stringify!($id)) {
+                    $target = self.$id.clone();
+                })*
+            };
+        }
+        if given("input") {
+            settings.input = self.input[0].clone();
+            settings.extra_inputs = self.input[1..].to_vec();
+        }
+        let io = &mut settings;
+        set! {
+            interleave => io.interleave,
+            output => io.output,
+            append => io.append,
+            dry_run => io.dry_run,
+            write_buffer_mb => io.write_buffer_mb,
+            format => io.format,
+            output_mode => io.output_mode,
+            doc_separator => io.doc_separator,
+            index => io.index,
+            group_by_thread => io.group_by_thread,
+            output_dir => io.output_dir,
+            max_open_files => io.max_open_files,
+            per_entry_output => io.per_entry_output,
+            by_month => io.by_month,
+            hf_layout => io.hf_layout,
+            output_sqlite => io.output_sqlite,
+            output_parquet => io.output_parquet,
+            parquet_row_group_size => io.parquet_row_group_size,
+            output_arrow => io.output_arrow,
+            hf_zstd => io.hf_zstd,
+            validation_fraction => io.validation_fraction,
+            test_fraction => io.test_fraction,
+            stats_file => io.stats_file,
+            run_manifest => io.run_manifest,
+            output_auto => io.output_auto,
+            rebuild => io.rebuild,
+            metrics_port => io.metrics_port,
+            verbose => io.verbose,
+            threads => io.threads,
+            io_nice => io.io_nice,
+            watch => io.watch,
+            manifest => io.manifest,
+            settle_secs => io.settle_secs,
+            verify => io.verify,
+            per_entry_stats => io.per_entry_stats,
+            corpus_stats => io.corpus_stats,
+            cjk_coverage => io.cjk_coverage,
+            unicode_block_stats => io.unicode_block_stats,
+            length_histogram => io.length_histogram,
+            hist_bin_width => io.hist_bin_width,
+            rare_chars_report => io.rare_chars_report,
+            rare_threshold => io.rare_threshold,
+            ngrams => io.ngrams,
+            ngram_n => io.ngram_n,
+            polls => io.polls,
+            output_pairs => io.output_pairs,
+            reply_graph => io.reply_graph,
+            reply_graph_window => io.reply_graph_window,
+            output_tree => io.output_tree,
+            english_out => io.english_out,
+            nicknames => io.nicknames,
+            errors_jsonl => io.errors_jsonl,
+            thread_stats => io.thread_stats,
+            error_log => io.error_log,
+            threads_out => io.threads_out,
+            threads_out_all => io.threads_out_all,
+            post_sentence_hist => io.post_sentence_hist,
+            tokenize_spm => io.tokenize_spm,
+            jyutping => io.jyutping,
+            jyutping_dict => io.jyutping_dicts,
+            train_spm => io.train_spm,
+            spm_vocab_size => io.spm_vocab_size,
+            spm_output => io.spm_output,
+        }
+        let config = &mut settings.extractor;
+        set! {
+            max_bigram_fraction => config.para.max_bigram_fraction,
+            max_letter_run => config.para.max_letter_run,
+            max_latin_run => config.para.max_latin_run,
+            truncate_latin_run => config.para.truncate_latin_run,
+            ratio_latin_as_word => config.para.ratio_latin_as_word,
+            min_distinct_tokens => config.para.min_distinct_tokens,
+            max_symbol_fraction => config.para.max_symbol_fraction,
+            min_len => config.para.min_len,
+            max_len => config.para.max_len,
+            length_unit => config.para.length_unit,
+            allow_short_cjk => config.para.allow_short_cjk,
+            min_cjk_ratio => config.para.min_cjk_ratio,
+            reject_latin => config.para.reject_latin,
+            require_cantonese => config.para.require_cantonese,
+            collapse_repeats => config.collapse_repeats,
+            normalize_elongation => config.normalize_elongation,
+            elongation_chars => config.elongation_chars,
+            require_sentence_end => config.require_sentence_end,
+            sentence_end_particles => config.sentence_end_particles,
+            urls_as_tokens => config.urls_as_tokens,
+            keep_emoji => config.keep_emoji,
+            keep_raw => config.keep_raw,
+            profanity => config.profanity,
+            profanity_list => config.profanity_list,
+            score_all => config.score_all,
+            deleted_patterns => config.deleted_patterns,
+            min_replies => config.min_replies,
+            min_likes => config.min_likes,
+            ban_users => config.ban_users,
+            include_votes => config.include_votes,
+            anonymize_users => config.anonymize_users,
+            anonymize_key => config.anonymize_key,
+            extract_polls => config.extract_polls,
+            quote_depth => config.quote_depth,
+            strikethrough => config.strikethrough,
+            spoiler => config.spoiler,
+            html_parser => config.html_parser,
+            english_min_words => config.english_min_words,
+            english_max_words => config.english_max_words,
+            news_posts => config.news_posts,
+            news_min_paragraphs => config.news_min_paragraphs,
+            news_min_avg_len => config.news_min_avg_len,
+            news_max_cantonese_density => config.news_max_cantonese_density,
+            dedup_posts => config.dedup_posts,
+            deduplicate => config.deduplicate,
+            dedup_window_days => config.dedup_window_days,
+            dedup_state => config.dedup_state,
+            dedup_hash => config.dedup_hash,
+            dedup_key => config.dedup_key,
+            dedup_bloom => config.dedup_bloom,
+            bloom_bits => config.bloom_bits,
+            bloom_hashes => config.bloom_hashes,
+            bloom_items => config.bloom_items,
+            bloom_fp_rate => config.bloom_fp_rate,
+            two_pass => config.two_pass,
+            min_char_count => config.min_char_count,
+            rare_pua => config.rare_pua,
+            rare_chars => config.rare_chars,
+            rare_char_token => config.rare_char_token,
+            max_threads_per_sentence => config.max_threads_per_sentence,
+            pass1_state => config.pass1_state,
+            drop_substrings => config.drop_substrings,
+            score_cmd => config.score_cmd,
+            score_threshold => config.score_threshold,
+            bert_model => config.bert_model,
+            bert_threshold => config.bert_threshold,
+            bert_batch_size => config.bert_batch_size,
+            post_process => config.post_process,
+            post_processors => config.post_processors,
+            weight_by_score => config.weight_by_score,
+            weight_exponent => config.weight_exponent,
+            reservoir => config.reservoir,
+            seed => config.seed,
+            batch_size => config.batch_size,
+            max_memory_mb => config.max_memory_mb,
+            detect_encoding => config.detect_encoding,
+            max_read_mbps => config.max_read_mbps,
+        }
+        // the two flags are given together, see their `requires`
+        if given("shard_index") {
+            config.shard = self
+                .shard_index
+                .zip(self.shard_count)
+                .map(|(index, count)| Shard { index, count });
+        }
+        config.extract_polls |= settings.polls.is_some();
+        config.extract_pairs |= settings.output_pairs.is_some();
+        config.collect_nicknames |= settings.nicknames.is_some();
+        config.collect_reply_graph |= settings.reply_graph.is_some();
+        config.collect_reply_trees |= settings.output_tree.is_some();
+        config.collect_english |= settings.english_out.is_some();
+        config.collect_api_errors |= settings.errors_jsonl.is_some();
+        config.collect_thread_stats |= settings.thread_stats.is_some();
+        config.collect_thread_meta |= settings.threads_out.is_some();
+        config.collect_post_sentences |= settings.post_sentence_hist.is_some();
+        if config.anonymize_key.is_none() {
+            config.anonymize_key = std::env::var(ANON_KEY_ENV).ok();
+        }
+        Ok(settings)
+    }
+}
+
+// A profile as the [extractor] section of a --config file
+#[derive(Serialize)]
+struct ProfileListing {
+    extractor: ExtractorConfig,
+}
+
+fn list_profiles() -> Result<(), toml::ser::Error> {
+    for profile in Profile::ALL {
+        println!("# {}: {}", profile.name(), profile.description());
+        let listing = ProfileListing {
+            extractor: profile.extractor(),
+        };
+        println!("{}", toml::to_string_pretty(&listing)?);
+    }
+    Ok(())
+}
+
+// The stats file of merge, recording the reports it adds up
+#[derive(Serialize)]
+struct MergedStatsReport<'a> {
+    inputs: &'a [PathBuf],
+    merge: MergeCounts,
+    #[serde(flatten)]
+    stats: &'a Stats,
+}
+
+// The stats file, recording the settings it was produced with
+#[derive(Serialize)]
+struct StatsReport<'a> {
+    settings: &'a Settings,
+    #[serde(flatten)]
+    stats: &'a Stats,
+    // with any deduplication
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<DedupStats>,
+}
+
+fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level.level())
+        .with_target(false)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr)
+        .init();
+    match run(cli, &matches) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn is_punc(c: char) -> bool {
-    PUNCS.contains(&c)
+fn run(cli: Cli, matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    match cli.command {
+        #[cfg(feature = "fetch")]
+        Some(Command::Fetch(args)) => fetch(args),
+        Some(Command::MergeDedup(args)) => merge_dedup(args),
+        Some(Command::Merge(args)) => merge(args),
+        Some(Command::DebugMsg(args)) => debug_msg(args),
+        Some(Command::Stats(args)) => stats(args),
+        Some(Command::Sample(args)) => sample(args),
+        // the matches of the subcommand tell given flags from defaults
+        Some(Command::Extract(args)) => extract_command(&args, matches.subcommand().unwrap().1),
+        None => extract_command(&cli.args, matches),
+    }
 }
 
-fn count_matching_chars(text: &str, regex: &Regex) -> usize {
-    text.chars()
-        .filter(|c| regex.is_match(&c.to_string()))
-        .count()
+fn extract_command(args: &Args, matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    if args.list_profiles {
+        list_profiles()?;
+        return Ok(());
+    }
+    let settings = args.settings(matches)?;
+    if args.print_config {
+        print!("{}", settings.to_toml());
+        return Ok(());
+    }
+    if let Some(threads) = settings.threads {
+        init_thread_pool(threads)?;
+    }
+    extract(&settings)
 }
 
-fn is_valid_para(para: &str) -> bool {
-    if para.is_empty() {
-        return false; // no content
+// The settings of --config, or of the profile alone, a given profile
+// overriding the one of the file
+fn preset_settings(
+    config: &Option<PathBuf>,
+    profile: Option<Profile>,
+) -> std::io::Result<Settings> {
+    match config {
+        Some(path) => Settings::load(path, profile),
+        None => Ok(profile.unwrap_or_default().settings()),
     }
-    if para == "此回覆已被刪除" {
-        return false;
+}
+
+fn stats(args: StatsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut settings = preset_settings(&args.config, args.profile)?;
+    settings.input = args.input[0].clone();
+    settings.extra_inputs = args.input[1..].to_vec();
+    settings.discard_output = true;
+    settings.corpus_stats = Some(args.output);
+    settings.stats_file = args.stats_file;
+    if let Some(threads) = args.threads.or(settings.threads) {
+        init_thread_pool(threads)?;
     }
-    if para.contains("分享自 LIHKG 討論區") {
-        return false;
+    extract(&settings)
+}
+
+fn sample(args: SampleArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut settings = preset_settings(&args.config, args.profile)?;
+    settings.input = args.input[0].clone();
+    settings.extra_inputs = args.input[1..].to_vec();
+    settings.output = args.output;
+    settings.format = args.format;
+    settings.extractor.reservoir = Some(args.size);
+    settings.extractor.seed = args.seed;
+    if let Some(threads) = args.threads.or(settings.threads) {
+        init_thread_pool(threads)?;
     }
-    let len = para.chars().count();
-    if len < 5 || len > 20 {
-        return false; // length < 5 or length > 20
+    extract(&settings)
+}
+
+// The settings writing the output --output-auto names, none if that output
+// was built already: its .meta.json is written last, so an output without
+// one is from a run cut short and built again
+fn auto_output(settings: &Settings, dir: &Path) -> std::io::Result<Option<Settings>> {
+    if settings.hf_layout.is_some() || settings.discard_output {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "output_auto needs an output file, hf_layout writes none",
+        ));
     }
-    if para.contains("http://") || para.contains("https://") {
-        return false; // includes URL
+    let inputs = settings
+        .inputs()
+        .map(InputFile::stat)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let output = dir.join(auto_output_name(settings, &inputs));
+    if !settings.rebuild && output.exists() && output.with_extension("meta.json").exists() {
+        tracing::info!(
+            "{} is built already, --rebuild builds it again",
+            output.display()
+        );
+        return Ok(None);
     }
+    std::fs::create_dir_all(dir)?;
+    Ok(Some(Settings {
+        output,
+        ..settings.clone()
+    }))
+}
 
-    let english_words_re = Regex::new(r"^[A-Za-z ]+$").unwrap();
-    if english_words_re.is_match(para) {
-        return false; // only English words
+fn init_thread_pool(threads: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if threads > cpus {
+        tracing::warn!(
+            "{} threads on {} logical CPUs, the extra threads only compete for them",
+            threads,
+            cpus
+        );
     }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()?;
+    Ok(())
+}
 
-    let date_re = Regex::new(r"^\d{4}.\d{2}.\d{2}$").unwrap();
-    if date_re.is_match(para) {
-        return false; // date
+fn merge_dedup(args: MergeDedupArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut merged: Option<DedupState> = None;
+    for path in &args.states {
+        let state = DedupState::load(path)?;
+        match &mut merged {
+            Some(merged) => merged.merge(state)?,
+            None => merged = Some(state),
+        }
     }
+    // clap requires at least one state
+    let merged = merged.unwrap();
+    merged.save(&args.output)?;
+    tracing::info!("merged {} sentences", merged.sentences.len());
+    Ok(())
+}
 
-    let time_re = Regex::new(r"^\d{2}:\d{2}:\d{2}$").unwrap();
-    if time_re.is_match(para) {
-        return false; // time
+fn merge(args: MergeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let inputs: Vec<PathBuf> = args.inputs.iter().chain(&args.input).cloned().collect();
+    if args.deduplicate && !(args.bloom_fp_rate > 0.0 && args.bloom_fp_rate < 1.0) {
+        return Err(format!(
+            "--bloom-fp-rate {} is not between 0 and 1",
+            args.bloom_fp_rate
+        )
+        .into());
+    }
+    let counts = Merge {
+        inputs: &inputs,
+        states: &args.states,
+        hash: args.dedup_hash,
+        dedup: if args.deduplicate {
+            let (bits, hashes) = bloom_params(args.bloom_items, args.bloom_fp_rate);
+            MergeDedup::Bloom(bits, hashes)
+        } else if args.dedup_exact {
+            MergeDedup::Exact(args.partitions)
+        } else {
+            MergeDedup::Off
+        },
+        shuffle_seed: args.shuffle.then_some(args.seed),
+    }
+    .run(&args.output, args.state_output.as_deref())?;
+    tracing::info!(
+        "merged {} sentences, dropped {} duplicates",
+        counts.sentences,
+        counts.duplicates
+    );
+    if let Some(path) = &args.stats_output {
+        let mut stats = Stats::default();
+        for input in &args.stats {
+            stats.merge(serde_json::from_reader(BufReader::new(File::open(input)?))?);
+        }
+        // each run's estimate is of its own sentences only
+        stats.expected_hash_collisions = None;
+        stats.bloom_false_positive_rate = None;
+        stats.read_mbps = None;
+        serde_json::to_writer_pretty(
+            File::create(path)?,
+            &MergedStatsReport {
+                inputs: &args.stats,
+                merge: counts,
+                stats: &stats,
+            },
+        )?;
     }
+    Ok(())
+}
 
-    let unique_chars: std::collections::HashSet<char> = para.chars().collect();
-    if unique_chars.len() * 5 < para.len() {
-        return false; // too many repeated characters
+fn debug_msg(args: DebugMsgArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = preset_settings(&args.config, args.profile)?;
+    let extractor = Extractor::new(&settings.extractor)?;
+    let html = match args.source.html {
+        Some(html) => html,
+        None => std::io::read_to_string(std::io::stdin())?,
+    };
+    let trace = extractor.trace_post(&html);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&trace)?);
+    } else {
+        print!("{}", trace.report());
     }
+    Ok(())
+}
 
-    true
+#[cfg(feature = "fetch")]
+fn fetch(args: FetchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let stats = lihkg::fetch::run(&lihkg::fetch::FetchConfig {
+        from: args.from,
+        to: args.to,
+        out: args.out,
+        progress: args.progress,
+        concurrency: args.concurrency,
+        rate: args.rate,
+        max_retries: args.max_retries,
+        base_url: args.base_url,
+    })?;
+    tracing::info!("{}", stats.summary());
+    Ok(())
 }
 
-fn convert_html_to_text(html: &str) -> String {
-    let mut document = Html::parse_fragment(html);
+fn extract(settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let started = SystemTime::now();
+    let dry_settings;
+    let settings = if settings.dry_run {
+        dry_settings = settings.without_outputs();
+        &dry_settings
+    } else {
+        settings
+    };
+    let auto_settings;
+    let settings = match &settings.output_auto {
+        Some(dir) => match auto_output(settings, dir)? {
+            Some(settings) => {
+                auto_settings = settings;
+                &auto_settings
+            }
+            None => return Ok(()),
+        },
+        None => settings,
+    };
+    let config = &settings.extractor;
+    if config.dedup_hash == DedupHash::Identity && config.dedup_state.is_some() {
+        return Err("dedup_state cannot be saved with the identity dedup_hash".into());
+    }
+    if config.dedup_bloom && config.dedup_window_days.is_some() {
+        return Err("dedup_bloom cannot be combined with dedup_window_days".into());
+    }
+    if config.dedup_bloom && !(config.bloom_fp_rate > 0.0 && config.bloom_fp_rate < 1.0) {
+        return Err(format!(
+            "bloom_fp_rate {} is not between 0 and 1",
+            config.bloom_fp_rate
+        )
+        .into());
+    }
+    if let Some(mbps) = config.max_read_mbps {
+        if !(mbps > 0.0 && mbps.is_finite()) {
+            return Err(format!("max_read_mbps {} is not above 0", mbps).into());
+        }
+    }
+    if let Some(class) = settings.io_nice {
+        set_io_priority(class)?;
+    }
+    if let Some(shard) = config.shard {
+        if shard.index >= shard.count {
+            return Err(format!(
+                "--shard-index {} is out of range for --shard-count {}",
+                shard.index, shard.count
+            )
+            .into());
+        }
+    }
+    if settings.append
+        && (settings.output_auto.is_some()
+            || settings.run_manifest.is_some()
+            || settings.hf_layout.is_some())
+    {
+        return Err("append cannot be combined with output_auto, run_manifest or hf_layout".into());
+    }
+    if config.keep_raw && (settings.format == OutputFormat::Text || settings.hf_layout.is_some()) {
+        return Err(
+            "keep_raw needs format jsonl, plain text and hf_layout have no raw field".into(),
+        );
+    }
+    if config.dedup_key == DedupKey::Raw && !config.keep_raw {
+        return Err("dedup_key raw needs keep_raw".into());
+    }
+    if settings.jyutping && (settings.format == OutputFormat::Text || settings.hf_layout.is_some())
+    {
+        return Err(
+            "jyutping needs format jsonl, plain text and hf_layout have no jyutping field".into(),
+        );
+    }
+    if settings.group_by_thread && settings.output_dir.is_none() {
+        return Err("group_by_thread needs an output_dir".into());
+    }
+    if settings.per_entry_output.is_some() && config.drop_substrings {
+        return Err("per_entry_output cannot be combined with drop_substrings".into());
+    }
+    if config.reservoir.is_some() && (settings.per_entry_output.is_some() || config.drop_substrings)
+    {
+        return Err("reservoir cannot be combined with per_entry_output or drop_substrings".into());
+    }
+    if settings.output_mode == OutputMode::Post
+        && settings.format == OutputFormat::Text
+        && settings.index.is_some()
+    {
+        return Err("index needs a line per post, output_mode post writes several".into());
+    }
+    let split_fractions = SplitFractions {
+        validation: settings.validation_fraction,
+        test: settings.test_fraction,
+        seed: config.seed,
+    };
+    split_fractions.check()?;
+    if settings.interleave
+        && (settings.per_entry_output.is_some()
+            || settings.output_sqlite.is_some()
+            || settings.output_parquet.is_some()
+            || settings.output_arrow.is_some()
+            || config.drop_substrings
+            || config.reservoir.is_some())
+    {
+        return Err(
+            "interleave cannot be combined with per_entry_output, output_sqlite, \
+                    output_parquet, output_arrow, drop_substrings or reservoir"
+                .into(),
+        );
+    }
+    // the command line rejects these through clap, a config file may not
+    let needs_whole_run = config.two_pass
+        || config.drop_substrings
+        || config.weight_by_score
+        || config.reservoir.is_some()
+        || settings.stats_file.is_some()
+        || settings.run_manifest.is_some()
+        || settings.output_auto.is_some()
+        || settings.corpus_stats.is_some()
+        || settings.cjk_coverage.is_some()
+        || settings.unicode_block_stats.is_some()
+        || settings.length_histogram.is_some()
+        || settings.rare_chars_report.is_some()
+        || settings.ngrams.is_some()
+        || settings.nicknames.is_some()
+        || settings.errors_jsonl.is_some()
+        || settings.thread_stats.is_some()
+        || settings.threads_out.is_some()
+        || settings.post_sentence_hist.is_some()
+        || settings.hf_layout.is_some()
+        || settings.output_parquet.is_some()
+        || settings.output_arrow.is_some()
+        || settings.by_month.is_some()
+        || settings.output_tree.is_some()
+        || settings.train_spm;
+    if settings.watch.is_some() && needs_whole_run {
+        return Err(
+            "watch mode cannot be combined with settings reporting or filtering \
+                    over the whole run"
+                .into(),
+        );
+    }
+    let inputs: Vec<&Path> = settings.inputs().collect();
+    let extractor = Extractor::new(config)?;
+    if settings.verify {
+        let mut problems = Vec::new();
+        for input in &inputs {
+            problems.extend(verify_archive(input, extractor.read_limit.as_ref())?);
+        }
+        for (entry, error) in &problems {
+            tracing::error!("unreadable: {}: {}", entry, error);
+        }
+        tracing::info!("verify: {} problem entries", problems.len());
+    }
+    let mut dedup = match config.dedup_window_days {
+        Some(days) => Some(Dedup::window_days(days, config.dedup_hash)),
+        None if config.dedup_bloom => Some(Dedup::bloom(bloom_filter(config), config.dedup_hash)?),
+        None if config.dedup_state.is_some() => Some(Dedup::exact_with_times(config.dedup_hash)),
+        None => config.deduplicate.then(|| Dedup::exact(config.dedup_hash)),
+    }
+    .map(|dedup| dedup.keyed_on(config.dedup_key));
+    if let (Some(dedup), Some(path)) = (&mut dedup, &config.dedup_state) {
+        if path.exists() {
+            dedup.load_state(path)?;
+        }
+    }
+    let pruner = if config.two_pass {
+        Some(Pruner {
+            state: pass_one(&inputs, &extractor, config)?,
+            min_char_count: config.min_char_count,
+            rare_pua: config.rare_pua,
+            rare_chars: config.rare_chars,
+            rare_char_token: config.rare_char_token.clone(),
+            max_threads: config.max_threads_per_sentence,
+        })
+    } else {
+        None
+    };
+    let sampler = if config.weight_by_score {
+        Some(WeightedSampler::new(
+            config.weight_exponent,
+            config.seed,
+            max_post_score(&inputs, &extractor)?,
+        ))
+    } else {
+        None
+    };
+
+    // Create or open the output files, watch mode and append keep adding to
+    // them
+    let appending = settings.watch.is_some() || settings.append;
+    let open = |path: &PathBuf| {
+        if !appending {
+            return File::create(path);
+        }
+        if !ends_with_newline(path)? {
+            tracing::warn!(
+                "{} does not end with a newline, its last line runs into the first one added",
+                path.display()
+            );
+        }
+        OpenOptions::new().create(true).append(true).open(path)
+    };
+    let index = match &settings.index {
+        Some(path) => Some((open(path)?, String::new())),
+        None => None,
+    };
+    let mut sinks: Vec<Box<dyn SentenceSink>> = Vec::new();
+    if let Some(path) = &settings.output_sqlite {
+        // replaced like the other outputs, watch mode and append keep adding
+        if !appending && path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        sinks.push(Box::new(SentenceDb::open(path)?));
+    }
+    if let Some(path) = &settings.output_parquet {
+        sinks.push(Box::new(ParquetOutput::create(
+            path,
+            settings.parquet_row_group_size,
+            config.score_all,
+        )?));
+    }
+    if let Some(path) = &settings.output_arrow {
+        sinks.push(Box::new(ArrowOutput::create(path)?));
+    }
+    let output = Output {
+        file: match &settings.hf_layout {
+            Some(_) => None,
+            None if settings.discard_output => None,
+            None => Some(BufWriter::with_capacity(
+                settings.write_buffer_mb << 20,
+                HashingWriter::new(open(&settings.output)?, settings.run_manifest.is_some()),
+            )),
+        },
+        hf: match &settings.hf_layout {
+            Some(dir) => Some(HfLayout::create(dir, split_fractions, settings.hf_zstd)?),
+            None => None,
+        },
+        sinks,
+        format: settings.format,
+        mode: settings.output_mode,
+        separator: settings.doc_separator.clone(),
+        group: Vec::new(),
+        buffer: String::new(),
+        index,
+        polls: match &settings.polls {
+            Some(path) => Some((open(path)?, String::new())),
+            None => None,
+        },
+        threads: match &settings.output_dir {
+            Some(dir) if settings.group_by_thread => Some(ThreadFiles::new(
+                dir,
+                settings.format.extension(),
+                appending,
+                settings.max_open_files,
+            )?),
+            _ => None,
+        },
+        months: match &settings.by_month {
+            Some(dir) => Some(BucketFiles::new(
+                dir,
+                settings.format.extension(),
+                appending,
+                settings.max_open_files,
+            )?),
+            None => None,
+        },
+        month_counts: BTreeMap::new(),
+        entries: match &settings.per_entry_output {
+            Some(dir) => Some(EntryFiles::new(dir, settings.format.extension())?),
+            None => None,
+        },
+        pairs: match &settings.output_pairs {
+            Some(path) => Some((open(path)?, String::new())),
+            None => None,
+        },
+        replies: match &settings.reply_graph {
+            Some(path) => Some((open(path)?, String::new())),
+            None => None,
+        },
+        english: match &settings.english_out {
+            Some(path) => Some((open(path)?, String::new())),
+            None => None,
+        },
+        corpus_stats: (settings.corpus_stats.is_some()
+            || settings.cjk_coverage.is_some()
+            || settings.unicode_block_stats.is_some())
+        .then(CorpusStats::default),
+        rare_chars: settings
+            .rare_chars_report
+            .is_some()
+            .then(RareChars::default),
+        ngrams: settings
+            .ngrams
+            .is_some()
+            .then(|| NgramCounts::new(settings.ngram_n)),
+        spm: match &settings.tokenize_spm {
+            Some(path) => Some(SpmModel::load(path)?),
+            None => None,
+        },
+        jyutping: match settings.jyutping {
+            true => Some(Jyutping::load(&settings.jyutping_dicts)?),
+            false => None,
+        },
+        spm_trainer: settings.train_spm.then(BpeTrainer::default),
+        lengths: BTreeMap::new(),
+        thread_table: settings.threads_out.is_some().then(ThreadTable::default),
+    };
+    let per_entry_stats = match &settings.per_entry_stats {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    let error_log = match &settings.error_log {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    let scorer = match &config.score_cmd {
+        Some(command) => Some(ExternalScorer::spawn(command)?),
+        None => None,
+    };
+    let post_processor = match &config.post_process {
+        Some(command) => Some(PostProcessCommand::spawn(command)?),
+        None => None,
+    };
+    let bert = match &config.bert_model {
+        Some(dir) => Some(BertScorer::load(dir, config.bert_batch_size)?),
+        None => None,
+    };
+    let metrics = match settings.metrics_port {
+        Some(port) => {
+            let metrics = Arc::new(Metrics::default());
+            lihkg::metrics::serve(port, metrics.clone())?;
+            Some(metrics)
+        }
+        None => None,
+    };
+    let mut run = Run {
+        config,
+        dry_run: settings.dry_run,
+        pruner,
+        dedup,
+        sampler,
+        scorer,
+        post_processor,
+        bert,
+        output,
+        per_entry_stats,
+        error_log,
+        stats: Stats::default(),
+        held: Vec::new(),
+        reservoir: config.reservoir.map(|n| Reservoir::new(n, config.seed)),
+        nicknames: HashMap::new(),
+        error_threads: BTreeMap::new(),
+        thread_posts: BTreeMap::new(),
+        reply_graph: settings
+            .reply_graph
+            .is_some()
+            .then(|| ReplyGraph::new(settings.reply_graph_window)),
+        input: 0,
+        interleaver: None,
+        metrics,
+    };
+
+    if let Some(dir) = &settings.watch {
+        watch(
+            dir,
+            &settings.manifest,
+            Duration::from_secs(settings.settle_secs),
+            |path| {
+                if extractor.reload_ban_list()? {
+                    tracing::info!("reloaded the banned users");
+                }
+                process_archive(path, &extractor, config.shard, |entry, result| {
+                    run.entry(entry, result)
+                })?;
+                run.save_dedup_state()?;
+                run.output.flush_file()?;
+                // a summary per file, nothing accumulates across files
+                let stats = std::mem::take(&mut run.stats);
+                tracing::info!("{}: {}", path.display(), stats.summary());
+                if settings.verbose {
+                    tracing::info!("{}", stats.histogram().trim_end());
+                }
+                Ok(())
+            },
+        )?;
+        return Ok(());
+    }
+
+    let input_stats = if settings.interleave && inputs.len() > 1 {
+        interleave(&inputs, &extractor, config.shard, &mut run)?
+    } else {
+        let mut input_stats = Vec::new();
+        for input in &inputs {
+            process_archive(input, &extractor, config.shard, |entry, result| {
+                run.entry(entry, result)
+            })?;
+            input_stats.push(std::mem::take(&mut run.stats));
+        }
+        input_stats
+    };
+    run.save_dedup_state()?;
+    // each input's own summary, then the totals
+    if inputs.len() > 1 {
+        for (input, stats) in inputs.iter().zip(&input_stats) {
+            tracing::info!("{}: {}", input.display(), stats.summary());
+        }
+    }
+    for stats in input_stats {
+        run.stats.merge(stats);
+    }
+    let Run {
+        mut stats,
+        mut output,
+        held,
+        reservoir,
+        nicknames,
+        error_threads,
+        thread_posts,
+        dedup,
+        ..
+    } = run;
+
+    if config.drop_substrings {
+        let sentences: Vec<&str> = held.iter().map(|r| r.text.as_str()).collect();
+        let contained = contained_in_longer(&sentences);
+        for (record, contained) in held.iter().zip(contained) {
+            if contained {
+                stats.substrings_dropped += 1;
+            } else {
+                output.push(record)?;
+            }
+        }
+        output.flush()?;
+    }
+    if let Some(reservoir) = reservoir {
+        *stats.rejected.entry(RejectReason::Sampled).or_default() += reservoir.dropped();
+        for record in reservoir.into_sample() {
+            output.push(&record)?;
+        }
+        output.flush()?;
+    }
+    output.flush_file()?;
+    if let Some(hf) = output.hf.take() {
+        hf.finish()?;
+    }
+    for sink in std::mem::take(&mut output.sinks) {
+        sink.finish()?;
+    }
 
-    // Remove blockquote
-    let blockquote_selector = Selector::parse("blockquote").unwrap();
-    let node_ids: Vec<_> = document
-        .select(&blockquote_selector)
-        .map(|x| x.id())
-        .collect();
-    for id in node_ids {
-        document.remove_from_parent(&id);
+    let deduplicating = dedup.is_some() || config.dedup_posts;
+    stats.expected_hash_collisions = dedup.as_ref().and_then(Dedup::expected_collisions);
+    stats.bloom_false_positive_rate = dedup.as_ref().and_then(Dedup::false_positive_rate);
+    stats.read_mbps = extractor
+        .read_limit
+        .as_ref()
+        .and_then(ReadLimit::achieved_mbps);
+    tracing::info!("{}", stats.summary());
+    if settings.verbose {
+        tracing::info!("{}", stats.histogram().trim_end());
+    }
+    let report = StatsReport {
+        settings,
+        stats: &stats,
+        dedup: deduplicating.then(|| DedupStats::new(&stats, output.lengths.values().sum())),
+    };
+    if let Some(path) = &settings.stats_file {
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+    }
+    if settings.dry_run {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    if let Some(path) = &settings.run_manifest {
+        let outputs = [
+            (settings.hf_layout.is_none() && !settings.discard_output).then_some(&settings.output),
+            settings.hf_layout.as_ref(),
+            settings.output_sqlite.as_ref(),
+            settings.output_parquet.as_ref(),
+            settings.output_arrow.as_ref(),
+            settings
+                .group_by_thread
+                .then_some(settings.output_dir.as_ref())
+                .flatten(),
+            settings.per_entry_output.as_ref(),
+            settings.by_month.as_ref(),
+        ];
+        let run_timestamp = started.duration_since(UNIX_EPOCH).unwrap_or_default();
+        serde_json::to_writer_pretty(
+            File::create(path)?,
+            &RunManifest {
+                input_files: inputs.iter().map(|p| p.display().to_string()).collect(),
+                config: settings,
+                output_files: outputs
+                    .into_iter()
+                    .flatten()
+                    .map(|p| p.display().to_string())
+                    .collect(),
+                run_timestamp: iso8601(run_timestamp.as_secs() as i64),
+                total_sentences: output.lengths.values().sum(),
+                output_sha256: output.file.as_ref().and_then(|f| f.get_ref().sha256()),
+            },
+        )?;
+    }
+    if settings.output_auto.is_some() {
+        let inputs = inputs
+            .iter()
+            .map(|path| InputFile::stat(path))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        serde_json::to_writer_pretty(
+            File::create(settings.output.with_extension("meta.json"))?,
+            &CorpusMeta {
+                version: env!("CARGO_PKG_VERSION"),
+                config: settings,
+                inputs: &inputs,
+                stats: &stats,
+            },
+        )?;
+    }
+    if let (Some(path), Some(corpus_stats)) = (&settings.corpus_stats, &output.corpus_stats) {
+        serde_json::to_writer_pretty(File::create(path)?, &corpus_stats.report())?;
+    }
+    if let (Some(path), Some(corpus_stats)) = (&settings.cjk_coverage, &output.corpus_stats) {
+        std::fs::write(path, corpus_stats.cjk_coverage_tsv())?;
+        let (seen, total) = corpus_stats.cjk_coverage();
+        tracing::info!(
+            "cjk coverage: {} of {} unified ideographs ({:.2}%)",
+            seen,
+            total,
+            seen as f64 * 100.0 / total as f64
+        );
+    }
+    if let (Some(path), Some(corpus_stats)) = (&settings.unicode_block_stats, &output.corpus_stats)
+    {
+        std::fs::write(path, corpus_stats.unicode_blocks_tsv())?;
+    }
+    if let (Some(path), Some(ngrams)) = (&settings.ngrams, &output.ngrams) {
+        std::fs::write(path, ngrams.tsv())?;
+    }
+    if let Some(path) = &settings.nicknames {
+        std::fs::write(path, counts_tsv("nickname", &nicknames))?;
+    }
+    if let Some(path) = &settings.errors_jsonl {
+        let mut file = BufWriter::new(File::create(path)?);
+        for (code, thread_ids) in &error_threads {
+            let line = json!({
+                // null for responses without a code
+                "error_code": code.parse::<i64>().ok(),
+                "error_message": stats.api_errors.get(code).map(|e| e.message.as_str()),
+                "thread_ids": thread_ids,
+            });
+            writeln!(file, "{}", line)?;
+        }
+        file.flush()?;
+    }
+    if let Some(path) = &settings.thread_stats {
+        std::fs::write(path, thread_stats_tsv(&extractor.thread_stats()))?;
+    }
+    if let Some(path) = &settings.output_tree {
+        let mut file = BufWriter::new(File::create(path)?);
+        for (thread_id, posts) in &thread_posts {
+            writeln!(file, "{}", thread_tree(*thread_id, posts))?;
+        }
+        file.flush()?;
+    }
+    if let (Some(path), Some(threads)) = (&settings.threads_out, &output.thread_table) {
+        let mut file = BufWriter::new(File::create(path)?);
+        let jsonl = path.extension().is_some_and(|ext| ext == "jsonl");
+        threads.write(&mut file, jsonl, settings.threads_out_all)?;
+        file.flush()?;
+    }
+    if let Some(path) = &settings.post_sentence_hist {
+        std::fs::write(path, post_sentences_tsv(&stats.sentences_per_post))?;
+    }
+    if let Some(spm_trainer) = &output.spm_trainer {
+        let model = spm_trainer.train(settings.spm_vocab_size)?;
+        model.save(&settings.spm_output)?;
+        tracing::info!(
+            "trained a {} piece BPE model into {}",
+            model.pieces.len(),
+            settings.spm_output.display()
+        );
+    }
+    if let Some(dir) = &settings.by_month {
+        std::fs::write(dir.join(MONTHS_TSV), months_tsv(&output.month_counts))?;
+    }
+    if let (Some(path), Some(rare_chars)) = (&settings.rare_chars_report, &output.rare_chars) {
+        std::fs::write(path, rare_chars.report_tsv(settings.rare_threshold))?;
+    }
+    if let Some(path) = &settings.length_histogram {
+        std::fs::write(
+            path,
+            length_histogram_tsv(&output.lengths, settings.hist_bin_width),
+        )?;
     }
 
-    // Convert to text
-    document.root_element().text().collect()
+    Ok(())
 }
 
-fn process_line(line: &str, result: &mut String) -> Result<(), serde_json::Error> {
-    let line = line.split("\t").nth(2).unwrap();
-    let obj: Value = serde_json::from_str(line)?;
+// Per-run state applied to each entry's sentences in archive order
+struct Run<'a> {
+    config: &'a ExtractorConfig,
+    // with --dry-run, the dedup state is not saved
+    dry_run: bool,
+    pruner: Option<Pruner>,
+    dedup: Option<Dedup>,
+    sampler: Option<WeightedSampler>,
+    scorer: Option<ExternalScorer>,
+    post_processor: Option<PostProcessCommand>,
+    bert: Option<BertScorer>,
+    output: Output,
+    per_entry_stats: Option<File>,
+    // lines that were not UTF-8 for --error-log
+    error_log: Option<File>,
+    stats: Stats,
+    // sentences kept back for --drop-substrings
+    held: Vec<SentenceRecord>,
+    // the sample of --reservoir, written at the end
+    reservoir: Option<Reservoir>,
+    // posts per nickname for --nicknames
+    nicknames: HashMap<String, u64>,
+    // thread ids per API error code for --errors-jsonl
+    error_threads: BTreeMap<String, BTreeSet<u64>>,
+    // posts by thread for --output-tree
+    thread_posts: BTreeMap<u64, Vec<Value>>,
+    // recent posts per thread for --reply-graph
+    reply_graph: Option<ReplyGraph>,
+    // the input of the entries being handed over, with --interleave
+    input: usize,
+    interleaver: Option<Interleaver>,
+    // counters of --metrics-port
+    metrics: Option<Arc<Metrics>>,
+}
 
-    if obj["success"].as_i64() == Some(1) {
-        if let Some(item_data) = obj["response"]["item_data"].as_array() {
-            for item in item_data {
-                if let Some(msg) = item["msg"].as_str() {
-                    let text = convert_html_to_text(msg);
-                    let paras = text.split("\n");
-                    for para in paras {
-                        let para = para.trim();
-                        if is_valid_para(para) {
-                            let num_cjk = count_matching_chars(para, &CJK_REGEX);
-                            let num_total = para.chars().count();
-                            if num_cjk >= 5 && num_cjk > ((num_total as f32 * 0.8).round() as usize)
-                            {
-                                let para = filter_irrelevant_chars(para);
-                                result.push_str(&para);
-                                result.push('\n');
-                            }
-                        }
+impl Run<'_> {
+    fn save_dedup_state(&self) -> std::io::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        if let (Some(dedup), Some(path)) = (&self.dedup, &self.config.dedup_state) {
+            dedup.save_state(path)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, entry: &EntryInfo, result: Batch) -> std::io::Result<()> {
+        let stats = &mut self.stats;
+        let mut entry_stats = EntryStats::new(&entry.name, &result.stats);
+        entry_stats.part = entry.part;
+        let errors = result.stats.json_errors + result.stats.corrupt_entries;
+        let duplicates_before = stats.duplicate_posts + stats.duplicate_sentences;
+        stats.merge(result.stats);
+        let mut records = Vec::with_capacity(result.records.len());
+        for mut record in result.records {
+            if let Some(pruner) = &self.pruner {
+                if let Err(reason) = pruner.check(&mut record) {
+                    stats.reject(reason);
+                    continue;
+                }
+            }
+            if let Some(dedup) = &mut self.dedup {
+                if dedup.is_duplicate(&record) {
+                    stats.duplicate_sentences += 1;
+                    continue;
+                }
+            }
+            if let Some(sampler) = &self.sampler {
+                if !sampler.keep(&record) {
+                    stats.reject(RejectReason::Sampled);
+                    continue;
+                }
+            }
+            records.push(record);
+        }
+        if let Some(post_processor) = &mut self.post_processor {
+            let sentences: Vec<&str> = records.iter().map(|r| r.text.as_str()).collect();
+            let replaced = post_processor.process(&sentences)?;
+            records = records
+                .into_iter()
+                .zip(replaced)
+                .filter_map(|(record, text)| match text {
+                    Some(text) => Some(SentenceRecord { text, ..record }),
+                    None => {
+                        stats.reject(RejectReason::PostProcess);
+                        None
                     }
+                })
+                .collect();
+        }
+        if let Some(scorer) = &mut self.scorer {
+            let sentences: Vec<&str> = records.iter().map(|r| r.text.as_str()).collect();
+            let scores = scorer.score(&sentences)?;
+            for (record, score) in records.iter_mut().zip(scores) {
+                record.score = Some(score);
+            }
+            if let Some(threshold) = self.config.score_threshold {
+                records.retain(|record| {
+                    let keep = record.score >= Some(threshold);
+                    if !keep {
+                        stats.reject(RejectReason::Score);
+                    }
+                    keep
+                });
+            }
+        }
+        if let Some(bert) = &self.bert {
+            let sentences: Vec<&str> = records.iter().map(|r| r.text.as_str()).collect();
+            let scores = bert.score(&sentences)?;
+            let threshold = self.config.bert_threshold.unwrap_or(0.0);
+            let mut scores = scores.into_iter();
+            records.retain(|_| {
+                let keep = scores.next() >= Some(threshold);
+                if !keep {
+                    stats.reject(RejectReason::Bert);
+                }
+                keep
+            });
+        }
+        for (nickname, count) in result.nicknames {
+            *self.nicknames.entry(nickname).or_default() += count;
+        }
+        for (code, thread_id) in result.api_errors {
+            self.error_threads
+                .entry(code)
+                .or_default()
+                .insert(thread_id);
+        }
+        for (quote, reply) in &result.pairs {
+            self.output.push_pair(quote, reply);
+        }
+        for para in &result.english {
+            self.output.push_english(para);
+        }
+        for (thread_id, post) in result.thread_posts {
+            self.thread_posts.entry(thread_id).or_default().push(post);
+        }
+        if let Some(threads) = &mut self.output.thread_table {
+            for thread in result.threads {
+                threads.observe(thread);
+            }
+        }
+        if let Some(graph) = &mut self.reply_graph {
+            for post in result.quotes {
+                if let Some(edge) = graph.push(post) {
+                    self.output.push_edge(&edge);
                 }
             }
         }
+        for record in records {
+            if let Some(reservoir) = &mut self.reservoir {
+                reservoir.offer(record);
+                continue;
+            }
+            if self.config.drop_substrings {
+                self.held.push(record);
+                continue;
+            }
+            match &mut self.interleaver {
+                Some(interleaver) => interleaver.push(self.input, record),
+                None => self.output.push(&record)?,
+            }
+            entry_stats.sentences_emitted += 1;
+        }
+        if let Some(interleaver) = &mut self.interleaver {
+            interleaver.write(&mut self.output)?;
+        }
+        self.output.flush_entry(entry)?;
+        if let Some(metrics) = &self.metrics {
+            let duplicates = stats.duplicate_posts + stats.duplicate_sentences;
+            metrics
+                .lines
+                .fetch_add(entry_stats.lines_read, Ordering::Relaxed);
+            metrics
+                .sentences
+                .fetch_add(entry_stats.sentences_emitted, Ordering::Relaxed);
+            metrics.errors.fetch_add(errors, Ordering::Relaxed);
+            metrics
+                .duplicates
+                .fetch_add(duplicates - duplicates_before, Ordering::Relaxed);
+        }
+        entry_stats.duration_ms = entry.started.elapsed().as_millis() as u64;
+        if let (Some(file), false) = (&mut self.error_log, result.non_utf8.is_empty()) {
+            let mut lines = String::new();
+            for line in &result.non_utf8 {
+                let mut logged = serde_json::to_value(line)?;
+                logged["entry"] = json!(entry.name);
+                lines.push_str(&format!("{}\n", logged));
+            }
+            file.write_all(lines.as_bytes())?;
+        }
+        if let Some(file) = &mut self.per_entry_stats {
+            serde_json::to_writer(&mut *file, &entry_stats)?;
+            file.write_all(b"\n")?;
+            file.flush()?;
+        }
+        stats.entries.push(entry_stats);
+        Ok(())
     }
+}
 
-    Ok(())
+// Sentences queued per input, written a post at a time taking the inputs in
+// turn. Posts are only written while every input still running has one
+// queued, unless too many sentences are waiting on a slow input.
+struct Interleaver {
+    queues: Vec<VecDeque<SentenceRecord>>,
+    running: Vec<bool>,
+    next: usize,
+    queued: usize,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let tar_xz = File::open("./data/lihkg-1800000-2800000-csv.tar.xz")?;
-    let tar = XzDecoder::new(BufReader::new(tar_xz));
-    let mut archive = Archive::new(tar);
-
-    // Create or open the output file
-    let mut output_file = File::create("sentences2.txt")?;
-
-    for file in archive.entries()? {
-        let file = file.unwrap();
-        let reader = BufReader::new(file);
-        let result = reader
-            .lines()
-            .map(|line| line.unwrap())
-            .collect::<Vec<_>>()
-            .par_iter()
-            .fold(
-                || String::new(),
-                |mut buffer, line| {
-                    process_line(&line, &mut buffer).unwrap();
-                    buffer
-                },
-            )
-            .reduce(
-                || String::new(),
-                |mut buffer1, buffer2| {
-                    buffer1.push_str(&buffer2);
-                    buffer1
-                },
-            );
-        output_file.write_all(result.as_bytes()).unwrap();
+// Sentences queued before posts are written without waiting for every input
+const MAX_INTERLEAVE_QUEUED: usize = 100_000;
+
+impl Interleaver {
+    fn new(inputs: usize) -> Self {
+        Interleaver {
+            queues: vec![VecDeque::new(); inputs],
+            running: vec![true; inputs],
+            next: 0,
+            queued: 0,
+        }
     }
 
-    Ok(())
+    fn push(&mut self, input: usize, record: SentenceRecord) {
+        self.queues[input].push_back(record);
+        self.queued += 1;
+    }
+
+    // Every post of an input comes in one batch, so queued posts are whole
+    fn finish(&mut self, input: usize, output: &mut Output) -> std::io::Result<()> {
+        self.running[input] = false;
+        self.write(output)
+    }
+
+    fn write(&mut self, output: &mut Output) -> std::io::Result<()> {
+        loop {
+            let waiting = self
+                .queues
+                .iter()
+                .zip(&self.running)
+                .any(|(queue, running)| *running && queue.is_empty());
+            if self.queued == 0 || (waiting && self.queued < MAX_INTERLEAVE_QUEUED) {
+                return Ok(());
+            }
+            let queue = &mut self.queues[self.next];
+            self.next = (self.next + 1) % self.running.len();
+            let Some(first) = queue.pop_front() else {
+                continue;
+            };
+            let post = |r: &SentenceRecord| (r.thread_id, r.post_hash, r.kind);
+            output.push(&first)?;
+            self.queued -= 1;
+            while queue.front().is_some_and(|r| post(r) == post(&first)) {
+                output.push(&queue.pop_front().unwrap())?;
+                self.queued -= 1;
+            }
+        }
+    }
+}
+
+// Processes the inputs at once, handing their entries to `run` as they
+// complete while the interleaver mixes their posts. Returns the stats of
+// each input.
+fn interleave(
+    inputs: &[&Path],
+    extractor: &Extractor,
+    shard: Option<Shard>,
+    run: &mut Run,
+) -> std::io::Result<Vec<Stats>> {
+    let mut input_stats: Vec<Stats> = inputs.iter().map(|_| Stats::default()).collect();
+    run.interleaver = Some(Interleaver::new(inputs.len()));
+    std::thread::scope(|scope| {
+        // a batch per input in flight, so a fast input waits for the writer
+        // rather than filling memory
+        let (sender, receiver) = mpsc::sync_channel(inputs.len());
+        let workers: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let result = process_archive(input, extractor, shard, |entry, batch| {
+                        sender.send((i, Some((entry.clone(), batch)))).map_err(|_| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::BrokenPipe,
+                                "the output stopped",
+                            )
+                        })
+                    });
+                    // the writer may be gone after an error of its own
+                    let _ = sender.send((i, None));
+                    result
+                })
+            })
+            .collect();
+        drop(sender);
+        let mut result = Ok(());
+        // dropping the receiver on an error stops the workers
+        for (i, batch) in receiver {
+            run.input = i;
+            std::mem::swap(&mut run.stats, &mut input_stats[i]);
+            result = match batch {
+                Some((entry, batch)) => run.entry(&entry, batch),
+                None => {
+                    let interleaver = run.interleaver.as_mut().unwrap();
+                    interleaver
+                        .finish(i, &mut run.output)
+                        .and_then(|()| run.output.flush())
+                }
+            };
+            std::mem::swap(&mut run.stats, &mut input_stats[i]);
+            if result.is_err() {
+                break;
+            }
+        }
+        for worker in workers {
+            let worker_result = worker.join().expect("input worker panicked");
+            result = result.and(worker_result);
+        }
+        result
+    })?;
+    run.interleaver = None;
+    Ok(input_stats)
+}
+
+// Everything that sees the sentences actually written
+struct Output {
+    // none with --hf-layout, which takes the lines instead
+    file: Option<BufWriter<HashingWriter<File>>>,
+    hf: Option<HfLayout>,
+    // the --output-sqlite, --output-parquet and --output-arrow sinks, taking
+    // every sentence and told the entry of each
+    sinks: Vec<Box<dyn SentenceSink>>,
+    format: OutputFormat,
+    mode: OutputMode,
+    separator: String,
+    // sentences of the line being grouped in the paragraph and document modes
+    group: Vec<SentenceRecord>,
+    buffer: String,
+    // the --index file and its pending lines, one per line of `buffer`
+    index: Option<(File, String)>,
+    // the --polls file and its pending lines, taking the poll texts
+    polls: Option<(File, String)>,
+    // the --output-pairs file and its pending lines
+    pairs: Option<(File, String)>,
+    // the --reply-graph file and its pending lines
+    replies: Option<(File, String)>,
+    // the --english-out file and its pending lines
+    english: Option<(File, String)>,
+    // per-thread files of --group-by-thread, taking the sentences of threads
+    threads: Option<ThreadFiles>,
+    // per-month files of --by-month, taking a copy of every line
+    months: Option<BucketFiles<String>>,
+    // sentences per month of --by-month
+    month_counts: BTreeMap<String, u64>,
+    // files of --per-entry-output, taking the output of each entry
+    entries: Option<EntryFiles>,
+    corpus_stats: Option<CorpusStats>,
+    rare_chars: Option<RareChars>,
+    ngrams: Option<NgramCounts>,
+    spm: Option<SpmModel>,
+    jyutping: Option<Jyutping>,
+    spm_trainer: Option<BpeTrainer>,
+    lengths: BTreeMap<usize, u64>,
+    // threads and their written sentences for --threads-out
+    thread_table: Option<ThreadTable>,
+}
+
+impl Output {
+    fn push(&mut self, record: &SentenceRecord) -> std::io::Result<()> {
+        let annotated;
+        let written = match (&self.spm, &self.jyutping) {
+            (None, None) => record,
+            (spm, jyutping) => {
+                annotated = SentenceRecord {
+                    text: match spm {
+                        Some(spm) => spm.encode(&record.text).join(" "),
+                        None => record.text.clone(),
+                    },
+                    jyutping: jyutping.as_ref().map(|j| j.annotate(&record.text)),
+                    ..record.clone()
+                };
+                &annotated
+            }
+        };
+        if let (Some(hf), OutputMode::Sentence) = (&mut self.hf, self.mode) {
+            hf.push(written);
+        } else if self.mode == OutputMode::Sentence {
+            self.emit(record, format_line(written, self.format));
+        } else {
+            let paragraphs = self.mode == OutputMode::Paragraph;
+            let key = |r: &SentenceRecord| {
+                let block = paragraphs.then_some(r.block);
+                (r.thread_id, r.post_hash, r.kind, block)
+            };
+            if self
+                .group
+                .last()
+                .is_some_and(|last| key(last) != key(written))
+            {
+                self.emit_group();
+            }
+            self.group.push(written.clone());
+        }
+        if let Some(corpus_stats) = &mut self.corpus_stats {
+            corpus_stats.observe(&record.text);
+        }
+        if let Some(rare_chars) = &mut self.rare_chars {
+            rare_chars.observe(&record.text);
+        }
+        if let Some(ngrams) = &mut self.ngrams {
+            ngrams.observe(&record.text);
+        }
+        for sink in &mut self.sinks {
+            sink.write(record)?;
+        }
+        if let (Some(threads), Some(thread_id)) = (&mut self.thread_table, record.thread_id) {
+            threads.count_sentence(thread_id);
+        }
+        if self.months.is_some() {
+            *self
+                .month_counts
+                .entry(month_key(record.reply_time))
+                .or_default() += 1;
+        }
+        if let Some(spm_trainer) = &mut self.spm_trainer {
+            spm_trainer.observe(&record.text);
+        }
+        *self.lengths.entry(record.text.chars().count()).or_default() += 1;
+        Ok(())
+    }
+
+    // Writes a line to the sink taking the record
+    fn emit(&mut self, record: &SentenceRecord, mut line: String) {
+        line.push('\n');
+        if let Some(months) = &mut self.months {
+            months.push(month_key(record.reply_time), line.clone());
+        }
+        if let (Some((_, buffer)), Some(_)) = (&mut self.polls, record.kind) {
+            buffer.push_str(&line);
+            return;
+        }
+        match (&mut self.threads, record.thread_id) {
+            (Some(threads), Some(thread_id)) => threads.push(thread_id, line),
+            _ => {
+                self.buffer.push_str(&line);
+                if let Some((_, buffer)) = &mut self.index {
+                    buffer.push_str(&serde_json::to_string(&record.index_entry()).unwrap());
+                    buffer.push('\n');
+                }
+            }
+        }
+    }
+
+    // Writes the grouped sentences as one line with the metadata of the first
+    fn emit_group(&mut self) {
+        let group = std::mem::take(&mut self.group);
+        let Some(first) = group.first() else {
+            return;
+        };
+        let sentences: Vec<&str> = group.iter().map(|r| r.text.as_str()).collect();
+        let separator = match self.mode {
+            OutputMode::Post => "\n",
+            _ => &self.separator,
+        };
+        if let Some(hf) = &mut self.hf {
+            hf.push(&SentenceRecord {
+                text: sentences.join(separator),
+                ..first.clone()
+            });
+            return;
+        }
+        let line = match self.format {
+            // the blank line ends the post
+            OutputFormat::Text if self.mode == OutputMode::Post => sentences.join("\n") + "\n",
+            OutputFormat::Text => sentences.join(separator),
+            OutputFormat::Jsonl => {
+                let mut object = serde_json::to_value(first).unwrap();
+                object.as_object_mut().unwrap().remove("text");
+                object["sentences"] = sentences.into();
+                // a reading per sentence, beside its text
+                if first.jyutping.is_some() {
+                    let readings: Vec<&str> =
+                        group.iter().filter_map(|r| r.jyutping.as_deref()).collect();
+                    object["jyutping"] = readings.into();
+                }
+                object.to_string()
+            }
+        };
+        self.emit(first, line);
+    }
+
+    fn push_pair(&mut self, quote: &str, reply: &str) {
+        if let Some((_, buffer)) = &mut self.pairs {
+            buffer.push_str(quote);
+            buffer.push('\t');
+            buffer.push_str(reply);
+            buffer.push('\n');
+        }
+    }
+
+    fn push_english(&mut self, para: &str) {
+        if let Some((_, buffer)) = &mut self.english {
+            buffer.push_str(para);
+            buffer.push('\n');
+        }
+    }
+
+    fn push_edge(&mut self, edge: &ReplyEdge) {
+        if let Some((_, buffer)) = &mut self.replies {
+            buffer.push_str(&serde_json::to_string(edge).unwrap());
+            buffer.push('\n');
+        }
+    }
+
+    // Writes the output of an entry to its own file with --per-entry-output,
+    // each entry getting a fresh handle
+    fn flush_entry(&mut self, entry: &EntryInfo) -> std::io::Result<()> {
+        self.emit_group();
+        if let Some(entries) = &mut self.entries {
+            let continued = entry.part.is_some_and(|part| part > 0);
+            let file = entries.file(&entry.name, continued)?;
+            file.write_all(self.buffer.as_bytes())?;
+            self.buffer.clear();
+        }
+        for sink in &mut self.sinks {
+            sink.end_entry(Some(&entry.name))?;
+        }
+        self.flush()
+    }
+
+    // Empties the write buffer of the output file, which `flush` leaves to
+    // fill over entries
+    fn flush_file(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.emit_group();
+        if let Some(file) = &mut self.file {
+            file.write_all(self.buffer.as_bytes())?;
+        }
+        self.buffer.clear();
+        let sinks = [
+            &mut self.index,
+            &mut self.polls,
+            &mut self.pairs,
+            &mut self.replies,
+            &mut self.english,
+        ];
+        for (file, buffer) in sinks.into_iter().flatten() {
+            file.write_all(buffer.as_bytes())?;
+            buffer.clear();
+        }
+        if let Some(threads) = &mut self.threads {
+            threads.flush()?;
+        }
+        if let Some(months) = &mut self.months {
+            months.flush()?;
+        }
+        if let Some(hf) = &mut self.hf {
+            hf.flush()?;
+        }
+        // sentences written outside an entry, as with --drop-substrings
+        for sink in &mut self.sinks {
+            sink.end_entry(None)?;
+        }
+        Ok(())
+    }
+}
+
+// True for a missing or empty file too, as there is no line to run into
+fn ends_with_newline(path: &Path) -> std::io::Result<bool> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(true);
+    }
+    let mut last = [0];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
+
+// The --dedup-bloom filter, sized for bloom_items at bloom_fp_rate unless
+// its bits or hashes are given
+fn bloom_filter(config: &ExtractorConfig) -> BloomFilter {
+    let bits = config
+        .bloom_bits
+        .unwrap_or_else(|| bloom_params(config.bloom_items, config.bloom_fp_rate).0);
+    let hashes = config
+        .bloom_hashes
+        .unwrap_or_else(|| optimal_hashes(bits, config.bloom_items));
+    BloomFilter::new(bits, hashes)
+}
+
+fn pass_one(
+    inputs: &[&Path],
+    extractor: &Extractor,
+    config: &ExtractorConfig,
+) -> std::io::Result<Pass1State> {
+    if let Some(path) = &config.pass1_state {
+        if path.exists() {
+            tracing::info!("reusing pass one state from {}", path.display());
+            return Pass1State::load(path);
+        }
+    }
+    let mut collector = Pass1Collector::default();
+    for input in inputs {
+        process_archive(input, extractor, None, |_, result| {
+            result.records.iter().for_each(|r| collector.observe(r));
+            Ok(())
+        })?;
+    }
+    extractor.reset_seen_posts();
+    let state = collector.finish();
+    if let Some(path) = &config.pass1_state {
+        state.save(path)?;
+    }
+    Ok(state)
+}
+
+// Best post score among the accepted sentences, normalizing the sampling weights
+fn max_post_score(inputs: &[&Path], extractor: &Extractor) -> std::io::Result<i64> {
+    let mut max = 0;
+    for input in inputs {
+        process_archive(input, extractor, None, |_, result| {
+            for record in &result.records {
+                max = max.max(record.post_score.unwrap_or(0));
+            }
+            Ok(())
+        })?;
+    }
+    extractor.reset_seen_posts();
+    Ok(max)
 }
@@ -0,0 +1,205 @@
+use crate::config::Settings;
+use crate::months::civil_date;
+use crate::stats::Stats;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// A writer passing its bytes on and, if asked to, hashing them on the way,
+// so the digest of a file is had without reading it back
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, hash: bool) -> Self {
+        HashingWriter {
+            inner,
+            hasher: hash.then(Sha256::new),
+        }
+    }
+
+    // The hex SHA-256 of the bytes written so far, none unless hashing
+    pub fn sha256(&self) -> Option<String> {
+        let digest = self.hasher.clone()?.finalize();
+        Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// A unix time as an ISO 8601 UTC time
+pub fn iso8601(secs: i64) -> String {
+    let (year, month, day) = civil_date(secs.div_euclid(86400));
+    let secs = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// What --run-manifest records of a run, enough to build the same corpus
+// again and to tell whether an output is the one built
+#[derive(Debug, Serialize)]
+pub struct RunManifest<'a> {
+    pub input_files: Vec<String>,
+    pub config: &'a Settings,
+    pub output_files: Vec<String>,
+    pub run_timestamp: String,
+    pub total_sentences: u64,
+    // of the bytes written to the output file, none without one
+    pub output_sha256: Option<String>,
+}
+
+// An input as --output-auto names the output by it, without reading it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InputFile {
+    pub path: String,
+    pub size: u64,
+    // unix seconds, none where the filesystem keeps no modification time
+    pub modified: Option<u64>,
+}
+
+impl InputFile {
+    pub fn stat(path: &Path) -> io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(InputFile {
+            path: path.display().to_string(),
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|time| time.as_secs()),
+        })
+    }
+}
+
+// The first 12 hex digits of the SHA-256 of `bytes`
+fn short_hash(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    digest[..6].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The settings deciding what a run writes, without where it reads and
+// writes or how fast, so the same build from elsewhere hashes the same
+pub fn config_hash(settings: &Settings) -> String {
+    let settings = Settings {
+        input: Default::default(),
+        extra_inputs: Vec::new(),
+        output: Default::default(),
+        output_auto: None,
+        rebuild: false,
+        threads: None,
+        verbose: false,
+        metrics_port: None,
+        ..settings.clone()
+    };
+    short_hash(settings.to_toml().as_bytes())
+}
+
+pub fn input_hash(inputs: &[InputFile]) -> String {
+    let mut listing = String::new();
+    for input in inputs {
+        let modified = input.modified.map(|m| m.to_string()).unwrap_or_default();
+        listing += &format!("{}\t{}\t{}\n", input.path, input.size, modified);
+    }
+    short_hash(listing.as_bytes())
+}
+
+// corpus-<confighash>-<inputhash>.<format>, the output --output-auto writes
+pub fn auto_output_name(settings: &Settings, inputs: &[InputFile]) -> String {
+    format!(
+        "corpus-{}-{}.{}",
+        config_hash(settings),
+        input_hash(inputs),
+        settings.format.extension()
+    )
+}
+
+// What --output-auto writes beside the output once the run is done, the
+// output counting as built from then on
+#[derive(Debug, Serialize)]
+pub struct CorpusMeta<'a> {
+    pub version: &'static str,
+    pub config: &'a Settings,
+    pub inputs: &'a [InputFile],
+    pub stats: &'a Stats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_what_it_writes() {
+        let mut writer = HashingWriter::new(Vec::new(), true);
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"bc").unwrap();
+        assert_eq!(writer.inner, b"abc");
+        assert_eq!(
+            writer.sha256().unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(HashingWriter::new(Vec::new(), false).sha256(), None);
+    }
+
+    #[test]
+    fn times_are_iso8601() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(1709164800 + 3723), "2024-02-29T01:02:03Z");
+    }
+
+    #[test]
+    fn auto_names_follow_the_settings_and_inputs() {
+        let inputs = [InputFile {
+            path: "lihkg.tar.xz".to_string(),
+            size: 100,
+            modified: Some(1700000000),
+        }];
+        let settings = Settings::default();
+        let name = auto_output_name(&settings, &inputs);
+        assert!(
+            name.starts_with("corpus-") && name.ends_with(".txt"),
+            "{}",
+            name
+        );
+        assert_eq!(name.len(), "corpus-".len() + 12 + 1 + 12 + ".txt".len());
+
+        // where the run writes and its threads leave the name alone
+        let moved = Settings {
+            output: "elsewhere.txt".into(),
+            threads: Some(4),
+            ..Settings::default()
+        };
+        assert_eq!(auto_output_name(&moved, &inputs), name);
+        let mut stricter = Settings::default();
+        stricter.extractor.para.max_len += 1;
+        assert_ne!(config_hash(&stricter), config_hash(&settings));
+        let touched = [InputFile {
+            modified: Some(1700000001),
+            ..inputs[0].clone()
+        }];
+        assert_ne!(input_hash(&touched), input_hash(&inputs));
+    }
+}
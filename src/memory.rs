@@ -0,0 +1,54 @@
+// Share of the limit past which a warning is logged
+const WARN_FRACTION: f64 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryUse {
+    Below,
+    // resident bytes past the warning share of the limit
+    Near(u64),
+    Over(u64),
+}
+
+// Resident memory of this process in bytes, known on Linux only
+pub fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    vm_rss(&status)
+}
+
+fn vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb = line["VmRSS:".len()..].trim().strip_suffix("kB")?;
+    Some(kb.trim().parse::<u64>().ok()? * 1024)
+}
+
+// Below when the resident memory is unknown
+pub fn check(limit_mb: u64) -> MemoryUse {
+    let Some(bytes) = resident_bytes() else {
+        return MemoryUse::Below;
+    };
+    let limit = limit_mb << 20;
+    if bytes > limit {
+        MemoryUse::Over(bytes)
+    } else if bytes as f64 > limit as f64 * WARN_FRACTION {
+        MemoryUse::Near(bytes)
+    } else {
+        MemoryUse::Below
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_resident_memory() {
+        let status = "Name:\tlihkg\nVmPeak:\t  20000 kB\nVmRSS:\t   1536 kB\nThreads:\t4\n";
+        assert_eq!(vm_rss(status), Some(1536 * 1024));
+        assert_eq!(vm_rss("Name:\tlihkg\n"), None);
+        if cfg!(target_os = "linux") {
+            assert!(resident_bytes().unwrap() > 0);
+            assert!(matches!(check(1), MemoryUse::Over(_)));
+            assert_eq!(check(u64::MAX >> 20), MemoryUse::Below);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use crate::bloom::BloomFilter;
+use crate::dedup::{DedupHash, DedupState, DedupStateWriter};
+use crate::sampling::SplitMix64;
+use crate::sink::SinkFile;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use xz2::read::XzDecoder;
+
+// Sentence files of several runs or shards, streamed into one output in
+// input order, and with `MergeDedup::Exact` their --dedup-state files
+// merged into one state.
+pub struct Merge<'a> {
+    pub inputs: &'a [PathBuf],
+    // merged into the output state, they do not drop sentences as they
+    // describe the inputs themselves
+    pub states: &'a [PathBuf],
+    pub hash: DedupHash,
+    pub dedup: MergeDedup,
+    // write the sentences shuffled with this seed, see `ShuffleBuckets`
+    pub shuffle_seed: Option<u64>,
+}
+
+// How a merge drops repeated sentences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeDedup {
+    // none dropped, the inputs concatenated
+    Off,
+    // by a Bloom filter of these bits and hashes, in fixed memory at the
+    // cost of dropping the odd sentence never seen
+    Bloom(u64, u32),
+    // by hash in this many passes over the inputs, each holding only the
+    // hashes falling in its partition, so memory is bounded by the distinct
+    // sentences over the partitions. With more than one partition the
+    // output holds the sentences of each partition in turn, in input order
+    // within it. The only one merging dedup states.
+    Exact(u64),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct MergeCounts {
+    pub sentences: u64,
+    pub duplicates: u64,
+}
+
+impl Merge<'_> {
+    // Writes the merged sentences, zstd compressed for a .zst path, and the
+    // merged state if asked
+    pub fn run(&self, output: &Path, state_output: Option<&Path>) -> io::Result<MergeCounts> {
+        if self.dedup != MergeDedup::Off && self.hash == DedupHash::Identity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "deduplicating a merge needs hashed dedup keys",
+            ));
+        }
+        let partitions = match self.dedup {
+            MergeDedup::Exact(partitions) => partitions.max(1),
+            _ if !self.states.is_empty() || state_output.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only an exact merge takes dedup states",
+                ));
+            }
+            _ => 1,
+        };
+        let mut file = SinkFile::create(output, has_extension(output, "zst"))?;
+        let mut bloom = match self.dedup {
+            MergeDedup::Bloom(bits, hashes) => Some(BloomFilter::new(bits, hashes)),
+            _ => None,
+        };
+        let mut shuffle = match self.shuffle_seed {
+            Some(seed) => Some((ShuffleBuckets::create(output)?, SplitMix64(seed))),
+            None => None,
+        };
+        let mut state = match state_output {
+            Some(path) => Some(DedupStateWriter::create(path, self.hash)?),
+            None => None,
+        };
+        let mut counts = MergeCounts::default();
+        for partition in 0..partitions {
+            let in_partition = |hash: u64| hash % partitions == partition;
+            let mut seen = DedupState {
+                hash: self.hash,
+                sentences: HashMap::new(),
+            };
+            for path in self.states {
+                seen.merge(DedupState::load_where(path, in_partition)?)?;
+            }
+            // sentences of the inputs, apart from those only in the states
+            let mut written = HashSet::new();
+            for path in self.inputs {
+                for line in open_input(path)?.lines() {
+                    let line = line?;
+                    if self.dedup != MergeDedup::Off {
+                        let (text, time) = sentence(&line);
+                        let hash = self.hash.hash(&text).unwrap();
+                        if !in_partition(hash) {
+                            continue;
+                        }
+                        let new = match &mut bloom {
+                            Some(filter) => filter.insert(hash),
+                            None => written.insert(hash),
+                        };
+                        if !new {
+                            counts.duplicates += 1;
+                            continue;
+                        }
+                        if state.is_some() {
+                            let last = seen.sentences.entry(hash).or_insert(time);
+                            *last = (*last).max(time);
+                        }
+                    }
+                    match &mut shuffle {
+                        Some((buckets, rng)) => buckets.push(&line, rng)?,
+                        None => {
+                            let writer = file.writer();
+                            writer.write_all(line.as_bytes())?;
+                            writer.write_all(b"\n")?;
+                        }
+                    }
+                    counts.sentences += 1;
+                }
+            }
+            if let Some(state) = &mut state {
+                for (hash, time) in seen.sentences {
+                    state.push(hash, time)?;
+                }
+            }
+        }
+        if let Some((buckets, mut rng)) = shuffle {
+            buckets.finish(file.writer(), &mut rng)?;
+        }
+        file.finish()?;
+        if let Some(state) = state {
+            state.finish()?;
+        }
+        Ok(counts)
+    }
+}
+
+// Files a shuffle spreads the lines over, each shuffled in memory in turn,
+// so only about 1 / SHUFFLE_BUCKETS of the output is held at a time
+const SHUFFLE_BUCKETS: u64 = 64;
+
+// The lines of a shuffled merge, each put in a random bucket file beside the
+// output and written out by `finish` bucket by bucket, each bucket shuffled
+struct ShuffleBuckets {
+    paths: Vec<PathBuf>,
+    files: Vec<BufWriter<File>>,
+}
+
+impl ShuffleBuckets {
+    fn create(output: &Path) -> io::Result<Self> {
+        let name = output.file_name().unwrap_or_default().to_string_lossy();
+        let paths: Vec<PathBuf> = (0..SHUFFLE_BUCKETS)
+            .map(|i| output.with_file_name(format!("{}.shuffle{}", name, i)))
+            .collect();
+        let files = paths
+            .iter()
+            .map(|path| Ok(BufWriter::new(File::create(path)?)))
+            .collect::<io::Result<_>>()?;
+        Ok(ShuffleBuckets { paths, files })
+    }
+
+    fn push(&mut self, line: &str, rng: &mut SplitMix64) -> io::Result<()> {
+        let file = &mut self.files[rng.below(SHUFFLE_BUCKETS) as usize];
+        file.write_all(line.as_bytes())?;
+        file.write_all(b"\n")
+    }
+
+    fn finish(self, writer: &mut dyn Write, rng: &mut SplitMix64) -> io::Result<()> {
+        for mut file in self.files {
+            file.flush()?;
+        }
+        for path in self.paths {
+            let bucket = std::fs::read_to_string(&path)?;
+            let mut lines: Vec<&str> = bucket.lines().collect();
+            // Fisher-Yates
+            for i in (1..lines.len()).rev() {
+                lines.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+            for line in lines {
+                writer.write_all(line.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+// A text or jsonl output file, decompressed by its .zst or .xz extension
+pub fn open_input(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    Ok(if has_extension(path, "zst") {
+        Box::new(BufReader::new(zstd::Decoder::new(file)?))
+    } else if has_extension(path, "xz") {
+        Box::new(BufReader::new(XzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|e| e == extension)
+}
+
+// The text a line is deduplicated by with its reply_time, the text field
+// of a jsonl record or else the line itself
+fn sentence(line: &str) -> (Cow<'_, str>, i64) {
+    if line.starts_with('{') {
+        if let Ok(record) = serde_json::from_str::<Value>(line) {
+            if let Some(text) = record["text"].as_str() {
+                let time = record["reply_time"].as_i64().unwrap_or(0);
+                return (Cow::Owned(text.to_string()), time);
+            }
+        }
+    }
+    (Cow::Borrowed(line), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_write_every_sentence_once() {
+        let dir = std::env::temp_dir().join(format!("lihkg-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let shard1 = dir.join("shard1.txt");
+        std::fs::write(
+            &shard1,
+            "我哋今日去咗飲茶\n點心好好食呀\n我哋今日去咗飲茶\n",
+        )
+        .unwrap();
+        let shard2 = dir.join("shard2.jsonl.zst");
+        let jsonl = "{\"text\":\"點心好好食呀\",\"reply_time\":5}\n{\"text\":\"聽日再去過先啦\",\"reply_time\":9}\n";
+        std::fs::write(&shard2, zstd::encode_all(jsonl.as_bytes(), 0).unwrap()).unwrap();
+        let mut old = DedupState::default();
+        old.sentences.insert(1, 3);
+        old.save(&dir.join("old.state")).unwrap();
+
+        let inputs = [shard1, shard2];
+        let states = [dir.join("old.state")];
+        let mut outputs = Vec::new();
+        for partitions in [1, 3] {
+            let merge = Merge {
+                inputs: &inputs,
+                states: &states,
+                hash: DedupHash::Xxhash,
+                dedup: MergeDedup::Exact(partitions),
+                shuffle_seed: None,
+            };
+            let output = dir.join(format!("merged{}.txt", partitions));
+            let state = dir.join(format!("merged{}.state", partitions));
+            let counts = merge.run(&output, Some(&state)).unwrap();
+            assert_eq!(
+                counts,
+                MergeCounts {
+                    sentences: 3,
+                    duplicates: 2
+                }
+            );
+            let mut lines: Vec<String> = std::fs::read_to_string(&output)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect();
+            lines.sort();
+            outputs.push(lines);
+            let state = DedupState::load(&state).unwrap();
+            assert_eq!(state.sentences.len(), 4);
+            assert_eq!(state.sentences[&1], 3);
+            assert_eq!(
+                state.sentences[&xxhash_rust::xxh3::xxh3_64("聽日再去過先啦".as_bytes())],
+                9
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn plain_merge_concatenates_the_inputs() {
+        let dir = std::env::temp_dir().join(format!("lihkg-concat-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let inputs = [dir.join("a.txt"), dir.join("b.txt")];
+        std::fs::write(&inputs[0], "我哋今日去咗飲茶\n點心好好食呀\n").unwrap();
+        std::fs::write(&inputs[1], "我哋今日去咗飲茶\n").unwrap();
+        let merge = Merge {
+            inputs: &inputs,
+            states: &[],
+            hash: DedupHash::Identity,
+            dedup: MergeDedup::Off,
+            shuffle_seed: None,
+        };
+        let output = dir.join("merged.txt");
+        let counts = merge.run(&output, None).unwrap();
+        let merged = std::fs::read_to_string(&output).unwrap();
+        let state = merge.run(&output, Some(&dir.join("merged.state")));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            counts,
+            MergeCounts {
+                sentences: 3,
+                duplicates: 0
+            }
+        );
+        assert_eq!(merged, "我哋今日去咗飲茶\n點心好好食呀\n我哋今日去咗飲茶\n");
+        assert!(state.is_err());
+    }
+
+    #[test]
+    fn shuffled_bloom_merge_keeps_each_sentence_once() {
+        let dir = std::env::temp_dir().join(format!("lihkg-shuffle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("sentences.txt");
+        let sentences: Vec<String> = (0..1000).map(|i| format!("第{}句", i)).collect();
+        // every sentence twice, the last line without its newline
+        let twice = [sentences.join("\n"), sentences.join("\n")].join("\n");
+        std::fs::write(&input, twice).unwrap();
+        let inputs = [input];
+        let merged = |seed| {
+            let merge = Merge {
+                inputs: &inputs,
+                states: &[],
+                hash: DedupHash::Xxhash,
+                dedup: {
+                    let (bits, hashes) = crate::bloom::bloom_params(1000, 1e-6);
+                    MergeDedup::Bloom(bits, hashes)
+                },
+                shuffle_seed: Some(seed),
+            };
+            let output = dir.join("merged.txt");
+            let counts = merge.run(&output, None).unwrap();
+            assert_eq!(
+                counts,
+                MergeCounts {
+                    sentences: 1000,
+                    duplicates: 1000
+                }
+            );
+            std::fs::read_to_string(&output).unwrap()
+        };
+        let first = merged(42);
+        assert_eq!(first, merged(42));
+        assert_ne!(first, merged(7));
+        // the buckets are gone
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut lines: Vec<&str> = first.lines().collect();
+        assert_ne!(lines, sentences);
+        lines.sort();
+        let mut expected: Vec<&str> = sentences.iter().map(String::as_str).collect();
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
+}
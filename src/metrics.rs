@@ -0,0 +1,125 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tiny_http::{Header, Response, Server};
+
+// Counters of a run, updated once per archive entry and read by the
+// metrics server
+#[derive(Debug)]
+pub struct Metrics {
+    pub lines: AtomicU64,
+    pub sentences: AtomicU64,
+    // bad json lines and unreadable archive entries
+    pub errors: AtomicU64,
+    // duplicate posts and sentences
+    pub duplicates: AtomicU64,
+    started: Instant,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            lines: AtomicU64::new(0),
+            sentences: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Metrics {
+    // The Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let counters = [
+            (
+                "lihkg_lines_processed_total",
+                "Dump lines read",
+                &self.lines,
+            ),
+            (
+                "lihkg_sentences_emitted_total",
+                "Sentences written",
+                &self.sentences,
+            ),
+            (
+                "lihkg_errors_total",
+                "Bad json lines and unreadable archive entries",
+                &self.errors,
+            ),
+            (
+                "lihkg_duplicates_dropped_total",
+                "Duplicate posts and sentences dropped",
+                &self.duplicates,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            text.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+                counter.load(Ordering::Relaxed)
+            ));
+        }
+        let name = "lihkg_processing_duration_seconds";
+        text.push_str(&format!(
+            "# HELP {name} Seconds since processing started\n# TYPE {name} gauge\n{name} {}\n",
+            self.started.elapsed().as_secs_f64()
+        ));
+        text
+    }
+}
+
+// Serves the metrics on every interface at /metrics from a thread of its
+// own, for as long as the process runs
+pub fn serve(port: u16, metrics: Arc<Metrics>) -> io::Result<()> {
+    let server = Server::http(("0.0.0.0", port)).map_err(io::Error::other)?;
+    let content_type =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4; charset=utf-8").unwrap();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" | "/" => {
+                    Response::from_string(metrics.render()).with_header(content_type.clone())
+                }
+                _ => Response::from_string("not found\n").with_status_code(404),
+            };
+            // a scraper hanging up early is its own problem
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn metrics_are_scraped() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.lines.fetch_add(20, Ordering::Relaxed);
+        metrics.duplicates.fetch_add(3, Ordering::Relaxed);
+        // a port free a moment ago
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        serve(port, metrics).unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+        assert!(response.contains("\nlihkg_lines_processed_total 20\n"));
+        assert!(response.contains("\nlihkg_sentences_emitted_total 0\n"));
+        assert!(response.contains("\nlihkg_duplicates_dropped_total 3\n"));
+        assert!(response.contains("# TYPE lihkg_processing_duration_seconds gauge\n"));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+// Bucket of sentences without a post time
+pub const UNKNOWN_MONTH: &str = "unknown";
+
+// Name of the table of sentences per month, next to the month files
+pub const MONTHS_TSV: &str = "months.tsv";
+
+// Hong Kong time, UTC+8 without daylight saving since 1979, so a post
+// lands in the month its poster saw
+const HKT_OFFSET_SECS: i64 = 8 * 3600;
+
+// The YYYY-MM of a unix reply time in Hong Kong time
+pub fn month_key(reply_time: Option<i64>) -> String {
+    let Some(secs) = reply_time else {
+        return UNKNOWN_MONTH.to_string();
+    };
+    let (year, month, _) = civil_date((secs + HKT_OFFSET_SECS).div_euclid(86400));
+    format!("{:04}-{:02}", year, month)
+}
+
+// The civil year, month and day of days since 1970-01-01, after Howard
+// Hinnant's days_from_civil inverse
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months counted from March
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Sentences per month in order, the unknown bucket last
+pub fn months_tsv(counts: &BTreeMap<String, u64>) -> String {
+    let mut tsv = "month\tsentences\n".to_string();
+    for (month, count) in counts {
+        tsv.push_str(&format!("{}\t{}\n", month, count));
+    }
+    tsv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posts_fall_in_their_hong_kong_month() {
+        assert_eq!(month_key(Some(0)), "1970-01");
+        // 2023-10-31 23:00 in Hong Kong, 15:00 UTC
+        assert_eq!(month_key(Some(1698764400)), "2023-10");
+        // 2023-11-01 00:30 in Hong Kong, still October in UTC
+        assert_eq!(month_key(Some(1698769800)), "2023-11");
+        assert_eq!(month_key(Some(1709164800)), "2024-02");
+        assert_eq!(month_key(None), UNKNOWN_MONTH);
+        let counts = BTreeMap::from([
+            ("unknown".to_string(), 1),
+            ("2023-11".to_string(), 2),
+            ("2023-10".to_string(), 3),
+        ]);
+        assert_eq!(
+            months_tsv(&counts),
+            "month\tsentences\n2023-10\t3\n2023-11\t2\nunknown\t1\n"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::config::ExtractorConfig;
+use crate::filters::CANTONESE_MARKERS;
+use aho_corasick::AhoCorasick;
+
+// Phrases of newswire copy, bylines and source credits rarely typed by
+// posters themselves
+pub const NEWSWIRE_PHRASES: &[&str] = &[
+    "【",
+    "記者",
+    "報道",
+    "報導",
+    "綜合報道",
+    "據悉",
+    "本報訊",
+    "新華社",
+    "中央社",
+    "通訊社",
+];
+
+// Tells posts pasting a news article from posts written by their poster: an
+// article has many long paragraphs in standard written Chinese and carries
+// the phrases of its wire. A long rant may share the first two and even a
+// phrase, but not the lack of Cantonese.
+pub struct NewsDetector {
+    min_paragraphs: usize,
+    min_avg_len: usize,
+    // `CANTONESE_MARKERS` per non-space char
+    max_cantonese_density: f64,
+    phrases: AhoCorasick,
+}
+
+impl NewsDetector {
+    pub fn new(config: &ExtractorConfig) -> Self {
+        NewsDetector {
+            min_paragraphs: config.news_min_paragraphs,
+            min_avg_len: config.news_min_avg_len,
+            max_cantonese_density: config.news_max_cantonese_density,
+            phrases: AhoCorasick::new(NEWSWIRE_PHRASES).unwrap(),
+        }
+    }
+
+    // Whether the text of a post, after conversion, reads as a news article
+    pub fn is_news(&self, text: &str) -> bool {
+        let paragraphs: Vec<&str> = text
+            .split('\n')
+            .map(str::trim)
+            .filter(|para| !para.is_empty())
+            .collect();
+        if paragraphs.is_empty() || paragraphs.len() < self.min_paragraphs {
+            return false;
+        }
+        let chars: usize = paragraphs.iter().map(|para| para.chars().count()).sum();
+        if chars / paragraphs.len() < self.min_avg_len {
+            return false;
+        }
+        let non_space = text.chars().filter(|c| !c.is_whitespace()).count();
+        let markers = text
+            .chars()
+            .filter(|&c| CANTONESE_MARKERS.contains(c))
+            .count();
+        markers as f64 / non_space as f64 <= self.max_cantonese_density
+            && self.phrases.is_match(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{convert_post, Markup};
+
+    #[test]
+    fn flags_a_pasted_article_but_not_a_rant() {
+        let detector = NewsDetector::new(&ExtractorConfig::default());
+        let text = |html| convert_post(html, Markup::default()).0;
+        let article = text(include_str!("../tests/fixtures/news_article.html"));
+        let rant = text(include_str!("../tests/fixtures/long_rant.html"));
+        assert!(detector.is_news(&article));
+        // as long and as many paragraphs, and it mentions 記者
+        assert!(!detector.is_news(&rant));
+        // the same article cut down to a quoted line or two
+        let excerpt: Vec<&str> = article.lines().take(3).collect();
+        assert!(!detector.is_news(&excerpt.join("\n")));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::quality::Quality;
+use crate::sink::{SentenceSink, SinkReport};
+use crate::{cjk_ratio, SentenceRecord};
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, FloatType, Int32Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+const SCHEMA: &str = "message sentence {
+    REQUIRED BYTE_ARRAY text (UTF8);
+    OPTIONAL BYTE_ARRAY source_file (UTF8);
+    REQUIRED FLOAT cjk_ratio;
+    REQUIRED INT32 char_count;
+}";
+
+// Columns added with --score-all
+const QUALITY_SCHEMA: &str = "
+    REQUIRED DOUBLE quality;
+    REQUIRED INT32 length;
+    REQUIRED DOUBLE cjk_fraction;
+    REQUIRED DOUBLE repetition;
+    REQUIRED BOOLEAN has_url;
+    REQUIRED INT32 blocklist_hits;
+    REQUIRED INT32 cantonese_markers;
+";
+
+struct Row {
+    text: String,
+    source_file: Option<String>,
+    cjk_ratio: f32,
+    char_count: i32,
+    quality: Quality,
+}
+
+// The written sentences as a snappy compressed Parquet file, in row groups
+// of `row_group_size` rows. Rows taken by `push` get their source file from
+// the next `set_source`, as the archive entry they came from is known once
+// it is complete.
+pub struct ParquetOutput {
+    writer: SerializedFileWriter<File>,
+    row_group_size: usize,
+    // whether the quality columns are written
+    quality: bool,
+    unsourced: Vec<Row>,
+    rows: Vec<Row>,
+    // rows in the row groups written
+    written: u64,
+}
+
+impl ParquetOutput {
+    pub fn create(path: &Path, row_group_size: usize, quality: bool) -> io::Result<Self> {
+        let schema = if quality {
+            SCHEMA.replace('}', &format!("{}}}", QUALITY_SCHEMA))
+        } else {
+            SCHEMA.to_string()
+        };
+        let schema = Arc::new(parse_message_type(&schema).unwrap());
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(row_group_size)
+            .build();
+        let writer = SerializedFileWriter::new(File::create(path)?, schema, Arc::new(properties))
+            .map_err(io::Error::other)?;
+        Ok(ParquetOutput {
+            writer,
+            row_group_size: row_group_size.max(1),
+            quality,
+            unsourced: Vec::new(),
+            rows: Vec::new(),
+            written: 0,
+        })
+    }
+
+    pub fn push(&mut self, record: &SentenceRecord) {
+        self.unsourced.push(Row {
+            text: record.text.clone(),
+            source_file: None,
+            cjk_ratio: cjk_ratio(&record.text) as f32,
+            char_count: record.text.chars().count() as i32,
+            quality: record.quality.unwrap_or_default(),
+        });
+    }
+
+    // Gives the rows pushed since the last call their source, writing the
+    // row groups filled
+    pub fn set_source(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        for mut row in self.unsourced.drain(..) {
+            row.source_file = source_file.map(String::from);
+            self.rows.push(row);
+        }
+        while self.rows.len() >= self.row_group_size {
+            let rest = self.rows.split_off(self.row_group_size);
+            let rows = std::mem::replace(&mut self.rows, rest);
+            self.write_row_group(&rows).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    // Writes the last row group and the footer
+    pub fn finish(mut self) -> io::Result<()> {
+        self.set_source(None)?;
+        let rows = std::mem::take(&mut self.rows);
+        if !rows.is_empty() {
+            self.write_row_group(&rows).map_err(io::Error::other)?;
+        }
+        self.writer.close().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn write_row_group(&mut self, rows: &[Row]) -> Result<(), ParquetError> {
+        self.written += rows.len() as u64;
+        let mut row_group = self.writer.next_row_group()?;
+        let texts: Vec<ByteArray> = rows.iter().map(|r| r.text.as_str().into()).collect();
+        let sources: Vec<ByteArray> = rows
+            .iter()
+            .filter_map(|r| r.source_file.as_deref())
+            .map(ByteArray::from)
+            .collect();
+        // 1 where the optional source is present
+        let source_levels: Vec<i16> = rows
+            .iter()
+            .map(|r| r.source_file.is_some() as i16)
+            .collect();
+        let ratios: Vec<f32> = rows.iter().map(|r| r.cjk_ratio).collect();
+        let counts: Vec<i32> = rows.iter().map(|r| r.char_count).collect();
+
+        let mut column = row_group.next_column()?.unwrap();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&texts, None, None)?;
+        column.close()?;
+        let mut column = row_group.next_column()?.unwrap();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&sources, Some(&source_levels), None)?;
+        column.close()?;
+        let mut column = row_group.next_column()?.unwrap();
+        column
+            .typed::<FloatType>()
+            .write_batch(&ratios, None, None)?;
+        column.close()?;
+        let mut column = row_group.next_column()?.unwrap();
+        column
+            .typed::<Int32Type>()
+            .write_batch(&counts, None, None)?;
+        column.close()?;
+        if self.quality {
+            let doubles = |f: fn(&Quality) -> f64| -> Vec<f64> {
+                rows.iter().map(|r| f(&r.quality)).collect()
+            };
+            let ints = |f: fn(&Quality) -> usize| -> Vec<i32> {
+                rows.iter().map(|r| f(&r.quality) as i32).collect()
+            };
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<DoubleType>()
+                .write_batch(&doubles(|q| q.quality), None, None)?;
+            column.close()?;
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<Int32Type>()
+                .write_batch(&ints(|q| q.length), None, None)?;
+            column.close()?;
+            for values in [doubles(|q| q.cjk_fraction), doubles(|q| q.repetition)] {
+                let mut column = row_group.next_column()?.unwrap();
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&values, None, None)?;
+                column.close()?;
+            }
+            let has_url: Vec<bool> = rows.iter().map(|r| r.quality.has_url).collect();
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<BoolType>()
+                .write_batch(&has_url, None, None)?;
+            column.close()?;
+            for values in [ints(|q| q.blocklist_hits), ints(|q| q.cantonese_markers)] {
+                let mut column = row_group.next_column()?.unwrap();
+                column
+                    .typed::<Int32Type>()
+                    .write_batch(&values, None, None)?;
+                column.close()?;
+            }
+        }
+        row_group.close()?;
+        Ok(())
+    }
+}
+
+impl SentenceSink for ParquetOutput {
+    fn write(&mut self, record: &SentenceRecord) -> io::Result<()> {
+        self.push(record);
+        Ok(())
+    }
+
+    fn end_entry(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        self.set_source(source_file)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<SinkReport> {
+        self.set_source(None)?;
+        let records = self.written + self.rows.len() as u64;
+        ParquetOutput::finish(*self)?;
+        Ok(SinkReport {
+            records,
+            bytes: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    #[test]
+    fn rows_are_written_in_groups() {
+        let path = std::env::temp_dir().join(format!("lihkg-{}.parquet", std::process::id()));
+        let mut output = ParquetOutput::create(&path, 2, false).unwrap();
+        for text in ["我哋今日去咗飲茶", "點心好好食", "OK啦"] {
+            output.push(&SentenceRecord {
+                text: text.to_string(),
+                ..Default::default()
+            });
+        }
+        output.set_source(Some("3300001.csv")).unwrap();
+        output.push(&SentenceRecord {
+            text: "我覺得你講得啱".to_string(),
+            ..Default::default()
+        });
+        output.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows: Vec<Vec<Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.unwrap()
+                    .get_column_iter()
+                    .map(|(_, f)| f.clone())
+                    .collect()
+            })
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[2],
+            [
+                Field::Str("OK啦".to_string()),
+                Field::Str("3300001.csv".to_string()),
+                Field::Float(1.0 / 3.0),
+                Field::Int(3)
+            ]
+        );
+        assert_eq!(rows[3][1], Field::Null);
+    }
+}
@@ -0,0 +1,700 @@
+use crate::encoding::{transcode, NonUtf8Line};
+use crate::memory::{self, MemoryUse};
+use crate::throttle::{ReadLimit, Throttled};
+use crate::{Batch, Extractor};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::Instant;
+use tar::Archive;
+use xxhash_rust::xxh64::xxh64;
+use xz2::read::XzDecoder;
+
+#[derive(Clone)]
+pub struct EntryInfo {
+    pub name: String,
+    pub started: Instant,
+    // set when the entry is emitted in parts, see `process_entry`
+    pub part: Option<usize>,
+}
+
+impl EntryInfo {
+    fn start(name: String) -> Self {
+        EntryInfo {
+            name,
+            started: Instant::now(),
+            part: None,
+        }
+    }
+}
+
+// One of `count` disjoint parts of the input. Entries of a multi-entry
+// archive are assigned by the xxh64 hash of their path, single-entry inputs
+// line by line by line number, so the assignment depends on nothing but the
+// input and running every index covers it exactly once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    pub fn owns_entry(&self, name: &str) -> bool {
+        xxh64(name.as_bytes(), 0) % self.count == self.index
+    }
+
+    pub fn owns_line(&self, line_number: usize) -> bool {
+        line_number as u64 % self.count == self.index
+    }
+
+    // The lines owned of those numbered from `first`
+    fn filter_lines(&self, first: usize, lines: Vec<String>) -> Vec<String> {
+        lines
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.owns_line(first + i))
+            .map(|(_, line)| line)
+            .collect()
+    }
+}
+
+// Runs the extractor over every entry of a .tar.xz archive, handing each
+// entry's batch to `emit` in archive order. Any other input, such as a
+// single .csv.xz dump or the output of `fetch`, is read as one entry of
+// lines, decompressed if it ends in .xz. Lines failing to parse are counted
+// in the batch stats and skipped. With a shard, entries and lines of other
+// shards are skipped.
+//
+// An entry that cannot be read, e.g. truncated or failing to decompress, is
+// logged and emitted as an empty batch counting one corrupt entry. If the
+// archive stream itself breaks, the entries before it are kept and the run
+// ends there.
+pub fn process_archive(
+    path: &Path,
+    extractor: &Extractor,
+    shard: Option<Shard>,
+    mut emit: impl FnMut(&EntryInfo, Batch) -> io::Result<()>,
+) -> io::Result<()> {
+    let file = BufReader::new(Throttled::new(
+        File::open(path)?,
+        extractor.read_limit.as_ref(),
+    ));
+    let name = path.to_string_lossy().into_owned();
+    if !name.ends_with(".tar.xz") {
+        let compressed = name.ends_with(".xz");
+        let entry = EntryInfo::start(name);
+        return if compressed {
+            let reader = BufReader::new(XzDecoder::new(file));
+            extract_entry(&entry, reader, extractor, shard, &mut emit)
+        } else {
+            extract_entry(&entry, file, extractor, shard, &mut emit)
+        };
+    }
+    // with a memory limit the first entry is not held, the archive is read
+    // ahead instead to tell whether it is the only one
+    let only_entry = match (shard, extractor.max_memory_mb) {
+        (Some(_), Some(_)) => Some(has_one_entry(path, extractor)?),
+        _ => None,
+    };
+    let tar = XzDecoder::new(file);
+    let mut archive = Archive::new(tar);
+
+    // with a shard the first entry is held until we know whether it is the
+    // only one
+    let mut first = None;
+    for (i, file) in archive.entries()?.enumerate() {
+        let file = match file.and_then(|file| Ok((file.path()?.into_owned(), file))) {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::error!("archive unreadable after {} entries, stopping: {}", i, e);
+                break;
+            }
+        };
+        let (name, file) = file;
+        let entry = EntryInfo::start(name.to_string_lossy().into_owned());
+        let Some(shard) = shard else {
+            extract_entry(&entry, BufReader::new(file), extractor, None, &mut emit)?;
+            continue;
+        };
+        match only_entry {
+            Some(true) => {
+                extract_entry(
+                    &entry,
+                    BufReader::new(file),
+                    extractor,
+                    Some(shard),
+                    &mut emit,
+                )?;
+                continue;
+            }
+            Some(false) => {
+                if shard.owns_entry(&entry.name) {
+                    extract_entry(&entry, BufReader::new(file), extractor, None, &mut emit)?;
+                }
+                continue;
+            }
+            None => {}
+        }
+        if i == 0 {
+            first = Some((entry, read_lines(BufReader::new(file), extractor)));
+            continue;
+        }
+        if let Some((first, read)) = first.take() {
+            if shard.owns_entry(&first.name) {
+                match read {
+                    Ok((lines, decoding)) => {
+                        process_entry(&first, &lines, decoding, extractor, &mut emit)?
+                    }
+                    Err(e) => report_corrupt(&first, e, &mut emit)?,
+                }
+            }
+        }
+        if shard.owns_entry(&entry.name) {
+            extract_entry(&entry, BufReader::new(file), extractor, None, &mut emit)?;
+        }
+    }
+    // the only entry, split by lines, is reported corrupt by shard 0 alone
+    if let (Some((entry, read)), Some(shard)) = (first, shard) {
+        match read {
+            Ok((lines, decoding)) => {
+                let lines = shard.filter_lines(0, lines);
+                process_entry(&entry, &lines, decoding, extractor, &mut emit)?;
+            }
+            Err(e) if shard.index == 0 => report_corrupt(&entry, e, &mut emit)?,
+            Err(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Whether the archive holds a single entry, reading it through to the
+// header of the second one
+fn has_one_entry(path: &Path, extractor: &Extractor) -> io::Result<bool> {
+    let file = BufReader::new(Throttled::new(
+        File::open(path)?,
+        extractor.read_limit.as_ref(),
+    ));
+    let mut archive = Archive::new(XzDecoder::new(file));
+    // an unreadable stream ends the entries as in `process_archive`
+    let entries = archive.entries()?.take(2).take_while(Result::is_ok);
+    Ok(entries.count() <= 1)
+}
+
+// Reads and extracts an entry, with a shard only the lines it owns by line
+// number
+fn extract_entry(
+    entry: &EntryInfo,
+    reader: impl BufRead,
+    extractor: &Extractor,
+    shard: Option<Shard>,
+    emit: &mut impl FnMut(&EntryInfo, Batch) -> io::Result<()>,
+) -> io::Result<()> {
+    if let Some(limit_mb) = extractor.max_memory_mb {
+        return stream_entry(entry, reader, extractor, shard, limit_mb, emit);
+    }
+    let Some((lines, decoding)) = read_entry(entry, reader, extractor, emit)? else {
+        return Ok(());
+    };
+    let lines = match shard {
+        Some(shard) => shard.filter_lines(0, lines),
+        None => lines,
+    };
+    process_entry(entry, &lines, decoding, extractor, emit)
+}
+
+// The lines of an entry with a batch counting the lines read in another
+// encoding, or None after reporting it as corrupt
+fn read_entry(
+    entry: &EntryInfo,
+    reader: impl BufRead,
+    extractor: &Extractor,
+    emit: &mut impl FnMut(&EntryInfo, Batch) -> io::Result<()>,
+) -> io::Result<Option<(Vec<String>, Batch)>> {
+    match read_lines(reader, extractor) {
+        Ok(read) => Ok(Some(read)),
+        Err(e) => {
+            report_corrupt(entry, e, emit)?;
+            Ok(None)
+        }
+    }
+}
+
+fn read_lines(reader: impl BufRead, extractor: &Extractor) -> io::Result<(Vec<String>, Batch)> {
+    let mut chunks = LineChunks::new(reader, extractor.detect_encoding);
+    Ok(chunks.next(usize::MAX)?.unwrap_or_default())
+}
+
+fn report_corrupt(
+    entry: &EntryInfo,
+    e: io::Error,
+    emit: &mut impl FnMut(&EntryInfo, Batch) -> io::Result<()>,
+) -> io::Result<()> {
+    tracing::warn!("skipping corrupt entry {}: {}", entry.name, e);
+    let mut batch = Batch::default();
+    batch.stats.corrupt_entries += 1;
+    emit(entry, batch)
+}
+
+// The lines of an entry a chunk at a time, as `BufRead::lines` gives them
+// but split at a lone "\r" too. When detecting encodings, those that are not
+// UTF-8 are read in the encoding detected for them, or dropped when that
+// fails.
+struct LineChunks<R> {
+    reader: R,
+    detect_encoding: bool,
+    // lines read so far, counting a "\r" as part of its line
+    read: usize,
+}
+
+impl<R: BufRead> LineChunks<R> {
+    fn new(reader: R, detect_encoding: bool) -> Self {
+        LineChunks {
+            reader,
+            detect_encoding,
+            read: 0,
+        }
+    }
+
+    // The next `n` lines with a batch counting those read in another
+    // encoding, None at the end
+    fn next(&mut self, n: usize) -> io::Result<Option<(Vec<String>, Batch)>> {
+        let mut lines = Vec::new();
+        let mut decoding = Batch::default();
+        let mut read = 0;
+        while read < n {
+            let mut bytes = Vec::new();
+            if self.reader.read_until(b'\n', &mut bytes)? == 0 {
+                break;
+            }
+            read += 1;
+            self.read += 1;
+            if bytes.last() == Some(&b'\n') {
+                bytes.pop();
+                if bytes.last() == Some(&b'\r') {
+                    bytes.pop();
+                }
+            }
+            let bytes = match String::from_utf8(bytes) {
+                Ok(line) => {
+                    lines.push(line);
+                    continue;
+                }
+                Err(e) if self.detect_encoding => e.into_bytes(),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            let transcoded = transcode(&bytes);
+            decoding.non_utf8.push(NonUtf8Line {
+                line: self.read,
+                encoding: transcoded.as_ref().map(|(_, encoding)| encoding.name()),
+                text: String::from_utf8_lossy(&bytes).into_owned(),
+            });
+            match transcoded {
+                Some((line, _)) => {
+                    decoding.stats.transcoded_lines += 1;
+                    lines.push(line);
+                }
+                None => decoding.stats.encoding_errors += 1,
+            }
+        }
+        if read == 0 {
+            return Ok(None);
+        }
+        Ok(Some((split_carriage_returns(lines), decoding)))
+    }
+}
+
+// `LineChunks` ends lines at "\n" and "\r\n", this also ends them at a lone
+// "\r" and drops the empty lines of a "\r\r\n"
+fn split_carriage_returns(lines: Vec<String>) -> Vec<String> {
+    if !lines.iter().any(|line| line.contains('\r')) {
+        return lines;
+    }
+    lines
+        .iter()
+        .flat_map(|line| {
+            let mut parts: Vec<&str> = line.split('\r').collect();
+            if parts.len() > 1 {
+                parts.retain(|part| !part.is_empty());
+            }
+            parts
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+// Reads every entry through without extracting, returning the entries that
+// cannot be read with their errors. An unreadable archive stream is reported
+// under the name of the archive.
+pub fn verify_archive(
+    path: &Path,
+    limit: Option<&ReadLimit>,
+) -> io::Result<Vec<(String, io::Error)>> {
+    let file = BufReader::new(Throttled::new(File::open(path)?, limit));
+    let name = path.to_string_lossy().into_owned();
+    let mut problems = Vec::new();
+    if !name.ends_with(".tar.xz") {
+        let result = if name.ends_with(".xz") {
+            io::copy(&mut XzDecoder::new(file), &mut io::sink())
+        } else {
+            io::copy(&mut { file }, &mut io::sink())
+        };
+        if let Err(e) = result {
+            problems.push((name, e));
+        }
+        return Ok(problems);
+    }
+    let mut archive = Archive::new(XzDecoder::new(file));
+    for file in archive.entries()? {
+        let mut file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                problems.push((name, e));
+                break;
+            }
+        };
+        let entry = match file.path() {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(e) => {
+                problems.push((name, e));
+                break;
+            }
+        };
+        if let Err(e) = io::copy(&mut file, &mut io::sink()) {
+            problems.push((entry, e));
+        }
+    }
+    Ok(problems)
+}
+
+// Lines read and extracted between checks of the memory limit
+const MEMORY_CHECK_LINES: usize = 10_000;
+
+fn process_entry(
+    entry: &EntryInfo,
+    lines: &[String],
+    mut decoding: Batch,
+    extractor: &Extractor,
+    emit: &mut impl FnMut(&EntryInfo, Batch) -> io::Result<()>,
+) -> io::Result<()> {
+    let _span = tracing::info_span!("process_archive", entry = %entry.name).entered();
+    decoding.merge(extract_lines(lines, extractor));
+    emit(entry, decoding)
+}
+
+// With a memory limit the entry is read and extracted a chunk of lines at a
+// time, so only the chunk and the results not written yet are held. Whenever
+// the resident memory is over the limit after a chunk, the results so far
+// are emitted and dropped, and the entry continues in a new part. An entry
+// failing to read partway keeps the parts emitted before and drops the rest
+// as a corrupt entry.
+fn stream_entry(
+    entry: &EntryInfo,
+    reader: impl BufRead,
+    extractor: &Extractor,
+    shard: Option<Shard>,
+    limit_mb: u64,
+    emit: &mut impl FnMut(&EntryInfo, Batch) -> io::Result<()>,
+) -> io::Result<()> {
+    let _span = tracing::info_span!("process_archive", entry = %entry.name).entered();
+    let mut chunks = LineChunks::new(reader, extractor.detect_encoding);
+    let mut part = entry.clone();
+    let mut batch = Batch::default();
+    let mut near = false;
+    // the line number of the chunk's first line, for the shard
+    let mut first_line = 0;
+    loop {
+        let (lines, decoding) = match chunks.next(MEMORY_CHECK_LINES) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => return report_corrupt(entry, e, emit),
+        };
+        let count = lines.len();
+        let lines = match shard {
+            Some(shard) => shard.filter_lines(first_line, lines),
+            None => lines,
+        };
+        first_line += count;
+        batch.merge(decoding);
+        batch.merge(extract_lines(&lines, extractor));
+        drop(lines);
+        match memory::check(limit_mb) {
+            MemoryUse::Below => near = false,
+            MemoryUse::Near(bytes) => {
+                if !near {
+                    tracing::warn!(
+                        "{} MB resident, near the limit of {} MB",
+                        bytes >> 20,
+                        limit_mb
+                    );
+                }
+                near = true;
+            }
+            MemoryUse::Over(bytes) => {
+                tracing::warn!(
+                    "{} MB resident, over the limit of {} MB, writing {} early",
+                    bytes >> 20,
+                    limit_mb,
+                    part.name
+                );
+                let number = part.part.unwrap_or(0);
+                part.part = Some(number);
+                emit(&part, std::mem::take(&mut batch))?;
+                part.part = Some(number + 1);
+            }
+        }
+    }
+    // nothing is left when the last chunk was written early
+    if part.part.is_some() && batch.stats.lines == 0 {
+        return Ok(());
+    }
+    emit(&part, batch)
+}
+
+fn extract_lines(lines: &[String], extractor: &Extractor) -> Batch {
+    // rayon's own bounds unless the batch size is set
+    let (min_len, max_len) = match extractor.batch_size {
+        Some(n) => (n, n.saturating_mul(4)),
+        None => (1, usize::MAX),
+    };
+    // the workers report their lines under the entry's span
+    let span = tracing::Span::current();
+    lines
+        .par_iter()
+        .with_min_len(min_len)
+        .with_max_len(max_len)
+        .fold(Batch::default, |mut batch, line| {
+            if let Err(e) = extractor.process_line(line, &mut batch) {
+                span.in_scope(|| tracing::warn!("skipping unparsable line: {}", e));
+                batch.stats.json_errors += 1;
+            }
+            batch
+        })
+        .reduce(Batch::default, |mut batch1, batch2| {
+            batch1.merge(batch2);
+            batch1
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ExtractorConfig, ParaConfig};
+    use std::io::Write;
+    use std::sync::Arc;
+    use xz2::write::XzEncoder;
+
+    // A .tar.xz of entries with distinct pseudo-random sentences, poorly
+    // compressible so cutting the file short lands inside the last entry
+    fn write_archive(path: &Path, entries: usize) {
+        let mut tar = tar::Builder::new(XzEncoder::new(File::create(path).unwrap(), 6));
+        let mut seed = 1u32;
+        for e in 0..entries {
+            let data = entry_lines(&mut seed, 300);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, format!("{}.csv", e), data.as_bytes())
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap().flush().unwrap();
+    }
+
+    // `lines` lines of a post each
+    fn entry_lines(seed: &mut u32, lines: usize) -> String {
+        let mut data = String::new();
+        for _ in 0..lines {
+            let msg: String = (0..12)
+                .map(|_| {
+                    *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    char::from_u32(0x4e00 + (*seed >> 16) % 0x5000).unwrap()
+                })
+                .collect();
+            data.push_str(&format!(
+                "a\tb\t{{\"success\":1,\"response\":{{\"item_data\":[{{\"msg\":\"{}\"}}]}}}}\n",
+                msg
+            ));
+        }
+        data
+    }
+
+    // The sentences and the lines and parse errors counted, over every batch
+    fn extract_all(
+        path: &Path,
+        max_memory_mb: Option<u64>,
+        shard: Option<Shard>,
+    ) -> (Vec<String>, u64, u64) {
+        let extractor = Extractor::new(&ExtractorConfig {
+            max_memory_mb,
+            ..Default::default()
+        })
+        .unwrap();
+        let (mut sentences, mut lines, mut errors) = (Vec::new(), 0, 0);
+        process_archive(path, &extractor, shard, |_, batch| {
+            sentences.extend(batch.records.into_iter().map(|r| r.text));
+            lines += batch.stats.lines;
+            errors += batch.stats.json_errors;
+            Ok(())
+        })
+        .unwrap();
+        (sentences, lines, errors)
+    }
+
+    #[test]
+    fn truncated_entries_are_skipped() {
+        let path = std::env::temp_dir().join(format!("lihkg-trunc-{}.tar.xz", std::process::id()));
+        write_archive(&path, 3);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() * 5 / 6]).unwrap();
+
+        let problems = verify_archive(&path, None).unwrap();
+        assert_eq!(problems[0].0, "2.csv");
+
+        let extractor = Extractor::new(&ExtractorConfig::default()).unwrap();
+        let mut emitted = Vec::new();
+        process_archive(&path, &extractor, None, |entry, batch| {
+            emitted.push((
+                entry.name.clone(),
+                batch.stats.corrupt_entries,
+                batch.records.len(),
+            ));
+            Ok(())
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            emitted,
+            vec![
+                ("0.csv".to_string(), 0, 300),
+                ("1.csv".to_string(), 0, 300),
+                ("2.csv".to_string(), 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_corrupt_only_entry_is_counted_by_one_shard() {
+        let path = std::env::temp_dir().join(format!("lihkg-shard-{}.tar.xz", std::process::id()));
+        write_archive(&path, 1);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let extractor = Extractor::new(&ExtractorConfig::default()).unwrap();
+        let mut corrupt = Vec::new();
+        for index in 0..3 {
+            let shard = Shard { index, count: 3 };
+            process_archive(&path, &extractor, Some(shard), |_, batch| {
+                corrupt.push((index, batch.stats.corrupt_entries));
+                Ok(())
+            })
+            .unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(corrupt, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn workers_share_the_para_config() {
+        let path = std::env::temp_dir().join(format!("lihkg-share-{}.tar.xz", std::process::id()));
+        write_archive(&path, 2);
+        // small jobs, so every worker gets some
+        let extractor = Extractor::new(&ExtractorConfig {
+            batch_size: Some(8),
+            ..Default::default()
+        })
+        .unwrap();
+        let para = extractor.para_config();
+        let mut sentences = 0;
+        process_archive(&path, &extractor, None, |_, batch| {
+            sentences += batch.records.len();
+            Ok(())
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sentences, 600);
+        // the workers read the extractor's config, none kept a handle or a
+        // config of its own
+        assert_eq!(Arc::strong_count(&para), 2);
+        let on_workers: Vec<Arc<ParaConfig>> = (0..64)
+            .into_par_iter()
+            .map(|_| extractor.para_config())
+            .collect();
+        assert!(on_workers.iter().all(|p| Arc::ptr_eq(p, &para)));
+    }
+
+    // the memory check reads /proc
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn entries_over_the_memory_limit_are_emitted_in_parts() {
+        let path = std::env::temp_dir().join(format!("lihkg-parts-{}.csv", std::process::id()));
+        std::fs::write(&path, "a\tb\t{}\n".repeat(MEMORY_CHECK_LINES * 2 + 1)).unwrap();
+        // any process is over a 1 MB limit
+        let extractor = Extractor::new(&ExtractorConfig {
+            max_memory_mb: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut emitted = Vec::new();
+        process_archive(&path, &extractor, None, |entry, batch| {
+            emitted.push((entry.part, batch.stats.lines));
+            Ok(())
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let chunk = MEMORY_CHECK_LINES as u64;
+        assert_eq!(emitted, [(Some(0), chunk), (Some(1), chunk), (Some(2), 1)]);
+    }
+
+    // the memory check reads /proc
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn chunked_entries_give_the_same_output() {
+        let dir = std::env::temp_dir();
+        let csv = dir.join(format!("lihkg-chunks-{}.csv", std::process::id()));
+        // over two chunks, with lines failing to parse and a lone "\r"
+        let mut seed = 1u32;
+        let mut data = entry_lines(&mut seed, MEMORY_CHECK_LINES * 2 + 7);
+        data.insert_str(0, "not json\n");
+        data.push_str("not json either\r");
+        data.push_str(&entry_lines(&mut seed, 3));
+        std::fs::write(&csv, data).unwrap();
+        let single = dir.join(format!("lihkg-chunks-{}.tar.xz", std::process::id()));
+        write_archive(&single, 1);
+        let several = dir.join(format!("lihkg-chunks-many-{}.tar.xz", std::process::id()));
+        write_archive(&several, 5);
+
+        let shards = [None, Some(Shard { index: 1, count: 3 })];
+        for path in [&csv, &single, &several] {
+            for shard in shards {
+                let whole = extract_all(path, None, shard);
+                assert!(!whole.0.is_empty());
+                // every chunk written early, and none
+                assert_eq!(extract_all(path, Some(1), shard), whole);
+                assert_eq!(extract_all(path, Some(1 << 40), shard), whole);
+            }
+        }
+        for path in [csv, single, several] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn shards_partition_entries_and_lines() {
+        let shards: Vec<Shard> = (0..3).map(|index| Shard { index, count: 3 }).collect();
+        for name in ["a.csv", "b.csv", "3300000.csv", "dump/3300001.csv"] {
+            assert_eq!(shards.iter().filter(|s| s.owns_entry(name)).count(), 1);
+        }
+        let lines: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        let mut seen: Vec<String> = shards
+            .iter()
+            .flat_map(|s| s.filter_lines(0, lines.clone()))
+            .collect();
+        seen.sort_by_key(|line| line.parse::<u32>().unwrap());
+        assert_eq!(seen, lines);
+        // pinned so the assignment cannot silently change between versions
+        assert_eq!(xxh64(b"3300000.csv", 0), 6336351428140897995);
+        assert!(shards[6336351428140897995 % 3].owns_entry("3300000.csv"));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::scorer::BATCH_SIZE;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+
+// A long-running external command reading one sentence per line on stdin
+// and answering with its replacement, one line per sentence, on stdout. Like
+// the score command it must flush its answers per batch.
+//
+// A command exiting drops the sentence it had not answered, which is logged,
+// and is started again for the sentences after it. An empty answer drops
+// its sentence without an error.
+pub struct PostProcessCommand {
+    command: String,
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl PostProcessCommand {
+    // The command is run through `sh -c` so it can carry its own arguments
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().unwrap());
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(PostProcessCommand {
+            command: command.to_string(),
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    // The replacement of each sentence, None for the dropped ones
+    pub fn process(&mut self, sentences: &[&str]) -> io::Result<Vec<Option<String>>> {
+        let mut replaced = Vec::with_capacity(sentences.len());
+        while replaced.len() < sentences.len() {
+            let rest = &sentences[replaced.len()..];
+            self.process_batch(&rest[..rest.len().min(BATCH_SIZE)], &mut replaced)?;
+        }
+        Ok(replaced)
+    }
+
+    // Answers the batch up to the end or to the sentence the command exited
+    // on, writing from a separate thread so neither side can block on a
+    // full pipe
+    fn process_batch(
+        &mut self,
+        batch: &[&str],
+        replaced: &mut Vec<Option<String>>,
+    ) -> io::Result<()> {
+        let stdin = &mut self.stdin;
+        let stdout = &mut self.stdout;
+        let answered = thread::scope(|scope| -> io::Result<usize> {
+            let writer = scope.spawn(move || -> io::Result<()> {
+                for sentence in batch {
+                    stdin.write_all(sentence.as_bytes())?;
+                    stdin.write_all(b"\n")?;
+                }
+                stdin.flush()
+            });
+
+            let mut answered = 0;
+            let mut line = String::new();
+            while answered < batch.len() {
+                line.clear();
+                if stdout.read_line(&mut line)? == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches(['\n', '\r']);
+                replaced.push((!line.is_empty()).then(|| line.to_string()));
+                answered += 1;
+            }
+            match writer.join().unwrap() {
+                // the command exited without reading everything
+                Err(error) if answered < batch.len() && error.kind() == ErrorKind::BrokenPipe => {}
+                result => result?,
+            }
+            Ok(answered)
+        })?;
+        if answered < batch.len() {
+            let status = self.child.wait()?;
+            tracing::error!(
+                "post-process command `{}` exited ({}) before answering {:?}, dropping it",
+                self.command,
+                status,
+                batch[answered]
+            );
+            replaced.push(None);
+            *self = PostProcessCommand::spawn(&self.command)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PostProcessCommand {
+    fn drop(&mut self) {
+        let _ = self.stdin.flush();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A rewrite of each sentence run in the workers, unlike the command
+pub trait PostProcessor: Send + Sync {
+    fn process(&self, text: String) -> String;
+}
+
+// The processors of --post-processors, applied in turn
+pub struct PostProcessorChain(pub Vec<Box<dyn PostProcessor>>);
+
+// Repetitions of a char kept by dedup-chars unless given
+pub const DEFAULT_MAX_RUN: usize = 2;
+// What numbers becomes unless given
+pub const DEFAULT_NUMBER_TOKEN: &str = "<num>";
+
+impl PostProcessorChain {
+    // A comma separated list of processor names, those taking an argument
+    // written name=arg: norm, dedup-chars[=max run], unescape, whitespace
+    // and numbers[=token]
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut processors: Vec<Box<dyn PostProcessor>> = Vec::new();
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (name, arg) = match item.split_once('=') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (item, None),
+            };
+            let processor: Box<dyn PostProcessor> = match (name, arg) {
+                ("norm", None) => Box::new(FullwidthNormalizer),
+                ("dedup-chars", arg) => Box::new(RepeatedCharReducer {
+                    max_run: match arg {
+                        Some(arg) => arg
+                            .parse()
+                            .ok()
+                            .filter(|&max_run| max_run > 0)
+                            .ok_or_else(|| format!("bad max run in post processor {}", item))?,
+                        None => DEFAULT_MAX_RUN,
+                    },
+                }),
+                ("unescape", None) => Box::new(HtmlEntityDecoder),
+                ("whitespace", None) => Box::new(WhitespaceNormalizer),
+                ("numbers", arg) => Box::new(NumberNormalizer {
+                    token: arg.unwrap_or(DEFAULT_NUMBER_TOKEN).to_string(),
+                }),
+                _ => return Err(format!("unknown post processor {}", item)),
+            };
+            processors.push(processor);
+        }
+        Ok(PostProcessorChain(processors))
+    }
+}
+
+impl PostProcessor for PostProcessorChain {
+    fn process(&self, text: String) -> String {
+        self.0
+            .iter()
+            .fold(text, |text, processor| processor.process(text))
+    }
+}
+
+// Fullwidth ASCII forms and the ideographic space as their ASCII chars
+pub struct FullwidthNormalizer;
+
+impl PostProcessor for FullwidthNormalizer {
+    fn process(&self, text: String) -> String {
+        text.chars()
+            .map(|c| match c {
+                '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap(),
+                '\u{3000}' => ' ',
+                c => c,
+            })
+            .collect()
+    }
+}
+
+// Runs of a char longer than `max_run` cut to it, whatever the char, unlike
+// `normalize_elongation`
+pub struct RepeatedCharReducer {
+    pub max_run: usize,
+}
+
+impl PostProcessor for RepeatedCharReducer {
+    fn process(&self, text: String) -> String {
+        let mut reduced = String::with_capacity(text.len());
+        let mut last = None;
+        let mut run = 0;
+        for c in text.chars() {
+            run = if last == Some(c) { run + 1 } else { 1 };
+            last = Some(c);
+            if run <= self.max_run {
+                reduced.push(c);
+            }
+        }
+        reduced
+    }
+}
+
+// The named entities common in posts and numeric ones, as left by html
+// escaped twice. Unknown entities are kept as written.
+pub struct HtmlEntityDecoder;
+
+impl PostProcessor for HtmlEntityDecoder {
+    fn process(&self, text: String) -> String {
+        if !text.contains('&') {
+            return text;
+        }
+        let mut decoded = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find('&') {
+            decoded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let entity = rest[1..]
+                .find(';')
+                .filter(|&end| end <= 8)
+                .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end + 2)));
+            match entity {
+                Some((c, len)) => {
+                    decoded.push(c);
+                    rest = &rest[len..];
+                }
+                None => {
+                    decoded.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        decoded.push_str(rest);
+        decoded
+    }
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{A0}'),
+        _ => {
+            let code = match name.strip_prefix('#')? {
+                hex if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok()?,
+                dec => dec.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// Runs of whitespace as one space, none at the ends
+pub struct WhitespaceNormalizer;
+
+impl PostProcessor for WhitespaceNormalizer {
+    fn process(&self, text: String) -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+}
+
+// Each run of digits, with the decimal point or thousands separators inside
+// it, replaced by `token`
+pub struct NumberNormalizer {
+    pub token: String,
+}
+
+impl PostProcessor for NumberNormalizer {
+    fn process(&self, text: String) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut normalized = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < chars.len() {
+            if !chars[i].is_ascii_digit() {
+                normalized.push(chars[i]);
+                i += 1;
+                continue;
+            }
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || (matches!(chars[i], '.' | ',')
+                        && chars.get(i + 1).is_some_and(char::is_ascii_digit)))
+            {
+                i += 1;
+            }
+            normalized.push_str(&self.token);
+        }
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_applies_processors_in_turn() {
+        let chain =
+            PostProcessorChain::parse("unescape, norm,dedup-chars,whitespace,numbers").unwrap();
+        assert_eq!(
+            chain.process("  好正呀呀呀呀 &amp;　ＯＫ&#33;  用咗$1,234.5 ".to_string()),
+            "好正呀呀 & OK! 用咗$<num>"
+        );
+        let chain = PostProcessorChain::parse("dedup-chars=1,numbers=0").unwrap();
+        assert_eq!(chain.process("哈哈哈 2024年".to_string()), "哈 0年");
+        assert_eq!(
+            HtmlEntityDecoder.process("a &lt;b&gt; &#x4F60; &bogus; & c".to_string()),
+            "a <b> 你 &bogus; & c"
+        );
+        assert!(PostProcessorChain::parse("norm,upper").is_err());
+        assert!(PostProcessorChain::parse("dedup-chars=0").is_err());
+        assert!(PostProcessorChain::parse("").unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn output_replaces_every_line() {
+        let mut processor = PostProcessCommand::spawn("sed -u 's/呀/啊/g'").unwrap();
+        let sentences: Vec<String> = (0..3000).map(|i| format!("第{}句呀", i)).collect();
+        let sentences: Vec<&str> = sentences.iter().map(String::as_str).collect();
+        let replaced = processor.process(&sentences).unwrap();
+        assert_eq!(replaced.len(), 3000);
+        assert_eq!(replaced[2999].as_deref(), Some("第2999句啊"));
+    }
+
+    #[test]
+    fn failing_sentences_are_dropped() {
+        // fails on sentences with 錯, answering the others
+        let mut processor = PostProcessCommand::spawn(
+            "while read -r line; do case $line in *錯*) exit 1;; esac; echo \"$line\"; done",
+        )
+        .unwrap();
+        let replaced = processor
+            .process(&["第一句", "有錯嘅句", "第三句", "又錯", ""])
+            .unwrap();
+        assert_eq!(
+            replaced,
+            [
+                Some("第一句".to_string()),
+                None,
+                Some("第三句".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+}
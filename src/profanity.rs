@@ -0,0 +1,163 @@
+use crate::list_entries;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Common Cantonese vulgar terms and their creative spellings. Entries made
+// only of ASCII letters and digits are matched as whole tokens, everything
+// else as a substring.
+pub const BUILTIN_WORDS: &[&str] = &[
+    "屌",
+    "𨳒",
+    "閪",
+    "撚",
+    "𨶙",
+    "㞗",
+    "戇鳩",
+    "戇𨳊",
+    "鳩嗚",
+    "柒頭",
+    "7頭",
+    "仆街",
+    "仆你個街",
+    "冚家鏟",
+    "冚家剷",
+    "屌你老母",
+    "丟你老母",
+    "老味",
+    "on9",
+    "on99",
+    "dllm",
+    "diu",
+    "lun",
+    "pk",
+];
+
+pub const MASK: &str = "***";
+
+lazy_static! {
+    static ref LATIN_TOKEN_REGEX: Regex = Regex::new(r"[A-Za-z0-9]+").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfanityMode {
+    #[default]
+    Keep,
+    Drop,
+    Mask,
+}
+
+pub struct Profanity {
+    substrings: Option<Regex>,
+    tokens: HashMap<String, String>,
+}
+
+impl Profanity {
+    pub fn new<S: AsRef<str>>(words: &[S]) -> Self {
+        let mut substrings: Vec<&str> = Vec::new();
+        let mut tokens = HashMap::new();
+        for word in words {
+            let word = word.as_ref();
+            if word.chars().all(|c| c.is_ascii_alphanumeric()) {
+                tokens.insert(word.to_ascii_lowercase(), word.to_string());
+            } else {
+                substrings.push(word);
+            }
+        }
+        // longest entries first so "屌你老母" wins over "屌"
+        substrings.sort_by_key(|word| std::cmp::Reverse(word.chars().count()));
+        let substrings = (!substrings.is_empty()).then(|| {
+            let alternatives: Vec<String> = substrings.iter().map(|w| regex::escape(w)).collect();
+            Regex::new(&alternatives.join("|")).unwrap()
+        });
+        Profanity { substrings, tokens }
+    }
+
+    pub fn builtin() -> Self {
+        Profanity::new(BUILTIN_WORDS)
+    }
+
+    // One entry per line, blank lines and lines starting with '#' are ignored
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Profanity::new(&list_entries(&contents)))
+    }
+
+    // Non-overlapping (start, end, entry) byte spans in order of appearance
+    pub fn find(&self, text: &str) -> Vec<(usize, usize, String)> {
+        let mut spans = Vec::new();
+        if let Some(substrings) = &self.substrings {
+            spans.extend(
+                substrings
+                    .find_iter(text)
+                    .map(|m| (m.start(), m.end(), m.as_str().to_string())),
+            );
+        }
+        for m in LATIN_TOKEN_REGEX.find_iter(text) {
+            if let Some(word) = self.tokens.get(&m.as_str().to_ascii_lowercase()) {
+                spans.push((m.start(), m.end(), word.clone()));
+            }
+        }
+        spans.sort();
+        let mut end = 0;
+        spans.retain(|span| {
+            let keep = span.0 >= end;
+            if keep {
+                end = span.1;
+            }
+            keep
+        });
+        spans
+    }
+
+    pub fn mask(text: &str, spans: &[(usize, usize, String)]) -> String {
+        let mut masked = String::with_capacity(text.len());
+        let mut last = 0;
+        for (start, end, _) in spans {
+            masked.push_str(&text[last..*start]);
+            masked.push_str(MASK);
+            last = *end;
+        }
+        masked.push_str(&text[last..]);
+        masked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(text: &str) -> Vec<String> {
+        Profanity::builtin()
+            .find(text)
+            .into_iter()
+            .map(|(_, _, word)| word)
+            .collect()
+    }
+
+    #[test]
+    fn cjk_entries_match_as_substrings() {
+        assert_eq!(entries("佢真係好7頭呀"), ["7頭"]);
+        assert_eq!(entries("屌你老母咩事"), ["屌你老母"]);
+        assert!(entries("今日天氣好好").is_empty());
+    }
+
+    #[test]
+    fn latin_entries_match_as_tokens() {
+        assert_eq!(entries("on9仔又嚟"), ["on9"]);
+        assert_eq!(entries("DLLM咩料"), ["dllm"]);
+        assert!(entries("食咗diuretic").is_empty());
+        assert!(entries("lunch食乜").is_empty());
+    }
+
+    #[test]
+    fn mask_replaces_spans_with_fixed_width() {
+        let text = "on9仔真係仆街";
+        let spans = Profanity::builtin().find(text);
+        assert_eq!(Profanity::mask(text, &spans), "***仔真係***");
+    }
+}
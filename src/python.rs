@@ -0,0 +1,91 @@
+use crate::config::ExtractorConfig;
+use crate::{Batch, Extractor};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+// Python bindings of the `python` feature. Configs are dicts shaped like
+// the [extractor] section of a --config file, passed through json so they
+// are read exactly as the TOML is. Extraction runs without the GIL.
+
+fn config(py: Python, dict: Option<&Bound<PyDict>>) -> PyResult<ExtractorConfig> {
+    let Some(dict) = dict else {
+        return Ok(ExtractorConfig::default());
+    };
+    let json: String = py
+        .import("json")?
+        .call_method1("dumps", (dict,))?
+        .extract()?;
+    serde_json::from_str(&json).map_err(|e| PyValueError::new_err(format!("config: {}", e)))
+}
+
+fn extractor(config: &ExtractorConfig) -> PyResult<Extractor> {
+    Extractor::new(config).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+// An extractor kept across calls, compiling its filters once and
+// remembering the posts seen when deduplicating them
+#[pyclass(name = "Extractor", module = "lihkg")]
+struct PyExtractor {
+    extractor: Extractor,
+}
+
+#[pymethods]
+impl PyExtractor {
+    #[new]
+    #[pyo3(signature = (config=None))]
+    fn new(py: Python, config: Option<&Bound<PyDict>>) -> PyResult<Self> {
+        let config = self::config(py, config)?;
+        Ok(PyExtractor {
+            extractor: extractor(&config)?,
+        })
+    }
+
+    fn extract_paragraphs(&self, py: Python, msg_html: &str) -> Vec<String> {
+        py.allow_threads(|| self.extractor.extract_paragraphs(msg_html))
+    }
+
+    // The records of a dump line as dicts, as the jsonl output writes them
+    fn extract_from_line(&self, py: Python, line: &str) -> PyResult<PyObject> {
+        let records = py.allow_threads(|| {
+            let mut batch = Batch::default();
+            self.extractor.process_line(line, &mut batch)?;
+            serde_json::to_string(&batch.records)
+        });
+        let records = records.map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(py
+            .import("json")?
+            .call_method1("loads", (records,))?
+            .unbind())
+    }
+}
+
+#[pyfunction]
+fn convert_html_to_text(py: Python, html: &str) -> String {
+    py.allow_threads(|| crate::convert_html_to_text(html))
+}
+
+#[pyfunction]
+#[pyo3(signature = (msg_html, config=None))]
+fn extract_paragraphs(
+    py: Python,
+    msg_html: &str,
+    config: Option<&Bound<PyDict>>,
+) -> PyResult<Vec<String>> {
+    Ok(PyExtractor::new(py, config)?.extract_paragraphs(py, msg_html))
+}
+
+#[pyfunction]
+#[pyo3(signature = (line, config=None))]
+fn extract_from_line(py: Python, line: &str, config: Option<&Bound<PyDict>>) -> PyResult<PyObject> {
+    PyExtractor::new(py, config)?.extract_from_line(py, line)
+}
+
+#[pymodule]
+fn lihkg(m: &Bound<PyModule>) -> PyResult<()> {
+    m.add_class::<PyExtractor>()?;
+    m.add_function(wrap_pyfunction!(convert_html_to_text, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_paragraphs, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_from_line, m)?)?;
+    Ok(())
+}
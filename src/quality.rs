@@ -0,0 +1,138 @@
+use crate::cjk_ratio;
+use crate::filters::CANTONESE_MARKERS;
+use crate::profanity::Profanity;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+// Score from which a sentence would have been written by the default
+// pipeline, see `QualityScorer::score`
+pub const ACCEPT_THRESHOLD: f64 = 0.5;
+
+// Highest score of a sentence the default pipeline rejects, leaving a gap
+// below `ACCEPT_THRESHOLD`
+const REJECTED_CEILING: f64 = 0.4;
+
+// Weights of the features in the score, adding up to 1
+pub const LENGTH_WEIGHT: f64 = 0.15;
+pub const CJK_WEIGHT: f64 = 0.25;
+pub const REPETITION_WEIGHT: f64 = 0.2;
+pub const URL_WEIGHT: f64 = 0.1;
+pub const BLOCKLIST_WEIGHT: f64 = 0.1;
+pub const CANTONESE_WEIGHT: f64 = 0.2;
+
+// Cantonese marker chars for a full Cantonese score
+const FULL_CANTONESE_MARKERS: usize = 2;
+
+// The features of a paragraph as the filters see it, before irrelevant
+// chars are stripped, and the score combining them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Quality {
+    pub quality: f64,
+    // chars
+    pub length: usize,
+    // share of CJK chars
+    pub cjk_fraction: f64,
+    // share of chars repeating an earlier one
+    pub repetition: f64,
+    pub has_url: bool,
+    // matches of the profanity list
+    pub blocklist_hits: usize,
+    // chars of `CANTONESE_MARKERS`
+    pub cantonese_markers: usize,
+}
+
+// Scores every candidate sentence with --score-all instead of rejecting it.
+//
+// Each feature maps to a sub-score in [0, 1]: the length is 1 inside the
+// accepted range and falls off outside it, the CJK fraction counts as is,
+// repetition and blocklist hits lower their sub-scores, a URL zeroes its
+// sub-score and two Cantonese markers give a full one. Their weighted sum is
+// the sentence's soft quality. A sentence the default filters accept scores
+// 0.5 + soft / 2 and any other 0.4 * soft, so keeping the sentences scoring at
+// least `ACCEPT_THRESHOLD` is exactly the default output, and sentences on
+// either side of it are ranked by their soft quality.
+pub struct QualityScorer {
+    lengths: RangeInclusive<usize>,
+    blocklist: Profanity,
+}
+
+impl QualityScorer {
+    pub fn new(lengths: RangeInclusive<usize>, blocklist: Profanity) -> Self {
+        QualityScorer { lengths, blocklist }
+    }
+
+    // `accepted` is the verdict of the default filters on the paragraph
+    pub fn score(&self, para: &str, accepted: bool) -> Quality {
+        let chars: Vec<char> = para.chars().collect();
+        let length = chars.len();
+        let distinct: HashSet<&char> = chars.iter().collect();
+        let mut quality = Quality {
+            quality: 0.0,
+            length,
+            cjk_fraction: cjk_ratio(para),
+            repetition: 1.0 - distinct.len() as f64 / length.max(1) as f64,
+            has_url: para.contains("http://") || para.contains("https://"),
+            blocklist_hits: self.blocklist.find(para).len(),
+            cantonese_markers: chars
+                .iter()
+                .filter(|&&c| CANTONESE_MARKERS.contains(c))
+                .count(),
+        };
+        let soft = LENGTH_WEIGHT * self.length_score(length)
+            + CJK_WEIGHT * quality.cjk_fraction
+            + REPETITION_WEIGHT * (1.0 - quality.repetition)
+            + URL_WEIGHT * (!quality.has_url as u8 as f64)
+            + BLOCKLIST_WEIGHT / (1 + quality.blocklist_hits) as f64
+            + CANTONESE_WEIGHT
+                * (quality.cantonese_markers.min(FULL_CANTONESE_MARKERS) as f64
+                    / FULL_CANTONESE_MARKERS as f64);
+        let soft = soft.clamp(0.0, 1.0);
+        quality.quality = if accepted {
+            ACCEPT_THRESHOLD + soft * (1.0 - ACCEPT_THRESHOLD)
+        } else {
+            REJECTED_CEILING * soft
+        };
+        quality
+    }
+
+    fn length_score(&self, length: usize) -> f64 {
+        let (min, max) = (*self.lengths.start(), *self.lengths.end());
+        if length < min {
+            length as f64 / min as f64
+        } else if length > max {
+            max as f64 / length as f64
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_rank_sentences_on_either_side_of_the_threshold() {
+        let scorer = QualityScorer::new(5..=20, Profanity::builtin());
+        let good = scorer.score("我哋今日去咗飲茶", true);
+        assert_eq!(good.length, 8);
+        assert_eq!(good.cjk_fraction, 1.0);
+        assert_eq!(good.repetition, 0.0);
+        assert_eq!(good.cantonese_markers, 2);
+        assert!((good.quality - 1.0).abs() < 1e-9);
+        let plain = scorer.score("點心好好食", true);
+        assert!(plain.quality >= ACCEPT_THRESHOLD && plain.quality < good.quality);
+
+        let url = scorer.score("睇下呢個 https://lihkg.com", false);
+        assert!(url.has_url);
+        let vulgar = scorer.score("仆街啦你", false);
+        assert_eq!(vulgar.blocklist_hits, 1);
+        let spam = scorer.score("哈哈哈哈哈哈哈哈哈哈", false);
+        assert_eq!(spam.repetition, 0.9);
+        for rejected in [url, vulgar, spam] {
+            assert!(rejected.quality < ACCEPT_THRESHOLD, "{:?}", rejected);
+        }
+        assert!(spam.quality < vulgar.quality);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+// Example sentences kept per char for the report
+const EXAMPLES: usize = 3;
+
+// The Private Use Areas, where HKSCS chars of legacy Big5 fonts land when
+// converted without the HKSCS tables
+pub fn is_pua(c: char) -> bool {
+    matches!(c as u32, 0xE000..=0xF8FF | 0xF0000..=0xFFFFD | 0x100000..=0x10FFFD)
+}
+
+// Char counts over the written sentences with the first sentences each char
+// appeared in, for --rare-chars-report
+#[derive(Debug, Default)]
+pub struct RareChars {
+    chars: HashMap<char, (u64, Vec<String>)>,
+}
+
+impl RareChars {
+    pub fn observe(&mut self, sentence: &str) {
+        for c in sentence.chars() {
+            let (count, examples) = self.chars.entry(c).or_default();
+            *count += 1;
+            // a char repeated in one sentence gives it once
+            if examples.len() < EXAMPLES && examples.last().is_none_or(|last| last != sentence) {
+                examples.push(sentence.to_string());
+            }
+        }
+    }
+
+    // A row per char written fewer than `threshold` times and per PUA char,
+    // rarest first, with its examples separated by " | "
+    pub fn report_tsv(&self, threshold: u64) -> String {
+        let mut rows: Vec<(&char, &(u64, Vec<String>))> = self
+            .chars
+            .iter()
+            .filter(|(&c, (count, _))| *count < threshold || is_pua(c))
+            .collect();
+        rows.sort_by_key(|(&c, (count, _))| (*count, c));
+        let mut tsv = String::from("char\tcodepoint\tcount\tpua\texamples\n");
+        for (&c, (count, examples)) in rows {
+            tsv.push_str(&format!(
+                "{}\tU+{:04X}\t{}\t{}\t{}\n",
+                c,
+                c as u32,
+                count,
+                is_pua(c),
+                examples.join(" | ")
+            ));
+        }
+        tsv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_rare_and_private_use_chars() {
+        let mut chars = RareChars::default();
+        for sentence in [
+            "今日天氣好好",
+            "今日天氣好䶮",
+            "今日\u{E05E}天氣",
+            "今日好熱",
+        ] {
+            chars.observe(sentence);
+        }
+        assert!(is_pua('\u{E05E}') && !is_pua('䶮'));
+        assert_eq!(
+            chars.report_tsv(2),
+            "char\tcodepoint\tcount\tpua\texamples\n\
+             䶮\tU+4DAE\t1\tfalse\t今日天氣好䶮\n\
+             熱\tU+71B1\t1\tfalse\t今日好熱\n\
+             \u{E05E}\tU+E05E\t1\ttrue\t今日\u{E05E}天氣\n"
+        );
+        let report = chars.report_tsv(4);
+        assert!(report
+            .contains("\n天\tU+5929\t3\tfalse\t今日天氣好好 | 今日天氣好䶮 | 今日\u{E05E}天氣\n"));
+    }
+}
@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+// Chars of a post's text it is matched on. Quotes of long posts are cut
+// short, so only the start of a post is compared.
+pub const KEY_CHARS: usize = 32;
+
+// A post as seen by the reply graph, in the order of the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostQuote {
+    pub thread_id: u64,
+    pub post_id: Option<String>,
+    // `quote_key` of the post's own text, without its quotes
+    pub key: String,
+    // `quote_key` of the post it quotes, if it has a quote
+    pub quote: Option<String>,
+}
+
+// The start of a text without its whitespace, which the conversion of a
+// quote may not keep as the post had it
+pub fn quote_key(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .take(KEY_CHARS)
+        .collect()
+}
+
+// A json line of --reply-graph, with a null quoted post for quotes of no
+// post in the window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplyEdge {
+    pub thread_id: u64,
+    pub post_id: Option<String>,
+    pub quoted_post_id: Option<String>,
+}
+
+// The last `window` posts of each thread, the newest last, which quotes are
+// matched against
+pub struct ReplyGraph {
+    window: usize,
+    threads: HashMap<u64, VecDeque<(Option<String>, String)>>,
+}
+
+impl ReplyGraph {
+    pub fn new(window: usize) -> Self {
+        ReplyGraph {
+            window,
+            threads: HashMap::new(),
+        }
+    }
+
+    // The edge of a post with a quote, to the newest post in the window
+    // starting with the quoted text
+    pub fn push(&mut self, post: PostQuote) -> Option<ReplyEdge> {
+        let recent = self.threads.entry(post.thread_id).or_default();
+        let edge = post.quote.map(|quote| ReplyEdge {
+            thread_id: post.thread_id,
+            post_id: post.post_id.clone(),
+            quoted_post_id: recent
+                .iter()
+                .rev()
+                .find(|(_, key)| !quote.is_empty() && key.starts_with(&quote))
+                .and_then(|(post_id, _)| post_id.clone()),
+        });
+        if !post.key.is_empty() && self.window > 0 {
+            if recent.len() == self.window {
+                recent.pop_front();
+            }
+            recent.push_back((post.post_id, post.key));
+        }
+        edge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(post_id: &str, text: &str, quote: Option<&str>) -> PostQuote {
+        PostQuote {
+            thread_id: 1,
+            post_id: Some(post_id.to_string()),
+            key: quote_key(text),
+            quote: quote.map(quote_key),
+        }
+    }
+
+    #[test]
+    fn quotes_match_recent_posts_by_prefix() {
+        let mut graph = ReplyGraph::new(2);
+        let long = "樓主講得啱，呢間茶餐廳嘅奶茶真係全港最好飲，我每日都去飲一杯先返工";
+        assert_eq!(graph.push(post("1", long, None)), None);
+        assert_eq!(graph.push(post("2", "我哋今日去咗飲茶", None)), None);
+        let edge = |post_id: &str, quoted: Option<&str>| {
+            Some(ReplyEdge {
+                thread_id: 1,
+                post_id: Some(post_id.to_string()),
+                quoted_post_id: quoted.map(String::from),
+            })
+        };
+        // a quote cut short and with other line breaks
+        let cut = "樓主講得啱，\n呢間茶餐廳嘅奶茶";
+        assert_eq!(
+            graph.push(post("3", "同意", Some(cut))),
+            edge("3", Some("1"))
+        );
+        // post 1 has left the window of 2
+        assert_eq!(graph.push(post("4", "係咪", Some(cut))), edge("4", None));
+        assert_eq!(
+            graph.push(post("5", "點心呢", Some("同意"))),
+            edge("5", Some("3"))
+        );
+        assert_eq!(graph.push(post("6", "圖", Some(""))), edge("6", None));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::value_as_i64;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+
+// Parent of the posts replying to no post of the thread
+pub const ROOT: u64 = 0;
+
+// The id a post is replied to by, its post_id when numeric and else its
+// number in the thread
+pub fn post_number(item: &Value) -> Option<u64> {
+    value_as_i64(&item["post_id"])
+        .or_else(|| value_as_i64(&item["msg_num"]))
+        .filter(|&id| id > 0)
+        .map(|id| id as u64)
+}
+
+// The post an item replies to, by reply_id or else parent_id
+pub fn parent_number(item: &Value) -> Option<u64> {
+    value_as_i64(&item["reply_id"])
+        .or_else(|| value_as_i64(&item["parent_id"]))
+        .filter(|&id| id > 0)
+        .map(|id| id as u64)
+}
+
+// The replies to each post in item order, the posts replying to nothing,
+// to themselves or to a post missing from `items` under `ROOT`. Items
+// without an id are left out.
+pub fn build_reply_tree(items: &[Value]) -> HashMap<u64, Vec<u64>> {
+    let ids: HashSet<u64> = items.iter().filter_map(post_number).collect();
+    let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+    for item in items {
+        let Some(id) = post_number(item) else {
+            continue;
+        };
+        let parent = parent_number(item)
+            .filter(|parent| *parent != id && ids.contains(parent))
+            .unwrap_or(ROOT);
+        children.entry(parent).or_default().push(id);
+    }
+    children
+}
+
+// The posts of a thread nested under the posts they reply to, each item
+// with a "children" array. Posts caught in a cycle of replies, out of reach
+// from the root, are added to it.
+pub fn thread_tree(thread_id: u64, items: &[Value]) -> Value {
+    let children = build_reply_tree(items);
+    let by_id: HashMap<u64, &Value> = items
+        .iter()
+        .filter_map(|item| Some((post_number(item)?, item)))
+        .collect();
+    let mut placed = HashSet::new();
+    let mut roots = nest(ROOT, &children, &by_id, &mut placed);
+    for item in items {
+        if let Some(id) = post_number(item).filter(|id| !placed.contains(id)) {
+            placed.insert(id);
+            let mut node = node(item);
+            node["children"] = Value::Array(nest(id, &children, &by_id, &mut placed));
+            roots.push(node);
+        }
+    }
+    json!({"thread_id": thread_id, "children": roots})
+}
+
+fn nest(
+    parent: u64,
+    children: &HashMap<u64, Vec<u64>>,
+    by_id: &HashMap<u64, &Value>,
+    placed: &mut HashSet<u64>,
+) -> Vec<Value> {
+    let mut nodes = Vec::new();
+    for &id in children.get(&parent).into_iter().flatten() {
+        // a repeated id shows once
+        if !placed.insert(id) {
+            continue;
+        }
+        let mut node = node(by_id[&id]);
+        node["children"] = Value::Array(nest(id, children, by_id, placed));
+        nodes.push(node);
+    }
+    nodes
+}
+
+fn node(item: &Value) -> Value {
+    match item {
+        Value::Object(fields) => Value::Object(fields.clone()),
+        _ => Value::Object(Map::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_nest_under_their_parents() {
+        let items = [
+            json!({"msg_num": "1", "text": "樓主"}),
+            json!({"msg_num": "2", "reply_id": "1", "text": "回樓主"}),
+            json!({"msg_num": "3", "parent_id": 2, "text": "回二樓"}),
+            json!({"msg_num": "4", "reply_id": 1, "text": "又回樓主"}),
+            json!({"msg_num": "5", "reply_id": 99, "text": "回唔見咗嘅post"}),
+        ];
+        let tree = build_reply_tree(&items);
+        assert_eq!(tree[&ROOT], [1, 5]);
+        assert_eq!(tree[&1], [2, 4]);
+        assert_eq!(tree[&2], [3]);
+
+        let nested = thread_tree(7, &items);
+        assert_eq!(nested["thread_id"], 7);
+        let first = &nested["children"][0];
+        assert_eq!(first["text"], "樓主");
+        assert_eq!(first["children"][0]["children"][0]["text"], "回二樓");
+        assert_eq!(first["children"][1]["text"], "又回樓主");
+        assert_eq!(nested["children"][1]["text"], "回唔見咗嘅post");
+    }
+
+    #[test]
+    fn posts_in_a_reply_cycle_are_kept() {
+        let items = [
+            json!({"msg_num": 1, "reply_id": 2}),
+            json!({"msg_num": 2, "reply_id": 1}),
+        ];
+        let nested = thread_tree(7, &items);
+        assert_eq!(nested["children"][0]["msg_num"], 1);
+        assert_eq!(nested["children"][0]["children"][0]["msg_num"], 2);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::SentenceRecord;
+use xxhash_rust::xxh64::xxh64;
+
+// Keeps each sentence with probability weight / max_weight, where
+// weight = (1 + max(post_score, 0))^exponent and max_weight is the weight of
+// the best scored post found by a first pass over the run. Sentences of the
+// best posts are always kept.
+//
+// The decision is a pure function of the seed, the sentence text and its
+// post score: the same input with the same seed yields the same output
+// regardless of thread scheduling or archive order, and a given sentence
+// from equally scored posts is either always kept or always dropped.
+pub struct WeightedSampler {
+    exponent: f64,
+    seed: u64,
+    max_weight: f64,
+}
+
+impl WeightedSampler {
+    pub fn new(exponent: f64, seed: u64, max_post_score: i64) -> Self {
+        WeightedSampler {
+            exponent,
+            seed,
+            max_weight: weight(max_post_score, exponent),
+        }
+    }
+
+    // Posts without a score count as scoring zero
+    pub fn probability(&self, record: &SentenceRecord) -> f64 {
+        let weight = weight(record.post_score.unwrap_or(0), self.exponent);
+        (weight / self.max_weight).min(1.0)
+    }
+
+    pub fn keep(&self, record: &SentenceRecord) -> bool {
+        let hash = xxh64(record.text.as_bytes(), self.seed);
+        // the top 53 bits as a uniform draw in [0, 1)
+        let draw = (hash >> 11) as f64 / (1u64 << 53) as f64;
+        draw < self.probability(record)
+    }
+}
+
+pub fn weight(post_score: i64, exponent: f64) -> f64 {
+    (1.0 + post_score.max(0) as f64).powf(exponent)
+}
+
+// A uniform sample of `capacity` of the sentences offered, by Vitter's
+// algorithm R: the i-th sentence replaces a random slot with probability
+// capacity / i, so after n offers each of them is held with probability
+// capacity / n.
+//
+// Uniformity needs a single reservoir seeing every sentence. Rayon workers
+// only extract, the sentences reach the reservoir one at a time in archive
+// order on the thread writing the output, so the sample is the same for a
+// given input and seed however the work was scheduled.
+pub struct Reservoir {
+    capacity: usize,
+    seen: u64,
+    rng: SplitMix64,
+    // with their offer index, to write them back in input order
+    held: Vec<(u64, SentenceRecord)>,
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            rng: SplitMix64(seed),
+            held: Vec::with_capacity(capacity.min(1 << 20)),
+        }
+    }
+
+    pub fn offer(&mut self, record: SentenceRecord) {
+        let index = self.seen;
+        self.seen += 1;
+        if self.held.len() < self.capacity {
+            self.held.push((index, record));
+            return;
+        }
+        let slot = self.rng.below(self.seen);
+        if slot < self.capacity as u64 {
+            self.held[slot as usize] = (index, record);
+        }
+    }
+
+    // Sentences offered and not in the sample
+    pub fn dropped(&self) -> u64 {
+        self.seen - self.held.len() as u64
+    }
+
+    // The sample in the order the sentences were offered
+    pub fn into_sample(mut self) -> Vec<SentenceRecord> {
+        self.held.sort_unstable_by_key(|&(index, _)| index);
+        self.held.into_iter().map(|(_, record)| record).collect()
+    }
+}
+
+// Steele, Lea and Flood's generator, seeded directly with --seed
+pub struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, n) by Lemire's multiply and reject
+    pub fn below(&mut self, n: u64) -> u64 {
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let product = self.next() as u128 * n as u128;
+            if product as u64 >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(i: usize, post_score: i64) -> SentenceRecord {
+        SentenceRecord {
+            text: format!("第{}句測試句子", i),
+            post_score: Some(post_score),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn acceptance_follows_weights() {
+        const N: usize = 20_000;
+        let sampler = WeightedSampler::new(1.0, 42, 9);
+        for (score, expected) in [(9, 1.0), (4, 0.5), (1, 0.2), (-3, 0.1)] {
+            let kept = (0..N).filter(|&i| sampler.keep(&record(i, score))).count();
+            let rate = kept as f64 / N as f64;
+            assert!(
+                (rate - expected).abs() < 0.02,
+                "score {}: rate {} expected {}",
+                score,
+                rate,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn decisions_depend_only_on_seed() {
+        let a = WeightedSampler::new(0.5, 7, 100);
+        let b = WeightedSampler::new(0.5, 7, 100);
+        let c = WeightedSampler::new(0.5, 8, 100);
+        let keep = |sampler: &WeightedSampler| {
+            (0..1000)
+                .map(|i| sampler.keep(&record(i, 3)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keep(&a), keep(&b));
+        assert_ne!(keep(&a), keep(&c));
+    }
+
+    #[test]
+    fn reservoir_is_uniform_across_seeds() {
+        const SENTENCES: usize = 100;
+        const CAPACITY: usize = 10;
+        const SEEDS: u64 = 20_000;
+        let mut counts = [0u64; SENTENCES];
+        for seed in 0..SEEDS {
+            let mut reservoir = Reservoir::new(CAPACITY, seed);
+            for i in 0..SENTENCES {
+                reservoir.offer(record(i, 0));
+            }
+            assert_eq!(reservoir.dropped(), (SENTENCES - CAPACITY) as u64);
+            let sample = reservoir.into_sample();
+            assert_eq!(sample.len(), CAPACITY);
+            let mut last = None;
+            for record in sample {
+                let i = (0..SENTENCES).find(|&i| record.text == format!("第{}句測試句子", i));
+                assert!(i > last, "sample out of input order");
+                last = i;
+                counts[i.unwrap()] += 1;
+            }
+        }
+        // every sentence is held by 2000 of the seeds in expectation, with a
+        // standard deviation of about 42
+        let expected = SEEDS as f64 * CAPACITY as f64 / SENTENCES as f64;
+        for (i, &count) in counts.iter().enumerate() {
+            assert!(
+                (count as f64 - expected).abs() < 200.0,
+                "sentence {}: held {} times, expected {}",
+                i,
+                count,
+                expected
+            );
+        }
+        // and the sample depends on the seed
+        let sample = |seed| {
+            let mut reservoir = Reservoir::new(CAPACITY, seed);
+            for i in 0..SENTENCES {
+                reservoir.offer(record(i, 0));
+            }
+            reservoir.into_sample()
+        };
+        assert_eq!(sample(3), sample(3));
+        assert_ne!(sample(3), sample(4));
+    }
+}
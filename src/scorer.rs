@@ -0,0 +1,125 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+
+// Sentences sent per round trip, the child only needs to keep up with one
+// batch at a time
+pub const BATCH_SIZE: usize = 1024;
+
+// A long-running external command reading one sentence per line on stdin
+// and answering with one float score per line on stdout. The command must
+// flush its answers per batch rather than wait for more input.
+pub struct ExternalScorer {
+    command: String,
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ExternalScorer {
+    // The command is run through `sh -c` so it can carry its own arguments
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().unwrap());
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(ExternalScorer {
+            command: command.to_string(),
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    pub fn score(&mut self, sentences: &[&str]) -> io::Result<Vec<f64>> {
+        let mut scores = Vec::with_capacity(sentences.len());
+        for batch in sentences.chunks(BATCH_SIZE) {
+            self.score_batch(batch, &mut scores)?;
+        }
+        Ok(scores)
+    }
+
+    // Writes from a separate thread so neither side can block on a full pipe
+    fn score_batch(&mut self, batch: &[&str], scores: &mut Vec<f64>) -> io::Result<()> {
+        let stdin = &mut self.stdin;
+        let stdout = &mut self.stdout;
+        let command = &self.command;
+        let child = &mut self.child;
+        thread::scope(|scope| {
+            let writer = scope.spawn(move || -> io::Result<()> {
+                for sentence in batch {
+                    stdin.write_all(sentence.as_bytes())?;
+                    stdin.write_all(b"\n")?;
+                }
+                stdin.flush()
+            });
+
+            let mut line = String::new();
+            for sentence in batch {
+                line.clear();
+                if stdout.read_line(&mut line)? == 0 {
+                    let status = match child.try_wait() {
+                        Ok(Some(status)) => status.to_string(),
+                        _ => "closed its stdout".to_string(),
+                    };
+                    return Err(io::Error::other(format!(
+                        "score command `{}` exited early ({}) before scoring {:?}",
+                        command, status, sentence
+                    )));
+                }
+                let score = line.trim().parse().map_err(|_| {
+                    io::Error::other(format!(
+                        "score command `{}` returned {:?} instead of a number for {:?}",
+                        command,
+                        line.trim_end(),
+                        sentence
+                    ))
+                })?;
+                scores.push(score);
+            }
+            writer.join().unwrap()
+        })
+    }
+}
+
+impl Drop for ExternalScorer {
+    fn drop(&mut self) {
+        let _ = self.stdin.flush();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_every_line() {
+        let mut scorer =
+            ExternalScorer::spawn("while read -r line; do echo ${#line}; done").unwrap();
+        let sentences: Vec<String> = (0..3000).map(|i| "好".repeat(i % 7 + 1)).collect();
+        let sentences: Vec<&str> = sentences.iter().map(String::as_str).collect();
+        let scores = scorer.score(&sentences).unwrap();
+        assert_eq!(scores.len(), 3000);
+        assert!(scores.iter().all(|&s| s > 0.0));
+    }
+
+    #[test]
+    fn non_numeric_output_names_the_sentence() {
+        let mut scorer = ExternalScorer::spawn("while read -r line; do echo x$line; done").unwrap();
+        let error = scorer.score(&["今日天氣好好"]).unwrap_err().to_string();
+        assert!(error.contains("今日天氣好好"), "{}", error);
+    }
+
+    #[test]
+    fn early_exit_names_the_sentence() {
+        let mut scorer = ExternalScorer::spawn("head -n 1 >/dev/null; echo 0.5").unwrap();
+        let error = scorer.score(&["第一句", "第二句"]).unwrap_err().to_string();
+        assert!(error.contains("第二句"), "{}", error);
+    }
+}
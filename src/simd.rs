@@ -0,0 +1,97 @@
+use std::arch::x86_64::*;
+
+// The ASCII chars kept by `filter_irrelevant_chars`, as byte ranges for
+// `_mm_cmpestrm`: alphanumerics and the ASCII entries of `PUNCS`, i.e.
+// every printable char except space, '+' and '='
+const KEPT_RANGES: [u8; 6] = [b'!', b'*', b',', b'<', b'>', b'~'];
+
+const MODE: i32 = _SIDD_UBYTE_OPS | _SIDD_CMP_RANGES | _SIDD_UNIT_MASK;
+
+// `filter_irrelevant_chars` taking runs of ASCII 16 bytes at a time. Chunks
+// stop at the first non-ASCII byte, whose char and the tail of the text
+// shorter than a chunk go through the scalar check.
+//
+// Safety: the CPU must support SSE4.2
+#[target_feature(enable = "sse4.2")]
+pub unsafe fn filter_irrelevant_chars(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut filtered = String::with_capacity(text.len());
+    let mut ranges = [0u8; 16];
+    ranges[..KEPT_RANGES.len()].copy_from_slice(&KEPT_RANGES);
+    let ranges = _mm_loadu_si128(ranges.as_ptr() as *const __m128i);
+
+    // `i` always sits on a char boundary
+    let mut i = 0;
+    while i + 16 <= bytes.len() {
+        let chunk = _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i);
+        let non_ascii = _mm_movemask_epi8(chunk) as u32;
+        let ascii_len = non_ascii.trailing_zeros().min(16) as usize;
+        if ascii_len > 0 {
+            let matched = _mm_cmpestrm::<MODE>(ranges, KEPT_RANGES.len() as i32, chunk, 16);
+            let mut kept = _mm_movemask_epi8(matched) as u32 & ((1 << ascii_len) - 1);
+            if kept == (1 << ascii_len) - 1 {
+                filtered.push_str(&text[i..i + ascii_len]);
+            } else {
+                while kept != 0 {
+                    filtered.push(bytes[i + kept.trailing_zeros() as usize] as char);
+                    kept &= kept - 1;
+                }
+            }
+            i += ascii_len;
+        }
+        if ascii_len < 16 {
+            let c = text[i..].chars().next().unwrap();
+            if crate::is_relevant_char(c) {
+                filtered.push(c);
+            }
+            i += c.len_utf8();
+        }
+    }
+    filtered.extend(text[i..].chars().filter(|c| crate::is_relevant_char(*c)));
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter_irrelevant_chars_scalar;
+
+    fn check(text: &str) {
+        if !is_x86_feature_detected!("sse4.2") {
+            return;
+        }
+        let simd = unsafe { filter_irrelevant_chars(text) };
+        assert_eq!(simd, filter_irrelevant_chars_scalar(text), "{:?}", text);
+    }
+
+    #[test]
+    fn every_ascii_char_matches_scalar() {
+        let ascii: String = (0..128u8).map(char::from).collect();
+        check(&ascii);
+        for c in (0..128u8).map(char::from) {
+            check(&c.to_string().repeat(17));
+        }
+    }
+
+    #[test]
+    fn mixed_text_matches_scalar() {
+        check("");
+        check("今日放工去旺角食嘢，OK啦都幾好食！price 唔算貴，$68 有個set 😂😂");
+        check("exactly sixteen!");
+        check("fifteen chars 1好");
+        check("sixteen chars 12好");
+        check("a\0b\0c\0d\0e\0f\0g\0h\0i\0j\0 + = 早晨");
+        // pseudo-random mixes, so non-ASCII chars land at every offset of a chunk
+        let alphabet: Vec<char> = "aZ09 +=!~\t\n，好😂ａ\u{200b}".chars().collect();
+        let mut seed = 1u32;
+        for len in 0..200 {
+            let text: String = (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    alphabet[(seed >> 16) as usize % alphabet.len()]
+                })
+                .collect();
+            check(&text);
+        }
+    }
+}
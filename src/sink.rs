@@ -0,0 +1,356 @@
+use crate::config::OutputFormat;
+use crate::SentenceRecord;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use xxhash_rust::xxh3::xxh3_64;
+
+// What a sink wrote by the time it finished
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SinkReport {
+    pub records: u64,
+    // bytes before compression, for sinks writing lines
+    pub bytes: Option<u64>,
+}
+
+impl SinkReport {
+    pub fn merge(&mut self, other: SinkReport) {
+        self.records += other.records;
+        self.bytes = match (self.bytes, other.bytes) {
+            (None, None) => None,
+            (bytes, other) => Some(bytes.unwrap_or(0) + other.unwrap_or(0)),
+        };
+    }
+}
+
+// Somewhere the written sentences go. A sink may hold records until
+// `end_entry` gives the archive entry they came from, or until `finish`.
+pub trait SentenceSink: Send {
+    fn write(&mut self, record: &SentenceRecord) -> io::Result<()>;
+
+    // Ends the records of an archive entry, None for records written outside
+    // one, as with --drop-substrings
+    fn end_entry(&mut self, _source_file: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<SinkReport>;
+}
+
+// A record as a line of the output, without its newline
+pub fn format_line(record: &SentenceRecord, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => record.text.clone(),
+        OutputFormat::Jsonl => serde_json::to_string(record).unwrap(),
+    }
+}
+
+// A file written as is or through zstd, which `finish` ends the frame of
+pub enum SinkFile {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl SinkFile {
+    pub fn create(path: &Path, zstd: bool) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(if zstd {
+            SinkFile::Zstd(zstd::Encoder::new(file, 0)?)
+        } else {
+            SinkFile::Plain(file)
+        })
+    }
+
+    pub fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            SinkFile::Plain(file) => file,
+            SinkFile::Zstd(encoder) => encoder,
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            SinkFile::Plain(mut file) => file.flush(),
+            SinkFile::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+// Sentences as lines of text or jsonl, one per record
+pub struct LineSink {
+    file: SinkFile,
+    format: OutputFormat,
+    report: SinkReport,
+}
+
+impl LineSink {
+    pub fn create(path: &Path, format: OutputFormat, zstd: bool) -> io::Result<Self> {
+        Ok(LineSink {
+            file: SinkFile::create(path, zstd)?,
+            format,
+            report: SinkReport {
+                records: 0,
+                bytes: Some(0),
+            },
+        })
+    }
+}
+
+impl SentenceSink for LineSink {
+    fn write(&mut self, record: &SentenceRecord) -> io::Result<()> {
+        let mut line = format_line(record, self.format);
+        line.push('\n');
+        self.file.writer().write_all(line.as_bytes())?;
+        self.report.records += 1;
+        self.report.bytes = self.report.bytes.map(|bytes| bytes + line.len() as u64);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<SinkReport> {
+        self.file.finish()?;
+        Ok(self.report)
+    }
+}
+
+// Records spread over several sinks by thread, so the sentences of a thread
+// stay together, and by text for records without one
+pub struct ShardedSink {
+    shards: Vec<Box<dyn SentenceSink>>,
+}
+
+impl ShardedSink {
+    pub fn new(shards: Vec<Box<dyn SentenceSink>>) -> Self {
+        assert!(!shards.is_empty(), "a sharded sink needs a shard");
+        ShardedSink { shards }
+    }
+
+    pub fn shard_of(record: &SentenceRecord, shards: usize) -> usize {
+        let hash = match record.thread_id {
+            Some(thread_id) => xxh3_64(&thread_id.to_le_bytes()),
+            None => xxh3_64(record.text.as_bytes()),
+        };
+        (hash % shards as u64) as usize
+    }
+}
+
+impl SentenceSink for ShardedSink {
+    fn write(&mut self, record: &SentenceRecord) -> io::Result<()> {
+        let shard = ShardedSink::shard_of(record, self.shards.len());
+        self.shards[shard].write(record)
+    }
+
+    fn end_entry(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        for shard in &mut self.shards {
+            shard.end_entry(source_file)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<SinkReport> {
+        let mut report = SinkReport::default();
+        for shard in self.shards {
+            report.merge(shard.finish()?);
+        }
+        Ok(report)
+    }
+}
+
+// A sink written from several threads, as the rayon workers would, each
+// write holding the lock. Records of a worker keep their order, those of
+// different workers interleave.
+#[derive(Clone)]
+pub struct SharedSink(Arc<Mutex<Box<dyn SentenceSink>>>);
+
+impl SharedSink {
+    pub fn new(sink: Box<dyn SentenceSink>) -> Self {
+        SharedSink(Arc::new(Mutex::new(sink)))
+    }
+
+    pub fn write(&self, record: &SentenceRecord) -> io::Result<()> {
+        self.lock().write(record)
+    }
+
+    pub fn end_entry(&self, source_file: Option<&str>) -> io::Result<()> {
+        self.lock().end_entry(source_file)
+    }
+
+    // Finishes the sink, which fails while other handles to it are alive
+    pub fn finish(self) -> io::Result<SinkReport> {
+        let sink =
+            Arc::try_unwrap(self.0).map_err(|_| io::Error::other("the sink is still shared"))?;
+        sink.into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .finish()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Box<dyn SentenceSink>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow_output::ArrowOutput;
+    use crate::parquet_output::ParquetOutput;
+    use crate::sqlite::SentenceDb;
+    use rayon::prelude::*;
+    use std::path::PathBuf;
+
+    fn records() -> Vec<SentenceRecord> {
+        [
+            (Some(1), "我哋今日去咗飲茶"),
+            (Some(2), "點心好好食"),
+            (Some(1), "OK啦"),
+            (None, "我覺得你講得啱"),
+        ]
+        .into_iter()
+        .map(|(thread_id, text)| SentenceRecord {
+            text: text.to_string(),
+            thread_id,
+            ..Default::default()
+        })
+        .collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lihkg-sink-{}-{}", std::process::id(), name))
+    }
+
+    fn feed(mut sink: Box<dyn SentenceSink>, records: &[SentenceRecord]) -> SinkReport {
+        for (i, record) in records.iter().enumerate() {
+            sink.write(record).unwrap();
+            if i == 1 {
+                sink.end_entry(Some("3300001.csv")).unwrap();
+            }
+        }
+        sink.finish().unwrap()
+    }
+
+    #[test]
+    fn line_sinks_write_each_format() {
+        let records = records();
+        let text = "我哋今日去咗飲茶\n點心好好食\nOK啦\n我覺得你講得啱\n";
+        for (format, zstd) in [
+            (OutputFormat::Text, false),
+            (OutputFormat::Text, true),
+            (OutputFormat::Jsonl, false),
+        ] {
+            let path = temp_path("lines");
+            let sink = LineSink::create(&path, format, zstd).unwrap();
+            let report = feed(Box::new(sink), &records);
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let written = match zstd {
+                true => zstd::decode_all(bytes.as_slice()).unwrap(),
+                false => bytes,
+            };
+            let written = String::from_utf8(written).unwrap();
+            assert_eq!(report.records, 4);
+            assert_eq!(report.bytes, Some(written.len() as u64));
+            match format {
+                OutputFormat::Text => assert_eq!(written, text),
+                OutputFormat::Jsonl => {
+                    let lines: Vec<&str> = written.lines().collect();
+                    assert_eq!(lines[0], serde_json::to_string(&records[0]).unwrap());
+                    assert_eq!(lines.len(), 4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn table_sinks_take_every_record() {
+        let records = records();
+        let sqlite = temp_path("db");
+        let parquet = temp_path("parquet");
+        let arrow = temp_path("arrow");
+        let sinks: Vec<Box<dyn SentenceSink>> = vec![
+            Box::new(SentenceDb::open(&sqlite).unwrap()),
+            Box::new(ParquetOutput::create(&parquet, 2, false).unwrap()),
+            Box::new(ArrowOutput::create(&arrow).unwrap()),
+        ];
+        for sink in sinks {
+            assert_eq!(
+                feed(sink, &records),
+                SinkReport {
+                    records: 4,
+                    bytes: None
+                }
+            );
+        }
+        let conn = rusqlite::Connection::open(&sqlite).unwrap();
+        let sourced: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sentences WHERE source_file = '3300001.csv'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(sourced, 2);
+        for path in [sqlite, parquet, arrow] {
+            assert!(std::fs::metadata(&path).unwrap().len() > 0);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn shards_keep_threads_together() {
+        let records = records();
+        let paths: Vec<PathBuf> = (0..3).map(|i| temp_path(&format!("shard{}", i))).collect();
+        let shards = paths
+            .iter()
+            .map(|path| {
+                let sink = LineSink::create(path, OutputFormat::Text, false).unwrap();
+                Box::new(sink) as Box<dyn SentenceSink>
+            })
+            .collect();
+        let report = feed(Box::new(ShardedSink::new(shards)), &records);
+        assert_eq!(report.records, 4);
+        let mut lines = 0;
+        for (i, path) in paths.iter().enumerate() {
+            let written = std::fs::read_to_string(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            for record in &records {
+                let here = written.lines().any(|line| line == record.text);
+                assert_eq!(here, ShardedSink::shard_of(record, 3) == i);
+            }
+            lines += written.lines().count();
+        }
+        assert_eq!(lines, 4);
+        let thread = |text| records.iter().find(|r| r.text == text).unwrap();
+        assert_eq!(
+            ShardedSink::shard_of(thread("OK啦"), 3),
+            ShardedSink::shard_of(thread("我哋今日去咗飲茶"), 3)
+        );
+    }
+
+    #[test]
+    fn shared_sink_takes_writes_of_every_worker() {
+        let path = temp_path("shared");
+        let sink = SharedSink::new(Box::new(
+            LineSink::create(&path, OutputFormat::Text, false).unwrap(),
+        ));
+        (0..1000).into_par_iter().for_each(|i| {
+            let record = SentenceRecord {
+                text: format!("第{}句", i),
+                ..Default::default()
+            };
+            sink.clone().write(&record).unwrap();
+        });
+        let handle = sink.clone();
+        assert!(handle.finish().is_err());
+        assert_eq!(sink.finish().unwrap().records, 1000);
+        let mut lines: Vec<String> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        lines.sort();
+        lines.dedup();
+        assert_eq!(lines.len(), 1000);
+    }
+}
@@ -0,0 +1,669 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+// Marks the start of each whitespace separated word, as in SentencePiece
+pub const WORD_BOUNDARY: char = '▁';
+
+// Longest piece the trainer creates, SentencePiece's max_sentencepiece_length
+pub const MAX_PIECE_CHARS: usize = 16;
+
+// The pieces every trained model starts with, at SentencePiece's default ids
+const META_PIECES: [(&str, PieceType); 3] = [
+    ("<unk>", PieceType::Unknown),
+    ("<s>", PieceType::Control),
+    ("</s>", PieceType::Control),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Unused,
+    Byte,
+}
+
+impl PieceType {
+    fn from_proto(value: u64) -> Self {
+        match value {
+            2 => PieceType::Unknown,
+            3 => PieceType::Control,
+            4 => PieceType::UserDefined,
+            5 => PieceType::Unused,
+            6 => PieceType::Byte,
+            _ => PieceType::Normal,
+        }
+    }
+
+    fn to_proto(self) -> u64 {
+        match self {
+            PieceType::Normal => 1,
+            PieceType::Unknown => 2,
+            PieceType::Control => 3,
+            PieceType::UserDefined => 4,
+            PieceType::Unused => 5,
+            PieceType::Byte => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub piece: String,
+    pub score: f32,
+    pub kind: PieceType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelType {
+    Unigram,
+    Bpe,
+    Word,
+    Char,
+}
+
+// A SentencePiece model as stored in a .model file. Only the parts needed
+// to split text into pieces are read; the precompiled normalization rules
+// of trained models are not applied, the extracted sentences being clean
+// already.
+#[derive(Debug, Clone)]
+pub struct SpmModel {
+    pub pieces: Vec<Piece>,
+    pub model_type: ModelType,
+    pub add_dummy_prefix: bool,
+    pub remove_extra_whitespaces: bool,
+    pub escape_whitespaces: bool,
+    // ids of the pieces text can be split into
+    ids: HashMap<String, usize>,
+    max_piece_chars: usize,
+}
+
+impl SpmModel {
+    pub fn new(pieces: Vec<Piece>, model_type: ModelType) -> Self {
+        let mut model = SpmModel {
+            pieces,
+            model_type,
+            add_dummy_prefix: true,
+            remove_extra_whitespaces: true,
+            escape_whitespaces: true,
+            ids: HashMap::new(),
+            max_piece_chars: 1,
+        };
+        model.index();
+        model
+    }
+
+    fn index(&mut self) {
+        self.ids.clear();
+        for (id, piece) in self.pieces.iter().enumerate() {
+            if matches!(piece.kind, PieceType::Normal | PieceType::UserDefined) {
+                self.ids.insert(piece.piece.clone(), id);
+                self.max_piece_chars = self.max_piece_chars.max(piece.piece.chars().count());
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut model = SpmModel::new(Vec::new(), ModelType::Unigram);
+        let mut reader = ProtoReader::new(bytes);
+        while let Some((field, value)) = reader.next()? {
+            match (field, value) {
+                (1, Field::Bytes(piece)) => model.pieces.push(read_piece(piece)?),
+                (2, Field::Bytes(trainer_spec)) => {
+                    let mut reader = ProtoReader::new(trainer_spec);
+                    while let Some((field, value)) = reader.next()? {
+                        if let (3, Field::Varint(model_type)) = (field, value) {
+                            model.model_type = match model_type {
+                                2 => ModelType::Bpe,
+                                3 => ModelType::Word,
+                                4 => ModelType::Char,
+                                _ => ModelType::Unigram,
+                            };
+                        }
+                    }
+                }
+                (3, Field::Bytes(normalizer_spec)) => {
+                    let mut reader = ProtoReader::new(normalizer_spec);
+                    while let Some((field, value)) = reader.next()? {
+                        match (field, value) {
+                            (3, Field::Varint(v)) => model.add_dummy_prefix = v != 0,
+                            (4, Field::Varint(v)) => model.remove_extra_whitespaces = v != 0,
+                            (5, Field::Varint(v)) => model.escape_whitespaces = v != 0,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if model.pieces.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a SentencePiece model, no pieces found",
+            ));
+        }
+        model.index();
+        Ok(model)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for piece in &self.pieces {
+            let mut message = Vec::new();
+            put_bytes(&mut message, 1, piece.piece.as_bytes());
+            put_key(&mut message, 2, WIRE_FIXED32);
+            message.extend_from_slice(&piece.score.to_le_bytes());
+            put_varint_field(&mut message, 3, piece.kind.to_proto());
+            put_bytes(&mut bytes, 1, &message);
+        }
+        let mut trainer_spec = Vec::new();
+        let model_type = match self.model_type {
+            ModelType::Unigram => 1,
+            ModelType::Bpe => 2,
+            ModelType::Word => 3,
+            ModelType::Char => 4,
+        };
+        put_varint_field(&mut trainer_spec, 3, model_type);
+        put_varint_field(&mut trainer_spec, 4, self.pieces.len() as u64);
+        put_bytes(&mut bytes, 2, &trainer_spec);
+        let mut normalizer_spec = Vec::new();
+        put_bytes(&mut normalizer_spec, 1, b"identity");
+        put_varint_field(&mut normalizer_spec, 3, self.add_dummy_prefix as u64);
+        put_varint_field(
+            &mut normalizer_spec,
+            4,
+            self.remove_extra_whitespaces as u64,
+        );
+        put_varint_field(&mut normalizer_spec, 5, self.escape_whitespaces as u64);
+        put_bytes(&mut bytes, 3, &normalizer_spec);
+        bytes
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        let text = if self.remove_extra_whitespaces {
+            text.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            text.to_string()
+        };
+        let mut normalized = String::with_capacity(text.len() + 3);
+        if self.add_dummy_prefix && !text.is_empty() {
+            normalized.push(WORD_BOUNDARY);
+        }
+        if self.escape_whitespaces {
+            normalized.extend(
+                text.chars()
+                    .map(|c| if c == ' ' { WORD_BOUNDARY } else { c }),
+            );
+        } else {
+            normalized.push_str(&text);
+        }
+        normalized
+    }
+
+    // Splits `text` into pieces, chars missing from the model stand alone
+    pub fn encode(&self, text: &str) -> Vec<String> {
+        let normalized = self.normalize(text);
+        match self.model_type {
+            ModelType::Unigram => self.encode_unigram(&normalized),
+            ModelType::Bpe => self.encode_bpe(&normalized),
+            ModelType::Word => split_words(&normalized),
+            ModelType::Char => normalized.chars().map(String::from).collect(),
+        }
+    }
+
+    // The segmentation with the highest total piece score
+    fn encode_unigram(&self, text: &str) -> Vec<String> {
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .collect();
+        let n = offsets.len() - 1;
+        let min_score = self
+            .pieces
+            .iter()
+            .map(|p| p.score)
+            .fold(f32::INFINITY, f32::min);
+        let unknown_score = min_score - 10.0;
+        // best score of the text up to each char and where its last piece starts
+        let mut best = vec![(f32::NEG_INFINITY, 0); n + 1];
+        best[0].0 = 0.0;
+        for start in 0..n {
+            let (score, _) = best[start];
+            if score == f32::NEG_INFINITY {
+                continue;
+            }
+            let mut single_char = false;
+            for len in 1..=self.max_piece_chars.min(n - start) {
+                let end = start + len;
+                if let Some(&id) = self.ids.get(&text[offsets[start]..offsets[end]]) {
+                    single_char |= len == 1;
+                    let score = score + self.pieces[id].score;
+                    if score > best[end].0 {
+                        best[end] = (score, start);
+                    }
+                }
+            }
+            if !single_char && score + unknown_score > best[start + 1].0 {
+                best[start + 1] = (score + unknown_score, start);
+            }
+        }
+        let mut pieces = Vec::new();
+        let mut end = n;
+        while end > 0 {
+            let start = best[end].1;
+            pieces.push(text[offsets[start]..offsets[end]].to_string());
+            end = start;
+        }
+        pieces.reverse();
+        pieces
+    }
+
+    // Merges the adjacent pieces forming the best scored piece, leftmost
+    // first, until no pair forms a piece
+    fn encode_bpe(&self, text: &str) -> Vec<String> {
+        let mut symbols: Vec<String> = text.chars().map(String::from).collect();
+        loop {
+            let mut best: Option<(f32, usize)> = None;
+            let mut merged = String::new();
+            for i in 0..symbols.len().saturating_sub(1) {
+                merged.clear();
+                merged.push_str(&symbols[i]);
+                merged.push_str(&symbols[i + 1]);
+                if let Some(&id) = self.ids.get(&merged) {
+                    let score = self.pieces[id].score;
+                    if best.is_none_or(|(best, _)| score > best) {
+                        best = Some((score, i));
+                    }
+                }
+            }
+            let Some((_, i)) = best else {
+                return symbols;
+            };
+            let next = symbols.remove(i + 1);
+            symbols[i].push_str(&next);
+        }
+    }
+}
+
+fn split_words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for c in text.chars() {
+        match words.last_mut() {
+            Some(word) if c != WORD_BOUNDARY => word.push(c),
+            _ => words.push(c.to_string()),
+        }
+    }
+    words
+}
+
+// Collects the words of the written sentences and learns BPE merges from
+// them, like `spm_train --model_type=bpe` with whitespace splitting. Each
+// sentence without spaces is a single word, so no piece spans two
+// sentences.
+#[derive(Debug, Default)]
+pub struct BpeTrainer {
+    words: HashMap<String, u64>,
+}
+
+impl BpeTrainer {
+    pub fn observe(&mut self, sentence: &str) {
+        for word in sentence.split_whitespace() {
+            let mut marked = String::with_capacity(word.len() + 3);
+            marked.push(WORD_BOUNDARY);
+            marked.push_str(word);
+            *self.words.entry(marked).or_default() += 1;
+        }
+    }
+
+    // A model of the meta pieces, the merged pieces in the order they were
+    // learned and every char seen. Fewer pieces than `vocab_size` are
+    // returned when no pair is left to merge.
+    pub fn train(&self, vocab_size: usize) -> io::Result<SpmModel> {
+        let mut char_counts: HashMap<char, u64> = HashMap::new();
+        for (word, count) in &self.words {
+            for c in word.chars() {
+                *char_counts.entry(c).or_default() += count;
+            }
+        }
+        let mut chars: Vec<(char, u64)> = char_counts.into_iter().collect();
+        chars.sort_by_key(|&(c, count)| (Reverse(count), c));
+        let required = META_PIECES.len() + chars.len();
+        if vocab_size < required {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "vocab size {} is below the {} meta pieces and chars the sentences need",
+                    vocab_size, required
+                ),
+            ));
+        }
+
+        // symbols are numbered chars first, most frequent first, then in the
+        // order they are merged, which also breaks ties between pairs
+        let mut symbols: Vec<String> = chars.iter().map(|(c, _)| c.to_string()).collect();
+        let mut symbol_ids: HashMap<String, u32> = symbols
+            .iter()
+            .enumerate()
+            .map(|(id, symbol)| (symbol.clone(), id as u32))
+            .collect();
+        let mut words: Vec<(Vec<u32>, u64)> = Vec::with_capacity(self.words.len());
+        let mut sorted_words: Vec<(&String, &u64)> = self.words.iter().collect();
+        sorted_words.sort();
+        for (word, &count) in sorted_words {
+            words.push((
+                word.chars().map(|c| symbol_ids[&c.to_string()]).collect(),
+                count,
+            ));
+        }
+        let mut pair_counts: HashMap<(u32, u32), u64> = HashMap::new();
+        let mut pair_words: HashMap<(u32, u32), HashSet<usize>> = HashMap::new();
+        for (w, (word, count)) in words.iter().enumerate() {
+            for pair in word.windows(2) {
+                let pair = (pair[0], pair[1]);
+                *pair_counts.entry(pair).or_default() += count;
+                pair_words.entry(pair).or_default().insert(w);
+            }
+        }
+        // stale entries are skipped when their count no longer matches
+        let mut heap: BinaryHeap<(u64, Reverse<(u32, u32)>)> = pair_counts
+            .iter()
+            .map(|(&pair, &count)| (count, Reverse(pair)))
+            .collect();
+
+        let mut merged_pieces: Vec<u32> = Vec::new();
+        while required + merged_pieces.len() < vocab_size {
+            let Some((count, Reverse(pair))) = heap.pop() else {
+                break;
+            };
+            if count == 0 || pair_counts.get(&pair) != Some(&count) {
+                continue;
+            }
+            let merged = format!("{}{}", symbols[pair.0 as usize], symbols[pair.1 as usize]);
+            if merged.chars().count() > MAX_PIECE_CHARS {
+                continue;
+            }
+            // the same string may come out of different merges
+            let merged_id = match symbol_ids.get(&merged) {
+                Some(&id) => id,
+                None => {
+                    let id = symbols.len() as u32;
+                    symbol_ids.insert(merged.clone(), id);
+                    symbols.push(merged);
+                    merged_pieces.push(id);
+                    id
+                }
+            };
+
+            let mut deltas: HashMap<(u32, u32), i64> = HashMap::new();
+            for w in pair_words.remove(&pair).unwrap_or_default() {
+                let (word, count) = &mut words[w];
+                let count = *count as i64;
+                for old in word.windows(2) {
+                    *deltas.entry((old[0], old[1])).or_default() -= count;
+                }
+                let mut merged_word = Vec::with_capacity(word.len());
+                let mut i = 0;
+                while i < word.len() {
+                    if i + 1 < word.len() && (word[i], word[i + 1]) == pair {
+                        merged_word.push(merged_id);
+                        i += 2;
+                    } else {
+                        merged_word.push(word[i]);
+                        i += 1;
+                    }
+                }
+                for new in merged_word.windows(2) {
+                    let new = (new[0], new[1]);
+                    *deltas.entry(new).or_default() += count;
+                    pair_words.entry(new).or_default().insert(w);
+                }
+                *word = merged_word;
+            }
+            for (pair, delta) in deltas {
+                if delta == 0 {
+                    continue;
+                }
+                let count = pair_counts.entry(pair).or_default();
+                *count = (*count as i64 + delta) as u64;
+                if *count > 0 {
+                    heap.push((*count, Reverse(pair)));
+                }
+            }
+        }
+
+        let mut pieces: Vec<Piece> = META_PIECES
+            .iter()
+            .map(|&(piece, kind)| Piece {
+                piece: piece.to_string(),
+                score: 0.0,
+                kind,
+            })
+            .collect();
+        let learned = merged_pieces.iter().copied().chain(0..chars.len() as u32);
+        for (rank, id) in learned.enumerate() {
+            pieces.push(Piece {
+                piece: symbols[id as usize].clone(),
+                score: -(rank as f32),
+                kind: PieceType::Normal,
+            });
+        }
+        Ok(SpmModel::new(pieces, ModelType::Bpe))
+    }
+}
+
+// Protocol buffer wire format, just enough for the model file
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_BYTES: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+enum Field<'a> {
+    Varint(u64),
+    Fixed32([u8; 4]),
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+struct ProtoReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ProtoReader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(truncated());
+        };
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "varint too long",
+        ))
+    }
+
+    fn next(&mut self) -> io::Result<Option<(u64, Field<'a>)>> {
+        if self.pos == self.bytes.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            WIRE_VARINT => Field::Varint(self.varint()?),
+            WIRE_FIXED64 => {
+                self.take(8)?;
+                Field::Fixed64
+            }
+            WIRE_BYTES => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            WIRE_FIXED32 => Field::Fixed32(self.take(4)?.try_into().unwrap()),
+            wire => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported wire type {}", wire),
+                ))
+            }
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "truncated model")
+}
+
+fn read_piece(bytes: &[u8]) -> io::Result<Piece> {
+    let mut piece = Piece {
+        piece: String::new(),
+        score: 0.0,
+        kind: PieceType::Normal,
+    };
+    let mut reader = ProtoReader::new(bytes);
+    while let Some((field, value)) = reader.next()? {
+        match (field, value) {
+            (1, Field::Bytes(text)) => {
+                piece.piece = String::from_utf8(text.to_vec())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            }
+            (2, Field::Fixed32(score)) => piece.score = f32::from_le_bytes(score),
+            (3, Field::Varint(kind)) => piece.kind = PieceType::from_proto(kind),
+            _ => {}
+        }
+    }
+    Ok(piece)
+}
+
+fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn put_key(bytes: &mut Vec<u8>, field: u64, wire: u64) {
+    put_varint(bytes, field << 3 | wire);
+}
+
+fn put_varint_field(bytes: &mut Vec<u8>, field: u64, value: u64) {
+    put_key(bytes, field, WIRE_VARINT);
+    put_varint(bytes, value);
+}
+
+fn put_bytes(bytes: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_key(bytes, field, WIRE_BYTES);
+    put_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trainer(sentences: &[&str]) -> BpeTrainer {
+        let mut trainer = BpeTrainer::default();
+        for sentence in sentences {
+            trainer.observe(sentence);
+        }
+        trainer
+    }
+
+    #[test]
+    fn bpe_merges_the_most_frequent_pairs() {
+        let trainer = trainer(&["我哋去飲茶", "我哋去行街", "我哋返屋企", "飲茶"]);
+        // 3 meta pieces and 11 chars, ties go to the pair of more frequent chars
+        let model = trainer.train(17).unwrap();
+        let learned: Vec<&str> = model.pieces[3..6]
+            .iter()
+            .map(|p| p.piece.as_str())
+            .collect();
+        assert_eq!(learned, ["▁我", "▁我哋", "飲茶"]);
+        assert_eq!(model.encode("我哋去飲茶"), ["▁我哋", "去", "飲茶"]);
+        assert_eq!(model.encode("佢哋 飲茶"), ["▁", "佢", "哋", "▁", "飲茶"]);
+        assert!(trainer.train(13).is_err());
+    }
+
+    #[test]
+    fn training_stops_when_nothing_is_left_to_merge() {
+        let model = trainer(&["我哋去飲茶"]).train(8000).unwrap();
+        assert_eq!(model.pieces.len(), 3 + 6 + 5);
+        assert_eq!(model.encode("我哋去飲茶"), ["▁我哋去飲茶"]);
+    }
+
+    #[test]
+    fn models_round_trip() {
+        let model = trainer(&["我哋去飲茶", "我哋去行街"]).train(20).unwrap();
+        let loaded = SpmModel::from_bytes(&model.to_bytes()).unwrap();
+        assert_eq!(loaded.pieces, model.pieces);
+        assert_eq!(loaded.model_type, ModelType::Bpe);
+        assert_eq!(loaded.encode("我哋去行街"), model.encode("我哋去行街"));
+        assert!(SpmModel::from_bytes(&model.to_bytes()[..20]).is_err());
+    }
+
+    #[test]
+    fn unigram_picks_the_best_segmentation() {
+        let piece = |piece: &str, score| Piece {
+            piece: piece.to_string(),
+            score,
+            kind: PieceType::Normal,
+        };
+        let model = SpmModel::new(
+            vec![
+                Piece {
+                    piece: "<unk>".to_string(),
+                    score: 0.0,
+                    kind: PieceType::Unknown,
+                },
+                piece("▁", -2.0),
+                piece("▁飲茶", -3.0),
+                piece("飲", -2.0),
+                piece("茶", -2.0),
+                piece("去", -2.5),
+                piece("▁去", -4.0),
+            ],
+            ModelType::Unigram,
+        );
+        assert_eq!(model.encode("飲茶"), ["▁飲茶"]);
+        assert_eq!(model.encode("去飲茶"), ["▁去", "飲", "茶"]);
+        assert_eq!(model.encode("去食"), ["▁去", "食"]);
+    }
+}
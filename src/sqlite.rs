@@ -0,0 +1,154 @@
+use crate::sink::{SentenceSink, SinkReport};
+use crate::{cjk_ratio, SentenceRecord};
+use rusqlite::{params, Connection};
+use std::io;
+use std::path::Path;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS sentences (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    text TEXT NOT NULL,
+    source_file TEXT,
+    cjk_ratio REAL,
+    char_count INTEGER,
+    timestamp INTEGER
+)";
+
+struct Row {
+    text: String,
+    cjk_ratio: f64,
+    char_count: usize,
+    // reply time of the post
+    timestamp: Option<i64>,
+}
+
+// The written sentences as rows of a SQLite table, queryable without loading
+// the corpus. Rows are held by `push` and inserted by `commit`, one
+// transaction per archive entry.
+pub struct SentenceDb {
+    conn: Connection,
+    pending: Vec<Row>,
+    inserted: u64,
+}
+
+impl SentenceDb {
+    // Adds to the table of an existing database
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(io::Error::other)?;
+        conn.execute(SCHEMA, []).map_err(io::Error::other)?;
+        Ok(SentenceDb {
+            conn,
+            pending: Vec::new(),
+            inserted: 0,
+        })
+    }
+
+    pub fn push(&mut self, record: &SentenceRecord) {
+        self.pending.push(Row {
+            text: record.text.clone(),
+            cjk_ratio: cjk_ratio(&record.text),
+            char_count: record.text.chars().count(),
+            timestamp: record.reply_time,
+        });
+    }
+
+    // Inserts the pending rows as coming from `source_file`
+    pub fn commit(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        insert(&mut self.conn, &self.pending, source_file).map_err(io::Error::other)?;
+        self.inserted += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl SentenceSink for SentenceDb {
+    fn write(&mut self, record: &SentenceRecord) -> io::Result<()> {
+        self.push(record);
+        Ok(())
+    }
+
+    fn end_entry(&mut self, source_file: Option<&str>) -> io::Result<()> {
+        self.commit(source_file)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<SinkReport> {
+        self.commit(None)?;
+        Ok(SinkReport {
+            records: self.inserted,
+            bytes: None,
+        })
+    }
+}
+
+fn insert(conn: &mut Connection, rows: &[Row], source_file: Option<&str>) -> rusqlite::Result<()> {
+    let transaction = conn.transaction()?;
+    {
+        let mut statement = transaction.prepare_cached(
+            "INSERT INTO sentences (text, source_file, cjk_ratio, char_count, timestamp)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for row in rows {
+            statement.execute(params![
+                row.text,
+                source_file,
+                row.cjk_ratio,
+                row.char_count,
+                row.timestamp
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences_are_queryable() {
+        let path = std::env::temp_dir().join(format!("lihkg-{}.sqlite", std::process::id()));
+        let mut db = SentenceDb::open(&path).unwrap();
+        for (text, reply_time) in [("我哋今日去咗飲茶", Some(1697328000)), ("OK啦", None)]
+        {
+            db.push(&SentenceRecord {
+                text: text.to_string(),
+                reply_time,
+                ..Default::default()
+            });
+        }
+        db.commit(Some("dump/3300001.csv")).unwrap();
+        let rows: Vec<(String, String, f64, i64, Option<i64>)> = db
+            .conn
+            .prepare("SELECT text, source_file, cjk_ratio, char_count, timestamp FROM sentences ORDER BY id")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            rows,
+            [
+                (
+                    "我哋今日去咗飲茶".to_string(),
+                    "dump/3300001.csv".to_string(),
+                    1.0,
+                    8,
+                    Some(1697328000)
+                ),
+                (
+                    "OK啦".to_string(),
+                    "dump/3300001.csv".to_string(),
+                    1.0 / 3.0,
+                    3,
+                    None
+                )
+            ]
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use crate::filters::RejectReason;
+use crate::PostContent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Paragraph counts per length in chars, the last bucket holds everything
+// at or above the cap
+pub const LENGTH_HISTOGRAM_CAP: usize = 100;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LengthHistogram(pub Vec<u64>);
+
+impl LengthHistogram {
+    pub fn add(&mut self, len: usize) {
+        let len = len.min(LENGTH_HISTOGRAM_CAP);
+        if self.0.len() <= len {
+            self.0.resize(len + 1, 0);
+        }
+        self.0[len] += 1;
+    }
+
+    pub fn merge(&mut self, other: &LengthHistogram) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (count, other) in self.0.iter_mut().zip(&other.0) {
+            *count += other;
+        }
+    }
+
+    pub fn get(&self, len: usize) -> u64 {
+        self.0.get(len).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EntryStats {
+    pub entry: String,
+    pub lines_read: u64,
+    pub json_errors: u64,
+    pub items_found: u64,
+    pub paragraphs_valid: u64,
+    pub sentences_emitted: u64,
+    pub duration_ms: u64,
+    // the part of an entry written early under --max-memory-mb
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part: Option<usize>,
+}
+
+impl EntryStats {
+    pub fn new(entry: &str, stats: &Stats) -> Self {
+        EntryStats {
+            entry: entry.to_string(),
+            lines_read: stats.lines,
+            json_errors: stats.json_errors,
+            items_found: stats.items,
+            paragraphs_valid: stats.sentences,
+            ..Default::default()
+        }
+    }
+}
+
+// Read back from stats files to merge them, fields missing from older
+// files count as zero
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub lines: u64,
+    pub json_errors: u64,
+    // archive entries skipped because they could not be read
+    pub corrupt_entries: u64,
+    // lines read in another encoding than UTF-8, and those that could not be
+    // read in any, with --detect-encoding
+    pub transcoded_lines: u64,
+    pub encoding_errors: u64,
+    pub items: u64,
+    // page responses skipped for a thread below --min-replies
+    pub thread_too_small: u64,
+    // posts skipped for fewer likes than --min-likes
+    pub posts_too_few_likes: u64,
+    // posts skipped per --ban-users entry they matched
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub banned_posts: BTreeMap<String, u64>,
+    // posts giving no text, see `NonTextPosts`
+    pub non_text_posts: NonTextPosts,
+    // responses without success by their error code, see `api_error_code`
+    pub api_errors: BTreeMap<String, ApiErrors>,
+    pub paragraphs: u64,
+    pub sentences: u64,
+    pub duplicate_posts: u64,
+    pub duplicate_sentences: u64,
+    // posts with a paragraph rejected as deleted
+    pub deleted_posts: u64,
+    // posts taken for a pasted news article, see `news::NewsDetector`
+    pub news_posts: u64,
+    // paragraphs of English only and those of them written to --english-out
+    pub english_paragraphs: u64,
+    pub english_sentences: u64,
+    pub substrings_dropped: u64,
+    // pairs of distinct sentences expected to share a dedup hash, from the
+    // number of distinct sentences and the 64 bit hash range
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_hash_collisions: Option<f64>,
+    // share of unseen sentences the --dedup-bloom filter drops by the end
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bloom_false_positive_rate: Option<f64>,
+    // MB per second read from the input files under --max-read-mbps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_mbps: Option<f64>,
+    pub rejected: BTreeMap<RejectReason, u64>,
+    // matches per profanity list entry, whether dropped or masked
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profanity: BTreeMap<String, u64>,
+    // non-empty paragraphs before and after filtering
+    pub considered_lengths: LengthHistogram,
+    pub accepted_lengths: LengthHistogram,
+    // posts by their valid sentences, before deduplication
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sentences_per_post: BTreeMap<u64, u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<EntryStats>,
+}
+
+impl Stats {
+    pub fn reject(&mut self, reason: RejectReason) {
+        *self.rejected.entry(reason).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        self.lines += other.lines;
+        self.json_errors += other.json_errors;
+        self.corrupt_entries += other.corrupt_entries;
+        self.transcoded_lines += other.transcoded_lines;
+        self.encoding_errors += other.encoding_errors;
+        self.items += other.items;
+        self.thread_too_small += other.thread_too_small;
+        self.posts_too_few_likes += other.posts_too_few_likes;
+        for (entry, posts) in other.banned_posts {
+            *self.banned_posts.entry(entry).or_default() += posts;
+        }
+        self.paragraphs += other.paragraphs;
+        self.sentences += other.sentences;
+        self.duplicate_posts += other.duplicate_posts;
+        self.non_text_posts.merge(&other.non_text_posts);
+        for (code, errors) in other.api_errors {
+            let merged = self.api_errors.entry(code).or_default();
+            merged.count += errors.count;
+            if merged.message.is_empty() {
+                merged.message = errors.message;
+            }
+        }
+        self.duplicate_sentences += other.duplicate_sentences;
+        self.deleted_posts += other.deleted_posts;
+        self.news_posts += other.news_posts;
+        self.english_paragraphs += other.english_paragraphs;
+        self.english_sentences += other.english_sentences;
+        self.substrings_dropped += other.substrings_dropped;
+        for (reason, count) in other.rejected {
+            *self.rejected.entry(reason).or_default() += count;
+        }
+        for (word, count) in other.profanity {
+            *self.profanity.entry(word).or_default() += count;
+        }
+        self.considered_lengths.merge(&other.considered_lengths);
+        self.accepted_lengths.merge(&other.accepted_lengths);
+        for (sentences, posts) in other.sentences_per_post {
+            *self.sentences_per_post.entry(sentences).or_default() += posts;
+        }
+        self.entries.extend(other.entries);
+    }
+
+    // Length distribution and per-entry yield as plain text
+    pub fn histogram(&self) -> String {
+        const WIDTH: u64 = 50;
+        let max = self.considered_lengths.0.iter().copied().max().unwrap_or(0);
+        let mut out = String::from("length  considered  accepted\n");
+        for len in 1..self.considered_lengths.0.len() {
+            let considered = self.considered_lengths.get(len);
+            let accepted = self.accepted_lengths.get(len);
+            let bar = |count: u64| (count * WIDTH).div_ceil(max.max(1)) as usize;
+            let label = if len == LENGTH_HISTOGRAM_CAP {
+                format!("{}+", len)
+            } else {
+                len.to_string()
+            };
+            out.push_str(&format!(
+                "{:>6}  {:>10}  {:>8}  {}{}\n",
+                label,
+                considered,
+                accepted,
+                "#".repeat(bar(accepted)),
+                ".".repeat(bar(considered) - bar(accepted)),
+            ));
+        }
+        if !self.entries.is_empty() {
+            out.push_str("\nentry  lines_read  sentences_emitted\n");
+            for entry in &self.entries {
+                out.push_str(&format!(
+                    "{}  {}  {}\n",
+                    entry.entry, entry.lines_read, entry.sentences_emitted
+                ));
+            }
+        }
+        out
+    }
+
+    pub fn summary(&self) -> String {
+        let rejected = self
+            .rejected
+            .iter()
+            .map(|(reason, count)| format!("{}={}", reason.as_str(), count))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let api_errors = self
+            .api_errors
+            .iter()
+            .map(|(code, errors)| format!("{}={}", code, errors.count))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut summary = format!(
+            "lines={} json_errors={} corrupt_entries={} thread_too_small={} posts_too_few_likes={} paragraphs={} sentences={} duplicate_posts={} duplicate_sentences={} deleted_posts={} news_posts={} substrings_dropped={} api_errors: {} rejected: {}",
+            self.lines,
+            self.json_errors,
+            self.corrupt_entries,
+            self.thread_too_small,
+            self.posts_too_few_likes,
+            self.paragraphs,
+            self.sentences,
+            self.duplicate_posts,
+            self.duplicate_sentences,
+            self.deleted_posts,
+            self.news_posts,
+            self.substrings_dropped,
+            api_errors,
+            rejected
+        );
+        if self.english_paragraphs > 0 {
+            summary.push_str(&format!(
+                " english_paragraphs={} english_sentences={}",
+                self.english_paragraphs, self.english_sentences
+            ));
+        }
+        if self.transcoded_lines + self.encoding_errors > 0 {
+            summary.push_str(&format!(
+                " transcoded_lines={} encoding_errors={}",
+                self.transcoded_lines, self.encoding_errors
+            ));
+        }
+        if !self.banned_posts.is_empty() {
+            let banned = self
+                .banned_posts
+                .iter()
+                .map(|(entry, posts)| format!("{}={}", entry, posts))
+                .collect::<Vec<_>>()
+                .join(" ");
+            summary.push_str(&format!(" banned_posts: {}", banned));
+        }
+        if let Some(collisions) = self.expected_hash_collisions {
+            summary.push_str(&format!(" expected_hash_collisions={:.3e}", collisions));
+        }
+        if let Some(rate) = self.bloom_false_positive_rate {
+            summary.push_str(&format!(" bloom_false_positive_rate={:.3e}", rate));
+        }
+        if let Some(mbps) = self.read_mbps {
+            summary.push_str(&format!(" read_mbps={:.2}", mbps));
+        }
+        summary
+    }
+}
+
+// What deduplication dropped over a run, for the stats file. Exact
+// sentence dedup is by hash, see `Stats::expected_hash_collisions` for the
+// sentences it may have dropped wrongly.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DedupStats {
+    pub exact_duplicates_dropped: u64,
+    pub post_duplicates_dropped: u64,
+    pub unique_sentences_written: u64,
+}
+
+impl DedupStats {
+    pub fn new(stats: &Stats, written: u64) -> Self {
+        DedupStats {
+            exact_duplicates_dropped: stats.duplicate_sentences,
+            post_duplicates_dropped: stats.duplicate_posts,
+            unique_sentences_written: written,
+        }
+    }
+}
+
+// Posts whose HTML gave no text, by what it held instead. An image beside a
+// quote makes the post image only.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NonTextPosts {
+    // images or stickers, LIHKG's emoji included
+    pub image_only: u64,
+    pub quote_only: u64,
+    pub empty: u64,
+}
+
+impl NonTextPosts {
+    pub fn count(&mut self, content: PostContent) {
+        if content.images > 0 {
+            self.image_only += 1;
+        } else if content.quotes > 0 {
+            self.quote_only += 1;
+        } else {
+            self.empty += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &NonTextPosts) {
+        self.image_only += other.image_only;
+        self.quote_only += other.quote_only;
+        self.empty += other.empty;
+    }
+}
+
+// Responses of one error code, e.g. 100 for a thread that does not exist,
+// with the message of the first one
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiErrors {
+    pub message: String,
+    pub count: u64,
+}
+
+impl ApiErrors {
+    pub fn count(&mut self, message: &str) {
+        if self.message.is_empty() {
+            self.message = message.to_string();
+        }
+        self.count += 1;
+    }
+}
+
+// Totals of one thread for --thread-stats
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ThreadStats {
+    pub posts: u64,
+    pub sentences: u64,
+    // non-empty paragraphs and the sum of their CJK char ratios
+    pub paragraphs: u64,
+    pub cjk_ratio_sum: f64,
+    // posts shown as removed by their poster or moderators
+    pub deleted: u64,
+    pub min_reply_time: Option<i64>,
+    pub max_reply_time: Option<i64>,
+}
+
+impl ThreadStats {
+    pub fn observe_reply_time(&mut self, reply_time: i64) {
+        self.min_reply_time = Some(
+            self.min_reply_time
+                .map_or(reply_time, |t| t.min(reply_time)),
+        );
+        self.max_reply_time = Some(
+            self.max_reply_time
+                .map_or(reply_time, |t| t.max(reply_time)),
+        );
+    }
+
+    pub fn merge(&mut self, other: &ThreadStats) {
+        self.posts += other.posts;
+        self.sentences += other.sentences;
+        self.paragraphs += other.paragraphs;
+        self.cjk_ratio_sum += other.cjk_ratio_sum;
+        self.deleted += other.deleted;
+        for reply_time in [other.min_reply_time, other.max_reply_time]
+            .into_iter()
+            .flatten()
+        {
+            self.observe_reply_time(reply_time);
+        }
+    }
+
+    pub fn avg_cjk_ratio(&self) -> f64 {
+        if self.paragraphs == 0 {
+            0.0
+        } else {
+            self.cjk_ratio_sum / self.paragraphs as f64
+        }
+    }
+}
+
+// One row per number of valid sentences a post gave, posts giving none
+// included
+pub fn post_sentences_tsv(sentences_per_post: &BTreeMap<u64, u64>) -> String {
+    let mut tsv = String::from("sentences_per_post\tpost_count\n");
+    for (sentences, posts) in sentences_per_post {
+        tsv.push_str(&format!("{}\t{}\n", sentences, posts));
+    }
+    tsv
+}
+
+// One row per thread in thread id order, unknown reply times left empty
+pub fn thread_stats_tsv(threads: &[(u64, ThreadStats)]) -> String {
+    let mut threads: Vec<&(u64, ThreadStats)> = threads.iter().collect();
+    threads.sort_by_key(|(thread_id, _)| *thread_id);
+    let time = |t: Option<i64>| t.map(|t| t.to_string()).unwrap_or_default();
+    let mut tsv = String::from(
+        "thread_id\ttotal_posts\tvalid_sentences\tavg_cjk_ratio\tmin_reply_time\tmax_reply_time\tdeleted_posts\n",
+    );
+    for (thread_id, stats) in threads {
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\t{:.4}\t{}\t{}\t{}\n",
+            thread_id,
+            stats.posts,
+            stats.sentences,
+            stats.avg_cjk_ratio(),
+            time(stats.min_reply_time),
+            time(stats.max_reply_time),
+            stats.deleted
+        ));
+    }
+    tsv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histograms_merge_and_cap() {
+        let mut a = LengthHistogram::default();
+        a.add(3);
+        a.add(500);
+        let mut b = LengthHistogram::default();
+        b.add(3);
+        b.add(7);
+        a.merge(&b);
+        assert_eq!(a.get(3), 2);
+        assert_eq!(a.get(7), 1);
+        assert_eq!(a.get(LENGTH_HISTOGRAM_CAP), 1);
+        assert_eq!(a.0.len(), LENGTH_HISTOGRAM_CAP + 1);
+    }
+
+    #[test]
+    fn thread_stats_rows() {
+        let mut a = ThreadStats {
+            posts: 1,
+            sentences: 2,
+            paragraphs: 2,
+            cjk_ratio_sum: 1.5,
+            ..Default::default()
+        };
+        a.observe_reply_time(200);
+        let mut b = ThreadStats {
+            posts: 1,
+            paragraphs: 1,
+            cjk_ratio_sum: 0.0,
+            deleted: 1,
+            ..Default::default()
+        };
+        b.observe_reply_time(100);
+        b.observe_reply_time(300);
+        a.merge(&b);
+        assert_eq!((a.min_reply_time, a.max_reply_time), (Some(100), Some(300)));
+        let tsv = thread_stats_tsv(&[(7, a), (3, ThreadStats::default())]);
+        assert_eq!(
+            tsv.lines().skip(1).collect::<Vec<_>>(),
+            ["3\t0\t0\t0.0000\t\t\t0", "7\t2\t2\t0.5000\t100\t300\t1"]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const BASE: u64 = 0x100000001b3;
+
+// Marks every sentence that is a strict substring of a longer one.
+//
+// Short sentences are indexed by (length, rolling hash); every sentence then
+// slides a window of each indexed length over itself, so the cost is linear
+// in the number of sentences times the squared maximum length instead of
+// quadratic in the number of sentences. Hash hits are verified.
+pub fn contained_in_longer(sentences: &[&str]) -> Vec<bool> {
+    let chars: Vec<Vec<char>> = sentences.iter().map(|s| s.chars().collect()).collect();
+
+    let mut index: HashMap<usize, HashMap<u64, Vec<usize>>> = HashMap::new();
+    for (i, s) in chars.iter().enumerate() {
+        if !s.is_empty() {
+            index
+                .entry(s.len())
+                .or_default()
+                .entry(hash(s))
+                .or_default()
+                .push(i);
+        }
+    }
+    let mut lengths: Vec<usize> = index.keys().copied().collect();
+    lengths.sort_unstable();
+
+    let contained: Vec<AtomicBool> = sentences.iter().map(|_| AtomicBool::new(false)).collect();
+    chars.par_iter().for_each(|s| {
+        for &len in lengths.iter().take_while(|&&len| len < s.len()) {
+            let candidates = &index[&len];
+            let top = BASE.wrapping_pow(len as u32 - 1);
+            let mut h = hash(&s[..len]);
+            for start in 0..=s.len() - len {
+                if start > 0 {
+                    h = h
+                        .wrapping_sub((s[start - 1] as u64).wrapping_mul(top))
+                        .wrapping_mul(BASE)
+                        .wrapping_add(s[start + len - 1] as u64);
+                }
+                if let Some(matches) = candidates.get(&h) {
+                    for &j in matches {
+                        if chars[j] == s[start..start + len] {
+                            contained[j].store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+        }
+    });
+    contained.into_iter().map(AtomicBool::into_inner).collect()
+}
+
+fn hash(chars: &[char]) -> u64 {
+    chars
+        .iter()
+        .fold(0u64, |h, &c| h.wrapping_mul(BASE).wrapping_add(c as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_prefixes_and_infixes() {
+        let sentences = [
+            "今日天氣好好",
+            "今日天氣好好呀各位",
+            "天氣好好呀",
+            "我想去行山",
+            "天氣",
+        ];
+        assert_eq!(
+            contained_in_longer(&sentences),
+            [true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn identical_sentences_are_kept() {
+        assert_eq!(
+            contained_in_longer(&["好好食呀", "好好食呀"]),
+            [false, false]
+        );
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let sentences = ["abcab", "bca", "cab", "abc", "cabx", "xab", "b", ""];
+        let expected: Vec<bool> = sentences
+            .iter()
+            .map(|s| {
+                !s.is_empty()
+                    && sentences
+                        .iter()
+                        .any(|t| t.chars().count() > s.chars().count() && t.contains(s))
+            })
+            .collect();
+        assert_eq!(contained_in_longer(&sentences), expected);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::value_as_i64;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+// What the responses of a thread say about it, each field as first seen in
+// input order, with the sentences of the thread written
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ThreadMeta {
+    pub thread_id: u64,
+    pub title: Option<String>,
+    // the category's name, else its cat_id
+    pub category: Option<String>,
+    pub create_time: Option<i64>,
+    pub total_replies: Option<i64>,
+    pub sentences: u64,
+}
+
+impl ThreadMeta {
+    pub fn from_response(thread_id: u64, response: &Value) -> Self {
+        let category = match &response["category"]["name"] {
+            Value::String(name) => Some(name.clone()),
+            _ => value_as_i64(&response["cat_id"])
+                .or_else(|| value_as_i64(&response["category"]["cat_id"]))
+                .map(|id| id.to_string()),
+        };
+        ThreadMeta {
+            thread_id,
+            title: response["title"].as_str().map(String::from),
+            category,
+            create_time: value_as_i64(&response["create_time"]),
+            total_replies: value_as_i64(&response["total_replies"])
+                .or_else(|| value_as_i64(&response["no_of_reply"])),
+            sentences: 0,
+        }
+    }
+
+    // Takes the fields still unknown from a later page of the thread
+    pub fn fill(&mut self, other: ThreadMeta) {
+        self.title = self.title.take().or(other.title);
+        self.category = self.category.take().or(other.category);
+        self.create_time = self.create_time.or(other.create_time);
+        self.total_replies = self.total_replies.or(other.total_replies);
+        self.sentences += other.sentences;
+    }
+}
+
+// The threads of a run by id, as responses arrive and sentences are written
+#[derive(Debug, Default)]
+pub struct ThreadTable {
+    threads: BTreeMap<u64, ThreadMeta>,
+}
+
+impl ThreadTable {
+    pub fn observe(&mut self, meta: ThreadMeta) {
+        match self.threads.get_mut(&meta.thread_id) {
+            Some(thread) => thread.fill(meta),
+            None => {
+                self.threads.insert(meta.thread_id, meta);
+            }
+        }
+    }
+
+    pub fn count_sentence(&mut self, thread_id: u64) {
+        self.threads
+            .entry(thread_id)
+            .or_insert_with(|| ThreadMeta {
+                thread_id,
+                ..Default::default()
+            })
+            .sentences += 1;
+    }
+
+    // The threads by id, those without a written sentence only with
+    // `include_empty`
+    pub fn threads(&self, include_empty: bool) -> impl Iterator<Item = &ThreadMeta> {
+        self.threads
+            .values()
+            .filter(move |thread| include_empty || thread.sentences > 0)
+    }
+
+    // As json lines, else as csv with a header row
+    pub fn write(&self, mut out: impl Write, jsonl: bool, include_empty: bool) -> io::Result<()> {
+        if !jsonl {
+            writeln!(
+                out,
+                "thread_id,title,category,create_time,total_replies,sentences"
+            )?;
+        }
+        for thread in self.threads(include_empty) {
+            if jsonl {
+                writeln!(out, "{}", serde_json::to_string(thread)?)?;
+                continue;
+            }
+            let number = |n: Option<i64>| n.map(|n| n.to_string()).unwrap_or_default();
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                thread.thread_id,
+                csv_field(thread.title.as_deref().unwrap_or_default()),
+                csv_field(thread.category.as_deref().unwrap_or_default()),
+                number(thread.create_time),
+                number(thread.total_replies),
+                thread.sentences
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A csv field, quoted when it holds a comma, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pages_fill_in_what_earlier_ones_lack() {
+        let mut table = ThreadTable::default();
+        table.observe(ThreadMeta::from_response(
+            7,
+            &json!({"thread_id": "7", "page": "2", "total_replies": "120"}),
+        ));
+        table.count_sentence(7);
+        table.observe(ThreadMeta::from_response(
+            7,
+            &json!({
+                "title": "食咩好, \"求推介\"",
+                "category": {"cat_id": "1", "name": "吹水台"},
+                "create_time": 1600000000,
+                "total_replies": "121",
+            }),
+        ));
+        table.observe(ThreadMeta::from_response(8, &json!({"cat_id": 5})));
+        table.count_sentence(7);
+
+        let threads: Vec<&ThreadMeta> = table.threads(false).collect();
+        assert_eq!(
+            threads,
+            [&ThreadMeta {
+                thread_id: 7,
+                title: Some("食咩好, \"求推介\"".to_string()),
+                category: Some("吹水台".to_string()),
+                create_time: Some(1600000000),
+                total_replies: Some(120),
+                sentences: 2,
+            }]
+        );
+
+        let mut csv = Vec::new();
+        table.write(&mut csv, false, true).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "thread_id,title,category,create_time,total_replies,sentences\n\
+             7,\"食咩好, \"\"求推介\"\"\",吹水台,1600000000,120,2\n\
+             8,,5,,,0\n"
+        );
+        let mut jsonl = Vec::new();
+        table.write(&mut jsonl, true, false).unwrap();
+        let line: Value = serde_json::from_slice(&jsonl).unwrap();
+        assert_eq!(line["category"], "吹水台");
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// Bytes a read may take at once, and that may be read ahead of the rate
+// after a pause: the rate's worth of 50ms, at least a page. The bucket is
+// empty at the first read, so the run does not start with a burst.
+const BURST_SECS: f64 = 0.05;
+const MIN_BURST: usize = 4096;
+
+// A cap on the bytes read from the input files per second, shared by every
+// reader of the run so inputs read at once stay under it together. The
+// bytes are those of the files as stored, before decompression.
+pub struct ReadLimit {
+    bytes_per_sec: f64,
+    burst: usize,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    // may go below zero, the debt the reader taking it sleeps off
+    tokens: f64,
+    refilled: Instant,
+    bytes: u64,
+    // of the first and the last read
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl ReadLimit {
+    pub fn new(mb_per_sec: f64) -> Self {
+        let bytes_per_sec = mb_per_sec * (1 << 20) as f64;
+        let burst = ((bytes_per_sec * BURST_SECS) as usize).max(MIN_BURST);
+        ReadLimit {
+            bytes_per_sec,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled: Instant::now(),
+                bytes: 0,
+                first: None,
+                last: None,
+            }),
+        }
+    }
+
+    // Counts `bytes` as read, sleeping until the rate allows them
+    fn take(&self, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            if bucket.first.is_none() {
+                bucket.first = Some(now);
+                bucket.refilled = now;
+            }
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.bytes_per_sec;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst as f64) - bytes as f64;
+            bucket.refilled = now;
+            bucket.bytes += bytes as u64;
+            let wait = (-bucket.tokens).max(0.0) / self.bytes_per_sec;
+            bucket.last = Some(now + Duration::from_secs_f64(wait));
+            wait
+        };
+        if wait > 0.0 {
+            std::thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+
+    // MB per second from the first read to the end of the last, none before
+    // the reads took any time
+    pub fn achieved_mbps(&self) -> Option<f64> {
+        let bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let secs = bucket.last?.duration_since(bucket.first?).as_secs_f64();
+        (secs > 0.0).then(|| bucket.bytes as f64 / (1 << 20) as f64 / secs)
+    }
+}
+
+// A reader held to a limit, reading at most a burst at once so a large
+// buffer above it, such as the decompressor's, cannot pull more ahead.
+// Without a limit it reads as the inner reader does.
+pub struct Throttled<'a, R> {
+    inner: R,
+    limit: Option<&'a ReadLimit>,
+}
+
+impl<'a, R: Read> Throttled<'a, R> {
+    pub fn new(inner: R, limit: Option<&'a ReadLimit>) -> Self {
+        Throttled { inner, limit }
+    }
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limit) = self.limit else {
+            return self.inner.read(buf);
+        };
+        let len = buf.len().min(limit.burst);
+        let read = self.inner.read(&mut buf[..len])?;
+        limit.take(read);
+        Ok(read)
+    }
+}
+
+// The I/O scheduling class --io-nice puts the process in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoNice {
+    // disk time only when no other process wants it
+    Idle,
+    // the lowest priority of the default class
+    BestEffort,
+}
+
+// Sets the I/O class of this process with ioprio_set, as ionice does. The
+// schedulers honoring it are those of local disks, not of network mounts.
+#[cfg(target_os = "linux")]
+pub fn set_io_priority(class: IoNice) -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    let priority = match class {
+        IoNice::Idle => 3 << IOPRIO_CLASS_SHIFT,
+        IoNice::BestEffort => (2 << IOPRIO_CLASS_SHIFT) | 7,
+    };
+    // 0 is the calling process
+    let result = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_io_priority(_class: IoNice) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "io_nice is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_stay_under_the_limit() {
+        let limit = ReadLimit::new(2.0);
+        let data = vec![7u8; 1 << 19];
+        let mut reader = Throttled::new(data.as_slice(), Some(&limit));
+        let started = Instant::now();
+        let mut buf = vec![0; 1 << 20];
+        let mut read = 0;
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(n <= limit.burst);
+            read += n;
+        }
+        assert_eq!(read, data.len());
+        // half a MB at 2 MB/s
+        let secs = started.elapsed().as_secs_f64();
+        assert!(secs >= 0.24, "{}", secs);
+        let mbps = limit.achieved_mbps().unwrap();
+        assert!(mbps <= 2.0 * 1.05, "{}", mbps);
+
+        let mut unlimited = Throttled::new(data.as_slice(), None);
+        assert_eq!(unlimited.read(&mut buf).unwrap(), data.len());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::filters::{ParaFilter, RejectReason};
+use crate::stats::Stats;
+use crate::{count_matching_chars, Extractor, CJK_REGEX};
+use serde::Serialize;
+
+// What the conversion and the checks made of one post, for debugging why a
+// sentence is or is not in the corpus
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PostTrace {
+    // after HTML conversion
+    pub text: String,
+    // the non-blank lines of the text
+    pub paragraphs: Vec<ParaTrace>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParaTrace {
+    pub paragraph: String,
+    // as checked, after rewrites such as --collapse-repeats
+    pub normalized: String,
+    pub chars: usize,
+    pub cjk_chars: usize,
+    pub cjk_ratio: f64,
+    // every check run on its own, the first rejection is the one counted
+    pub checks: Vec<CheckVerdict>,
+    pub sentence: Option<String>,
+    pub rejected: Option<RejectReason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckVerdict {
+    pub check: &'static str,
+    pub rejected: Option<RejectReason>,
+}
+
+impl Extractor {
+    // The steps `process_text` takes on the html of a post
+    pub fn trace_post(&self, html: &str) -> PostTrace {
+        let (text, _) = self.html.to_text(html, self.markup);
+        let paragraphs = text
+            .split('\n')
+            .map(str::trim)
+            .filter(|para| !para.is_empty())
+            .map(|para| self.trace_para(para))
+            .collect();
+        PostTrace { text, paragraphs }
+    }
+
+    fn trace_para(&self, paragraph: &str) -> ParaTrace {
+        let normalized = self.normalize_para(paragraph);
+        let mut checks: Vec<CheckVerdict> = self
+            .chain
+            .trace(&normalized)
+            .into_iter()
+            .map(|(check, result)| CheckVerdict {
+                check,
+                rejected: result.err(),
+            })
+            .collect();
+        checks.push(CheckVerdict {
+            check: "CjkRatio",
+            rejected: self.cjk_ratio.check(&normalized).err(),
+        });
+        let result = self
+            .check_para(&normalized)
+            .and_then(|()| self.clean_para(&normalized, &mut Stats::default()));
+        let chars = normalized.chars().count();
+        let cjk_chars = count_matching_chars(&normalized, &CJK_REGEX);
+        ParaTrace {
+            paragraph: paragraph.to_string(),
+            normalized: normalized.into_owned(),
+            chars,
+            cjk_chars,
+            cjk_ratio: cjk_chars as f64 / chars.max(1) as f64,
+            checks,
+            rejected: result.as_ref().err().copied(),
+            sentence: result.ok(),
+        }
+    }
+}
+
+impl PostTrace {
+    pub fn report(&self) -> String {
+        let mut report = format!("text after conversion:\n{}\n", self.text);
+        for (i, para) in self.paragraphs.iter().enumerate() {
+            report.push_str(&format!("\nparagraph {}: {:?}\n", i + 1, para.paragraph));
+            if para.normalized != para.paragraph {
+                report.push_str(&format!("  normalized: {:?}\n", para.normalized));
+            }
+            report.push_str(&format!(
+                "  chars: {}, cjk: {}, cjk ratio: {:.2}\n",
+                para.chars, para.cjk_chars, para.cjk_ratio
+            ));
+            for check in &para.checks {
+                let verdict = match check.rejected {
+                    Some(reason) => format!("rejected ({})", reason.as_str()),
+                    None => "accepted".to_string(),
+                };
+                report.push_str(&format!("  {}: {}\n", check.check, verdict));
+            }
+            match (&para.sentence, para.rejected) {
+                (Some(sentence), _) => report.push_str(&format!("  => sentence {:?}\n", sentence)),
+                (None, Some(reason)) => {
+                    report.push_str(&format!("  => rejected: {}\n", reason.as_str()))
+                }
+                (None, None) => {}
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_agrees_with_extraction() {
+        let extractor = Extractor::default();
+        let html = "我哋今日去咗飲茶<br />睇下https://a.hk<br /><br />好";
+        let trace = extractor.trace_post(html);
+        assert_eq!(trace.paragraphs.len(), 3);
+        let sentences: Vec<String> = trace
+            .paragraphs
+            .iter()
+            .filter_map(|para| para.sentence.clone())
+            .collect();
+        assert_eq!(sentences, extractor.extract_paragraphs(html));
+
+        let url = &trace.paragraphs[1];
+        assert_eq!(url.rejected, Some(RejectReason::Url));
+        assert!(url
+            .checks
+            .iter()
+            .any(|c| c.check == "Url" && c.rejected == Some(RejectReason::Url)));
+        let short = &trace.paragraphs[2];
+        assert_eq!((short.chars, short.cjk_chars), (1, 1));
+        assert_eq!(short.rejected, Some(RejectReason::Length));
+        assert!(trace.report().contains("  => rejected: length\n"));
+    }
+}
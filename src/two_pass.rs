@@ -0,0 +1,219 @@
+use crate::config::RareCharsMode;
+use crate::filters::RejectReason;
+use crate::rare_chars::is_pua;
+use crate::SentenceRecord;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use xxhash_rust::xxh64::xxh64;
+
+const MAGIC: &[u8; 8] = b"LIHKGP1\n";
+
+// Corpus-level counts gathered by the first pass over the accepted sentences
+#[derive(Debug, Default, PartialEq)]
+pub struct Pass1State {
+    pub char_counts: HashMap<char, u64>,
+    // distinct threads per sentence hash, only kept for sentences seen in
+    // more than one thread
+    pub thread_counts: HashMap<u64, u32>,
+}
+
+#[derive(Default)]
+pub struct Pass1Collector {
+    state: Pass1State,
+    seen: HashSet<u64>,
+}
+
+impl Pass1Collector {
+    pub fn observe(&mut self, record: &SentenceRecord) {
+        for c in record.text.chars() {
+            *self.state.char_counts.entry(c).or_default() += 1;
+        }
+        let hash = xxh64(record.text.as_bytes(), 0);
+        let thread_id = record.thread_id.unwrap_or_default();
+        if self.seen.insert(xxh64(&thread_id.to_le_bytes(), hash)) {
+            *self.state.thread_counts.entry(hash).or_default() += 1;
+        }
+    }
+
+    pub fn finish(mut self) -> Pass1State {
+        self.state.thread_counts.retain(|_, threads| *threads > 1);
+        self.state
+    }
+}
+
+impl Pass1State {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.char_counts.len() as u64).to_le_bytes())?;
+        for (c, count) in &self.char_counts {
+            writer.write_all(&(*c as u32).to_le_bytes())?;
+            writer.write_all(&count.to_le_bytes())?;
+        }
+        writer.write_all(&(self.thread_counts.len() as u64).to_le_bytes())?;
+        for (hash, threads) in &self.thread_counts {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&threads.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a pass one state file",
+            ));
+        }
+        let mut state = Pass1State::default();
+        for _ in 0..read_u64(&mut reader)? {
+            let c = char::from_u32(read_u32(&mut reader)?)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid char"))?;
+            state.char_counts.insert(c, read_u64(&mut reader)?);
+        }
+        for _ in 0..read_u64(&mut reader)? {
+            let hash = read_u64(&mut reader)?;
+            state.thread_counts.insert(hash, read_u32(&mut reader)?);
+        }
+        Ok(state)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// Second pass pruning against the pass one counts
+pub struct Pruner {
+    pub state: Pass1State,
+    // chars seen fewer times than this are rare
+    pub min_char_count: Option<u64>,
+    // private use chars are rare
+    pub rare_pua: bool,
+    // dropping sentences with rare chars or replacing the chars
+    pub rare_chars: RareCharsMode,
+    pub rare_char_token: String,
+    // drop sentences appearing in more distinct threads than this
+    pub max_threads: Option<u32>,
+}
+
+impl Pruner {
+    // Rejects the record or replaces the rare chars in its text
+    pub fn check(&self, record: &mut SentenceRecord) -> Result<(), RejectReason> {
+        if let Some(max_threads) = self.max_threads {
+            let hash = xxh64(record.text.as_bytes(), 0);
+            if self.state.thread_counts.get(&hash).copied().unwrap_or(1) > max_threads {
+                return Err(RejectReason::Copypasta);
+            }
+        }
+        if !record.text.chars().any(|c| self.is_rare(c)) {
+            return Ok(());
+        }
+        match self.rare_chars {
+            RareCharsMode::DropSentence => Err(RejectReason::RareChar),
+            RareCharsMode::Replace => {
+                let mut text = String::with_capacity(record.text.len());
+                for c in record.text.chars() {
+                    match self.is_rare(c) {
+                        true => text.push_str(&self.rare_char_token),
+                        false => text.push(c),
+                    }
+                }
+                record.text = text;
+                Ok(())
+            }
+        }
+    }
+
+    fn is_rare(&self, c: char) -> bool {
+        let below = |min_count| self.state.char_counts.get(&c).copied().unwrap_or(0) < min_count;
+        self.min_char_count.is_some_and(below) || (self.rare_pua && is_pua(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(text: &str, thread_id: u64) -> SentenceRecord {
+        SentenceRecord {
+            text: text.to_string(),
+            thread_id: Some(thread_id),
+            ..Default::default()
+        }
+    }
+
+    fn collect(records: &[SentenceRecord]) -> Pass1State {
+        let mut collector = Pass1Collector::default();
+        records.iter().for_each(|r| collector.observe(r));
+        collector.finish()
+    }
+
+    #[test]
+    fn threads_are_counted_once_per_thread() {
+        let state = collect(&[
+            record("講多無謂食飯要緊", 1),
+            record("講多無謂食飯要緊", 1),
+            record("講多無謂食飯要緊", 2),
+            record("今日天氣好好", 1),
+        ]);
+        let hash = xxh64("講多無謂食飯要緊".as_bytes(), 0);
+        assert_eq!(state.thread_counts, HashMap::from([(hash, 2)]));
+        assert_eq!(state.char_counts[&'講'], 3);
+    }
+
+    #[test]
+    fn prunes_rare_chars_and_copypasta() {
+        let records = [
+            record("講多無謂食飯要緊", 1),
+            record("講多無謂食飯要緊", 2),
+            record("講多無謂食飯要緊", 3),
+            record("今日天氣好好", 1),
+            record("今日天氣好好", 2),
+            record("今日天氣好䶮", 1),
+        ];
+        let mut pruner = Pruner {
+            state: collect(&records),
+            min_char_count: Some(2),
+            rare_pua: true,
+            rare_chars: RareCharsMode::DropSentence,
+            rare_char_token: "\u{FFFD}".to_string(),
+            max_threads: Some(2),
+        };
+        let [mut copypasta, _, _, mut common, _, mut rare] = records;
+        assert_eq!(pruner.check(&mut copypasta), Err(RejectReason::Copypasta));
+        assert_eq!(pruner.check(&mut common), Ok(()));
+        assert_eq!(pruner.check(&mut rare.clone()), Err(RejectReason::RareChar));
+
+        pruner.rare_chars = RareCharsMode::Replace;
+        assert_eq!(pruner.check(&mut rare), Ok(()));
+        assert_eq!(rare.text, "今日天氣好\u{FFFD}");
+        // private use chars are rare however often they were seen
+        pruner.state.char_counts.insert('\u{E05E}', 100);
+        let mut pua = record("今日\u{E05E}天氣", 1);
+        assert_eq!(pruner.check(&mut pua), Ok(()));
+        assert_eq!(pua.text, "今日\u{FFFD}天氣");
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let state = collect(&[record("講多無謂食飯要緊", 1), record("講多無謂食飯要緊", 2)]);
+        let path = std::env::temp_dir().join(format!("lihkg-pass1-{}", std::process::id()));
+        state.save(&path).unwrap();
+        assert_eq!(Pass1State::load(&path).unwrap(), state);
+        std::fs::remove_file(path).unwrap();
+    }
+}
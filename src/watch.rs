@@ -0,0 +1,165 @@
+use notify::{RecursiveMode, Watcher};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// How often pending files are checked for a stable size
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Files the scraper may still be writing, each with the size it was last
+// seen at and since when. A file is ready once its size has not changed for
+// `settle`.
+pub struct Settling {
+    settle: Duration,
+    pending: BTreeMap<PathBuf, (u64, Instant)>,
+}
+
+impl Settling {
+    pub fn new(settle: Duration) -> Self {
+        Settling {
+            settle,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, path: PathBuf, size: u64, now: Instant) {
+        match self.pending.get_mut(&path) {
+            Some((last, _)) if *last == size => {}
+            Some(entry) => *entry = (size, now),
+            None => {
+                self.pending.insert(path, (size, now));
+            }
+        }
+    }
+
+    // Removes and returns the settled files in name order
+    pub fn ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let ready: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, since))| now.duration_since(*since) >= self.settle)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.pending.remove(path);
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &Path> {
+        self.pending.keys().map(PathBuf::as_path)
+    }
+}
+
+// Dump files are xz compressed, a plain csv or a whole .tar.xz archive
+pub fn is_dump(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "xz")
+}
+
+// Processed files, one path per line
+pub fn load_manifest(path: &Path) -> io::Result<HashSet<PathBuf>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().map(PathBuf::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// Processes the dump files already in `dir`, then every new one as it
+// appears, until the process is killed. A file is handed to `process` once
+// its size stays the same for `settle` and is then added to the manifest,
+// so a restarted watch skips it.
+pub fn watch(
+    dir: &Path,
+    manifest: &Path,
+    settle: Duration,
+    mut process: impl FnMut(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    // absolute, like the paths in notify events
+    let dir = &dir.canonicalize()?;
+    let mut done = load_manifest(manifest)?;
+    let mut manifest = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(manifest)?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(io::Error::other)?;
+
+    let mut settling = Settling::new(settle);
+    let mut candidates: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    loop {
+        let now = Instant::now();
+        let pending: Vec<PathBuf> = settling.pending().map(Path::to_path_buf).collect();
+        for path in candidates.drain(..).chain(pending) {
+            if !is_dump(&path) || done.contains(&path) {
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(metadata) => settling.observe(path, metadata.len(), now),
+                // removed before settling
+                Err(_) => settling.forget(&path),
+            }
+        }
+        for path in settling.ready(now) {
+            process(&path)?;
+            writeln!(manifest, "{}", path.display())?;
+            manifest.flush()?;
+            done.insert(path);
+        }
+
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(event) => {
+                candidates.extend(event.map_err(io::Error::other)?.paths);
+                candidates.extend(rx.try_iter().flat_map(|event| match event {
+                    Ok(event) => event.paths,
+                    Err(_) => Vec::new(),
+                }));
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("file watcher stopped"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_wait_for_a_stable_size() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        let mut settling = Settling::new(Duration::from_secs(5));
+        settling.observe("a.csv.xz".into(), 100, secs(0));
+        settling.observe("b.csv.xz".into(), 100, secs(0));
+        settling.observe("a.csv.xz".into(), 200, secs(3));
+        settling.observe("b.csv.xz".into(), 100, secs(3));
+        assert_eq!(settling.ready(secs(5)), vec![PathBuf::from("b.csv.xz")]);
+        settling.observe("a.csv.xz".into(), 200, secs(6));
+        assert!(settling.ready(secs(7)).is_empty());
+        assert_eq!(settling.ready(secs(8)), vec![PathBuf::from("a.csv.xz")]);
+        assert_eq!(settling.pending().count(), 0);
+    }
+
+    #[test]
+    fn only_xz_files_are_dumps() {
+        assert!(is_dump(Path::new("dir/3300000.csv.xz")));
+        assert!(is_dump(Path::new("dump.tar.xz")));
+        assert!(!is_dump(Path::new("dir/3300000.csv.xz.part")));
+        assert!(!is_dump(Path::new("manifest.txt")));
+    }
+}
@@ -0,0 +1,95 @@
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use xz2::write::XzEncoder;
+
+// A line of a dump file
+pub enum Line {
+    // `thread\tpage\tjson` as scraped
+    Dump(Value),
+    // written as is, for malformed input
+    Raw(&'static str),
+}
+
+impl Line {
+    // A successful page holding one post per message
+    pub fn posts(thread_id: u64, msgs: &[&str]) -> Line {
+        let items: Vec<Value> = msgs
+            .iter()
+            .enumerate()
+            .map(|(i, msg)| {
+                json!({
+                    "post_id": format!("{}:{}", thread_id, i + 1),
+                    "msg_num": (i + 1).to_string(),
+                    "msg": msg,
+                    "reply_time": 1697328000 + i as i64 * 60,
+                    "like_count": "2",
+                    "dislike_count": "1",
+                })
+            })
+            .collect();
+        Line::Dump(json!({
+            "success": 1,
+            "response": {"thread_id": thread_id.to_string(), "page": "1", "item_data": items},
+        }))
+    }
+
+    fn render(&self) -> String {
+        match self {
+            Line::Dump(value) => {
+                let thread_id = value["response"]["thread_id"].as_str().unwrap_or("0");
+                format!("{}\t1\t{}", thread_id, value)
+            }
+            Line::Raw(line) => line.to_string(),
+        }
+    }
+}
+
+// Writes a .tar.xz with one csv entry per (name, lines) pair, in order
+pub fn build_archive(path: &Path, entries: &[(&str, Vec<Line>)]) {
+    build_archive_with_endings(path, entries, &["\n"]);
+}
+
+// Like `build_archive`, ending the lines with `endings` in turn
+pub fn build_archive_with_endings(path: &Path, entries: &[(&str, Vec<Line>)], endings: &[&str]) {
+    let mut tar = tar::Builder::new(XzEncoder::new(File::create(path).unwrap(), 6));
+    for (name, lines) in entries {
+        let data: String = lines
+            .iter()
+            .zip(endings.iter().cycle())
+            .map(|(l, ending)| l.render() + ending)
+            .collect();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, data.as_bytes()).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap().flush().unwrap();
+}
+
+// A path under the temp dir unique to this test process
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("lihkg-{}-{}", std::process::id(), name))
+}
+
+// Compares against the checked-in golden file, or rewrites it when
+// UPDATE_GOLDEN is set
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1", path.display()));
+    assert!(
+        actual == expected,
+        "{} differs from the output, rerun with UPDATE_GOLDEN=1 if intended\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        expected,
+        actual
+    );
+}
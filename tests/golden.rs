@@ -0,0 +1,605 @@
+mod common;
+
+use common::{assert_golden, build_archive, build_archive_with_endings, temp_path, Line};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::process::Command;
+
+fn cases() -> Vec<(&'static str, Vec<Line>)> {
+    vec![
+        (
+            "3300001.csv",
+            vec![
+                Line::posts(3300001, &["我哋今日去咗飲茶", "此回覆已被刪除", "好"]),
+                Line::posts(
+                    3300001,
+                    &[
+                        "<blockquote>引用嘅嘢唔應該出現</blockquote>我覺得你講得啱",
+                        "<blockquote><blockquote>一層又一層</blockquote>兩層</blockquote>咁樣都得嘅咩",
+                    ],
+                ),
+                Line::posts(
+                    3300001,
+                    &[
+                        "睇下 https://lih.kg/3300001",
+                        "<a href=\"http://example.com\">http://example.com</a>",
+                        "分享自 LIHKG 討論區",
+                    ],
+                ),
+            ],
+        ),
+        (
+            "3300002.csv",
+            vec![
+                Line::posts(
+                    3300002,
+                    &[
+                        "<img src=\"/assets/faces/normal/smile.gif\" class=\"hkgmoji\" />奶茶真係唔錯",
+                        "笑死我😂😂呢個真係正",
+                        "今晚食咩好呢大家<br />有冇推介呀",
+                    ],
+                ),
+                Line::posts(3300002, &["hello world", "2023.10.15", "12:34:56"]),
+                Line::posts(3300002, &["哈哈哈哈哈哈哈哈哈哈哈", "OK啦我聽日再嚟過"]),
+                // the punchline struck through, and a spoiler
+                Line::posts(
+                    3300002,
+                    &[
+                        "老細話今年有花紅<del>係得個講字</del>",
+                        "<span class=\"spoiler\">主角死咗</span>估唔到啩",
+                    ],
+                ),
+            ],
+        ),
+        (
+            "3300003.csv",
+            vec![
+                // success variants
+                Line::Dump(json!({"success": 0, "error_code": 100, "error_message": "找不到"})),
+                Line::Dump(json!({"success": "1", "response": {"item_data": [{"msg": "字串嘅成功唔算數"}]}})),
+                Line::Dump(json!({"success": 1, "response": {}})),
+                Line::Dump(json!({"success": 1, "response": {"item_data": [{"no_msg": 1}]}})),
+                // malformed lines
+                Line::Raw(""),
+                Line::Raw("3300003\t1"),
+                Line::Raw("3300003\t1\t{\"success\": 1, \"response\""),
+                Line::Raw("not a dump line at all"),
+                Line::posts(3300003, &["落雨記得帶遮呀各位"]),
+            ],
+        ),
+    ]
+}
+
+fn run(args: &[&str], name: &str) -> String {
+    let archive = temp_path(&format!("{}.tar.xz", name));
+    let output = temp_path(&format!("{}.out", name));
+    build_archive(&archive, &cases());
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(archive).unwrap();
+    std::fs::remove_file(output).unwrap();
+    written
+}
+
+#[test]
+fn text_output() {
+    assert_golden("text_output.txt", &run(&[], "golden-text"));
+}
+
+#[test]
+fn jsonl_output() {
+    assert_golden(
+        "jsonl_output.jsonl",
+        &run(&["--format", "jsonl"], "golden-jsonl"),
+    );
+}
+
+#[test]
+fn struck_through_and_spoiler_text() {
+    assert_golden(
+        "markup_dropped.txt",
+        &run(
+            &["--strikethrough", "drop", "--spoiler", "drop"],
+            "golden-markup-dropped",
+        ),
+    );
+    assert_golden(
+        "markup_spoiler_tokens.txt",
+        &run(
+            &[
+                "--spoiler",
+                "token",
+                "--max-len",
+                "30",
+                "--min-cjk-ratio",
+                "0.25",
+            ],
+            "golden-markup-tokens",
+        ),
+    );
+}
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sample.tar.xz");
+
+fn run_profile(profile: &str) -> String {
+    let output = temp_path(&format!("profile-{}.out", profile));
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(SAMPLE)
+        .arg("--output")
+        .arg(&output)
+        .args(["--profile", profile])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(output).unwrap();
+    written
+}
+
+#[test]
+fn profiles() {
+    let [strict, default, lenient] = ["strict", "default", "lenient"].map(|profile| {
+        let written = run_profile(profile);
+        assert_golden(&format!("profile_{}.txt", profile), &written);
+        written
+    });
+    let expected = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/expected_output.txt"
+    );
+    assert_eq!(default, std::fs::read_to_string(expected).unwrap());
+    assert!(strict.lines().count() < default.lines().count());
+    assert!(default.lines().count() < lenient.lines().count());
+}
+
+#[test]
+fn index_follows_output() {
+    let posts: Vec<String> = (0..30)
+        .map(|i| match i % 3 {
+            0 => "我哋今日去咗飲茶".to_string(),
+            1 => format!("{}仔話落雨記得帶遮", char::from_u32(0x5f20 + i).unwrap()),
+            _ => "hello world".to_string(),
+        })
+        .collect();
+    let posts: Vec<&str> = posts.iter().map(String::as_str).collect();
+    // varied scores so the sampling drops some
+    let mut thread = Line::posts(3300001, &posts);
+    if let Line::Dump(value) = &mut thread {
+        let items = value["response"]["item_data"].as_array_mut().unwrap();
+        for (i, item) in items.iter_mut().enumerate() {
+            item["like_count"] = json!((i * 7 % 10).to_string());
+        }
+    }
+    let archive = temp_path("index.tar.xz");
+    let output = temp_path("index.out");
+    let index = temp_path("index.jsonl");
+    build_archive(
+        &archive,
+        &[
+            ("3300001.csv", vec![thread]),
+            ("3300002.csv", vec![Line::posts(3300002, &posts[..5])]),
+        ],
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--index")
+        .arg(&index)
+        .args(["--deduplicate", "--weight-by-score", "--seed", "3"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    let entries = std::fs::read_to_string(&index).unwrap();
+    for path in [archive, output, index] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert_eq!(written.lines().count(), entries.lines().count());
+    for (sentence, entry) in written.lines().zip(entries.lines()) {
+        let entry: serde_json::Value = serde_json::from_str(entry).unwrap();
+        let msg_num: usize = entry["msg_num"].as_u64().unwrap() as usize;
+        assert_eq!(sentence, posts[msg_num - 1]);
+        let thread_id = entry["thread_id"].as_u64().unwrap();
+        assert_eq!(entry["post_id"], format!("{}:{}", thread_id, msg_num));
+        let page = if msg_num > 25 { 2 } else { 1 };
+        assert_eq!(
+            entry["url"],
+            format!("https://lihkg.com/thread/{}/page/{}", thread_id, page)
+        );
+    }
+    assert_golden("index.jsonl", &entries);
+}
+
+#[test]
+fn polls_file() {
+    let page = |page: &str| {
+        Line::Dump(json!({"success": 1, "response": {
+            "thread_id": "3300004",
+            "page": page,
+            "vote_info": {
+                "question": "大家覺得呢套戲好唔好睇",
+                "options": [{"text": "好睇到喊咗出嚟"}, {"text": "唔好睇"}],
+            },
+            "item_data": [{"post_id": "3300004:1", "msg": "我哋今日去咗睇戲"}],
+        }}))
+    };
+    let archive = temp_path("polls.tar.xz");
+    let output = temp_path("polls.out");
+    let polls = temp_path("polls.jsonl");
+    build_archive(&archive, &[("3300004.csv", vec![page("1"), page("2")])]);
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--polls")
+        .arg(&polls)
+        .args(["--format", "jsonl"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    let poll_texts = std::fs::read_to_string(&polls).unwrap();
+    for path in [archive, output, polls] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert!(!written.contains("kind"));
+    assert_eq!(written.lines().count(), 2);
+    assert_golden("polls.jsonl", &poll_texts);
+}
+
+#[test]
+fn carriage_returns() {
+    // as dumped on Windows, with a lone "\r" ending one line
+    let archive = temp_path("crlf.tar.xz");
+    build_archive_with_endings(
+        &archive,
+        &[(
+            "3300006.csv",
+            vec![
+                Line::posts(
+                    3300006,
+                    &[
+                        "我哋今日去咗飲茶\r\n好開心呀",
+                        "落雨記得帶遮&#13;&#10;唔好整濕身",
+                    ],
+                ),
+                Line::posts(3300006, &["今晚食咩好呢&#13;有冇推介呀"]),
+                Line::Dump(json!({"success": 1, "response": {
+                    "thread_id": "3300006",
+                    "item_data": [{"msg": "奶茶真係唔錯&#13;&#13;&#10;咖啡都幾好"}],
+                }})),
+            ],
+        )],
+        &["\r\n", "\r", "\r\n"],
+    );
+    let output = temp_path("crlf.out");
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    for path in [archive, output] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert!(!written.contains('\r'));
+    assert_golden("carriage_returns.txt", &written);
+}
+
+#[test]
+fn pairs_file() {
+    let post = |msg: &str| json!({"msg": msg});
+    let archive = temp_path("pairs.tar.xz");
+    let output = temp_path("pairs.out");
+    let pairs = temp_path("pairs.tsv");
+    let page = Line::Dump(json!({"success": 1, "response": {
+        "thread_id": "3300005",
+        "item_data": [
+            post("呢間茶記好食過隔離嗰間"),
+            post("<blockquote>呢間茶記好食過隔離嗰間</blockquote>真係咁好食呀，我都想去試下"),
+            post("<blockquote><blockquote>呢間茶記好食過隔離嗰間</blockquote>真係咁好食呀，我都想去試下</blockquote>去咗就知啦，菠蘿油一流"),
+            post("<blockquote>真係咁好食呀，我都想去試下</blockquote>好"),
+        ],
+    }}));
+    build_archive(&archive, &[("3300005.csv", vec![page])]);
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--output-pairs")
+        .arg(&pairs)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let pair_lines = std::fs::read_to_string(&pairs).unwrap();
+    for path in [archive, output, pairs] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("pairs.tsv", &pair_lines);
+}
+
+#[test]
+fn reply_graph_file() {
+    let archive = temp_path("reply-graph.tar.xz");
+    let output = temp_path("reply-graph.out");
+    let graph = temp_path("reply-graph.jsonl");
+    let page = Line::posts(
+        3300008,
+        &[
+            "呢間茶記好食過隔離嗰間",
+            "<blockquote>呢間茶記好食過隔離嗰間</blockquote>真係咁好食呀",
+            "我哋今日去咗飲茶",
+            "<blockquote>真係咁好食呀</blockquote>去咗就知啦",
+            "<blockquote>呢個post已經唔見咗</blockquote>咩料",
+        ],
+    );
+    build_archive(&archive, &[("3300008.csv", vec![page])]);
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--reply-graph")
+        .arg(&graph)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let edges = std::fs::read_to_string(&graph).unwrap();
+    for path in [archive, output, graph] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("reply_graph.jsonl", &edges);
+}
+
+#[test]
+fn reply_tree_file() {
+    let archive = temp_path("reply-tree.tar.xz");
+    let output = temp_path("reply-tree.out");
+    let tree = temp_path("reply-tree.jsonl");
+    let post = |msg_num: u64, reply_id: Option<u64>, msg: &str| json!({"msg_num": msg_num.to_string(), "reply_id": reply_id, "msg": msg});
+    let page = |page: &str, items: Vec<Value>| {
+        Line::Dump(json!({"success": 1, "response": {
+            "thread_id": "3300009",
+            "page": page,
+            "item_data": items,
+        }}))
+    };
+    let pages = vec![
+        page(
+            "1",
+            vec![
+                post(1, None, "有冇人去過呢間茶記"),
+                post(2, Some(1), "去過，菠蘿油一流"),
+            ],
+        ),
+        page(
+            "2",
+            vec![
+                post(3, Some(2), "真係咁好食？"),
+                post(4, Some(1), "<p>冇去過</p><p>下次試下</p>"),
+            ],
+        ),
+    ];
+    build_archive(&archive, &[("3300009.csv", pages)]);
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--output-tree")
+        .arg(&tree)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let threads = std::fs::read_to_string(&tree).unwrap();
+    for path in [archive, output, tree] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("reply_tree.jsonl", &threads);
+}
+
+#[test]
+fn nicknames_file() {
+    let post = |nickname: &str| json!({"msg": "我哋今日去咗飲茶", "user": {"user_id": "1234", "nickname": nickname}});
+    let archive = temp_path("nicknames.tar.xz");
+    let output = temp_path("nicknames.out");
+    let nicknames = temp_path("nicknames.tsv");
+    let page = |thread: &str, names: &[&str]| {
+        Line::Dump(json!({"success": 1, "response": {
+            "thread_id": thread,
+            "item_data": names.iter().map(|name| post(name)).collect::<Vec<_>>(),
+        }}))
+    };
+    build_archive(
+        &archive,
+        &[
+            (
+                "3300006.csv",
+                vec![page("3300006", &["潮爆巴打", "連登", "毒L"])],
+            ),
+            (
+                "3300007.csv",
+                vec![page("3300007", &["毒L", "潮爆巴打", "毒L", "已刪除"])],
+            ),
+        ],
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--nicknames")
+        .arg(&nicknames)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let counts = std::fs::read_to_string(&nicknames).unwrap();
+    for path in [archive, output, nicknames] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("nicknames.tsv", &counts);
+}
+
+#[test]
+fn thread_stats_file() {
+    let archive = temp_path("thread-stats.tar.xz");
+    let output = temp_path("thread-stats.out");
+    let thread_stats = temp_path("thread-stats.tsv");
+    build_archive(
+        &archive,
+        &[
+            (
+                "3300008.csv",
+                vec![Line::posts(
+                    3300008,
+                    &["我哋今日去咗飲茶", "好", "check下個link先"],
+                )],
+            ),
+            (
+                "3300009.csv",
+                vec![Line::posts(3300009, &["呢間茶記好食過隔離嗰間"])],
+            ),
+        ],
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--thread-stats")
+        .arg(&thread_stats)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let rows = std::fs::read_to_string(&thread_stats).unwrap();
+    for path in [archive, output, thread_stats] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("thread_stats.tsv", &rows);
+}
+
+#[test]
+fn post_sentence_hist_file() {
+    let archive = temp_path("post-hist.tar.xz");
+    let output = temp_path("post-hist.out");
+    let hist = temp_path("post-hist.tsv");
+    build_archive(&archive, &cases());
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--post-sentence-hist")
+        .arg(&hist)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let rows = std::fs::read_to_string(&hist).unwrap();
+    for path in [archive, output, hist] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("post_sentence_hist.tsv", &rows);
+}
+
+#[test]
+fn per_entry_output_files() {
+    let archive = temp_path("per-entry.tar.xz");
+    let output = temp_path("per-entry.out");
+    let dir = temp_path("per-entry");
+    build_archive(
+        &archive,
+        &[
+            (
+                "dump/3300010.csv",
+                vec![Line::posts(3300010, &["我哋今日去咗飲茶"])],
+            ),
+            ("dump/3300011.csv", vec![Line::posts(3300011, &["好"])]),
+            (
+                "dump_3300010.csv",
+                vec![Line::posts(3300012, &["呢間茶記好食過隔離嗰間"])],
+            ),
+        ],
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&archive)
+        .arg("--output")
+        .arg(&output)
+        .arg("--per-entry-output")
+        .arg(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    let mut listing = String::new();
+    for file in files {
+        listing.push_str(&format!(
+            "== {}\n{}",
+            file.file_name().unwrap().to_string_lossy(),
+            std::fs::read_to_string(&file).unwrap()
+        ));
+    }
+    assert!(std::fs::read_to_string(&output).unwrap().is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+    for path in [archive, output] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("per_entry_output.txt", &listing);
+}
+
+#[test]
+fn output_modes() {
+    let archive = temp_path("modes.tar.xz");
+    let output = temp_path("modes.out");
+    build_archive(
+        &archive,
+        &[(
+            "3300013.csv",
+            vec![Line::posts(
+                3300013,
+                &[
+                    "我哋今日去咗飲茶<br />點心好好食呀<br /><br />聽日再去過先啦<br />好",
+                    "呢間茶記好食過隔離嗰間",
+                    "落雨記得帶遮呀<br />唔好整濕身",
+                ],
+            )],
+        )],
+    );
+    let mut written = String::new();
+    for (mode, format) in [
+        ("sentence", "text"),
+        ("paragraph", "text"),
+        ("document", "text"),
+        ("document", "jsonl"),
+        ("post", "text"),
+    ] {
+        let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+            .arg(&archive)
+            .arg("--output")
+            .arg(&output)
+            .args(["--output-mode", mode, "--format", format])
+            .args(["--doc-separator", " | "])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        written.push_str(&format!(
+            "== {} {}\n{}",
+            mode,
+            format,
+            std::fs::read_to_string(&output).unwrap()
+        ));
+    }
+    for path in [archive, output] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_golden("output_modes.txt", &written);
+}
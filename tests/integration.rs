@@ -0,0 +1,619 @@
+use lihkg::config::{ExtractorConfig, ParaConfig};
+use lihkg::dedup::DedupState;
+use lihkg::filters::RejectReason;
+use lihkg::pipeline::process_archive;
+use lihkg::profanity::ProfanityMode;
+use lihkg::quality::ACCEPT_THRESHOLD;
+use lihkg::Extractor;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::process::Command;
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/sample.tar.xz");
+const EXPECTED: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/expected_output.txt"
+);
+
+#[test]
+fn extracts_expected_sentences() {
+    let output = std::env::temp_dir().join(format!("lihkg-integration-{}.txt", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(SAMPLE)
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(written, std::fs::read_to_string(EXPECTED).unwrap());
+}
+
+#[test]
+fn subcommands_extract_report_and_sample() {
+    let dir = std::env::temp_dir().join(format!("lihkg-subcommands-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |args: &[&str]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+            .current_dir(&dir)
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{:?}", args);
+    };
+    run(&["extract", SAMPLE, "--output", "extracted.txt"]);
+    run(&["stats", SAMPLE, "--output", "stats.json"]);
+    run(&["sample", SAMPLE, "--output", "sample.txt", "-n", "3"]);
+    let expected = std::fs::read_to_string(EXPECTED).unwrap();
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("extracted.txt"), expected);
+    let report: serde_json::Value = serde_json::from_str(&read("stats.json")).unwrap();
+    assert_eq!(report["total_sentences"], expected.lines().count());
+    let sample = read("sample.txt");
+    assert_eq!(sample.lines().count(), 3);
+    assert!(sample
+        .lines()
+        .all(|line| expected.lines().any(|e| e == line)));
+    // stats writes no sentence file of its own
+    let mut names: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(names, ["extracted.txt", "sample.txt", "stats.json"]);
+}
+
+#[test]
+fn groups_sentences_by_thread() {
+    let dir = std::env::temp_dir().join(format!("lihkg-threads-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("rest.txt");
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(SAMPLE)
+        .arg("--output")
+        .arg(&output)
+        .arg("--group-by-thread")
+        .arg("--output-dir")
+        .arg(dir.join("threads"))
+        .args(["--max-open-files", "1"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut lines: Vec<String> = std::fs::read_to_string(&output)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    let mut threads = 0;
+    for file in std::fs::read_dir(dir.join("threads")).unwrap() {
+        let path = file.unwrap().path();
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        assert!(stem.parse::<u64>().is_ok(), "{}", path.display());
+        let content = std::fs::read_to_string(&path).unwrap();
+        lines.extend(content.lines().map(String::from));
+        threads += 1;
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(threads > 1);
+    let mut expected: Vec<String> = std::fs::read_to_string(EXPECTED)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    lines.sort();
+    expected.sort();
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn interleaves_inputs_attributing_their_stats() {
+    let output = std::env::temp_dir().join(format!("lihkg-interleave-{}.txt", std::process::id()));
+    let result = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .args([SAMPLE, SAMPLE, "--interleave", "--output"])
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    let mut lines: Vec<&str> = written.lines().collect();
+    let expected = std::fs::read_to_string(EXPECTED).unwrap();
+    let mut expected: Vec<&str> = expected.lines().chain(expected.lines()).collect();
+    lines.sort();
+    expected.sort();
+    assert_eq!(lines, expected);
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let summary = format!("{}: lines=20 ", SAMPLE);
+    assert_eq!(stderr.matches(&summary).count(), 2, "{}", stderr);
+    assert!(stderr.contains(" INFO lines=40 "), "{}", stderr);
+}
+
+#[test]
+fn stats_file_reports_what_dedup_dropped() {
+    let output = std::env::temp_dir().join(format!("lihkg-dedup-{}.txt", std::process::id()));
+    let stats = std::env::temp_dir().join(format!("lihkg-dedup-{}.json", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .args([SAMPLE, SAMPLE, "--deduplicate", "--output"])
+        .arg(&output)
+        .arg("--stats-file")
+        .arg(&stats)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&stats).unwrap()).unwrap();
+    std::fs::remove_file(output).unwrap();
+    std::fs::remove_file(stats).unwrap();
+    let sentences = std::fs::read_to_string(EXPECTED).unwrap().lines().count();
+    assert_eq!(
+        report["dedup"],
+        serde_json::json!({
+            "exact_duplicates_dropped": sentences,
+            "post_duplicates_dropped": 0,
+            "unique_sentences_written": written.lines().count(),
+        })
+    );
+    assert_eq!(written.lines().count(), sentences);
+}
+
+#[test]
+fn append_adds_to_the_output() {
+    let output = std::env::temp_dir().join(format!("lihkg-append-{}.txt", std::process::id()));
+    let run = || {
+        let result = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+            .args([SAMPLE, "--append", "--output"])
+            .arg(&output)
+            .output()
+            .unwrap();
+        assert!(result.status.success());
+        String::from_utf8(result.stderr).unwrap()
+    };
+    let expected = std::fs::read_to_string(EXPECTED).unwrap();
+    run();
+    assert!(!run().contains("newline"));
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        expected.repeat(2)
+    );
+
+    std::fs::write(&output, "冇換行").unwrap();
+    assert!(run().contains("does not end with a newline"));
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(written, format!("冇換行{}", expected));
+}
+
+#[test]
+fn dry_run_writes_nothing_and_prints_the_report() {
+    let dir = std::env::temp_dir().join(format!("lihkg-dry-run-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .args([SAMPLE, "--dry-run", "--deduplicate"])
+        .arg("--output")
+        .arg(dir.join("out.txt"))
+        .arg("--index")
+        .arg(dir.join("index.tsv"))
+        .arg("--dedup-state")
+        .arg(dir.join("dedup.state"))
+        .arg("--corpus-stats")
+        .arg(dir.join("corpus.json"))
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let written = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(written, 0);
+    let report: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    let expected = std::fs::read_to_string(EXPECTED).unwrap().lines().count();
+    assert_eq!(report["dedup"]["unique_sentences_written"], expected);
+    assert!(report["lines"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn run_manifest_matches_the_output() {
+    let output = std::env::temp_dir().join(format!("lihkg-manifest-{}.txt", std::process::id()));
+    let manifest = std::env::temp_dir().join(format!("lihkg-manifest-{}.json", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .args([SAMPLE, "--output"])
+        .arg(&output)
+        .arg("--run-manifest")
+        .arg(&manifest)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read(&output).unwrap();
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    std::fs::remove_file(manifest).unwrap();
+    let digest: String = Sha256::digest(&written)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(report["output_sha256"], digest);
+    assert_eq!(report["input_files"], serde_json::json!([SAMPLE]));
+    assert_eq!(
+        report["output_files"],
+        serde_json::json!([output.display().to_string()])
+    );
+    assert_eq!(
+        report["total_sentences"],
+        written.split(|&b| b == b'\n').count() - 1
+    );
+    assert_eq!(report["config"]["input"], SAMPLE);
+    assert!(report["run_timestamp"].as_str().unwrap().ends_with('Z'));
+}
+
+#[test]
+fn shard_flags_override_the_config_file_only_when_given() {
+    let dir = std::env::temp_dir();
+    let config = dir.join(format!("lihkg-shard-config-{}.toml", std::process::id()));
+    std::fs::write(&config, "[extractor]\nshard = { index = 1, count = 2 }\n").unwrap();
+    let shard = |flags: &[&str]| {
+        let output = dir.join(format!("lihkg-shard-config-{}.txt", std::process::id()));
+        let manifest = dir.join(format!("lihkg-shard-config-{}.json", std::process::id()));
+        let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+            .args([SAMPLE, "--config"])
+            .arg(&config)
+            .args(flags)
+            .arg("--output")
+            .arg(&output)
+            .arg("--run-manifest")
+            .arg(&manifest)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+        std::fs::remove_file(output).unwrap();
+        std::fs::remove_file(manifest).unwrap();
+        report["config"]["extractor"]["shard"].clone()
+    };
+    assert_eq!(shard(&[]), serde_json::json!({"index": 1, "count": 2}));
+    assert_eq!(
+        shard(&["--shard-index", "0", "--shard-count", "3"]),
+        serde_json::json!({"index": 0, "count": 3})
+    );
+    std::fs::remove_file(config).unwrap();
+}
+
+#[test]
+fn output_auto_builds_a_corpus_once() {
+    let dir = std::env::temp_dir().join(format!("lihkg-auto-{}", std::process::id()));
+    let build = |extra: &[&str]| {
+        let result = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+            .arg(SAMPLE)
+            .arg("--output-auto")
+            .arg(&dir)
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(result.status.success());
+        String::from_utf8(result.stderr).unwrap()
+    };
+    build(&[]);
+    let mut names: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 2, "{:?}", names);
+    let stem = names[0].strip_suffix(".meta.json").unwrap();
+    assert_eq!(names[1], format!("{}.txt", stem));
+    assert!(stem.starts_with("corpus-"));
+    let written = std::fs::read_to_string(dir.join(&names[1])).unwrap();
+    assert_eq!(written, std::fs::read_to_string(EXPECTED).unwrap());
+    let meta: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(&names[0])).unwrap()).unwrap();
+    assert_eq!(meta["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(meta["inputs"][0]["path"], SAMPLE);
+    assert_eq!(
+        meta["inputs"][0]["size"],
+        std::fs::metadata(SAMPLE).unwrap().len()
+    );
+    assert!(meta["stats"]["lines"].as_u64().unwrap() > 0);
+
+    // the same build is skipped, other settings name another corpus
+    std::fs::write(dir.join(&names[1]), "").unwrap();
+    assert!(build(&[]).contains("built already"));
+    assert_eq!(std::fs::read_to_string(dir.join(&names[1])).unwrap(), "");
+    build(&["--rebuild"]);
+    assert_eq!(
+        std::fs::read_to_string(dir.join(&names[1])).unwrap(),
+        written
+    );
+    build(&["--deduplicate"]);
+    let built = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(built, 4);
+}
+
+#[test]
+fn threads_out_counts_the_written_sentences() {
+    let output = std::env::temp_dir().join(format!("lihkg-threads-{}.txt", std::process::id()));
+    let threads = std::env::temp_dir().join(format!("lihkg-threads-{}.jsonl", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .args([SAMPLE, "--output"])
+        .arg(&output)
+        .arg("--threads-out")
+        .arg(&threads)
+        .arg("--threads-out-all")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap().lines().count() as u64;
+    let rows: Vec<serde_json::Value> = std::fs::read_to_string(&threads)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_file(&output).unwrap();
+    std::fs::remove_file(threads).unwrap();
+    // a row per thread, however many pages and workers it was spread over
+    let mut ids: Vec<u64> = rows
+        .iter()
+        .map(|r| r["thread_id"].as_u64().unwrap())
+        .collect();
+    ids.dedup();
+    assert_eq!(ids.len(), rows.len());
+    let sentences: u64 = rows.iter().map(|r| r["sentences"].as_u64().unwrap()).sum();
+    assert_eq!(sentences, written);
+}
+
+#[test]
+fn detects_the_encoding_of_non_utf8_lines() {
+    let dir = std::env::temp_dir().join(format!("lihkg-encoding-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let line = |msg: &str| {
+        format!(
+            "1\t1\t{{\"success\":1,\"response\":{{\"thread_id\":\"1\",\"item_data\":[{{\"msg\":\"{}\"}}]}}}}\n",
+            msg
+        )
+    };
+    let mut dump = line("我哋今日去咗飲茶").into_bytes();
+    let big5 = line("今日去飲茶真係好開心");
+    dump.extend_from_slice(&encoding_rs::BIG5.encode(&big5).0);
+    let input = dir.join("dump.txt");
+    std::fs::write(&input, dump).unwrap();
+    let output = dir.join("out.txt");
+    let log = dir.join("errors.jsonl");
+    let stats = dir.join("stats.json");
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg(&input)
+        .arg("--output")
+        .arg(&output)
+        .arg("--detect-encoding")
+        .arg("--error-log")
+        .arg(&log)
+        .arg("--stats-file")
+        .arg(&stats)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    let logged: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let stats: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&stats).unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(written, "我哋今日去咗飲茶\n今日去飲茶真係好開心\n");
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0]["line"], 2);
+    assert_eq!(logged[0]["encoding"], "Big5");
+    assert_eq!(stats["transcoded_lines"], 1);
+}
+
+#[test]
+fn merges_shard_outputs_states_and_stats() {
+    let dir = std::env::temp_dir().join(format!("lihkg-merge-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // the sample twice, as two shards holding the same threads
+    for shard in ["a", "b"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+            .arg(SAMPLE)
+            .arg("--output")
+            .arg(dir.join(format!("{}.txt", shard)))
+            .arg("--dedup-state")
+            .arg(dir.join(format!("{}.state", shard)))
+            .arg("--stats-file")
+            .arg(dir.join(format!("{}.json", shard)))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    let status = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .arg("merge")
+        .args([dir.join("a.txt"), dir.join("b.txt")])
+        .arg("--dedup-exact")
+        .arg("--output")
+        .arg(dir.join("merged.txt"))
+        .arg("--state")
+        .arg(dir.join("a.state"))
+        .arg("--state")
+        .arg(dir.join("b.state"))
+        .arg("--state-output")
+        .arg(dir.join("merged.state"))
+        .arg("--stats")
+        .arg(dir.join("a.json"))
+        .arg("--stats")
+        .arg(dir.join("b.json"))
+        .arg("--stats-output")
+        .arg(dir.join("merged.json"))
+        .args(["--partitions", "2"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let merged = std::fs::read_to_string(dir.join("merged.txt")).unwrap();
+    let state = DedupState::load(&dir.join("merged.state")).unwrap();
+    let stats: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("merged.json")).unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let mut lines: Vec<&str> = merged.lines().collect();
+    let expected = std::fs::read_to_string(EXPECTED).unwrap();
+    let mut expected: Vec<&str> = expected.lines().collect();
+    expected.sort();
+    expected.dedup();
+    lines.sort();
+    assert_eq!(lines, expected);
+    assert_eq!(state.sentences.len(), expected.len());
+    assert_eq!(stats["lines"], 40);
+    assert_eq!(stats["merge"]["sentences"], expected.len());
+    assert_eq!(stats["rejected"]["url"], 2);
+}
+
+#[test]
+fn jyutping_annotates_every_sentence() {
+    let output = std::env::temp_dir().join(format!("lihkg-jyutping-{}.jsonl", std::process::id()));
+    let dict = std::env::temp_dir().join(format!("lihkg-jyutping-{}.tsv", std::process::id()));
+    std::fs::write(&dict, "...\n銀行\tngan4 hong4\n").unwrap();
+    let result = Command::new(env!("CARGO_BIN_EXE_lihkg"))
+        .args([SAMPLE, "--format", "jsonl", "--jyutping", "--output"])
+        .arg(&output)
+        .arg("--jyutping-dict")
+        .arg(&dict)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&dict).unwrap();
+    if !cfg!(feature = "jyutping") {
+        assert!(!result.status.success());
+        assert!(String::from_utf8_lossy(&result.stderr).contains("the jyutping feature"));
+        return;
+    }
+    assert!(result.status.success());
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert!(written.lines().count() > 0);
+    for line in written.lines() {
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        let chars = record["text"]
+            .as_str()
+            .unwrap()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .count();
+        let syllables = record["jyutping"].as_str().unwrap().split(' ').count();
+        assert_eq!(syllables, chars, "{}", line);
+    }
+}
+
+#[test]
+fn writes_a_loadable_dataset_layout() {
+    for zstd in [false, true] {
+        let dir = std::env::temp_dir().join(format!("lihkg-hf-{}-{}", zstd, std::process::id()));
+        let mut command = Command::new(env!("CARGO_BIN_EXE_lihkg"));
+        command.arg(SAMPLE).arg("--hf-layout").arg(&dir).args([
+            "--validation-fraction",
+            "0.3",
+            "--test-fraction",
+            "0.3",
+        ]);
+        if zstd {
+            command.arg("--hf-zstd");
+        }
+        assert!(command.status().unwrap().success());
+        let infos: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("dataset_infos.json")).unwrap())
+                .unwrap();
+        let info = &infos["default"];
+        let features: Vec<&String> = info["features"].as_object().unwrap().keys().collect();
+        assert!(features.iter().any(|feature| *feature == "text"));
+        let mut rows = 0;
+        for (split, split_info) in info["splits"].as_object().unwrap() {
+            let name = match zstd {
+                false => format!("{}.jsonl", split),
+                true => format!("{}.jsonl.zst", split),
+            };
+            let bytes = std::fs::read(dir.join(name)).unwrap();
+            let bytes = match zstd {
+                false => bytes,
+                true => zstd::decode_all(&bytes[..]).unwrap(),
+            };
+            let text = String::from_utf8(bytes).unwrap();
+            for line in text.lines() {
+                let row: serde_json::Value = serde_json::from_str(line).unwrap();
+                let columns: Vec<&String> = row.as_object().unwrap().keys().collect();
+                assert_eq!(columns, features);
+                assert!(row["text"].is_string());
+            }
+            assert_eq!(split_info["num_examples"], text.lines().count());
+            assert_eq!(split_info["num_bytes"], text.len());
+            rows += text.lines().count();
+        }
+        assert_eq!(info["splits"].as_object().unwrap().len(), 3);
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            4,
+            "a file per split and the metadata"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            rows,
+            std::fs::read_to_string(EXPECTED).unwrap().lines().count()
+        );
+    }
+}
+
+#[test]
+fn rejects_each_kind_of_noise() {
+    let extractor = Extractor::new(&ExtractorConfig::default()).unwrap();
+    let mut stats = None;
+    process_archive(Path::new(SAMPLE), &extractor, None, |_, batch| {
+        stats = Some(batch.stats);
+        Ok(())
+    })
+    .unwrap();
+    let stats = stats.unwrap();
+    assert_eq!(stats.lines, 20);
+    assert_eq!(stats.json_errors, 0);
+    for reason in [
+        RejectReason::Deleted,
+        RejectReason::Url,
+        RejectReason::Date,
+        RejectReason::EnglishOnly,
+    ] {
+        assert!(stats.rejected.contains_key(&reason), "{:?}", reason);
+    }
+}
+
+#[test]
+fn score_threshold_reproduces_default_output() {
+    let texts = |config: &ExtractorConfig| {
+        let extractor = Extractor::new(config).unwrap();
+        let mut records = Vec::new();
+        process_archive(Path::new(SAMPLE), &extractor, None, |_, batch| {
+            records.extend(batch.records);
+            Ok(())
+        })
+        .unwrap();
+        records
+    };
+    let strict = ExtractorConfig {
+        profanity: ProfanityMode::Drop,
+        para: ParaConfig {
+            require_cantonese: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    for config in [ExtractorConfig::default(), strict] {
+        let expected: Vec<String> = texts(&config).into_iter().map(|r| r.text).collect();
+        let scored = texts(&ExtractorConfig {
+            score_all: true,
+            ..config
+        });
+        assert!(scored.len() > expected.len());
+        let kept: Vec<String> = scored
+            .into_iter()
+            .filter(|r| r.quality.unwrap().quality >= ACCEPT_THRESHOLD)
+            .map(|r| r.text)
+            .collect();
+        assert_eq!(kept, expected);
+    }
+}
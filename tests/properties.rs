@@ -0,0 +1,40 @@
+use lihkg::{filter_irrelevant_chars, is_punc, is_valid_para, CJK_REGEX};
+use proptest::prelude::*;
+
+// Mostly characters the filters care about, with arbitrary ones mixed in
+fn text() -> impl Strategy<Value = String> {
+    let c = prop_oneof![
+        any::<char>(),
+        prop::char::ranges(vec!['a'..='z', 'A'..='Z', '0'..='9', ' '..=' '].into()),
+        prop::char::range('\u{4e00}', '\u{9fff}'),
+        prop::sample::select(vec![
+            '，', '。', '！', '？', '「', '」', '…', '～', '/', ':'
+        ]),
+    ];
+    prop::collection::vec(c, 0..40).prop_map(|chars| chars.into_iter().collect())
+}
+
+fn is_relevant(c: char) -> bool {
+    CJK_REGEX.is_match(c.encode_utf8(&mut [0; 4])) || is_punc(c) || c.is_ascii_alphanumeric()
+}
+
+proptest! {
+    #[test]
+    fn filtered_text_only_keeps_relevant_chars(s in text()) {
+        let filtered = filter_irrelevant_chars(&s);
+        prop_assert!(filtered.chars().all(is_relevant), "{:?}", filtered);
+        prop_assert_eq!(filtered.chars().count(), s.chars().filter(|&c| is_relevant(c)).count());
+    }
+
+    #[test]
+    fn para_with_url_is_invalid(before in text(), after in text()) {
+        let para = format!("{}http://{}", before, after);
+        prop_assert!(!is_valid_para(&para));
+    }
+}
+
+// A constant input, no strategy needed
+#[test]
+fn empty_para_is_invalid() {
+    assert!(!is_valid_para(""));
+}
@@ -0,0 +1,53 @@
+# Smoke test of the Python module, see PYTHON.md:
+#   maturin develop && pytest tests/python
+import json
+
+import pytest
+
+import lihkg
+
+LINE = "1\t1\t" + json.dumps(
+    {
+        "success": 1,
+        "response": {
+            "thread_id": "3300001",
+            "item_data": [
+                {"msg": "我哋今日去咗飲茶<br />點心好好食", "like_count": 3},
+                {"msg": "<blockquote>引用</blockquote>我覺得你講得啱"},
+            ],
+        },
+    },
+    ensure_ascii=False,
+)
+
+
+def test_convert_html_to_text():
+    assert lihkg.convert_html_to_text("回覆<br /><b>粗體</b>") == "回覆\n粗體"
+    assert lihkg.convert_html_to_text("<blockquote>引用</blockquote>回覆") == "回覆"
+
+
+def test_extract_paragraphs():
+    html = "我哋今日去咗飲茶<br />ok<br />點心好好食"
+    assert lihkg.extract_paragraphs(html) == ["我哋今日去咗飲茶", "點心好好食"]
+    assert lihkg.extract_paragraphs(html, {"para": {"min_len": 6}}) == ["我哋今日去咗飲茶"]
+
+
+def test_extract_from_line():
+    records = lihkg.extract_from_line(LINE)
+    assert [r["text"] for r in records] == ["我哋今日去咗飲茶", "點心好好食", "我覺得你講得啱"]
+    assert records[0]["thread_id"] == 3300001
+    assert records[0]["post_score"] == 3
+
+
+def test_extractor_is_reused():
+    extractor = lihkg.Extractor({"dedup_posts": True})
+    assert len(extractor.extract_from_line(LINE)) == 3
+    # the same posts again are duplicates
+    assert extractor.extract_from_line(LINE) == []
+
+
+def test_bad_input_raises():
+    with pytest.raises(ValueError):
+        lihkg.Extractor({"no_such_setting": 1})
+    with pytest.raises(ValueError):
+        lihkg.extract_from_line("1\t1\t{not json")